@@ -0,0 +1,12 @@
+use cosmwasm_schema::write_api;
+
+use margined_perp::margined_backstop_pool::{ExecuteMsg, InstantiateMsg, MigrateMsg, QueryMsg};
+
+fn main() {
+    write_api! {
+        instantiate: InstantiateMsg,
+        execute: ExecuteMsg,
+        query: QueryMsg,
+        migrate: MigrateMsg,
+    }
+}
@@ -0,0 +1,118 @@
+#[cfg(not(feature = "library"))]
+use crate::error::ContractError;
+use crate::{
+    handle::{
+        absorb, close_position, close_position_reply, deposit, deposit_margin, update_config,
+        update_owner, withdraw, withdraw_margin,
+    },
+    query::{query_config, query_owner, query_positions, query_shares, query_state},
+    state::{store_config, store_total_shares, Config},
+};
+
+use cosmwasm_std::{
+    entry_point, to_binary, Binary, Deps, DepsMut, Env, MessageInfo, Reply, Response, StdError,
+    StdResult, Uint128,
+};
+use cw2::set_contract_version;
+use cw_controllers::Admin;
+use margined_common::validate::validate_ratio;
+use margined_perp::margined_backstop_pool::{ExecuteMsg, InstantiateMsg, MigrateMsg, QueryMsg};
+use margined_utils::contracts::helpers::EngineController;
+
+/// Contract name that is used for migration.
+const CONTRACT_NAME: &str = "crates.io:margined-backstop-pool";
+/// Contract version that is used for migration.
+const CONTRACT_VERSION: &str = env!("CARGO_PKG_VERSION");
+/// Owner admin
+pub const OWNER: Admin = Admin::new("owner");
+
+pub const CLOSE_POSITION_REPLY_ID: u64 = 1;
+
+#[cfg_attr(not(feature = "library"), entry_point)]
+pub fn instantiate(
+    deps: DepsMut,
+    _env: Env,
+    info: MessageInfo,
+    msg: InstantiateMsg,
+) -> Result<Response, ContractError> {
+    set_contract_version(deps.storage, CONTRACT_NAME, CONTRACT_VERSION)?;
+
+    let engine = deps.api.addr_validate(&msg.engine)?;
+    let keeper = deps.api.addr_validate(&msg.keeper)?;
+
+    // the pool holds the same collateral as the engine
+    let engine_config = EngineController(engine.clone()).config(&deps.querier)?;
+    validate_ratio(msg.margin_ratio, engine_config.decimals)?;
+
+    let config = Config {
+        engine,
+        keeper,
+        eligible_collateral: engine_config.eligible_collateral,
+        decimals: engine_config.decimals,
+        margin_ratio: msg.margin_ratio,
+    };
+
+    store_config(deps.storage, &config)?;
+    store_total_shares(deps.storage, &Uint128::zero())?;
+
+    OWNER.set(deps, Some(info.sender))?;
+
+    Ok(Response::default())
+}
+
+#[cfg_attr(not(feature = "library"), entry_point)]
+pub fn execute(deps: DepsMut, env: Env, info: MessageInfo, msg: ExecuteMsg) -> StdResult<Response> {
+    match msg {
+        ExecuteMsg::UpdateOwner { owner } => update_owner(deps, info, owner),
+        ExecuteMsg::UpdateConfig {
+            keeper,
+            margin_ratio,
+        } => update_config(deps, info, keeper, margin_ratio),
+        ExecuteMsg::Deposit { amount } => deposit(deps, env, info, amount),
+        ExecuteMsg::Withdraw { shares } => withdraw(deps, env, info, shares),
+        ExecuteMsg::Absorb { vamm, position_id } => absorb(deps, env, info, vamm, position_id),
+        ExecuteMsg::ClosePosition {
+            vamm,
+            position_id,
+            quote_asset_limit,
+        } => close_position(deps, info, vamm, position_id, quote_asset_limit),
+        ExecuteMsg::DepositMargin {
+            vamm,
+            position_id,
+            amount,
+        } => deposit_margin(deps, env, info, vamm, position_id, amount),
+        ExecuteMsg::WithdrawMargin {
+            vamm,
+            position_id,
+            amount,
+        } => withdraw_margin(deps, info, vamm, position_id, amount),
+    }
+}
+
+#[cfg_attr(not(feature = "library"), entry_point)]
+pub fn query(deps: Deps, env: Env, msg: QueryMsg) -> StdResult<Binary> {
+    match msg {
+        QueryMsg::Config {} => to_binary(&query_config(deps)?),
+        QueryMsg::GetOwner {} => to_binary(&query_owner(deps)?),
+        QueryMsg::State {} => to_binary(&query_state(deps, env)?),
+        QueryMsg::Shares { address } => to_binary(&query_shares(deps, env, address)?),
+        QueryMsg::Positions {} => to_binary(&query_positions(deps)?),
+    }
+}
+
+#[cfg_attr(not(feature = "library"), entry_point)]
+pub fn reply(deps: DepsMut, _env: Env, msg: Reply) -> StdResult<Response> {
+    match msg.id {
+        CLOSE_POSITION_REPLY_ID => close_position_reply(deps),
+        _ => Err(StdError::generic_err(format!(
+            "reply (id {:?}) invalid",
+            msg.id
+        ))),
+    }
+}
+
+#[cfg_attr(not(feature = "library"), entry_point)]
+pub fn migrate(deps: DepsMut, _env: Env, _msg: MigrateMsg) -> Result<Response, ContractError> {
+    set_contract_version(deps.storage, CONTRACT_NAME, CONTRACT_VERSION)?;
+    Ok(Response::new())
+}
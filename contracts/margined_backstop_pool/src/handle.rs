@@ -0,0 +1,421 @@
+use cosmwasm_std::{
+    Coin, CosmosMsg, DepsMut, Env, MessageInfo, Response, StdError, StdResult, SubMsg, Uint128,
+};
+use cw20::Cw20ExecuteMsg;
+use margined_common::{
+    asset::{Asset, AssetInfo},
+    messages::wasm_execute,
+    validate::validate_ratio,
+};
+use margined_perp::{margined_backstop_pool::PoolPosition, margined_engine::PnlCalcOption};
+use margined_utils::contracts::helpers::EngineController;
+
+use crate::{
+    contract::{CLOSE_POSITION_REPLY_ID, OWNER},
+    query::{query_held_position, query_positions_value},
+    state::{
+        is_position, read_config, read_positions, read_shares, read_tmp_position,
+        read_total_shares, remove_position, remove_tmp_position, save_position, store_config,
+        store_shares, store_tmp_position, store_total_shares, Config, MINIMUM_SHARES,
+    },
+};
+
+pub fn update_owner(deps: DepsMut, info: MessageInfo, owner: String) -> StdResult<Response> {
+    // validate the address
+    let valid_owner = deps.api.addr_validate(&owner)?;
+
+    OWNER
+        .execute_update_admin(deps, info, Some(valid_owner))
+        .map_err(|error| StdError::generic_err(error.to_string()))
+}
+
+pub fn update_config(
+    deps: DepsMut,
+    info: MessageInfo,
+    keeper: Option<String>,
+    margin_ratio: Option<Uint128>,
+) -> StdResult<Response> {
+    let mut config = read_config(deps.storage)?;
+
+    // check permission
+    if !OWNER.is_admin(deps.as_ref(), &info.sender)? {
+        return Err(StdError::generic_err("unauthorized"));
+    }
+
+    if let Some(keeper) = keeper {
+        config.keeper = deps.api.addr_validate(&keeper)?;
+    }
+
+    if let Some(margin_ratio) = margin_ratio {
+        validate_ratio(margin_ratio, config.decimals)?;
+        config.margin_ratio = margin_ratio;
+    }
+
+    store_config(deps.storage, &config)?;
+
+    Ok(Response::default().add_attribute("action", "update_config"))
+}
+
+/// Deposits collateral into the pool in exchange for shares
+pub fn deposit(
+    mut deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    amount: Uint128,
+) -> StdResult<Response> {
+    let config = read_config(deps.storage)?;
+
+    if amount.is_zero() {
+        return Err(StdError::generic_err("Input must be non-zero"));
+    }
+
+    prune_positions(&mut deps, &env, &config)?;
+
+    let mut response = Response::new();
+    let mut balance = config
+        .eligible_collateral
+        .query_balance(&deps.querier, env.contract.address.clone())?;
+
+    match config.eligible_collateral.clone() {
+        AssetInfo::NativeToken { .. } => {
+            let token = Asset {
+                info: config.eligible_collateral,
+                amount,
+            };
+            token.assert_sent_native_token_balance(&info)?;
+
+            // the sent funds are already part of the balance
+            balance = balance.checked_sub(amount)?;
+        }
+        AssetInfo::Token { .. } => {
+            let msg = config.eligible_collateral.into_msg(
+                env.contract.address.to_string(),
+                amount,
+                Some(info.sender.to_string()),
+            )?;
+            response = response.add_message(msg);
+        }
+    }
+
+    let total_value = balance.checked_add(query_positions_value(deps.as_ref(), &env)?)?;
+    let total_shares = read_total_shares(deps.storage)?;
+
+    // the minimum shares of the first deposit are locked and never withdrawn
+    let (shares, locked_shares) = if total_shares.is_zero() {
+        (amount.saturating_sub(MINIMUM_SHARES), MINIMUM_SHARES)
+    } else if total_value.is_zero() {
+        (amount, Uint128::zero())
+    } else {
+        (
+            amount.multiply_ratio(total_shares, total_value),
+            Uint128::zero(),
+        )
+    };
+
+    if shares.is_zero() {
+        return Err(StdError::generic_err("Deposit is too small"));
+    }
+
+    let provider_shares = read_shares(deps.storage, &info.sender)?.checked_add(shares)?;
+    store_shares(deps.storage, &info.sender, &provider_shares)?;
+    store_total_shares(
+        deps.storage,
+        &total_shares
+            .checked_add(shares)?
+            .checked_add(locked_shares)?,
+    )?;
+
+    Ok(response.add_attributes(vec![
+        ("action", "deposit"),
+        ("provider", info.sender.as_ref()),
+        ("amount", &amount.to_string()),
+        ("shares", &shares.to_string()),
+    ]))
+}
+
+/// Burns shares and returns the collateral they are worth, only idle collateral can be withdrawn
+pub fn withdraw(
+    mut deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    shares: Uint128,
+) -> StdResult<Response> {
+    let config = read_config(deps.storage)?;
+
+    let provider_shares = read_shares(deps.storage, &info.sender)?;
+    if shares.is_zero() || shares > provider_shares {
+        return Err(StdError::generic_err("Insufficient shares"));
+    }
+
+    prune_positions(&mut deps, &env, &config)?;
+
+    let balance = config
+        .eligible_collateral
+        .query_balance(&deps.querier, env.contract.address.clone())?;
+    let total_value = balance.checked_add(query_positions_value(deps.as_ref(), &env)?)?;
+    let total_shares = read_total_shares(deps.storage)?;
+
+    let amount = shares.multiply_ratio(total_value, total_shares);
+    if amount > balance {
+        return Err(StdError::generic_err("Insufficient idle liquidity"));
+    }
+
+    store_shares(
+        deps.storage,
+        &info.sender,
+        &provider_shares.checked_sub(shares)?,
+    )?;
+    store_total_shares(deps.storage, &total_shares.checked_sub(shares)?)?;
+
+    let mut response = Response::new();
+    if !amount.is_zero() {
+        response = response.add_message(config.eligible_collateral.into_msg(
+            info.sender.to_string(),
+            amount,
+            None,
+        )?);
+    }
+
+    Ok(response.add_attributes(vec![
+        ("action", "withdraw"),
+        ("provider", info.sender.as_ref()),
+        ("amount", &amount.to_string()),
+        ("shares", &shares.to_string()),
+    ]))
+}
+
+/// Called by the engine to take over a position that is being liquidated
+pub fn absorb(
+    mut deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    vamm: String,
+    position_id: u64,
+) -> StdResult<Response> {
+    let config = read_config(deps.storage)?;
+
+    // check permission
+    if info.sender != config.engine {
+        return Err(StdError::generic_err("unauthorized"));
+    }
+
+    let vamm = deps.api.addr_validate(&vamm)?;
+    let engine = EngineController(config.engine.clone());
+
+    // frees the slots of the positions the engine no longer holds for the pool
+    prune_positions(&mut deps, &env, &config)?;
+
+    // the pool posts margin on the notional at the mark price
+    let position_notional = engine
+        .get_unrealized_pnl(
+            &deps.querier,
+            vamm.to_string(),
+            position_id,
+            PnlCalcOption::SpotPrice,
+        )?
+        .position_notional;
+    let margin_amount = position_notional
+        .checked_mul(config.margin_ratio)?
+        .checked_div(config.decimals)?;
+
+    let balance = config
+        .eligible_collateral
+        .query_balance(&deps.querier, env.contract.address)?;
+    if margin_amount.is_zero() || margin_amount > balance {
+        return Err(StdError::generic_err("Insufficient liquidity"));
+    }
+
+    save_position(
+        deps.storage,
+        PoolPosition {
+            vamm: vamm.clone(),
+            position_id,
+        },
+    )?;
+
+    let msgs = with_margin(&config, margin_amount, |funds| {
+        engine.backstop_liquidate(vamm.to_string(), position_id, margin_amount, funds)
+    })?;
+
+    Ok(Response::new().add_messages(msgs).add_attributes(vec![
+        ("action", "absorb"),
+        ("vamm", vamm.as_ref()),
+        ("position_id", &position_id.to_string()),
+        ("margin_amount", &margin_amount.to_string()),
+    ]))
+}
+
+/// Allows the keeper to close a position held by the pool
+pub fn close_position(
+    deps: DepsMut,
+    info: MessageInfo,
+    vamm: String,
+    position_id: u64,
+    quote_asset_limit: Uint128,
+) -> StdResult<Response> {
+    let config = read_config(deps.storage)?;
+    let position = require_keeper_position(&deps, &info, &config, vamm, position_id)?;
+
+    let engine = EngineController(config.engine);
+    let msg = engine.close_position(
+        position.vamm.to_string(),
+        position.position_id,
+        quote_asset_limit,
+    )?;
+
+    store_tmp_position(deps.storage, &position)?;
+
+    Ok(Response::new()
+        .add_submessage(SubMsg::reply_on_success(msg, CLOSE_POSITION_REPLY_ID))
+        .add_attributes(vec![
+            ("action", "close_position"),
+            ("vamm", position.vamm.as_ref()),
+            ("position_id", &position_id.to_string()),
+        ]))
+}
+
+/// Allows the keeper to add margin to a position held by the pool
+pub fn deposit_margin(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    vamm: String,
+    position_id: u64,
+    amount: Uint128,
+) -> StdResult<Response> {
+    let config = read_config(deps.storage)?;
+    let position = require_keeper_position(&deps, &info, &config, vamm, position_id)?;
+
+    let balance = config
+        .eligible_collateral
+        .query_balance(&deps.querier, env.contract.address)?;
+    if amount.is_zero() || amount > balance {
+        return Err(StdError::generic_err("Insufficient liquidity"));
+    }
+
+    let engine = EngineController(config.engine.clone());
+    let msgs = with_margin(&config, amount, |funds| {
+        engine.deposit_margin(
+            position.vamm.to_string(),
+            position.position_id,
+            amount,
+            funds,
+        )
+    })?;
+
+    Ok(Response::new().add_messages(msgs).add_attributes(vec![
+        ("action", "deposit_margin"),
+        ("vamm", position.vamm.as_ref()),
+        ("position_id", &position_id.to_string()),
+        ("amount", &amount.to_string()),
+    ]))
+}
+
+/// Allows the keeper to take excess margin out of a position held by the pool
+pub fn withdraw_margin(
+    deps: DepsMut,
+    info: MessageInfo,
+    vamm: String,
+    position_id: u64,
+    amount: Uint128,
+) -> StdResult<Response> {
+    let config = read_config(deps.storage)?;
+    let position = require_keeper_position(&deps, &info, &config, vamm, position_id)?;
+
+    let engine = EngineController(config.engine);
+    let msg = engine.withdraw_margin(position.vamm.to_string(), position.position_id, amount)?;
+
+    Ok(Response::new().add_message(msg).add_attributes(vec![
+        ("action", "withdraw_margin"),
+        ("vamm", position.vamm.as_ref()),
+        ("position_id", &position_id.to_string()),
+        ("amount", &amount.to_string()),
+    ]))
+}
+
+/// Stops tracking a position once the engine no longer holds it
+pub fn close_position_reply(deps: DepsMut) -> StdResult<Response> {
+    let config = read_config(deps.storage)?;
+    let position = read_tmp_position(deps.storage)?;
+    remove_tmp_position(deps.storage);
+
+    // a close over the fluctuation limit only closes part of the position
+    let closed = EngineController(config.engine)
+        .position(
+            &deps.querier,
+            position.vamm.to_string(),
+            position.position_id,
+        )
+        .is_err();
+    if closed {
+        remove_position(deps.storage, &position)?;
+    }
+
+    Ok(Response::new().add_attributes(vec![
+        ("action", "close_position_reply"),
+        ("position_id", &position.position_id.to_string()),
+        ("closed", &closed.to_string()),
+    ]))
+}
+
+// Stops tracking the positions that were liquidated, deleveraged or closed outside of the pool
+fn prune_positions(deps: &mut DepsMut, env: &Env, config: &Config) -> StdResult<()> {
+    let engine = EngineController(config.engine.clone());
+
+    for position in read_positions(deps.storage)? {
+        if query_held_position(deps.as_ref(), env, &engine, &position).is_none() {
+            remove_position(deps.storage, &position)?;
+        }
+    }
+
+    Ok(())
+}
+
+fn require_keeper_position(
+    deps: &DepsMut,
+    info: &MessageInfo,
+    config: &Config,
+    vamm: String,
+    position_id: u64,
+) -> StdResult<PoolPosition> {
+    // check permission
+    if info.sender != config.keeper {
+        return Err(StdError::generic_err("unauthorized"));
+    }
+
+    let position = PoolPosition {
+        vamm: deps.api.addr_validate(&vamm)?,
+        position_id,
+    };
+    if !is_position(deps.storage, &position)? {
+        return Err(StdError::generic_err("Position is not held"));
+    }
+
+    Ok(position)
+}
+
+// Pays margin into the engine along with the engine message, cw20 margin is pulled by the engine
+fn with_margin(
+    config: &Config,
+    amount: Uint128,
+    engine_msg: impl FnOnce(Vec<Coin>) -> StdResult<CosmosMsg>,
+) -> StdResult<Vec<CosmosMsg>> {
+    match &config.eligible_collateral {
+        AssetInfo::NativeToken { denom } => Ok(vec![engine_msg(vec![Coin {
+            denom: denom.to_string(),
+            amount,
+        }])?]),
+        AssetInfo::Token { contract_addr } => Ok(vec![
+            wasm_execute(
+                contract_addr,
+                &Cw20ExecuteMsg::IncreaseAllowance {
+                    spender: config.engine.to_string(),
+                    amount,
+                    expires: None,
+                },
+                vec![],
+            )?,
+            engine_msg(vec![])?,
+        ]),
+    }
+}
@@ -0,0 +1,116 @@
+use cosmwasm_std::{Deps, Env, StdError, StdResult, Uint128};
+use margined_common::integer::Integer;
+use margined_perp::{
+    margined_backstop_pool::{
+        ConfigResponse, OwnerResponse, PoolPosition, PositionsResponse, SharesResponse,
+        StateResponse,
+    },
+    margined_engine::{PnlCalcOption, Position},
+};
+use margined_utils::contracts::helpers::EngineController;
+
+use crate::{
+    contract::OWNER,
+    state::{read_config, read_positions, read_shares, read_total_shares},
+};
+
+/// Queries contract owner from the admin
+pub fn query_owner(deps: Deps) -> StdResult<OwnerResponse> {
+    if let Some(owner) = OWNER.get(deps)? {
+        Ok(OwnerResponse { owner })
+    } else {
+        Err(StdError::generic_err("No owner set"))
+    }
+}
+
+/// Queries contract config
+pub fn query_config(deps: Deps) -> StdResult<ConfigResponse> {
+    read_config(deps.storage)
+}
+
+/// Queries the value held by the pool, idle collateral plus the equity of its positions
+pub fn query_state(deps: Deps, env: Env) -> StdResult<StateResponse> {
+    let config = read_config(deps.storage)?;
+
+    let balance = config
+        .eligible_collateral
+        .query_balance(&deps.querier, env.contract.address.clone())?;
+    let positions_value = query_positions_value(deps, &env)?;
+
+    Ok(StateResponse {
+        total_shares: read_total_shares(deps.storage)?,
+        balance,
+        positions_value,
+        total_value: balance.checked_add(positions_value)?,
+    })
+}
+
+/// Queries the shares of a liquidity provider and what they are worth
+pub fn query_shares(deps: Deps, env: Env, address: String) -> StdResult<SharesResponse> {
+    let address = deps.api.addr_validate(&address)?;
+    let shares = read_shares(deps.storage, &address)?;
+
+    let state = query_state(deps, env)?;
+    let value = if state.total_shares.is_zero() {
+        Uint128::zero()
+    } else {
+        shares.multiply_ratio(state.total_value, state.total_shares)
+    };
+
+    Ok(SharesResponse { shares, value })
+}
+
+/// Queries the positions held by the pool
+pub fn query_positions(deps: Deps) -> StdResult<PositionsResponse> {
+    Ok(PositionsResponse {
+        positions: read_positions(deps.storage)?,
+    })
+}
+
+/// Sums the remaining margin of the pool positions valued at the TWAP, so that shares
+/// cannot be priced on a spot price moved within the same block
+pub fn query_positions_value(deps: Deps, env: &Env) -> StdResult<Uint128> {
+    let config = read_config(deps.storage)?;
+    let engine = EngineController(config.engine);
+
+    let mut value = Uint128::zero();
+    for pool_position in read_positions(deps.storage)? {
+        let position = match query_held_position(deps, env, &engine, &pool_position) {
+            Some(position) => position,
+            None => continue,
+        };
+        let unrealized_pnl = engine
+            .get_unrealized_pnl(
+                &deps.querier,
+                pool_position.vamm.to_string(),
+                pool_position.position_id,
+                PnlCalcOption::Twap,
+            )?
+            .unrealized_pnl;
+
+        let equity = Integer::new_positive(position.margin) + unrealized_pnl;
+        if equity.is_positive() {
+            value = value.checked_add(equity.value)?;
+        }
+    }
+
+    Ok(value)
+}
+
+/// Queries a position of the pool from the engine, returns None once the position was
+/// liquidated, deleveraged or closed outside of the pool
+pub fn query_held_position(
+    deps: Deps,
+    env: &Env,
+    engine: &EngineController,
+    pool_position: &PoolPosition,
+) -> Option<Position> {
+    engine
+        .get_position_with_funding_payment(
+            &deps.querier,
+            pool_position.vamm.to_string(),
+            pool_position.position_id,
+        )
+        .ok()
+        .filter(|position| position.trader == env.contract.address)
+}
@@ -0,0 +1,108 @@
+use cosmwasm_std::{from_slice, to_vec, Addr, StdError, StdResult, Storage, Uint128};
+use cosmwasm_storage::{singleton, singleton_read, Bucket, ReadonlyBucket};
+use margined_perp::margined_backstop_pool::{ConfigResponse, PoolPosition};
+
+pub static KEY_CONFIG: &[u8] = b"config";
+pub static KEY_TOTAL_SHARES: &[u8] = b"total-shares";
+pub static KEY_POSITIONS: &[u8] = b"positions";
+pub static KEY_TMP_POSITION: &[u8] = b"tmp-position";
+
+static PREFIX_SHARES: &[u8] = b"shares";
+
+// max positions the pool holds at once, these are valued on every deposit and withdrawal
+pub const MAX_POSITIONS: usize = 30;
+// shares locked on the first deposit, so that the share price cannot be inflated by a
+// donation to an almost empty pool
+pub const MINIMUM_SHARES: Uint128 = Uint128::new(1_000);
+
+pub type Config = ConfigResponse;
+
+pub fn store_config(storage: &mut dyn Storage, config: &Config) -> StdResult<()> {
+    storage.set(KEY_CONFIG, &to_vec(config)?);
+    Ok(())
+}
+
+pub fn read_config(storage: &dyn Storage) -> StdResult<Config> {
+    match storage.get(KEY_CONFIG) {
+        Some(data) => from_slice(&data),
+        None => Err(StdError::generic_err("Config not found")),
+    }
+}
+
+pub fn store_total_shares(storage: &mut dyn Storage, total_shares: &Uint128) -> StdResult<()> {
+    singleton(storage, KEY_TOTAL_SHARES).save(total_shares)
+}
+
+pub fn read_total_shares(storage: &dyn Storage) -> StdResult<Uint128> {
+    Ok(singleton_read(storage, KEY_TOTAL_SHARES)
+        .may_load()?
+        .unwrap_or_default())
+}
+
+pub fn store_shares(storage: &mut dyn Storage, owner: &Addr, shares: &Uint128) -> StdResult<()> {
+    if shares.is_zero() {
+        Bucket::<Uint128>::new(storage, PREFIX_SHARES).remove(owner.as_bytes());
+        return Ok(());
+    }
+
+    Bucket::new(storage, PREFIX_SHARES).save(owner.as_bytes(), shares)
+}
+
+pub fn read_shares(storage: &dyn Storage, owner: &Addr) -> StdResult<Uint128> {
+    Ok(ReadonlyBucket::new(storage, PREFIX_SHARES)
+        .may_load(owner.as_bytes())?
+        .unwrap_or_default())
+}
+
+// adds a position absorbed by the pool, the number of positions is capped
+pub fn save_position(storage: &mut dyn Storage, position: PoolPosition) -> StdResult<()> {
+    let mut positions = read_positions(storage)?;
+
+    if positions.contains(&position) {
+        return Err(StdError::generic_err("Position is already held"));
+    }
+
+    if positions.len() >= MAX_POSITIONS {
+        return Err(StdError::generic_err("Too many positions held"));
+    }
+
+    positions.push(position);
+    storage.set(KEY_POSITIONS, &to_vec(&positions)?);
+    Ok(())
+}
+
+pub fn read_positions(storage: &dyn Storage) -> StdResult<Vec<PoolPosition>> {
+    match storage.get(KEY_POSITIONS) {
+        None => Ok(vec![]),
+        Some(data) => from_slice(&data),
+    }
+}
+
+pub fn is_position(storage: &dyn Storage, position: &PoolPosition) -> StdResult<bool> {
+    Ok(read_positions(storage)?.contains(position))
+}
+
+pub fn remove_position(storage: &mut dyn Storage, position: &PoolPosition) -> StdResult<()> {
+    let mut positions = read_positions(storage)?;
+
+    if let Some(index) = positions.iter().position(|x| x.eq(position)) {
+        positions.swap_remove(index);
+    } else {
+        return Err(StdError::generic_err("Position is not held"));
+    }
+
+    storage.set(KEY_POSITIONS, &to_vec(&positions)?);
+    Ok(())
+}
+
+pub fn store_tmp_position(storage: &mut dyn Storage, position: &PoolPosition) -> StdResult<()> {
+    singleton(storage, KEY_TMP_POSITION).save(position)
+}
+
+pub fn remove_tmp_position(storage: &mut dyn Storage) {
+    singleton::<PoolPosition>(storage, KEY_TMP_POSITION).remove()
+}
+
+pub fn read_tmp_position(storage: &dyn Storage) -> StdResult<PoolPosition> {
+    singleton_read(storage, KEY_TMP_POSITION).load()
+}
@@ -0,0 +1,68 @@
+mod tests;
+
+use cosmwasm_std::{Addr, Uint128};
+use margined_perp::margined_backstop_pool::InstantiateMsg;
+use margined_utils::{
+    contracts::helpers::BackstopPoolController, create_entry_points_testing,
+    cw_multi_test::Executor, testing::SimpleScenario,
+};
+
+pub struct BackstopPoolScenario {
+    pub scenario: SimpleScenario,
+    pub keeper: Addr,
+    pub pool: BackstopPoolController,
+}
+
+pub fn new_backstop_pool_scenario() -> BackstopPoolScenario {
+    let mut scenario = SimpleScenario::new(
+        Box::new(create_entry_points_testing!(margined_fee_pool)),
+        Box::new(create_entry_points_testing!(cw20_base)),
+        Box::new(
+            create_entry_points_testing!(margined_engine)
+                .with_reply(margined_engine::contract::reply),
+        ),
+        Box::new(create_entry_points_testing!(margined_vamm)),
+        Box::new(create_entry_points_testing!(margined_insurance_fund)),
+        Box::new(create_entry_points_testing!(mock_pricefeed)),
+    );
+    let keeper = scenario.carol.clone();
+
+    let pool_id = scenario.router.store_code(Box::new(
+        create_entry_points_testing!(crate).with_reply(crate::contract::reply),
+    ));
+    let pool_addr = scenario
+        .router
+        .instantiate_contract(
+            pool_id,
+            scenario.owner.clone(),
+            &InstantiateMsg {
+                engine: scenario.engine.addr().to_string(),
+                keeper: keeper.to_string(),
+                margin_ratio: Uint128::from(200_000_000u128), // 0.2
+            },
+            &[],
+            "backstop_pool",
+            None,
+        )
+        .unwrap();
+    let pool = BackstopPoolController(pool_addr);
+
+    // route liquidations to the pool at a 1% discount
+    let msg = scenario
+        .engine
+        .update_backstop_pool(
+            Some(pool.addr().to_string()),
+            Uint128::from(10_000_000u128), // 0.01
+        )
+        .unwrap();
+    scenario
+        .router
+        .execute(scenario.owner.clone(), msg)
+        .unwrap();
+
+    BackstopPoolScenario {
+        scenario,
+        keeper,
+        pool,
+    }
+}
@@ -0,0 +1,453 @@
+use cosmwasm_std::{StdError, Uint128};
+use cw20::Cw20ExecuteMsg;
+use margined_common::{asset::AssetInfo, integer::Integer};
+use margined_perp::{
+    margined_backstop_pool::PoolPosition,
+    margined_engine::{PnlCalcOption, Side},
+};
+use margined_utils::{
+    cw_multi_test::Executor,
+    testing::{to_decimals, SimpleScenario},
+};
+
+use crate::{
+    state::MINIMUM_SHARES,
+    testing::{new_backstop_pool_scenario, BackstopPoolScenario},
+};
+
+#[test]
+fn test_instantiation() {
+    let BackstopPoolScenario {
+        scenario: SimpleScenario { router, usdc, .. },
+        keeper,
+        pool,
+    } = new_backstop_pool_scenario();
+
+    let config = pool.config(&router.wrap()).unwrap();
+    assert_eq!(config.keeper, keeper);
+    assert_eq!(config.decimals, to_decimals(1u64));
+    assert_eq!(config.margin_ratio, Uint128::from(200_000_000u128));
+    assert_eq!(
+        config.eligible_collateral,
+        AssetInfo::Token {
+            contract_addr: usdc.addr()
+        }
+    );
+
+    let state = pool.state(&router.wrap()).unwrap();
+    assert_eq!(state.total_shares, Uint128::zero());
+    assert_eq!(state.total_value, Uint128::zero());
+}
+
+#[test]
+fn test_pool_absorbs_liquidation_and_keeper_closes() {
+    let BackstopPoolScenario {
+        scenario:
+            SimpleScenario {
+                mut router,
+                owner,
+                alice,
+                bob,
+                david,
+                engine,
+                usdc,
+                vamm,
+                insurance_fund,
+                ..
+            },
+        keeper,
+        pool,
+    } = new_backstop_pool_scenario();
+
+    // david provides liquidity to the pool
+    router
+        .execute_contract(
+            david.clone(),
+            usdc.addr(),
+            &Cw20ExecuteMsg::IncreaseAllowance {
+                spender: pool.addr().to_string(),
+                amount: to_decimals(100u64),
+                expires: None,
+            },
+            &[],
+        )
+        .unwrap();
+    let msg = pool.deposit(to_decimals(100u64), vec![]).unwrap();
+    router.execute(david.clone(), msg).unwrap();
+
+    // the minimum shares of the first deposit are locked in the pool
+    let shares = pool.shares(&router.wrap(), david.to_string()).unwrap();
+    assert_eq!(shares.shares, to_decimals(100u64) - MINIMUM_SHARES);
+    assert_eq!(shares.value, to_decimals(100u64) - MINIMUM_SHARES);
+
+    // alice goes long and bob shorts the price down
+    let msg = engine
+        .open_position(
+            vamm.addr().to_string(),
+            Side::Buy,
+            to_decimals(20u64),
+            to_decimals(10u64),
+            None,
+            None,
+            to_decimals(0u64),
+            vec![],
+        )
+        .unwrap();
+    router.execute(alice.clone(), msg).unwrap();
+
+    let msg = engine
+        .open_position(
+            vamm.addr().to_string(),
+            Side::Sell,
+            to_decimals(6u64),
+            to_decimals(5u64),
+            None,
+            None,
+            to_decimals(0u64),
+            vec![],
+        )
+        .unwrap();
+    router.execute(bob.clone(), msg).unwrap();
+
+    router.update_block(|block| {
+        block.time = block.time.plus_seconds(15);
+        block.height += 1;
+    });
+
+    // set maintenance ratio as 10% to allow liquidation
+    let msg = engine
+        .set_margin_ratios(Uint128::from(100_000_000u128))
+        .unwrap();
+    router.execute(owner.clone(), msg).unwrap();
+
+    let vamm_state_before = vamm.state(&router.wrap()).unwrap();
+    let alice_balance_before = usdc.balance(&router.wrap(), alice.clone()).unwrap();
+
+    let msg = engine
+        .liquidate(vamm.addr().to_string(), 1, to_decimals(0u64))
+        .unwrap();
+    router.execute(keeper.clone(), msg).unwrap();
+
+    // the vamm is untouched
+    let vamm_state = vamm.state(&router.wrap()).unwrap();
+    assert_eq!(vamm_state, vamm_state_before);
+
+    // the pool takes over the position 1% below the mark notional of 190.920502083
+    let position = engine
+        .position(&router.wrap(), vamm.addr().to_string(), 1)
+        .unwrap();
+    assert_eq!(position.trader, pool.addr());
+    assert_eq!(position.notional, Uint128::from(189_011_297_063u128));
+    assert_eq!(position.margin, Uint128::from(38_184_100_416u128));
+
+    let positions = pool.positions(&router.wrap()).unwrap().positions;
+    assert_eq!(
+        positions,
+        vec![PoolPosition {
+            vamm: vamm.addr(),
+            position_id: 1,
+        }]
+    );
+
+    // the pool is paid by the discount, alice receives all of her remaining margin of
+    // 9.011297063 at the discounted notional without a penalty
+    let alice_balance = usdc.balance(&router.wrap(), alice.clone()).unwrap();
+    assert_eq!(
+        alice_balance,
+        alice_balance_before + Uint128::from(9_011_297_063u128)
+    );
+
+    let insurance_balance = usdc.balance(&router.wrap(), insurance_fund.addr()).unwrap();
+    assert_eq!(insurance_balance, to_decimals(5000u64));
+
+    // the position is valued at the twap
+    let twap_pnl = engine
+        .get_unrealized_pnl(
+            &router.wrap(),
+            vamm.addr().to_string(),
+            1,
+            PnlCalcOption::Twap,
+        )
+        .unwrap()
+        .unrealized_pnl;
+    let state = pool.state(&router.wrap()).unwrap();
+    assert_eq!(state.balance, Uint128::from(61_815_899_584u128));
+    assert_eq!(
+        state.positions_value,
+        (Integer::new_positive(position.margin) + twap_pnl).value
+    );
+    assert_eq!(state.total_value, state.balance + state.positions_value);
+
+    // the liquidity used by the position cannot be withdrawn
+    let msg = pool.withdraw(shares.shares).unwrap();
+    let err = router.execute(david.clone(), msg).unwrap_err();
+    assert_eq!(
+        StdError::generic_err("Insufficient idle liquidity"),
+        err.downcast().unwrap()
+    );
+
+    // only the keeper manages the positions of the pool
+    let msg = pool
+        .close_position(vamm.addr().to_string(), 1, to_decimals(0u64))
+        .unwrap();
+    let err = router.execute(david.clone(), msg).unwrap_err();
+    assert_eq!(
+        StdError::generic_err("unauthorized"),
+        err.downcast().unwrap()
+    );
+
+    let msg = pool
+        .close_position(vamm.addr().to_string(), 1, to_decimals(0u64))
+        .unwrap();
+    router.execute(keeper.clone(), msg).unwrap();
+
+    let positions = pool.positions(&router.wrap()).unwrap().positions;
+    assert!(positions.is_empty());
+
+    let pool_balance = usdc.balance(&router.wrap(), pool.addr()).unwrap();
+    assert_eq!(pool_balance, Uint128::from(101_909_205_020u128));
+
+    // david withdraws the liquidity with the profit of the pool
+    let msg = pool.withdraw(shares.shares).unwrap();
+    router.execute(david.clone(), msg).unwrap();
+
+    // only the value of the locked shares is left
+    let pool_balance = usdc.balance(&router.wrap(), pool.addr()).unwrap();
+    assert_eq!(pool_balance, Uint128::from(1_020u128));
+    let state = pool.state(&router.wrap()).unwrap();
+    assert_eq!(state.total_shares, MINIMUM_SHARES);
+}
+
+#[test]
+fn test_first_deposit_must_exceed_minimum_shares() {
+    let BackstopPoolScenario {
+        scenario:
+            SimpleScenario {
+                mut router,
+                david,
+                usdc,
+                ..
+            },
+        pool,
+        ..
+    } = new_backstop_pool_scenario();
+
+    router
+        .execute_contract(
+            david.clone(),
+            usdc.addr(),
+            &Cw20ExecuteMsg::IncreaseAllowance {
+                spender: pool.addr().to_string(),
+                amount: to_decimals(100u64),
+                expires: None,
+            },
+            &[],
+        )
+        .unwrap();
+
+    let msg = pool.deposit(MINIMUM_SHARES, vec![]).unwrap();
+    let err = router.execute(david.clone(), msg).unwrap_err();
+    assert_eq!(
+        StdError::generic_err("Deposit is too small"),
+        err.downcast().unwrap()
+    );
+
+    let msg = pool
+        .deposit(MINIMUM_SHARES + Uint128::one(), vec![])
+        .unwrap();
+    router.execute(david.clone(), msg).unwrap();
+
+    let shares = pool.shares(&router.wrap(), david.to_string()).unwrap();
+    assert_eq!(shares.shares, Uint128::one());
+    let state = pool.state(&router.wrap()).unwrap();
+    assert_eq!(state.total_shares, MINIMUM_SHARES + Uint128::one());
+}
+
+#[test]
+fn test_pool_skips_positions_liquidated_outside_of_the_pool() {
+    let BackstopPoolScenario {
+        scenario:
+            SimpleScenario {
+                mut router,
+                owner,
+                alice,
+                bob,
+                david,
+                engine,
+                usdc,
+                vamm,
+                ..
+            },
+        keeper,
+        pool,
+    } = new_backstop_pool_scenario();
+
+    router
+        .execute_contract(
+            david.clone(),
+            usdc.addr(),
+            &Cw20ExecuteMsg::IncreaseAllowance {
+                spender: pool.addr().to_string(),
+                amount: to_decimals(200u64),
+                expires: None,
+            },
+            &[],
+        )
+        .unwrap();
+    let msg = pool.deposit(to_decimals(100u64), vec![]).unwrap();
+    router.execute(david.clone(), msg).unwrap();
+
+    let msg = engine
+        .open_position(
+            vamm.addr().to_string(),
+            Side::Buy,
+            to_decimals(20u64),
+            to_decimals(10u64),
+            None,
+            None,
+            to_decimals(0u64),
+            vec![],
+        )
+        .unwrap();
+    router.execute(alice.clone(), msg).unwrap();
+
+    let msg = engine
+        .open_position(
+            vamm.addr().to_string(),
+            Side::Sell,
+            to_decimals(6u64),
+            to_decimals(5u64),
+            None,
+            None,
+            to_decimals(0u64),
+            vec![],
+        )
+        .unwrap();
+    router.execute(bob.clone(), msg).unwrap();
+
+    router.update_block(|block| {
+        block.time = block.time.plus_seconds(15);
+        block.height += 1;
+    });
+
+    let msg = engine
+        .set_margin_ratios(Uint128::from(100_000_000u128))
+        .unwrap();
+    router.execute(owner.clone(), msg).unwrap();
+
+    let msg = engine
+        .liquidate(vamm.addr().to_string(), 1, to_decimals(0u64))
+        .unwrap();
+    router.execute(keeper.clone(), msg).unwrap();
+
+    let position = engine
+        .position(&router.wrap(), vamm.addr().to_string(), 1)
+        .unwrap();
+    assert_eq!(position.trader, pool.addr());
+
+    router.update_block(|block| {
+        block.time = block.time.plus_seconds(15);
+        block.height += 1;
+    });
+
+    // the pool position is liquidated through the vamm
+    let msg = engine.update_backstop_pool(None, Uint128::zero()).unwrap();
+    router.execute(owner.clone(), msg).unwrap();
+    let msg = engine
+        .set_margin_ratios(Uint128::from(500_000_000u128))
+        .unwrap();
+    router.execute(owner.clone(), msg).unwrap();
+
+    let msg = engine
+        .liquidate(vamm.addr().to_string(), 1, to_decimals(0u64))
+        .unwrap();
+    router.execute(keeper.clone(), msg).unwrap();
+    assert!(engine
+        .position(&router.wrap(), vamm.addr().to_string(), 1)
+        .is_err());
+
+    // the missing position is valued at zero instead of failing
+    let state = pool.state(&router.wrap()).unwrap();
+    assert_eq!(state.positions_value, Uint128::zero());
+    assert_eq!(state.total_value, state.balance);
+
+    // the next deposit stops tracking it
+    let msg = pool.deposit(to_decimals(100u64), vec![]).unwrap();
+    router.execute(david.clone(), msg).unwrap();
+
+    let positions = pool.positions(&router.wrap()).unwrap().positions;
+    assert!(positions.is_empty());
+}
+
+#[test]
+fn test_liquidation_falls_back_to_vamm_without_liquidity() {
+    let BackstopPoolScenario {
+        scenario:
+            SimpleScenario {
+                mut router,
+                owner,
+                alice,
+                bob,
+                engine,
+                vamm,
+                ..
+            },
+        keeper,
+        pool,
+    } = new_backstop_pool_scenario();
+
+    let msg = engine
+        .open_position(
+            vamm.addr().to_string(),
+            Side::Buy,
+            to_decimals(20u64),
+            to_decimals(10u64),
+            None,
+            None,
+            to_decimals(0u64),
+            vec![],
+        )
+        .unwrap();
+    router.execute(alice.clone(), msg).unwrap();
+
+    let msg = engine
+        .open_position(
+            vamm.addr().to_string(),
+            Side::Sell,
+            to_decimals(6u64),
+            to_decimals(5u64),
+            None,
+            None,
+            to_decimals(0u64),
+            vec![],
+        )
+        .unwrap();
+    router.execute(bob.clone(), msg).unwrap();
+
+    router.update_block(|block| {
+        block.time = block.time.plus_seconds(15);
+        block.height += 1;
+    });
+
+    let msg = engine
+        .set_margin_ratios(Uint128::from(100_000_000u128))
+        .unwrap();
+    router.execute(owner.clone(), msg).unwrap();
+
+    let vamm_state_before = vamm.state(&router.wrap()).unwrap();
+
+    // the pool is empty so the position is closed through the vamm
+    let msg = engine
+        .liquidate(vamm.addr().to_string(), 1, to_decimals(0u64))
+        .unwrap();
+    router.execute(keeper.clone(), msg).unwrap();
+
+    assert!(engine
+        .position(&router.wrap(), vamm.addr().to_string(), 1)
+        .is_err());
+    let vamm_state = vamm.state(&router.wrap()).unwrap();
+    assert_ne!(vamm_state, vamm_state_before);
+
+    let positions = pool.positions(&router.wrap()).unwrap().positions;
+    assert!(positions.is_empty());
+}
@@ -0,0 +1,383 @@
+use cosmwasm_std::{
+    to_vec, Addr, Binary, Deps, DepsMut, Env, MessageInfo, Response, StdError, StdResult, Storage,
+    SubMsg, Uint128,
+};
+use margined_common::asset::{Asset, AssetInfo};
+use margined_perp::margined_engine::{Side, SignedOrder, TraderBalanceResponse};
+
+use crate::{
+    blocklist::require_not_blocked,
+    handle::open_position_with_funds,
+    messages::{execute_payout, execute_transfer_from},
+    state::{
+        is_nonce_used, read_claimable, read_config, read_credit_proceeds, read_signing_key,
+        read_state, read_trader_balance, remove_signing_key, store_claimable,
+        store_credit_proceeds, store_signing_key, store_trader_balance, store_used_nonce,
+        SentFunds,
+    },
+    utils::{get_asset, keccak_256, require_non_zero_input, require_not_paused},
+};
+
+// Deposits collateral to the balance of the trader in the engine
+pub fn deposit(deps: DepsMut, env: Env, info: MessageInfo, amount: Uint128) -> StdResult<Response> {
+    let config = read_config(deps.storage)?;
+    let state = read_state(deps.storage)?;
+    let trader = info.sender.clone();
+
+    require_not_paused(state.pause)?;
+    require_non_zero_input(amount)?;
+
+    let mut msgs: Vec<SubMsg> = vec![];
+    match config.eligible_collateral {
+        AssetInfo::NativeToken { .. } => {
+            SentFunds {
+                asset: get_asset(info, config.eligible_collateral),
+                required: amount,
+                escrowed: false,
+                from_balance: false,
+            }
+            .are_sufficient()?;
+        }
+        AssetInfo::Token { .. } => {
+            msgs.push(execute_transfer_from(
+                deps.storage,
+                &trader,
+                &env.contract.address,
+                amount,
+            )?);
+        }
+    }
+
+    let balance = credit_trader_balance(deps.storage, &trader, 0, amount)?;
+
+    Ok(Response::new().add_submessages(msgs).add_attributes(vec![
+        ("action", "deposit"),
+        ("trader", trader.as_ref()),
+        ("amount", &amount.to_string()),
+        ("balance", &balance.to_string()),
+    ]))
+}
+
+// Withdraws collateral from the balance of the trader in the engine
+pub fn withdraw(deps: DepsMut, info: MessageInfo, amount: Uint128) -> StdResult<Response> {
+    let state = read_state(deps.storage)?;
+    let trader = info.sender;
+
+    require_not_paused(state.pause)?;
+    require_non_zero_input(amount)?;
+    require_not_blocked(deps.storage, &trader)?;
+
+    let balance = debit_trader_balance(deps.storage, &trader, 0, amount)?;
+
+    let msgs = execute_payout(deps.storage, &trader, amount)?;
+
+    Ok(Response::new().add_submessages(msgs).add_attributes(vec![
+        ("action", "withdraw"),
+        ("trader", trader.as_ref()),
+        ("amount", &amount.to_string()),
+        ("balance", &balance.to_string()),
+    ]))
+}
+
+// Opens a position paid from the balance of a sub-account of the trader instead of a transfer,
+// the position belongs to that sub-account
+#[allow(clippy::too_many_arguments)]
+pub fn open_position_from_balance(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    vamm: String,
+    side: Side,
+    margin_amount: Uint128,
+    leverage: Uint128,
+    take_profit: Option<Uint128>,
+    stop_loss: Option<Uint128>,
+    base_asset_limit: Uint128,
+    subaccount_id: Option<u32>,
+) -> StdResult<Response> {
+    let trader = info.sender;
+    let subaccount_id = subaccount_id.unwrap_or_default();
+    let funds = balance_funds(deps.storage, &trader, subaccount_id, margin_amount)?;
+
+    let (response, _) = open_position_with_funds(
+        deps,
+        env,
+        trader,
+        subaccount_id,
+        funds,
+        None,
+        vamm,
+        side,
+        margin_amount,
+        leverage,
+        take_profit,
+        stop_loss,
+        base_asset_limit,
+    )?;
+
+    Ok(response)
+}
+
+// Moves collateral between the balances of two sub-accounts of the sender
+pub fn transfer_between_subaccounts(
+    deps: DepsMut,
+    info: MessageInfo,
+    from_subaccount_id: u32,
+    to_subaccount_id: u32,
+    amount: Uint128,
+) -> StdResult<Response> {
+    let state = read_state(deps.storage)?;
+    let trader = info.sender;
+
+    require_not_paused(state.pause)?;
+    require_non_zero_input(amount)?;
+
+    if from_subaccount_id == to_subaccount_id {
+        return Err(StdError::generic_err("Sub-accounts must be different"));
+    }
+
+    debit_trader_balance(deps.storage, &trader, from_subaccount_id, amount)?;
+    credit_trader_balance(deps.storage, &trader, to_subaccount_id, amount)?;
+
+    Ok(Response::new().add_attributes(vec![
+        ("action", "transfer_between_subaccounts"),
+        ("trader", trader.as_ref()),
+        ("from_subaccount_id", &from_subaccount_id.to_string()),
+        ("to_subaccount_id", &to_subaccount_id.to_string()),
+        ("amount", &amount.to_string()),
+    ]))
+}
+
+// Sets whether the proceeds of the positions closed by the trader are credited to the balance
+pub fn update_credit_proceeds(
+    deps: DepsMut,
+    info: MessageInfo,
+    credit_proceeds: bool,
+) -> StdResult<Response> {
+    store_credit_proceeds(deps.storage, &info.sender, credit_proceeds)?;
+
+    Ok(Response::new().add_attributes(vec![
+        ("action", "update_credit_proceeds"),
+        ("trader", info.sender.as_ref()),
+        ("credit_proceeds", &credit_proceeds.to_string()),
+    ]))
+}
+
+// Transfers the payouts that failed to be transferred to the sender
+pub fn claim(deps: DepsMut, info: MessageInfo) -> StdResult<Response> {
+    require_not_blocked(deps.storage, &info.sender)?;
+
+    let amount = read_claimable(deps.storage, &info.sender)?;
+    if amount.is_zero() {
+        return Err(StdError::generic_err("Nothing to claim"));
+    }
+    store_claimable(deps.storage, &info.sender, Uint128::zero())?;
+
+    // a claim that fails to be transferred is credited back by the reply
+    let msgs = execute_payout(deps.storage, &info.sender, amount)?;
+
+    Ok(Response::new().add_submessages(msgs).add_attributes(vec![
+        ("action", "claim"),
+        ("receiver", info.sender.as_ref()),
+        ("amount", &amount.to_string()),
+    ]))
+}
+
+// Sets the secp256k1 public key that signs the orders of the trader, none removes it. The
+// trader is not recovered from the signature, the address derivation of a key differs between
+// chains, so the trader binds the key to its address with this message instead
+pub fn update_signing_key(
+    deps: DepsMut,
+    info: MessageInfo,
+    public_key: Option<Binary>,
+) -> StdResult<Response> {
+    let trader = info.sender;
+
+    match &public_key {
+        Some(public_key) => {
+            // compressed or uncompressed keys
+            if public_key.len() != 33 && public_key.len() != 65 {
+                return Err(StdError::generic_err("Invalid public key"));
+            }
+            store_signing_key(deps.storage, &trader, public_key)?;
+        }
+        None => remove_signing_key(deps.storage, &trader),
+    }
+
+    Ok(Response::new().add_attributes(vec![
+        ("action", "update_signing_key"),
+        ("trader", trader.as_ref()),
+        (
+            "public_key",
+            &public_key.map(|key| key.to_base64()).unwrap_or_default(),
+        ),
+    ]))
+}
+
+// prefix of the signed messages, a signature over an order cannot be used for another message
+const SIGNED_ORDER_DOMAIN: &[u8] = b"margined_engine/signed_order";
+
+/// Message signed for an order: the domain followed by the chain id, the engine, the trader and
+/// the JSON encoded order, each prefixed by its length
+pub fn signed_order_message(
+    chain_id: &str,
+    contract: &Addr,
+    trader: &Addr,
+    order: &SignedOrder,
+) -> StdResult<Vec<u8>> {
+    let mut message = SIGNED_ORDER_DOMAIN.to_vec();
+    for field in [
+        chain_id.as_bytes(),
+        contract.as_bytes(),
+        trader.as_bytes(),
+        &to_vec(order)?,
+    ] {
+        message.extend_from_slice(&(field.len() as u32).to_be_bytes());
+        message.extend_from_slice(field);
+    }
+
+    Ok(message)
+}
+
+// Opens the position of an order signed by the trader, paid from the balance of its sub-account.
+// The signature is checked against the key the trader registered with `update_signing_key`
+pub fn execute_signed_order(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    trader: String,
+    order: SignedOrder,
+    signature: Binary,
+) -> StdResult<Response> {
+    let trader = deps.api.addr_validate(&trader)?;
+
+    if env.block.time.seconds() > order.expiry {
+        return Err(StdError::generic_err("Signed order has expired"));
+    }
+
+    if is_nonce_used(deps.storage, &trader, order.nonce)? {
+        return Err(StdError::generic_err("Nonce is already used"));
+    }
+
+    let public_key = read_signing_key(deps.storage, &trader)?
+        .ok_or_else(|| StdError::generic_err("Trader has no signing key"))?;
+
+    // the order is signed for one chain, engine and trader so that it cannot be replayed
+    // elsewhere, nor against another trader registering the same key
+    let message =
+        signed_order_message(&env.block.chain_id, &env.contract.address, &trader, &order)?;
+    let verified = deps
+        .api
+        .secp256k1_verify(&keccak_256(&message), &signature, &public_key)
+        .unwrap_or(false);
+    if !verified {
+        return Err(StdError::generic_err("Invalid signature"));
+    }
+
+    store_used_nonce(deps.storage, &trader, order.nonce)?;
+
+    let subaccount_id = order.subaccount_id.unwrap_or_default();
+    let funds = balance_funds(deps.storage, &trader, subaccount_id, order.margin_amount)?;
+    let (response, _) = open_position_with_funds(
+        deps,
+        env,
+        trader,
+        subaccount_id,
+        funds,
+        None,
+        order.vamm,
+        order.side,
+        order.margin_amount,
+        order.leverage,
+        order.take_profit,
+        order.stop_loss,
+        order.base_asset_limit,
+    )?;
+
+    Ok(response.add_attributes(vec![
+        ("nonce", &order.nonce.to_string()),
+        ("relayer", info.sender.as_ref()),
+    ]))
+}
+
+pub fn query_trader_balance(
+    deps: Deps,
+    trader: String,
+    subaccount_id: Option<u32>,
+) -> StdResult<TraderBalanceResponse> {
+    let trader = deps.api.addr_validate(&trader)?;
+
+    Ok(TraderBalanceResponse {
+        balance: read_trader_balance(deps.storage, &trader, subaccount_id.unwrap_or_default())?,
+        credit_proceeds: read_credit_proceeds(deps.storage, &trader)?,
+    })
+}
+
+/// Adds the amount to the balance of a sub-account of the trader and returns the new balance
+pub fn credit_trader_balance(
+    storage: &mut dyn Storage,
+    trader: &Addr,
+    subaccount_id: u32,
+    amount: Uint128,
+) -> StdResult<Uint128> {
+    let balance = read_trader_balance(storage, trader, subaccount_id)?.checked_add(amount)?;
+    store_trader_balance(storage, trader, subaccount_id, balance)?;
+
+    Ok(balance)
+}
+
+/// Removes the amount from the balance of a sub-account of the trader and returns the new balance
+fn debit_trader_balance(
+    storage: &mut dyn Storage,
+    trader: &Addr,
+    subaccount_id: u32,
+    amount: Uint128,
+) -> StdResult<Uint128> {
+    let balance = read_trader_balance(storage, trader, subaccount_id)?
+        .checked_sub(amount)
+        .map_err(|_| StdError::generic_err("Insufficient trader balance"))?;
+    store_trader_balance(storage, trader, subaccount_id, balance)?;
+
+    Ok(balance)
+}
+
+/// Moves the whole balance of a sub-account into the funds of a position, the open position
+/// reply credits back what is left once the margin and fees are paid
+fn balance_funds(
+    storage: &mut dyn Storage,
+    trader: &Addr,
+    subaccount_id: u32,
+    margin_amount: Uint128,
+) -> StdResult<SentFunds> {
+    let config = read_config(storage)?;
+    let balance = read_trader_balance(storage, trader, subaccount_id)?;
+    if balance < margin_amount {
+        return Err(StdError::generic_err("Insufficient trader balance"));
+    }
+    store_trader_balance(storage, trader, subaccount_id, Uint128::zero())?;
+
+    Ok(SentFunds {
+        asset: Asset {
+            info: config.eligible_collateral,
+            amount: balance,
+        },
+        required: Uint128::zero(),
+        escrowed: true,
+        from_balance: true,
+    })
+}
+
+pub fn query_claimable_balance(deps: Deps, address: String) -> StdResult<Uint128> {
+    let address = deps.api.addr_validate(&address)?;
+    read_claimable(deps.storage, &address)
+}
+
+pub fn query_signing_key(deps: Deps, trader: String) -> StdResult<Option<Binary>> {
+    let trader = deps.api.addr_validate(&trader)?;
+    read_signing_key(deps.storage, &trader)
+}
+
+pub fn query_is_nonce_used(deps: Deps, trader: String, nonce: u64) -> StdResult<bool> {
+    let trader = deps.api.addr_validate(&trader)?;
+    is_nonce_used(deps.storage, &trader, nonce)
+}
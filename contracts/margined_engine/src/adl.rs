@@ -0,0 +1,432 @@
+use cosmwasm_std::{Addr, Deps, DepsMut, Env, Response, StdError, StdResult, SubMsg, Uint128};
+use margined_common::integer::Integer;
+use margined_perp::{
+    margined_engine::{
+        AdlRankResponse, AdlRankingResponse, Position, PositionUnrealizedPnlResponse,
+        RemainMarginResponse, Side,
+    },
+    margined_vamm::Direction,
+};
+
+use crate::{
+    limits::record_realized_pnl,
+    messages::{query_insurance_fund_balance, transfer_fees, withdraw_to_subaccount},
+    state::{
+        read_adl_candidates, read_config, read_position, read_state, remove_position,
+        remove_tmp_liquidator, store_adl_candidates, store_position, store_state, DEFAULT_LIMIT,
+        MAX_ADL_CANDIDATES, MAX_LIMIT,
+    },
+    utils::{
+        calc_remain_margin_with_funding_payment, get_mark_price_notional_unrealized_pnl,
+        keccak_256, realize_bad_debt, update_open_interest_notional,
+    },
+};
+
+struct AdlCandidate {
+    position: Position,
+    rank: AdlRankResponse,
+}
+
+/// Reads the ranked candidates of one side of a vamm, at most `MAX_ADL_CANDIDATES` positions are
+/// ranked so that a liquidation never scans the whole side
+fn read_ranked_candidates(deps: Deps, vamm: &Addr, side: &Side) -> StdResult<Vec<AdlCandidate>> {
+    let vamm_key = keccak_256(vamm.as_bytes());
+
+    rank_candidates(
+        deps,
+        &vamm_key,
+        side,
+        read_adl_candidates(deps.storage, &vamm_key, side)?,
+    )
+}
+
+/// Sorts positions of one side of a vamm by ADL score, the most profitable and most leveraged
+/// positions come first
+fn rank_candidates(
+    deps: Deps,
+    vamm_key: &[u8],
+    side: &Side,
+    position_ids: Vec<u64>,
+) -> StdResult<Vec<AdlCandidate>> {
+    let config = read_config(deps.storage)?;
+
+    let mut candidates = vec![];
+    for position_id in position_ids {
+        let position = read_position(deps.storage, vamm_key, position_id)?;
+        if position.side != *side {
+            return Err(StdError::generic_err("Position is on the wrong side"));
+        }
+        if position.size.is_zero() {
+            continue;
+        }
+
+        // positions are ranked at the mark price policy of the vamm
+        let PositionUnrealizedPnlResponse {
+            position_notional,
+            unrealized_pnl,
+        } = get_mark_price_notional_unrealized_pnl(deps, &position)?;
+
+        let remain_margin =
+            calc_remain_margin_with_funding_payment(deps, &position, Integer::zero())?;
+        let equity = Integer::new_positive(remain_margin.margin)
+            - Integer::new_positive(remain_margin.bad_debt)
+            + unrealized_pnl;
+
+        // score = pnl ratio * effective leverage, losing positions are ranked by pnl ratio only
+        let score = if remain_margin.margin.is_zero() {
+            unrealized_pnl
+        } else if unrealized_pnl.is_positive() && equity.is_positive() {
+            Integer::new_positive(
+                unrealized_pnl
+                    .value
+                    .checked_mul(position_notional)?
+                    .checked_div(remain_margin.margin)?
+                    .checked_mul(config.decimals)?
+                    .checked_div(equity.value)?,
+            )
+        } else {
+            unrealized_pnl * Integer::new_positive(config.decimals)
+                / Integer::new_positive(remain_margin.margin)
+        };
+
+        candidates.push(AdlCandidate {
+            rank: AdlRankResponse {
+                position_id,
+                trader: position.trader.clone(),
+                rank: 0u32,
+                score,
+                unrealized_pnl,
+            },
+            position,
+        });
+    }
+
+    candidates.sort_by(|a, b| {
+        b.rank
+            .score
+            .cmp(&a.rank.score)
+            .then(a.rank.position_id.cmp(&b.rank.position_id))
+    });
+
+    for (index, candidate) in candidates.iter_mut().enumerate() {
+        candidate.rank.rank = index as u32 + 1;
+    }
+
+    Ok(candidates)
+}
+
+/// Ranks the positions together with the current candidates of the side and stores the highest
+/// `MAX_ADL_CANDIDATES` of them in rank order, returns the stored candidates
+fn store_ranked_candidates(
+    deps: DepsMut,
+    vamm_key: &[u8],
+    side: &Side,
+    position_ids: Vec<u64>,
+) -> StdResult<Vec<u64>> {
+    let mut candidates = read_adl_candidates(deps.storage, vamm_key, side)?;
+    for position_id in position_ids {
+        if !candidates.contains(&position_id) {
+            candidates.push(position_id);
+        }
+    }
+
+    let candidates: Vec<u64> = rank_candidates(deps.as_ref(), vamm_key, side, candidates)?
+        .into_iter()
+        .take(MAX_ADL_CANDIDATES)
+        .map(|candidate| candidate.rank.position_id)
+        .collect();
+    store_adl_candidates(deps.storage, vamm_key, side, &candidates)?;
+
+    Ok(candidates)
+}
+
+/// Ranks a stored position against the ADL candidates of its side at the mark price, so that the
+/// candidates stay the highest ranked positions as positions are opened and updated
+pub fn update_adl_candidate(deps: DepsMut, position: &Position) -> StdResult<()> {
+    let vamm_key = keccak_256(position.vamm.as_bytes());
+    store_ranked_candidates(deps, &vamm_key, &position.side, vec![position.position_id])?;
+
+    Ok(())
+}
+
+/// Returns true if the bad debt the position would leave behind at the mark
+/// price cannot be covered by the insurance fund and the prepaid bad debt
+pub fn needs_auto_deleverage(deps: Deps, position: &Position) -> StdResult<bool> {
+    let unrealized_pnl = get_mark_price_notional_unrealized_pnl(deps, position)?.unrealized_pnl;
+    let remain_margin = calc_remain_margin_with_funding_payment(deps, position, unrealized_pnl)?;
+
+    exceeds_insurance_fund(deps, remain_margin.bad_debt)
+}
+
+/// Returns true if the bad debt cannot be covered by the insurance fund and
+/// the prepaid bad debt
+pub fn exceeds_insurance_fund(deps: Deps, bad_debt: Uint128) -> StdResult<bool> {
+    if bad_debt.is_zero() {
+        return Ok(false);
+    }
+
+    let state = read_state(deps.storage)?;
+    let insurance_balance = query_insurance_fund_balance(deps)?;
+
+    Ok(bad_debt > insurance_balance.checked_add(state.prepaid_bad_debt)?)
+}
+
+/// Closes a bankrupt position against the highest ranked opposite positions
+/// at the bankruptcy price, the vAMM reserves are left untouched
+pub fn auto_deleverage(mut deps: DepsMut, env: Env, position: &Position) -> StdResult<Response> {
+    let config = read_config(deps.storage)?;
+    let mut state = read_state(deps.storage)?;
+    let vamm_key = keccak_256(position.vamm.as_bytes());
+
+    // the bankruptcy notional is the notional at which the remaining margin is zero
+    let remain_margin =
+        calc_remain_margin_with_funding_payment(deps.as_ref(), position, Integer::zero())?;
+    let equity =
+        Integer::new_positive(remain_margin.margin) - Integer::new_positive(remain_margin.bad_debt);
+    let mut bankruptcy_notional = match position.direction {
+        Direction::AddToAmm => Integer::new_positive(position.notional) - equity,
+        Direction::RemoveFromAmm => Integer::new_positive(position.notional) + equity,
+    };
+    if bankruptcy_notional.is_negative() {
+        bankruptcy_notional = Integer::zero();
+    }
+    let bankruptcy_price = bankruptcy_notional
+        .value
+        .checked_mul(config.decimals)?
+        .checked_div(position.size.value)?;
+
+    let counter_side = match position.side {
+        Side::Buy => Side::Sell,
+        Side::Sell => Side::Buy,
+    };
+    let candidates = read_ranked_candidates(deps.as_ref(), &position.vamm, &counter_side)?;
+
+    let mut msgs: Vec<SubMsg> = vec![];
+    let mut attributes: Vec<(String, String)> = vec![];
+    let mut remaining_size = position.size.value;
+    let mut unfunded_bad_debt = Uint128::zero();
+
+    for AdlCandidate {
+        position: mut counter,
+        ..
+    } in candidates
+    {
+        if remaining_size.is_zero() {
+            break;
+        }
+
+        let size = Uint128::min(remaining_size, counter.size.value);
+        let closed_notional = counter
+            .notional
+            .checked_mul(size)?
+            .checked_div(counter.size.value)?;
+        let exit_notional = bankruptcy_notional
+            .value
+            .checked_mul(size)?
+            .checked_div(position.size.value)?;
+        let realized_pnl = match counter.direction {
+            Direction::AddToAmm => {
+                Integer::new_positive(exit_notional) - Integer::new_positive(closed_notional)
+            }
+            Direction::RemoveFromAmm => {
+                Integer::new_positive(closed_notional) - Integer::new_positive(exit_notional)
+            }
+        };
+
+        let RemainMarginResponse {
+            funding_payment: _,
+            margin,
+            bad_debt,
+            latest_premium_fraction,
+        } = calc_remain_margin_with_funding_payment(deps.as_ref(), &counter, realized_pnl)?;
+
+        // deleveraging must not push the counterparty into bad debt
+        if !bad_debt.is_zero() {
+            continue;
+        }
+
+        record_realized_pnl(
+            deps.storage,
+            &env,
+            &counter.trader,
+            counter.subaccount_id,
+            realized_pnl,
+        )?;
+
+        if size == counter.size.value {
+            let fees = counter.spread_fee.checked_add(counter.toll_fee)?;
+            let mut withdraw_amount = margin;
+            let mut fees_paid = Uint128::zero();
+            if withdraw_amount > fees {
+                withdraw_amount = withdraw_amount.checked_sub(fees)?;
+                fees_paid = fees;
+                msgs.append(&mut transfer_fees(
+                    deps.as_ref(),
+                    counter.trader.clone(),
+                    counter.spread_fee,
+                    counter.toll_fee,
+                    false,
+                )?);
+            }
+
+            if !withdraw_amount.is_zero() {
+                unfunded_bad_debt += withdraw_to_subaccount(
+                    deps.branch(),
+                    env.clone(),
+                    &mut state,
+                    &counter.trader,
+                    counter.subaccount_id,
+                    config.eligible_collateral.clone(),
+                    withdraw_amount,
+                    fees_paid,
+                    Uint128::zero(),
+                    &mut msgs,
+                )?;
+            }
+
+            remove_position(deps.storage, &vamm_key, &counter)?;
+        } else {
+            counter.size = match counter.side {
+                Side::Buy => counter.size - Integer::new_positive(size),
+                Side::Sell => counter.size + Integer::new_positive(size),
+            };
+            counter.notional = counter.notional.checked_sub(closed_notional)?;
+            counter.margin = margin;
+            counter.last_updated_premium_fraction = latest_premium_fraction;
+            counter.block_time = env.block.time.seconds();
+
+            store_position(deps.storage, &vamm_key, &counter, false)?;
+            update_adl_candidate(deps.branch(), &counter)?;
+        }
+
+        update_open_interest_notional(
+            deps.branch(),
+            &mut state,
+            position.vamm.clone(),
+            counter.side,
+            Integer::new_negative(closed_notional),
+            counter.trader.clone(),
+        )?;
+
+        remaining_size = remaining_size.checked_sub(size)?;
+        attributes.push((
+            "adl_position_id".to_string(),
+            counter.position_id.to_string(),
+        ));
+        attributes.push(("adl_size".to_string(), size.to_string()));
+        attributes.push(("adl_pnl".to_string(), realized_pnl.to_string()));
+    }
+
+    // the size no candidate could take is realized as bad debt at the mark price, the insurance
+    // fund covers what it can of it
+    let mut bad_debt = Uint128::zero();
+    if !remaining_size.is_zero() {
+        let unrealized_pnl =
+            get_mark_price_notional_unrealized_pnl(deps.as_ref(), position)?.unrealized_pnl;
+        let remain_margin =
+            calc_remain_margin_with_funding_payment(deps.as_ref(), position, unrealized_pnl)?;
+        bad_debt = remain_margin
+            .bad_debt
+            .checked_mul(remaining_size)?
+            .checked_div(position.size.value)?;
+
+        if !bad_debt.is_zero() {
+            realize_bad_debt(deps.as_ref(), bad_debt, &mut msgs, &mut state)?;
+        }
+    }
+
+    // the deleveraged position is closed at its bankruptcy price
+    let realized_pnl = match position.direction {
+        Direction::AddToAmm => bankruptcy_notional - Integer::new_positive(position.notional),
+        Direction::RemoveFromAmm => Integer::new_positive(position.notional) - bankruptcy_notional,
+    };
+    record_realized_pnl(
+        deps.storage,
+        &env,
+        &position.trader,
+        position.subaccount_id,
+        realized_pnl,
+    )?;
+
+    update_open_interest_notional(
+        deps.branch(),
+        &mut state,
+        position.vamm.clone(),
+        position.side,
+        Integer::new_negative(position.notional),
+        position.trader.clone(),
+    )?;
+
+    store_state(deps.storage, &state)?;
+    remove_position(deps.storage, &vamm_key, position)?;
+    remove_tmp_liquidator(deps.storage);
+
+    Ok(Response::new()
+        .add_submessages(msgs)
+        .add_attributes(vec![
+            ("action", "auto_deleverage"),
+            ("vamm", position.vamm.as_ref()),
+            ("pair", &position.pair),
+            ("position_id", &position.position_id.to_string()),
+            ("trader", position.trader.as_ref()),
+            ("bankruptcy_price", &bankruptcy_price.to_string()),
+            ("unfilled_size", &remaining_size.to_string()),
+            ("bad_debt", &bad_debt.to_string()),
+            ("unfunded_bad_debt", &unfunded_bad_debt.to_string()),
+        ])
+        .add_attributes(attributes))
+}
+
+/// Lets keepers propose positions of a side for the ADL candidates, the proposed and the current
+/// candidates are ranked together and the highest `MAX_ADL_CANDIDATES` of them are kept
+pub fn update_adl_candidates(
+    deps: DepsMut,
+    vamm: String,
+    side: Side,
+    position_ids: Vec<u64>,
+) -> StdResult<Response> {
+    let vamm = deps.api.addr_validate(&vamm)?;
+    let vamm_key = keccak_256(vamm.as_bytes());
+
+    if position_ids.len() > MAX_ADL_CANDIDATES {
+        return Err(StdError::generic_err("Too many positions"));
+    }
+
+    let candidates = store_ranked_candidates(deps, &vamm_key, &side, position_ids)?;
+
+    Ok(Response::new().add_attributes(vec![
+        ("action", "update_adl_candidates"),
+        ("vamm", vamm.as_ref()),
+        ("side", &format!("{:?}", side)),
+        ("candidates", &candidates.len().to_string()),
+    ]))
+}
+
+pub fn query_adl_ranking(
+    deps: Deps,
+    vamm: String,
+    side: Side,
+    limit: Option<u32>,
+) -> StdResult<AdlRankingResponse> {
+    let vamm = deps.api.addr_validate(&vamm)?;
+    let limit = limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT) as usize;
+
+    let ranks = read_ranked_candidates(deps, &vamm, &side)?
+        .into_iter()
+        .take(limit)
+        .map(|candidate| candidate.rank)
+        .collect();
+
+    Ok(AdlRankingResponse { ranks })
+}
+
+pub fn query_adl_rank(deps: Deps, vamm: String, position_id: u64) -> StdResult<AdlRankResponse> {
+    let vamm = deps.api.addr_validate(&vamm)?;
+    let position = read_position(deps.storage, &keccak_256(vamm.as_bytes()), position_id)?;
+
+    read_ranked_candidates(deps, &vamm, &position.side)?
+        .into_iter()
+        .find(|candidate| candidate.rank.position_id == position_id)
+        .map(|candidate| candidate.rank)
+        .ok_or_else(|| StdError::generic_err("Position is outside of the ADL ranking"))
+}
@@ -0,0 +1,123 @@
+use cosmwasm_std::{
+    Binary, Deps, DepsMut, MessageInfo, Response, StdError, StdResult, Storage, Uint128,
+};
+use margined_perp::margined_engine::{BackfillResponse, BackfillStage};
+
+use crate::state::{
+    backfill_position_by_subaccount, read_backfill, read_config, read_positions_page, read_state,
+    read_storage_version, remove_backfill, seed_open_interest_notional, store_backfill,
+    store_state, store_storage_version, store_total_liabilities, Backfill, DEFAULT_LIMIT,
+    MAX_LIMIT,
+};
+
+// version of the stored data, bumped when a migration has to rebuild some of it
+pub const STORAGE_VERSION: u32 = 1;
+
+/// Schedules the stages that rebuild the data stored by older versions, each runs once. The
+/// engine is paused until the stages are run in batches with `backfill`
+pub fn schedule_backfill(storage: &mut dyn Storage) -> StdResult<()> {
+    let version = read_storage_version(storage)?;
+    if version >= STORAGE_VERSION {
+        return Ok(());
+    }
+
+    let mut stages = vec![];
+    if version < 1 {
+        stages.push(BackfillStage::OpenInterest);
+        stages.push(BackfillStage::PositionBySubaccount);
+
+        // the liabilities are tracked from this version on, the older versions stored none of
+        // them so the total starts at zero
+        store_total_liabilities(storage, Uint128::zero())?;
+    }
+
+    store_storage_version(storage, STORAGE_VERSION)?;
+    store_backfill(
+        storage,
+        &Backfill {
+            stages,
+            start_after: None,
+            seen_vamms: vec![],
+        },
+    )?;
+
+    let mut state = read_state(storage)?;
+    state.pause = true;
+    store_state(storage, &state)
+}
+
+// Runs a batch of the pending backfill, can only be called by the owner
+pub fn backfill(deps: DepsMut, info: MessageInfo, limit: Option<u32>) -> StdResult<Response> {
+    let config = read_config(deps.storage)?;
+
+    // check permission
+    if info.sender != config.owner {
+        return Err(StdError::generic_err("unauthorized"));
+    }
+
+    let mut backfill = read_backfill(deps.storage)?
+        .ok_or_else(|| StdError::generic_err("No backfill is pending"))?;
+    let limit = limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT) as usize;
+    let start_after = backfill.start_after.take().map(|key| key.to_vec());
+    let stage = backfill.stages[0].clone();
+
+    let entries = match stage {
+        BackfillStage::OpenInterest => {
+            let page = read_positions_page(deps.storage, start_after, limit)?;
+            backfill.start_after = page.last().map(|(key, _)| Binary::from(key.as_slice()));
+            let entries = page.len();
+            seed_open_interest_notional(
+                deps.storage,
+                page.into_iter().map(|(_, position)| position).collect(),
+                &mut backfill.seen_vamms,
+            )?;
+            entries
+        }
+        BackfillStage::PositionBySubaccount => {
+            let page = read_positions_page(deps.storage, start_after, limit)?;
+            backfill.start_after = page.last().map(|(key, _)| Binary::from(key.as_slice()));
+            let entries = page.len();
+            backfill_position_by_subaccount(
+                deps.storage,
+                page.into_iter().map(|(_, position)| position).collect(),
+            )?;
+            entries
+        }
+    };
+
+    // a page that is not full is the last one of the stage
+    if entries < limit {
+        backfill.stages.remove(0);
+        backfill.start_after = None;
+    }
+
+    if backfill.stages.is_empty() {
+        remove_backfill(deps.storage);
+    } else {
+        store_backfill(deps.storage, &backfill)?;
+    }
+
+    Ok(Response::new().add_attributes(vec![
+        ("action", "backfill"),
+        ("stage", &format!("{:?}", stage)),
+        ("entries", &entries.to_string()),
+    ]))
+}
+
+pub fn query_backfill(deps: Deps) -> StdResult<BackfillResponse> {
+    Ok(BackfillResponse {
+        stages: read_backfill(deps.storage)?
+            .map(|backfill| backfill.stages)
+            .unwrap_or_default(),
+    })
+}
+
+/// Throws an error until the pending backfill is run, keeps the engine paused while the data it
+/// reads is being rebuilt
+pub fn require_no_backfill(storage: &dyn Storage) -> StdResult<()> {
+    if read_backfill(storage)?.is_some() {
+        return Err(StdError::generic_err("A backfill is pending"));
+    }
+
+    Ok(())
+}
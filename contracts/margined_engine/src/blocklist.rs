@@ -0,0 +1,101 @@
+use cosmwasm_std::{
+    Addr, Deps, DepsMut, MessageInfo, Response, StdError, StdResult, Storage, Uint128,
+};
+use margined_perp::margined_engine::EscrowResponse;
+
+use crate::state::{
+    is_blocked, read_blocklist, read_claimable, read_config, read_escrowed, read_escrows,
+    remove_blocked, store_blocked, store_claimable, store_escrowed,
+};
+
+// Blocks an address from opening positions, depositing margin and receiving payouts, can only
+// be called by the owner
+pub fn add_blocklist(deps: DepsMut, info: MessageInfo, address: String) -> StdResult<Response> {
+    let config = read_config(deps.storage)?;
+
+    // check permission
+    if info.sender != config.owner {
+        return Err(StdError::generic_err("unauthorized"));
+    }
+
+    let address = deps.api.addr_validate(&address)?;
+    if is_blocked(deps.storage, &address)? {
+        return Err(StdError::generic_err("address is already blocked"));
+    }
+
+    store_blocked(deps.storage, &address)?;
+
+    Ok(Response::new().add_attributes(vec![
+        ("action", "add_blocklist"),
+        ("address", address.as_str()),
+    ]))
+}
+
+// Unblocks an address, the payouts escrowed while it was blocked become claimable
+pub fn remove_blocklist(deps: DepsMut, info: MessageInfo, address: String) -> StdResult<Response> {
+    let config = read_config(deps.storage)?;
+
+    // check permission
+    if info.sender != config.owner {
+        return Err(StdError::generic_err("unauthorized"));
+    }
+
+    let address = deps.api.addr_validate(&address)?;
+    if !is_blocked(deps.storage, &address)? {
+        return Err(StdError::generic_err("address is not blocked"));
+    }
+
+    remove_blocked(deps.storage, &address);
+
+    let escrowed = read_escrowed(deps.storage, &address)?;
+    if !escrowed.is_zero() {
+        store_escrowed(deps.storage, &address, Uint128::zero())?;
+
+        let claimable = read_claimable(deps.storage, &address)?.checked_add(escrowed)?;
+        store_claimable(deps.storage, &address, claimable)?;
+    }
+
+    Ok(Response::new().add_attributes(vec![
+        ("action", "remove_blocklist"),
+        ("address", address.as_str()),
+        ("released", &escrowed.to_string()),
+    ]))
+}
+
+pub fn require_not_blocked(storage: &dyn Storage, address: &Addr) -> StdResult<()> {
+    if is_blocked(storage, address)? {
+        return Err(StdError::generic_err("address is blocked"));
+    }
+
+    Ok(())
+}
+
+pub fn query_is_blocked(deps: Deps, address: String) -> StdResult<bool> {
+    let address = deps.api.addr_validate(&address)?;
+
+    is_blocked(deps.storage, &address)
+}
+
+pub fn query_blocklist(
+    deps: Deps,
+    start_after: Option<String>,
+    limit: Option<u32>,
+) -> StdResult<Vec<Addr>> {
+    let start_after = start_after
+        .map(|address| deps.api.addr_validate(&address))
+        .transpose()?;
+
+    read_blocklist(deps.storage, start_after, limit)
+}
+
+pub fn query_escrows(
+    deps: Deps,
+    start_after: Option<String>,
+    limit: Option<u32>,
+) -> StdResult<Vec<EscrowResponse>> {
+    let start_after = start_after
+        .map(|address| deps.api.addr_validate(&address))
+        .transpose()?;
+
+    read_escrows(deps.storage, start_after, limit)
+}
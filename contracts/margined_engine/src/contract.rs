@@ -7,34 +7,63 @@ use cw_controllers::{Admin, Hooks};
 use margined_common::validate::{
     validate_decimal_places, validate_eligible_collateral, validate_margin_ratios, validate_ratio,
 };
-use margined_perp::margined_engine::{ExecuteMsg, InstantiateMsg, MigrateMsg, QueryMsg};
+use margined_perp::margined_engine::{
+    ExecuteMsg, InstantiateMsg, KeeperReward, LiquidationFeeSplit, MigrateMsg, QueryMsg,
+};
 
+use crate::account::{
+    claim, deposit, execute_signed_order, open_position_from_balance, query_claimable_balance,
+    query_is_nonce_used, query_signing_key, query_trader_balance, transfer_between_subaccounts,
+    update_credit_proceeds, update_signing_key, withdraw,
+};
+use crate::adl::{query_adl_rank, query_adl_ranking, update_adl_candidates};
+use crate::backfill::{backfill, query_backfill, schedule_backfill};
+use crate::blocklist::{
+    add_blocklist, query_blocklist, query_escrows, query_is_blocked, remove_blocklist,
+};
 use crate::error::ContractError;
-use crate::handle::{trigger_mutiple_tp_sl, trigger_tp_sl, update_operator, update_tp_sl};
+use crate::handle::{
+    ratchet_trailing_stop, trigger_mutiple_tp_sl, trigger_take_profit_level, trigger_tp_sl,
+    update_backstop_pool, update_keeper_reward, update_liquidation_fee_split,
+    update_mark_price_policy, update_open_interest_caps, update_operator,
+    update_take_profit_levels, update_tp_sl, update_tp_sl_trigger, update_trailing_stop,
+};
+use crate::limits::{query_trader_limits, update_self_limits, update_trader_limits};
+use crate::order::{
+    cancel_conditional_order, cancel_twap_order, execute_conditional_order, execute_twap_order,
+    place_conditional_order, place_twap_order, query_conditional_order, query_conditional_orders,
+    query_twap_order, query_twap_orders,
+};
 use crate::query::{
-    query_last_position_id, query_position_is_bad_debt, query_position_is_liquidated,
-    query_position_is_tpsl, query_positions,
+    query_last_position_id, query_mark_price_policy, query_market_state,
+    query_position_is_bad_debt, query_position_is_liquidated, query_position_is_tpsl,
+    query_positions, query_take_profit_levels,
 };
-use crate::state::{init_last_position_id, read_position};
+use crate::state::{init_last_position_id, read_config, read_position};
 use crate::tick::{query_tick, query_ticks};
 use crate::utils::{get_margin_ratio_calc_option, keccak_256};
 use crate::{
     handle::{
-        close_position, deposit_margin, liquidate, open_position, pay_funding, update_config,
+        backstop_liquidate, close_position, deposit_margin, liquidate, open_position,
+        open_position_by_size, pay_funding, realize_pnl, set_leverage, update_config,
         withdraw_margin,
     },
     query::{
         query_config, query_cumulative_premium_fraction, query_free_collateral, query_margin_ratio,
         query_pauser, query_position, query_position_notional_unrealized_pnl, query_state,
         query_trader_balance_with_funding_payment, query_trader_position_with_funding_payment,
+        query_whitelist_capabilities,
     },
     reply::{
-        close_position_reply, liquidate_reply, open_position_reply, partial_close_position_reply,
-        partial_liquidation_reply, pay_funding_reply,
+        backstop_pool_failure_reply, backstop_pool_reply, close_position_reply, liquidate_reply,
+        open_position_reply, partial_close_position_reply, partial_liquidation_reply,
+        pay_funding_reply, payout_reply,
     },
     state::{store_config, store_state, Config, State},
     utils::{
-        add_whitelist, parse_pay_funding, parse_swap, remove_whitelist, set_pause, update_pauser,
+        add_backstop_liquidator, add_whitelist, parse_pay_funding, parse_swap, parse_swap_amounts,
+        remove_backstop_liquidator, remove_whitelist, set_pause, update_pauser,
+        update_whitelist_capabilities,
     },
 };
 
@@ -46,6 +75,8 @@ const CONTRACT_VERSION: &str = env!("CARGO_PKG_VERSION");
 pub const PAUSER: Admin = Admin::new("pauser");
 /// Hooks controller for the base asset holding whitelist
 pub const WHITELIST: Hooks = Hooks::new("whitelist");
+/// Hooks controller for the liquidators allowed to take over positions
+pub const BACKSTOP_LIQUIDATORS: Hooks = Hooks::new("backstop_liquidators");
 
 pub const INCREASE_POSITION_REPLY_ID: u64 = 1;
 pub const CLOSE_POSITION_REPLY_ID: u64 = 2;
@@ -53,7 +84,13 @@ pub const PARTIAL_CLOSE_POSITION_REPLY_ID: u64 = 3;
 pub const LIQUIDATION_REPLY_ID: u64 = 4;
 pub const PARTIAL_LIQUIDATION_REPLY_ID: u64 = 5;
 pub const PAY_FUNDING_REPLY_ID: u64 = 6;
+pub const BACKSTOP_POOL_REPLY_ID: u64 = 7;
+pub const OPEN_POSITION_BY_SIZE_REPLY_ID: u64 = 8;
 pub const TRANSFER_FAILURE_REPLY_ID: u64 = 9;
+// payouts reply on error with an id of their own from the range that starts here, no other
+// reply may use an id of the range
+pub const PAYOUT_REPLY_ID: u64 = 1_000;
+pub const PAYOUT_REPLY_ID_RANGE: u64 = 1_000;
 
 #[cfg_attr(not(feature = "library"), entry_point)]
 pub fn instantiate(
@@ -108,6 +145,12 @@ pub fn instantiate(
         partial_liquidation_ratio: Uint128::zero(), // set as zero by default
         tp_sl_spread: msg.tp_sl_spread,
         liquidation_fee: msg.liquidation_fee,
+        backstop_pool: None,
+        backstop_discount: Uint128::zero(),
+        liquidation_fee_split: default_liquidation_fee_split(decimals),
+        max_liquidator_fee: None,
+        target_margin_ratio: Uint128::zero(), // fixed partial liquidation ratio by default
+        keeper_reward: KeeperReward::default(), // keepers are not rewarded by default
     };
 
     // Initialize last position id
@@ -142,6 +185,7 @@ pub fn execute(deps: DepsMut, env: Env, info: MessageInfo, msg: ExecuteMsg) -> S
             partial_liquidation_ratio,
             tp_sl_spread,
             liquidation_fee,
+            target_margin_ratio,
         } => update_config(
             deps,
             info,
@@ -153,11 +197,53 @@ pub fn execute(deps: DepsMut, env: Env, info: MessageInfo, msg: ExecuteMsg) -> S
             partial_liquidation_ratio,
             tp_sl_spread,
             liquidation_fee,
+            target_margin_ratio,
         ),
         ExecuteMsg::UpdateOperator { operator } => update_operator(deps, info, operator),
+        ExecuteMsg::UpdateBackstopPool {
+            backstop_pool,
+            discount,
+        } => update_backstop_pool(deps, info, backstop_pool, discount),
+        ExecuteMsg::UpdateLiquidationFeeSplit {
+            liquidation_fee_split,
+            max_liquidator_fee,
+        } => update_liquidation_fee_split(deps, info, liquidation_fee_split, max_liquidator_fee),
+        ExecuteMsg::UpdateMarkPricePolicy { vamm, policy } => {
+            update_mark_price_policy(deps, info, vamm, policy)
+        }
+        ExecuteMsg::UpdateOpenInterestCaps {
+            vamm,
+            long_cap,
+            short_cap,
+        } => update_open_interest_caps(deps, info, vamm, long_cap, short_cap),
+        ExecuteMsg::UpdateKeeperReward { keeper_reward } => {
+            update_keeper_reward(deps, info, keeper_reward)
+        }
+        ExecuteMsg::UpdateTraderLimits { trader, limits } => {
+            update_trader_limits(deps, info, trader, limits)
+        }
+        ExecuteMsg::UpdateSelfLimits {
+            limits,
+            subaccount_id,
+        } => update_self_limits(deps, env, info, limits, subaccount_id),
         ExecuteMsg::UpdatePauser { pauser } => update_pauser(deps, info, pauser),
-        ExecuteMsg::AddWhitelist { address } => add_whitelist(deps, info, address),
+        ExecuteMsg::AddWhitelist {
+            address,
+            capabilities,
+        } => add_whitelist(deps, info, address, capabilities),
+        ExecuteMsg::UpdateWhitelistCapabilities {
+            address,
+            capabilities,
+        } => update_whitelist_capabilities(deps, info, address, capabilities),
         ExecuteMsg::RemoveWhitelist { address } => remove_whitelist(deps, info, address),
+        ExecuteMsg::AddBackstopLiquidator { address } => {
+            add_backstop_liquidator(deps, info, address)
+        }
+        ExecuteMsg::RemoveBackstopLiquidator { address } => {
+            remove_backstop_liquidator(deps, info, address)
+        }
+        ExecuteMsg::AddBlocklist { address } => add_blocklist(deps, info, address),
+        ExecuteMsg::RemoveBlocklist { address } => remove_blocklist(deps, info, address),
         ExecuteMsg::OpenPosition {
             vamm,
             side,
@@ -166,6 +252,9 @@ pub fn execute(deps: DepsMut, env: Env, info: MessageInfo, msg: ExecuteMsg) -> S
             take_profit,
             stop_loss,
             base_asset_limit,
+            deadline,
+            worst_price,
+            subaccount_id,
         } => open_position(
             deps,
             env,
@@ -177,34 +266,181 @@ pub fn execute(deps: DepsMut, env: Env, info: MessageInfo, msg: ExecuteMsg) -> S
             take_profit,
             stop_loss,
             base_asset_limit,
+            deadline,
+            worst_price,
+            subaccount_id,
+        ),
+        ExecuteMsg::OpenPositionFromBalance {
+            vamm,
+            side,
+            margin_amount,
+            leverage,
+            take_profit,
+            stop_loss,
+            base_asset_limit,
+            subaccount_id,
+        } => open_position_from_balance(
+            deps,
+            env,
+            info,
+            vamm,
+            side,
+            margin_amount,
+            leverage,
+            take_profit,
+            stop_loss,
+            base_asset_limit,
+            subaccount_id,
         ),
+        ExecuteMsg::TransferBetweenSubaccounts {
+            from_subaccount_id,
+            to_subaccount_id,
+            amount,
+        } => transfer_between_subaccounts(deps, info, from_subaccount_id, to_subaccount_id, amount),
+        ExecuteMsg::OpenPositionBySize {
+            vamm,
+            side,
+            base_size,
+            margin_amount,
+            subaccount_id,
+        } => open_position_by_size(
+            deps,
+            env,
+            info,
+            vamm,
+            side,
+            base_size,
+            margin_amount,
+            subaccount_id,
+        ),
+        ExecuteMsg::Deposit { amount } => deposit(deps, env, info, amount),
+        ExecuteMsg::Withdraw { amount } => withdraw(deps, info, amount),
+        ExecuteMsg::Claim {} => claim(deps, info),
+        ExecuteMsg::UpdateCreditProceeds { credit_proceeds } => {
+            update_credit_proceeds(deps, info, credit_proceeds)
+        }
+        ExecuteMsg::UpdateSigningKey { public_key } => update_signing_key(deps, info, public_key),
+        ExecuteMsg::ExecuteSignedOrder {
+            trader,
+            order,
+            signature,
+        } => execute_signed_order(deps, env, info, trader, order, signature),
+        ExecuteMsg::PlaceConditionalOrder {
+            vamm,
+            order,
+            oco_order,
+        } => place_conditional_order(deps, env, info, vamm, order, oco_order),
+        ExecuteMsg::ExecuteConditionalOrder { order_id } => {
+            execute_conditional_order(deps, env, info, order_id)
+        }
+        ExecuteMsg::CancelConditionalOrder { order_id } => {
+            cancel_conditional_order(deps, info, order_id)
+        }
+        ExecuteMsg::PlaceTwapOrder {
+            vamm,
+            side,
+            margin_amount,
+            leverage,
+            slices,
+            interval,
+            price_limit,
+            subaccount_id,
+        } => place_twap_order(
+            deps,
+            env,
+            info,
+            vamm,
+            side,
+            margin_amount,
+            leverage,
+            slices,
+            interval,
+            price_limit,
+            subaccount_id,
+        ),
+        ExecuteMsg::ExecuteTwapOrder { order_id } => execute_twap_order(deps, env, info, order_id),
+        ExecuteMsg::CancelTwapOrder { order_id } => cancel_twap_order(deps, info, order_id),
         ExecuteMsg::UpdateTpSl {
             vamm,
             position_id,
             take_profit,
             stop_loss,
         } => update_tp_sl(deps, env, info, vamm, position_id, take_profit, stop_loss),
+        ExecuteMsg::UpdateTpSlTrigger {
+            vamm,
+            position_id,
+            tp_sl_trigger,
+        } => update_tp_sl_trigger(deps, info, vamm, position_id, tp_sl_trigger),
+        ExecuteMsg::UpdateTrailingStop {
+            vamm,
+            position_id,
+            distance,
+        } => update_trailing_stop(deps, info, vamm, position_id, distance),
+        ExecuteMsg::RatchetTrailingStop { vamm, position_id } => {
+            ratchet_trailing_stop(deps, vamm, position_id)
+        }
+        ExecuteMsg::UpdateTakeProfitLevels {
+            vamm,
+            position_id,
+            levels,
+        } => update_take_profit_levels(deps, info, vamm, position_id, levels),
+        ExecuteMsg::TriggerTakeProfitLevel { vamm, position_id } => {
+            trigger_take_profit_level(deps, info, vamm, position_id)
+        }
         ExecuteMsg::ClosePosition {
             vamm,
             position_id,
             quote_asset_limit,
-        } => close_position(deps, env, info, vamm, position_id, quote_asset_limit),
+            deadline,
+            worst_price,
+        } => close_position(
+            deps,
+            env,
+            info,
+            vamm,
+            position_id,
+            quote_asset_limit,
+            deadline,
+            worst_price,
+        ),
         ExecuteMsg::Liquidate {
             vamm,
             position_id,
             quote_asset_limit,
-        } => liquidate(deps, env, info, vamm, position_id, quote_asset_limit),
+            deadline,
+            worst_price,
+        } => liquidate(
+            deps,
+            env,
+            info,
+            vamm,
+            position_id,
+            quote_asset_limit,
+            deadline,
+            worst_price,
+        ),
+        ExecuteMsg::BackstopLiquidate {
+            vamm,
+            position_id,
+            margin_amount,
+        } => backstop_liquidate(deps, env, info, vamm, position_id, margin_amount),
+        ExecuteMsg::UpdateAdlCandidates {
+            vamm,
+            side,
+            position_ids,
+        } => update_adl_candidates(deps, vamm, side, position_ids),
+        ExecuteMsg::Backfill { limit } => backfill(deps, info, limit),
         ExecuteMsg::TriggerTpSl {
             vamm,
             position_id,
             take_profit,
-        } => trigger_tp_sl(deps, vamm, position_id, take_profit),
+        } => trigger_tp_sl(deps, info, vamm, position_id, take_profit),
         ExecuteMsg::TriggerMultipleTpSl {
             vamm,
             side,
             take_profit,
             limit,
-        } => trigger_mutiple_tp_sl(deps, vamm, side, take_profit, limit),
+        } => trigger_mutiple_tp_sl(deps, info, vamm, side, take_profit, limit),
         ExecuteMsg::PayFunding { vamm } => pay_funding(deps, env, info, vamm),
         ExecuteMsg::DepositMargin {
             vamm,
@@ -216,18 +452,35 @@ pub fn execute(deps: DepsMut, env: Env, info: MessageInfo, msg: ExecuteMsg) -> S
             position_id,
             amount,
         } => withdraw_margin(deps, env, info, vamm, position_id, amount),
+        ExecuteMsg::SetLeverage {
+            vamm,
+            position_id,
+            leverage,
+        } => set_leverage(deps, env, info, vamm, position_id, leverage),
+        ExecuteMsg::RealizePnl {
+            vamm,
+            position_id,
+            amount,
+        } => realize_pnl(deps, env, info, vamm, position_id, amount),
         ExecuteMsg::SetPause { pause } => set_pause(deps, env, info, pause),
     }
 }
 
 #[cfg_attr(not(feature = "library"), entry_point)]
-pub fn query(deps: Deps, _env: Env, msg: QueryMsg) -> StdResult<Binary> {
+pub fn query(deps: Deps, env: Env, msg: QueryMsg) -> StdResult<Binary> {
     match msg {
         QueryMsg::Config {} => to_binary(&query_config(deps)?),
         QueryMsg::State {} => to_binary(&query_state(deps)?),
         QueryMsg::GetPauser {} => to_binary(&query_pauser(deps)?),
         QueryMsg::IsWhitelisted { address } => to_binary(&WHITELIST.query_hook(deps, address)?),
         QueryMsg::GetWhitelist {} => to_binary(&WHITELIST.query_hooks(deps)?),
+        QueryMsg::WhitelistCapabilities { address } => {
+            to_binary(&query_whitelist_capabilities(deps, address)?)
+        }
+        QueryMsg::IsBackstopLiquidator { address } => {
+            to_binary(&BACKSTOP_LIQUIDATORS.query_hook(deps, address)?)
+        }
+        QueryMsg::GetBackstopLiquidators {} => to_binary(&BACKSTOP_LIQUIDATORS.query_hooks(deps)?),
         QueryMsg::Positions {
             vamm,
             filter,
@@ -261,6 +514,31 @@ pub fn query(deps: Deps, _env: Env, msg: QueryMsg) -> StdResult<Binary> {
             limit,
             order_by,
         )?),
+        QueryMsg::TraderBalance {
+            trader,
+            subaccount_id,
+        } => to_binary(&query_trader_balance(deps, trader, subaccount_id)?),
+        QueryMsg::ClaimableBalance { address } => {
+            to_binary(&query_claimable_balance(deps, address)?)
+        }
+        QueryMsg::SigningKey { trader } => to_binary(&query_signing_key(deps, trader)?),
+        QueryMsg::IsNonceUsed { trader, nonce } => {
+            to_binary(&query_is_nonce_used(deps, trader, nonce)?)
+        }
+        QueryMsg::ConditionalOrder { order_id } => {
+            to_binary(&query_conditional_order(deps, order_id)?)
+        }
+        QueryMsg::ConditionalOrders {
+            trader,
+            start_after,
+            limit,
+        } => to_binary(&query_conditional_orders(deps, trader, start_after, limit)?),
+        QueryMsg::TwapOrder { order_id } => to_binary(&query_twap_order(deps, order_id)?),
+        QueryMsg::TwapOrders {
+            trader,
+            start_after,
+            limit,
+        } => to_binary(&query_twap_orders(deps, trader, start_after, limit)?),
         QueryMsg::Tick {
             vamm,
             side,
@@ -301,6 +579,22 @@ pub fn query(deps: Deps, _env: Env, msg: QueryMsg) -> StdResult<Binary> {
         QueryMsg::FreeCollateral { vamm, position_id } => {
             to_binary(&query_free_collateral(deps, vamm, position_id)?)
         }
+        QueryMsg::MarketState { vamm } => to_binary(&query_market_state(deps, vamm)?),
+        QueryMsg::TraderLimits {
+            trader,
+            subaccount_id,
+        } => to_binary(&query_trader_limits(deps, env, trader, subaccount_id)?),
+        QueryMsg::IsBlocked { address } => to_binary(&query_is_blocked(deps, address)?),
+        QueryMsg::Blocklist { start_after, limit } => {
+            to_binary(&query_blocklist(deps, start_after, limit)?)
+        }
+        QueryMsg::Escrows { start_after, limit } => {
+            to_binary(&query_escrows(deps, start_after, limit)?)
+        }
+        QueryMsg::MarkPricePolicy { vamm } => to_binary(&query_mark_price_policy(deps, vamm)?),
+        QueryMsg::TakeProfitLevels { vamm, position_id } => {
+            to_binary(&query_take_profit_levels(deps, vamm, position_id)?)
+        }
         QueryMsg::BalanceWithFundingPayment { position_id } => to_binary(
             &query_trader_balance_with_funding_payment(deps, position_id)?,
         ),
@@ -326,6 +620,13 @@ pub fn query(deps: Deps, _env: Env, msg: QueryMsg) -> StdResult<Binary> {
             to_binary(&query_position_is_liquidated(deps, position_id, vamm)?)
         }
         QueryMsg::LastPositionId {} => to_binary(&query_last_position_id(deps)?),
+        QueryMsg::AdlRanking { vamm, side, limit } => {
+            to_binary(&query_adl_ranking(deps, vamm, side, limit)?)
+        }
+        QueryMsg::AdlRank { vamm, position_id } => {
+            to_binary(&query_adl_rank(deps, vamm, position_id)?)
+        }
+        QueryMsg::Backfill {} => to_binary(&query_backfill(deps)?),
     }
 }
 
@@ -335,7 +636,13 @@ pub fn reply(deps: DepsMut, env: Env, msg: Reply) -> StdResult<Response> {
         SubMsgResult::Ok(response) => match msg.id {
             INCREASE_POSITION_REPLY_ID => {
                 let (input, output, position_id) = parse_swap(response)?;
-                let response = open_position_reply(deps, env, input, output, position_id)?;
+                let response = open_position_reply(deps, env, input, output, position_id, false)?;
+                Ok(response)
+            }
+            OPEN_POSITION_BY_SIZE_REPLY_ID => {
+                // the swap gives the base for the quote, the reverse of an open
+                let (output, input, position_id) = parse_swap(response)?;
+                let response = open_position_reply(deps, env, input, output, position_id, true)?;
                 Ok(response)
             }
             CLOSE_POSITION_REPLY_ID => {
@@ -354,8 +661,15 @@ pub fn reply(deps: DepsMut, env: Env, msg: Reply) -> StdResult<Response> {
                 Ok(response)
             }
             PARTIAL_LIQUIDATION_REPLY_ID => {
-                let (input, output, position_id) = parse_swap(response)?;
-                let response = partial_liquidation_reply(deps, env, input, output, position_id)?;
+                let (base_asset_amount, quote_asset_amount, position_id) =
+                    parse_swap_amounts(response)?;
+                let response = partial_liquidation_reply(
+                    deps,
+                    env,
+                    base_asset_amount,
+                    quote_asset_amount,
+                    position_id,
+                )?;
                 Ok(response)
             }
             PAY_FUNDING_REPLY_ID => {
@@ -363,6 +677,7 @@ pub fn reply(deps: DepsMut, env: Env, msg: Reply) -> StdResult<Response> {
                 let response = pay_funding_reply(deps, env, premium_fraction, sender)?;
                 Ok(response)
             }
+            BACKSTOP_POOL_REPLY_ID => backstop_pool_reply(deps),
             _ => Err(StdError::generic_err(format!(
                 "reply (id {:?}) invalid",
                 msg.id
@@ -377,6 +692,10 @@ pub fn reply(deps: DepsMut, env: Env, msg: Reply) -> StdResult<Response> {
                 "open position failure - reply (id {:?})",
                 msg.id
             ))),
+            OPEN_POSITION_BY_SIZE_REPLY_ID => Err(StdError::generic_err(format!(
+                "open position by size failure - reply (id {:?})",
+                msg.id
+            ))),
             CLOSE_POSITION_REPLY_ID => Err(StdError::generic_err(format!(
                 "close position failure - reply (id {:?})",
                 msg.id
@@ -397,6 +716,10 @@ pub fn reply(deps: DepsMut, env: Env, msg: Reply) -> StdResult<Response> {
                 "funding payment failure - reply (id {:?})",
                 msg.id
             ))),
+            BACKSTOP_POOL_REPLY_ID => backstop_pool_failure_reply(deps),
+            id if (PAYOUT_REPLY_ID..PAYOUT_REPLY_ID + PAYOUT_REPLY_ID_RANGE).contains(&id) => {
+                payout_reply(deps, id, e.clone())
+            }
             _ => Err(StdError::generic_err(format!(
                 "reply (id {:?}) error {:?}",
                 msg.id, e
@@ -408,5 +731,27 @@ pub fn reply(deps: DepsMut, env: Env, msg: Reply) -> StdResult<Response> {
 #[cfg_attr(not(feature = "library"), entry_point)]
 pub fn migrate(deps: DepsMut, _env: Env, _msg: MigrateMsg) -> Result<Response, ContractError> {
     set_contract_version(deps.storage, CONTRACT_NAME, CONTRACT_VERSION)?;
+
+    // a config stored before the liquidation fee split existed reads it as all zero
+    let mut config = read_config(deps.storage)?;
+    if config.liquidation_fee_split == LiquidationFeeSplit::default() {
+        config.liquidation_fee_split = default_liquidation_fee_split(config.decimals);
+        store_config(deps.storage, &config)?;
+    }
+
+    // the open interest of each side of the markets is rebuilt from the live positions and the
+    // positions opened before sub-accounts existed are indexed under the main account, both in
+    // batches while the engine is paused
+    schedule_backfill(deps.storage)?;
+
     Ok(Response::new())
 }
+
+// the penalty is shared by the liquidator and the insurance fund by default
+fn default_liquidation_fee_split(decimals: Uint128) -> LiquidationFeeSplit {
+    LiquidationFeeSplit {
+        liquidator: decimals / Uint128::from(2u64),
+        insurance_fund: decimals - decimals / Uint128::from(2u64),
+        fee_pool: Uint128::zero(),
+    }
+}
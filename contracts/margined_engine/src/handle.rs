@@ -1,31 +1,46 @@
 use cosmwasm_std::{
-    Addr, DepsMut, Env, MessageInfo, Order, Response, StdError, StdResult, Storage, SubMsg, Uint128,
+    Addr, Deps, DepsMut, Env, MessageInfo, Order, Response, StdError, StdResult, Storage, SubMsg,
+    Uint128,
 };
 use margined_utils::{
-    contracts::helpers::VammController, tools::price_swap::get_output_price_with_reserves,
+    contracts::helpers::{BackstopPoolController, VammController},
+    tools::price_swap::get_output_price_with_reserves,
 };
 
 use crate::{
+    adl::{auto_deleverage, exceeds_insurance_fund, needs_auto_deleverage, update_adl_candidate},
+    blocklist::require_not_blocked,
     contract::{
-        CLOSE_POSITION_REPLY_ID, INCREASE_POSITION_REPLY_ID, LIQUIDATION_REPLY_ID,
+        BACKSTOP_LIQUIDATORS, BACKSTOP_POOL_REPLY_ID, CLOSE_POSITION_REPLY_ID,
+        INCREASE_POSITION_REPLY_ID, LIQUIDATION_REPLY_ID, OPEN_POSITION_BY_SIZE_REPLY_ID,
         PARTIAL_CLOSE_POSITION_REPLY_ID, PARTIAL_LIQUIDATION_REPLY_ID, PAY_FUNDING_REPLY_ID,
-        WHITELIST,
     },
-    messages::{execute_transfer_from, withdraw},
+    limits::{record_realized_pnl, require_trader_limits},
+    messages::{
+        execute_insurance_fund_withdrawal, execute_payout, execute_transfer, execute_transfer_from,
+        execute_transfer_to_insurance_fund, query_insurance_fund_balance, withdraw,
+        withdraw_to_subaccount,
+    },
     query::{query_free_collateral, query_margin_ratio, query_positions},
     state::{
-        increase_last_position_id, read_config, read_position, read_state, store_config,
-        store_position, store_sent_funds, store_state, store_tmp_liquidator, store_tmp_swap,
-        SentFunds, TmpReserveInfo, TmpSwapInfo,
+        increase_last_position_id, read_config, read_position, read_state, remove_position,
+        store_config, store_mark_price_policy, store_open_interest_caps, store_position,
+        store_sent_funds, store_state, store_tmp_keeper_reward, store_tmp_liquidation,
+        store_tmp_liquidator, store_tmp_swap, store_tmp_worst_price, Config, SentFunds,
+        TmpKeeperReward, TmpLiquidationInfo, TmpReserveInfo, TmpSwapInfo, MAX_TAKE_PROFIT_LEVELS,
     },
     tick::query_ticks,
     utils::{
-        calc_remain_margin_with_funding_payment, calculate_tp_sl_spread, check_tp_sl_price,
-        direction_to_side, get_asset, get_position_notional_unrealized_pnl, keccak_256,
-        position_to_side, require_additional_margin, require_bad_debt, require_insufficient_margin,
+        calc_keeper_reward, calc_liquidation_fees, calc_remain_margin_with_funding_payment,
+        calc_trailing_stop, calc_trigger_quote_asset_limit, calculate_tp_sl_spread,
+        check_tp_sl_price, direction_to_side, get_asset, get_mark_price_notional_unrealized_pnl,
+        get_position_notional_unrealized_pnl, get_tp_sl_close_price, get_trailing_price,
+        is_within_quote_asset_limit, keccak_256, more_protective_stop_loss, position_to_side,
+        realize_bad_debt, rebase_open_interest_notional, require_additional_margin,
+        require_bad_debt, require_before_deadline, require_insufficient_margin,
         require_is_not_over_price_diff_limit, require_non_zero_input, require_not_paused,
         require_not_restriction_mode, require_position_not_zero, require_vamm, side_to_direction,
-        update_reserve,
+        update_reserve, whitelist_capabilities, LiquidationFees,
     },
 };
 use margined_common::{
@@ -35,7 +50,9 @@ use margined_common::{
     validate::{validate_margin_ratios, validate_ratio},
 };
 use margined_perp::margined_engine::{
-    PnlCalcOption, Position, PositionFilter, PositionUnrealizedPnlResponse, Side,
+    KeeperReward, LiquidationFeeSplit, MarkPricePolicy, PnlCalcOption, Position, PositionFilter,
+    PositionUnrealizedPnlResponse, RemainMarginResponse, Side, TakeProfitLevel, TpSlTrigger,
+    TrailDistance, TrailingStop,
 };
 use margined_perp::margined_vamm::{CalcFeeResponse, Direction, ExecuteMsg};
 
@@ -62,6 +79,136 @@ pub fn update_operator(
     Ok(Response::default().add_attribute("action", "update_operator"))
 }
 
+pub fn update_backstop_pool(
+    deps: DepsMut,
+    info: MessageInfo,
+    backstop_pool: Option<String>,
+    discount: Uint128,
+) -> StdResult<Response> {
+    let mut config = read_config(deps.storage)?;
+
+    // check permission
+    if info.sender != config.owner {
+        return Err(StdError::generic_err("unauthorized"));
+    }
+
+    validate_ratio(discount, config.decimals)?;
+
+    // if None then liquidations go through the vamm only
+    config.backstop_pool = match backstop_pool {
+        Some(addr) => Some(deps.api.addr_validate(addr.as_str())?),
+        None => None,
+    };
+    config.backstop_discount = discount;
+
+    store_config(deps.storage, &config)?;
+
+    Ok(Response::default().add_attribute("action", "update_backstop_pool"))
+}
+
+pub fn update_liquidation_fee_split(
+    deps: DepsMut,
+    info: MessageInfo,
+    liquidation_fee_split: LiquidationFeeSplit,
+    max_liquidator_fee: Option<Uint128>,
+) -> StdResult<Response> {
+    let mut config = read_config(deps.storage)?;
+
+    // check permission
+    if info.sender != config.owner {
+        return Err(StdError::generic_err("unauthorized"));
+    }
+
+    // the whole penalty has to be distributed
+    let total = liquidation_fee_split
+        .liquidator
+        .checked_add(liquidation_fee_split.insurance_fund)?
+        .checked_add(liquidation_fee_split.fee_pool)?;
+    if total != config.decimals {
+        return Err(StdError::generic_err("Invalid liquidation fee split"));
+    }
+
+    config.liquidation_fee_split = liquidation_fee_split;
+    config.max_liquidator_fee = max_liquidator_fee;
+
+    store_config(deps.storage, &config)?;
+
+    Ok(Response::default().add_attribute("action", "update_liquidation_fee_split"))
+}
+
+pub fn update_mark_price_policy(
+    deps: DepsMut,
+    info: MessageInfo,
+    vamm: String,
+    policy: MarkPricePolicy,
+) -> StdResult<Response> {
+    let config = read_config(deps.storage)?;
+
+    // check permission
+    if info.sender != config.owner {
+        return Err(StdError::generic_err("unauthorized"));
+    }
+
+    let vamm = deps.api.addr_validate(&vamm)?;
+    require_vamm(deps.as_ref(), &config.insurance_fund, &vamm)?;
+
+    store_mark_price_policy(deps.storage, vamm.clone(), policy.clone())?;
+
+    Ok(Response::default().add_attributes(vec![
+        ("action", "update_mark_price_policy"),
+        ("vamm", vamm.as_str()),
+        ("policy", &format!("{:?}", policy)),
+    ]))
+}
+
+pub fn update_open_interest_caps(
+    deps: DepsMut,
+    info: MessageInfo,
+    vamm: String,
+    long_cap: Uint128,
+    short_cap: Uint128,
+) -> StdResult<Response> {
+    let config = read_config(deps.storage)?;
+
+    // check permission
+    if info.sender != config.owner {
+        return Err(StdError::generic_err("unauthorized"));
+    }
+
+    let vamm = deps.api.addr_validate(&vamm)?;
+    require_vamm(deps.as_ref(), &config.insurance_fund, &vamm)?;
+
+    store_open_interest_caps(deps.storage, vamm.clone(), long_cap, short_cap)?;
+
+    Ok(Response::default().add_attributes(vec![
+        ("action", "update_open_interest_caps"),
+        ("vamm", vamm.as_str()),
+        ("long_cap", &long_cap.to_string()),
+        ("short_cap", &short_cap.to_string()),
+    ]))
+}
+
+pub fn update_keeper_reward(
+    deps: DepsMut,
+    info: MessageInfo,
+    keeper_reward: KeeperReward,
+) -> StdResult<Response> {
+    let mut config = read_config(deps.storage)?;
+
+    // check permission
+    if info.sender != config.owner {
+        return Err(StdError::generic_err("unauthorized"));
+    }
+
+    validate_ratio(keeper_reward.notional_ratio, config.decimals)?;
+
+    config.keeper_reward = keeper_reward;
+
+    store_config(deps.storage, &config)?;
+
+    Ok(Response::default().add_attribute("action", "update_keeper_reward"))
+}
+
 #[allow(clippy::too_many_arguments)]
 pub fn update_config(
     deps: DepsMut,
@@ -74,6 +221,7 @@ pub fn update_config(
     partial_liquidation_ratio: Option<Uint128>,
     tp_sl_spread: Option<Uint128>,
     liquidation_fee: Option<Uint128>,
+    target_margin_ratio: Option<Uint128>,
 ) -> StdResult<Response> {
     let mut config = read_config(deps.storage)?;
 
@@ -129,6 +277,22 @@ pub fn update_config(
         config.liquidation_fee = liquidation_fee;
     }
 
+    // update target margin ratio of partial liquidations, zero uses the fixed ratio
+    if let Some(target_margin_ratio) = target_margin_ratio {
+        validate_ratio(target_margin_ratio, config.decimals)?;
+        config.target_margin_ratio = target_margin_ratio;
+    }
+
+    // the target is sized only for margin ratios above the liquidation fee, so it is checked
+    // again whenever it, the maintenance margin ratio or the liquidation fee is updated
+    if !config.target_margin_ratio.is_zero()
+        && config.target_margin_ratio <= config.maintenance_margin_ratio.max(config.liquidation_fee)
+    {
+        return Err(StdError::generic_err(
+            "Target margin ratio must be above the maintenance margin ratio and the liquidation fee",
+        ));
+    }
+
     store_config(deps.storage, &config)?;
 
     Ok(Response::default().add_attribute("action", "update_config"))
@@ -137,7 +301,7 @@ pub fn update_config(
 // Opens a position
 #[allow(clippy::too_many_arguments)]
 pub fn open_position(
-    deps: DepsMut,
+    mut deps: DepsMut,
     env: Env,
     info: MessageInfo,
     vamm: String,
@@ -147,24 +311,95 @@ pub fn open_position(
     take_profit: Option<Uint128>,
     stop_loss: Option<Uint128>,
     base_asset_limit: Uint128,
+    deadline: Option<u64>,
+    worst_price: Option<Uint128>,
+    subaccount_id: Option<u32>,
 ) -> StdResult<Response> {
+    require_before_deadline(&env, deadline)?;
+
+    let config = read_config(deps.storage)?;
+    let trader = info.sender.clone();
+    let funds = SentFunds {
+        asset: get_asset(info, config.eligible_collateral),
+        required: Uint128::zero(),
+        escrowed: false,
+        from_balance: false,
+    };
+
+    let (response, position_id) = open_position_with_funds(
+        deps.branch(),
+        env,
+        trader,
+        subaccount_id.unwrap_or_default(),
+        funds,
+        None,
+        vamm,
+        side,
+        margin_amount,
+        leverage,
+        take_profit,
+        stop_loss,
+        base_asset_limit,
+    )?;
+
+    // the reply of the position checks the average price
+    if let Some(worst_price) = worst_price {
+        store_tmp_worst_price(deps.storage, position_id, worst_price)?;
+    }
+
+    Ok(response)
+}
+
+// Opens a position for the trader paid with the given funds, these are escrowed by the engine
+// when an order is executed. Given a position id the position of the trader is increased instead,
+// the id of the opened or increased position is returned with the response
+#[allow(clippy::too_many_arguments)]
+pub fn open_position_with_funds(
+    deps: DepsMut,
+    env: Env,
+    trader: Addr,
+    subaccount_id: u32,
+    funds: SentFunds,
+    position_id: Option<u64>,
+    vamm: String,
+    side: Side,
+    margin_amount: Uint128,
+    leverage: Uint128,
+    take_profit: Option<Uint128>,
+    stop_loss: Option<Uint128>,
+    base_asset_limit: Uint128,
+) -> StdResult<(Response, u64)> {
     // validate address inputs
     let vamm = deps.api.addr_validate(&vamm)?;
     let vamm_controller = VammController(vamm.clone());
     let config = read_config(deps.storage)?;
     let state = read_state(deps.storage)?;
-    let trader = info.sender.clone();
 
     require_is_not_over_price_diff_limit(deps.as_ref(), &vamm_controller)?;
 
     require_not_paused(state.pause)?;
+    require_not_blocked(deps.storage, &trader)?;
     require_vamm(deps.as_ref(), &config.insurance_fund, &vamm)?;
 
     require_not_restriction_mode(&deps.as_ref(), &vamm, env.block.height, &trader)?;
     require_non_zero_input(margin_amount)?;
     require_non_zero_input(leverage)?;
 
-    let position_id = increase_last_position_id(deps.storage)?;
+    let is_new_position = position_id.is_none();
+    let position_id = match position_id {
+        Some(position_id) => {
+            let vamm_key = keccak_256(vamm.as_bytes());
+            let position = read_position(deps.storage, &vamm_key, position_id)?;
+            if position.trader != trader
+                || position.subaccount_id != subaccount_id
+                || position.side != side
+            {
+                return Err(StdError::generic_err("Position cannot be increased"));
+            }
+            position_id
+        }
+        None => increase_last_position_id(deps.storage)?,
+    };
 
     if leverage < config.decimals {
         return Err(StdError::generic_err("Leverage must be greater than 1"));
@@ -207,6 +442,17 @@ pub fn open_position(
         .checked_mul(leverage)?
         .checked_div(config.decimals)?;
 
+    require_trader_limits(
+        deps.as_ref(),
+        &env,
+        &trader,
+        subaccount_id,
+        &vamm,
+        is_new_position,
+        leverage,
+        open_notional,
+    )?;
+
     let entry_price =
         vamm_controller.input_price(&deps.querier, side_to_direction(&side), open_notional)?;
 
@@ -263,6 +509,150 @@ pub fn open_position(
             toll_fee,
             take_profit,
             stop_loss,
+            subaccount_id,
+            is_increase: !is_new_position,
+        },
+    )?;
+
+    store_sent_funds(deps.storage, &funds)?;
+
+    let response = Response::new().add_submessage(msg).add_attributes(vec![
+        ("action", "open_position"),
+        ("position_id", &position_id.to_string()),
+        ("position_side", &format!("{:?}", side)),
+        ("vamm", vamm.as_ref()),
+        (
+            "pair",
+            &format!("{}/{}", vamm_config.base_asset, vamm_config.quote_asset),
+        ),
+        ("trader", trader.as_ref()),
+        ("margin_amount", &margin_amount.to_string()),
+        ("leverage", &leverage.to_string()),
+        ("take_profit", &take_profit.unwrap_or_default().to_string()),
+        ("stop_loss", &stop_loss.unwrap_or_default().to_string()),
+    ]);
+
+    Ok((response, position_id))
+}
+
+// Opens a position of an exact base size, the notional and fees are known before the swap so the
+// margin left after fees has to cover the initial margin ratio of the implied leverage
+#[allow(clippy::too_many_arguments)]
+pub fn open_position_by_size(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    vamm: String,
+    side: Side,
+    base_size: Uint128,
+    margin_amount: Uint128,
+    subaccount_id: Option<u32>,
+) -> StdResult<Response> {
+    // validate address inputs
+    let vamm = deps.api.addr_validate(&vamm)?;
+    let vamm_controller = VammController(vamm.clone());
+    let config = read_config(deps.storage)?;
+    let state = read_state(deps.storage)?;
+    let trader = info.sender.clone();
+    let subaccount_id = subaccount_id.unwrap_or_default();
+
+    require_is_not_over_price_diff_limit(deps.as_ref(), &vamm_controller)?;
+
+    require_not_paused(state.pause)?;
+    require_not_blocked(deps.storage, &trader)?;
+    require_vamm(deps.as_ref(), &config.insurance_fund, &vamm)?;
+
+    require_not_restriction_mode(&deps.as_ref(), &vamm, env.block.height, &trader)?;
+    require_non_zero_input(base_size)?;
+    require_non_zero_input(margin_amount)?;
+
+    let vamm_config = vamm_controller.config(&deps.querier)?;
+
+    // a long removes the base from the amm and a short adds it
+    let base_direction = match side {
+        Side::Buy => Direction::RemoveFromAmm,
+        Side::Sell => Direction::AddToAmm,
+    };
+    let open_notional = vamm_controller.output_amount(&deps.querier, base_direction, base_size)?;
+    require_non_zero_input(open_notional)?;
+
+    let CalcFeeResponse {
+        spread_fee,
+        toll_fee,
+    } = vamm_controller.calc_fee(&deps.querier, open_notional)?;
+
+    // calculate the margin left after fees
+    let new_margin_amount = margin_amount
+        .checked_sub(spread_fee)
+        .and_then(|margin| margin.checked_sub(toll_fee))
+        .map_err(|_| StdError::generic_err("Margin does not cover the fees"))?;
+    require_non_zero_input(new_margin_amount)?;
+
+    if new_margin_amount > open_notional {
+        return Err(StdError::generic_err("Leverage must be greater than 1"));
+    }
+
+    let margin_ratio = new_margin_amount
+        .checked_mul(config.decimals)?
+        .checked_div(open_notional)?;
+
+    require_additional_margin(
+        Integer::from(margin_ratio),
+        Uint128::max(
+            config.initial_margin_ratio,
+            vamm_config.initial_margin_ratio,
+        ),
+    )?;
+
+    // the leverage is rounded up for the limits, the reply keeps the exact margin paid
+    let leverage = open_notional
+        .checked_mul(config.decimals)?
+        .checked_add(new_margin_amount)?
+        .checked_sub(Uint128::one())?
+        .checked_div(new_margin_amount)?;
+
+    require_trader_limits(
+        deps.as_ref(),
+        &env,
+        &trader,
+        subaccount_id,
+        &vamm,
+        true,
+        leverage,
+        open_notional,
+    )?;
+
+    let position_id = increase_last_position_id(deps.storage)?;
+
+    let msg = swap_output(
+        &vamm,
+        &direction_to_side(&base_direction),
+        position_id,
+        base_size,
+        open_notional,
+        OPEN_POSITION_BY_SIZE_REPLY_ID,
+    )?;
+
+    store_tmp_swap(
+        deps.storage,
+        &TmpSwapInfo {
+            position_id,
+            vamm: vamm.clone(),
+            pair: format!("{}/{}", vamm_config.base_asset, vamm_config.quote_asset),
+            trader: trader.clone(),
+            side,
+            margin_amount: new_margin_amount,
+            leverage,
+            open_notional,
+            position_notional: Uint128::zero(),
+            unrealized_pnl: Integer::zero(),
+            margin_to_vault: Integer::zero(),
+            spread_fee,
+            toll_fee,
+            take_profit: None,
+            stop_loss: None,
+            subaccount_id,
+            is_increase: false,
         },
     )?;
 
@@ -271,11 +661,13 @@ pub fn open_position(
         &SentFunds {
             asset: get_asset(info, config.eligible_collateral),
             required: Uint128::zero(),
+            escrowed: false,
+            from_balance: false,
         },
     )?;
 
     Ok(Response::new().add_submessage(msg).add_attributes(vec![
-        ("action", "open_position"),
+        ("action", "open_position_by_size"),
         ("position_id", &position_id.to_string()),
         ("position_side", &format!("{:?}", side)),
         ("vamm", vamm.as_ref()),
@@ -284,10 +676,10 @@ pub fn open_position(
             &format!("{}/{}", vamm_config.base_asset, vamm_config.quote_asset),
         ),
         ("trader", trader.as_ref()),
+        ("base_size", &base_size.to_string()),
         ("margin_amount", &margin_amount.to_string()),
+        ("open_notional", &open_notional.to_string()),
         ("leverage", &leverage.to_string()),
-        ("take_profit", &take_profit.unwrap_or_default().to_string()),
-        ("stop_loss", &stop_loss.unwrap_or_default().to_string()),
     ]))
 }
 
@@ -369,27 +761,477 @@ pub fn update_tp_sl(
     ]))
 }
 
-pub fn close_position(
+pub fn update_tp_sl_trigger(
     deps: DepsMut,
-    env: Env,
     info: MessageInfo,
     vamm: String,
     position_id: u64,
-    quote_amount_limit: Uint128,
+    tp_sl_trigger: TpSlTrigger,
 ) -> StdResult<Response> {
-    // read configuration and state information
-    let config = read_config(deps.storage)?;
-    let state = read_state(deps.storage)?;
-
-    // validate address inputs
     let vamm = deps.api.addr_validate(&vamm)?;
-    let trader = info.sender;
 
     // read the position for the trader from vamm
     let vamm_key = keccak_256(vamm.as_bytes());
-    let position = read_position(deps.storage, &vamm_key, position_id)?;
+    let mut position = read_position(deps.storage, &vamm_key, position_id)?;
 
-    if position.trader != trader {
+    let state = read_state(deps.storage)?;
+    require_not_paused(state.pause)?;
+    require_position_not_zero(position.size.value)?;
+
+    if position.trader != info.sender {
+        return Err(StdError::generic_err("Unauthorized"));
+    }
+
+    position.tp_sl_trigger = tp_sl_trigger;
+
+    store_position(deps.storage, &vamm_key, &position, false)?;
+
+    Ok(Response::new().add_attributes(vec![
+        ("action", "update_tp_sl_trigger"),
+        ("vamm", vamm.as_ref()),
+        ("position_id", &position_id.to_string()),
+        ("tp_sl_trigger", &format!("{:?}", position.tp_sl_trigger)),
+    ]))
+}
+
+pub fn update_trailing_stop(
+    deps: DepsMut,
+    info: MessageInfo,
+    vamm: String,
+    position_id: u64,
+    distance: Option<TrailDistance>,
+) -> StdResult<Response> {
+    let vamm = deps.api.addr_validate(&vamm)?;
+    let config = read_config(deps.storage)?;
+
+    // read the position for the trader from vamm
+    let vamm_key = keccak_256(vamm.as_bytes());
+    let mut position = read_position(deps.storage, &vamm_key, position_id)?;
+
+    let state = read_state(deps.storage)?;
+    require_not_paused(state.pause)?;
+    require_position_not_zero(position.size.value)?;
+
+    if position.trader != info.sender {
+        return Err(StdError::generic_err("Unauthorized"));
+    }
+
+    // if None then the trailing stop is removed, the stop loss stays where it was
+    position.trailing_stop = match distance {
+        Some(distance) => {
+            match distance {
+                TrailDistance::Absolute(amount) => {
+                    require_non_zero_input(amount)?;
+                }
+                TrailDistance::Ratio(ratio) => {
+                    require_non_zero_input(ratio)?;
+                    validate_ratio(ratio, config.decimals)?;
+                }
+            }
+
+            // a stop loss tighter than the trailing one is kept
+            let water_mark = get_trailing_price(deps.as_ref(), &position)?;
+            let stop_loss =
+                calc_trailing_stop(&position.side, water_mark, &distance, config.decimals)?;
+            position.stop_loss = Some(more_protective_stop_loss(
+                &position.side,
+                position.stop_loss,
+                stop_loss,
+            ));
+
+            Some(TrailingStop {
+                distance,
+                water_mark,
+            })
+        }
+        None => None,
+    };
+
+    store_position(deps.storage, &vamm_key, &position, false)?;
+
+    Ok(Response::new().add_attributes(vec![
+        ("action", "update_trailing_stop"),
+        ("vamm", vamm.as_ref()),
+        ("position_id", &position_id.to_string()),
+        (
+            "stop_loss",
+            &position.stop_loss.unwrap_or_default().to_string(),
+        ),
+    ]))
+}
+
+/// Moves the stop loss of a position with its trailing stop, callable by anyone
+pub fn ratchet_trailing_stop(deps: DepsMut, vamm: String, position_id: u64) -> StdResult<Response> {
+    let vamm = deps.api.addr_validate(&vamm)?;
+    let config = read_config(deps.storage)?;
+
+    // read the position for the trader from vamm
+    let vamm_key = keccak_256(vamm.as_bytes());
+    let mut position = read_position(deps.storage, &vamm_key, position_id)?;
+
+    let state = read_state(deps.storage)?;
+    require_not_paused(state.pause)?;
+    require_position_not_zero(position.size.value)?;
+
+    let mut trailing_stop = position
+        .trailing_stop
+        .clone()
+        .ok_or_else(|| StdError::generic_err("Position has no trailing stop"))?;
+
+    // the water mark and the stop loss only move in favour of the trader
+    let price = get_trailing_price(deps.as_ref(), &position)?;
+    let improved = match position.side {
+        Side::Buy => price > trailing_stop.water_mark,
+        Side::Sell => price < trailing_stop.water_mark,
+    };
+
+    if improved {
+        trailing_stop.water_mark = price;
+
+        let stop_loss = calc_trailing_stop(
+            &position.side,
+            price,
+            &trailing_stop.distance,
+            config.decimals,
+        )?;
+        position.stop_loss = Some(more_protective_stop_loss(
+            &position.side,
+            position.stop_loss,
+            stop_loss,
+        ));
+        position.trailing_stop = Some(trailing_stop.clone());
+
+        store_position(deps.storage, &vamm_key, &position, false)?;
+    }
+
+    Ok(Response::new().add_attributes(vec![
+        ("action", "ratchet_trailing_stop"),
+        ("vamm", vamm.as_ref()),
+        ("position_id", &position_id.to_string()),
+        ("water_mark", &trailing_stop.water_mark.to_string()),
+        (
+            "stop_loss",
+            &position.stop_loss.unwrap_or_default().to_string(),
+        ),
+    ]))
+}
+
+pub fn update_take_profit_levels(
+    deps: DepsMut,
+    info: MessageInfo,
+    vamm: String,
+    position_id: u64,
+    mut levels: Vec<TakeProfitLevel>,
+) -> StdResult<Response> {
+    let vamm = deps.api.addr_validate(&vamm)?;
+    let config = read_config(deps.storage)?;
+
+    // read the position for the trader from vamm
+    let vamm_key = keccak_256(vamm.as_bytes());
+    let mut position = read_position(deps.storage, &vamm_key, position_id)?;
+
+    let state = read_state(deps.storage)?;
+    require_not_paused(state.pause)?;
+    require_position_not_zero(position.size.value)?;
+
+    if position.trader != info.sender {
+        return Err(StdError::generic_err("Unauthorized"));
+    }
+
+    // an empty ladder removes the take profit levels
+    if !levels.is_empty() {
+        if levels.len() > MAX_TAKE_PROFIT_LEVELS {
+            return Err(StdError::generic_err("Too many take profit levels"));
+        }
+
+        let mut total_fraction = Uint128::zero();
+        for level in &levels {
+            require_non_zero_input(level.fraction)?;
+            total_fraction = total_fraction.checked_add(level.fraction)?;
+
+            match position.side {
+                Side::Buy => {
+                    if level.price <= position.entry_price {
+                        return Err(StdError::generic_err("TP price is too low"));
+                    }
+                }
+                Side::Sell => {
+                    if level.price >= position.entry_price {
+                        return Err(StdError::generic_err("TP price is too high"));
+                    }
+                }
+            }
+        }
+
+        if total_fraction != config.decimals {
+            return Err(StdError::generic_err(
+                "Take profit fractions must add up to the whole position",
+            ));
+        }
+
+        // the nearest level is triggered first
+        match position.side {
+            Side::Buy => levels.sort_by(|a, b| a.price.cmp(&b.price)),
+            Side::Sell => levels.sort_by(|a, b| b.price.cmp(&a.price)),
+        }
+    }
+
+    position.take_profit_levels = levels;
+
+    store_position(deps.storage, &vamm_key, &position, false)?;
+
+    Ok(Response::new().add_attributes(vec![
+        ("action", "update_take_profit_levels"),
+        ("vamm", vamm.as_ref()),
+        ("position_id", &position_id.to_string()),
+        (
+            "take_profit_levels",
+            &position.take_profit_levels.len().to_string(),
+        ),
+    ]))
+}
+
+/// Closes the share of the nearest take profit level of a position once its price is reached,
+/// the last level closes whatever is left of the position
+pub fn trigger_take_profit_level(
+    deps: DepsMut,
+    info: MessageInfo,
+    vamm: String,
+    position_id: u64,
+) -> StdResult<Response> {
+    let config = read_config(deps.storage)?;
+    let vamm = deps.api.addr_validate(&vamm)?;
+
+    let vamm_controller = VammController(vamm.clone());
+    let vamm_state = vamm_controller.state(&deps.querier)?;
+
+    // read the position for the trader from vamm
+    let vamm_key = keccak_256(vamm.as_bytes());
+    let position = read_position(deps.storage, &vamm_key, position_id)?;
+
+    // check that vamm is open
+    if !vamm_state.open {
+        return Err(StdError::generic_err("vAMM is not open"));
+    }
+
+    let state = read_state(deps.storage)?;
+    require_not_paused(state.pause)?;
+    require_position_not_zero(position.size.value)?;
+
+    let reserve = TmpReserveInfo {
+        quote_asset_reserve: vamm_state.quote_asset_reserve,
+        base_asset_reserve: vamm_state.base_asset_reserve,
+    };
+    let level_close = check_take_profit_level(deps.as_ref(), &config, &position, &reserve)?;
+
+    let keeper_reward = calc_keeper_reward(
+        &config.keeper_reward,
+        config.decimals,
+        level_close.quote_asset_amount,
+        Uint128::zero(),
+    )?;
+    if !keeper_reward.is_zero() {
+        store_tmp_keeper_reward(
+            deps.storage,
+            position_id,
+            &TmpKeeperReward {
+                keeper: info.sender,
+                amount: keeper_reward,
+            },
+        )?;
+    }
+
+    let msg = take_profit_level_msg(deps, &config, position, &level_close)?;
+
+    Ok(Response::new().add_submessage(msg).add_attributes(vec![
+        ("action", "trigger_take_profit_level"),
+        ("vamm", vamm.as_ref()),
+        ("position_id", &position_id.to_string()),
+        ("take_profit", &level_close.level.price.to_string()),
+        ("close_amount", &level_close.close_amount.to_string()),
+        ("keeper_reward", &keeper_reward.to_string()),
+    ]))
+}
+
+struct TakeProfitLevelClose {
+    level: TakeProfitLevel,
+    close_amount: Uint128,
+    quote_asset_amount: Uint128,
+    quote_asset_limit: Uint128,
+    position_notional: Uint128,
+    unrealized_pnl: Integer,
+}
+
+/// Checks that the nearest take profit level of a position is reached at the given reserves,
+/// and that closing its share does not slip past the level price by more than the price diff
+/// limit of the vamm
+fn check_take_profit_level(
+    deps: Deps,
+    config: &Config,
+    position: &Position,
+    reserve: &TmpReserveInfo,
+) -> StdResult<TakeProfitLevelClose> {
+    let level = position
+        .take_profit_levels
+        .first()
+        .cloned()
+        .ok_or_else(|| StdError::generic_err("Position has no take profit levels"))?;
+
+    // the level closes its fraction out of the fractions that are left
+    let remaining_fraction = position
+        .take_profit_levels
+        .iter()
+        .try_fold(Uint128::zero(), |acc, l| acc.checked_add(l.fraction))?;
+    let close_amount = position
+        .size
+        .value
+        .multiply_ratio(level.fraction, remaining_fraction);
+
+    let quote_asset_amount = get_output_price_with_reserves(
+        &position.direction,
+        close_amount,
+        reserve.quote_asset_reserve,
+        reserve.base_asset_reserve,
+    )?;
+    let (close_price, _) =
+        get_tp_sl_close_price(deps, position, close_amount, quote_asset_amount, true)?;
+
+    let (tp_spread, _) = calculate_tp_sl_spread(
+        config.tp_sl_spread,
+        level.price,
+        Uint128::zero(),
+        config.decimals,
+    )?;
+    let tp_sl_action = check_tp_sl_price(
+        close_price,
+        level.price,
+        Uint128::zero(),
+        tp_spread,
+        Uint128::zero(),
+        &position.side,
+    )?;
+    if tp_sl_action != "trigger_take_profit" {
+        return Err(StdError::generic_err("Take profit level is not reached"));
+    }
+
+    let quote_asset_limit =
+        calc_trigger_quote_asset_limit(deps, position, level.price, close_amount)?;
+    if !is_within_quote_asset_limit(&position.side, quote_asset_amount, quote_asset_limit) {
+        return Err(StdError::generic_err("Over take profit slippage limit"));
+    }
+
+    // the pnl of the whole position at the same reserves, the reply realizes its share
+    let position_notional = get_output_price_with_reserves(
+        &position.direction,
+        position.size.value,
+        reserve.quote_asset_reserve,
+        reserve.base_asset_reserve,
+    )?;
+    let unrealized_pnl = match position.direction {
+        Direction::AddToAmm => {
+            Integer::new_positive(position_notional) - Integer::new_positive(position.notional)
+        }
+        Direction::RemoveFromAmm => {
+            Integer::new_positive(position.notional) - Integer::new_positive(position_notional)
+        }
+    };
+
+    Ok(TakeProfitLevelClose {
+        level,
+        close_amount,
+        quote_asset_amount,
+        quote_asset_limit,
+        position_notional,
+        unrealized_pnl,
+    })
+}
+
+/// Removes the triggered level from the position and closes its share, through the partial
+/// close reply unless it is the last level
+fn take_profit_level_msg(
+    deps: DepsMut,
+    config: &Config,
+    mut position: Position,
+    level_close: &TakeProfitLevelClose,
+) -> StdResult<SubMsg> {
+    let vamm_key = keccak_256(position.vamm.as_bytes());
+    position.take_profit_levels.remove(0);
+
+    if position.take_profit_levels.is_empty() {
+        return internal_close_position(
+            deps.storage,
+            &position,
+            level_close.quote_asset_limit,
+            CLOSE_POSITION_REPLY_ID,
+        );
+    }
+
+    let side = position_to_side(position.size);
+    store_tmp_swap(
+        deps.storage,
+        &TmpSwapInfo {
+            position_id: position.position_id,
+            vamm: position.vamm.clone(),
+            pair: position.pair.clone(),
+            trader: position.trader.clone(),
+            side,
+            margin_amount: position.size.value,
+            leverage: config.decimals,
+            open_notional: level_close.quote_asset_amount,
+            position_notional: level_close.position_notional,
+            unrealized_pnl: level_close.unrealized_pnl,
+            margin_to_vault: Integer::zero(),
+            spread_fee: position
+                .spread_fee
+                .multiply_ratio(level_close.close_amount, position.size.value),
+            toll_fee: position
+                .toll_fee
+                .multiply_ratio(level_close.close_amount, position.size.value),
+            take_profit: position.take_profit,
+            stop_loss: position.stop_loss,
+            subaccount_id: position.subaccount_id,
+            is_increase: false,
+        },
+    )?;
+    store_position(deps.storage, &vamm_key, &position, false)?;
+
+    swap_input(
+        &position.vamm,
+        &side,
+        position.position_id,
+        level_close.quote_asset_amount,
+        Uint128::zero(),
+        true,
+        PARTIAL_CLOSE_POSITION_REPLY_ID,
+    )
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn close_position(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    vamm: String,
+    position_id: u64,
+    quote_amount_limit: Uint128,
+    deadline: Option<u64>,
+    worst_price: Option<Uint128>,
+) -> StdResult<Response> {
+    require_before_deadline(&env, deadline)?;
+
+    // read configuration and state information
+    let config = read_config(deps.storage)?;
+    let state = read_state(deps.storage)?;
+
+    // validate address inputs
+    let vamm = deps.api.addr_validate(&vamm)?;
+    let trader = info.sender;
+
+    // read the position for the trader from vamm
+    let vamm_key = keccak_256(vamm.as_bytes());
+    let position = read_position(deps.storage, &vamm_key, position_id)?;
+
+    if position.trader != trader {
         return Err(StdError::generic_err("Unauthorized"));
     }
 
@@ -401,6 +1243,10 @@ pub fn close_position(
     require_position_not_zero(position.size.value)?;
     require_not_restriction_mode(&deps.as_ref(), &vamm, env.block.height, &trader)?;
 
+    if let Some(worst_price) = worst_price {
+        store_tmp_worst_price(deps.storage, position_id, worst_price)?;
+    }
+
     // if it is long position, close a position means short it (which means base dir is AddToAmm) and vice versa
     let base_direction = if position.size > Integer::zero() {
         Direction::AddToAmm
@@ -456,6 +1302,8 @@ pub fn close_position(
                 toll_fee: position.toll_fee,
                 take_profit: position.take_profit,
                 stop_loss: position.stop_loss,
+                subaccount_id: position.subaccount_id,
+                is_increase: false,
             },
         )?;
 
@@ -499,6 +1347,7 @@ pub fn close_position(
 
 pub fn trigger_tp_sl(
     deps: DepsMut,
+    info: MessageInfo,
     vamm: String,
     position_id: u64,
     do_tp: bool,
@@ -531,9 +1380,13 @@ pub fn trigger_tp_sl(
         vamm_state.quote_asset_reserve,
         vamm_state.base_asset_reserve,
     )?;
-    let close_price = quote_asset_amount
-        .checked_mul(config.decimals)?
-        .checked_div(base_asset_amount)?;
+    let (close_price, quote_asset_limit) = get_tp_sl_close_price(
+        deps.as_ref(),
+        &position,
+        base_asset_amount,
+        quote_asset_amount,
+        do_tp,
+    )?;
 
     let stop_loss = position.stop_loss.unwrap_or_default();
     let take_profit = position.take_profit.unwrap_or_default();
@@ -556,10 +1409,27 @@ pub fn trigger_tp_sl(
     };
 
     if tp_sl_flag {
+        let keeper_reward = calc_keeper_reward(
+            &config.keeper_reward,
+            config.decimals,
+            quote_asset_amount,
+            Uint128::zero(),
+        )?;
+        if !keeper_reward.is_zero() {
+            store_tmp_keeper_reward(
+                deps.storage,
+                position_id,
+                &TmpKeeperReward {
+                    keeper: info.sender,
+                    amount: keeper_reward,
+                },
+            )?;
+        }
+
         msgs.push(internal_close_position(
             deps.storage,
             &position,
-            Uint128::zero(),
+            quote_asset_limit,
             CLOSE_POSITION_REPLY_ID,
         )?);
     }
@@ -577,7 +1447,8 @@ pub fn trigger_tp_sl(
 }
 
 pub fn trigger_mutiple_tp_sl(
-    deps: DepsMut,
+    mut deps: DepsMut,
+    info: MessageInfo,
     vamm: String,
     side: Side,
     do_tp: bool,
@@ -612,6 +1483,8 @@ pub fn trigger_mutiple_tp_sl(
     };
 
     let vamm_key = keccak_256(vamm.as_bytes());
+    // the keeper rewards of all the positions are capped together
+    let mut keeper_rewards = Uint128::zero();
 
     let ticks = query_ticks(
         deps.storage,
@@ -637,6 +1510,48 @@ pub fn trigger_mutiple_tp_sl(
             // check the position isn't zero
             require_position_not_zero(position.size.value)?;
 
+            // positions with a take profit ladder close the share of their nearest level
+            if do_tp && !position.take_profit_levels.is_empty() {
+                let level_close =
+                    match check_take_profit_level(deps.as_ref(), &config, position, &tmp_reserve) {
+                        Ok(level_close) => level_close,
+                        Err(_) => continue,
+                    };
+
+                let _ = update_reserve(
+                    &mut tmp_reserve,
+                    level_close.quote_asset_amount,
+                    level_close.close_amount,
+                    &position.direction,
+                );
+
+                let keeper_reward = calc_keeper_reward(
+                    &config.keeper_reward,
+                    config.decimals,
+                    level_close.quote_asset_amount,
+                    keeper_rewards,
+                )?;
+                if !keeper_reward.is_zero() {
+                    keeper_rewards = keeper_rewards.checked_add(keeper_reward)?;
+                    store_tmp_keeper_reward(
+                        deps.storage,
+                        position.position_id,
+                        &TmpKeeperReward {
+                            keeper: info.sender.clone(),
+                            amount: keeper_reward,
+                        },
+                    )?;
+                }
+
+                msgs.push(take_profit_level_msg(
+                    deps.branch(),
+                    &config,
+                    position.clone(),
+                    &level_close,
+                )?);
+                continue;
+            }
+
             let base_asset_amount = position.size.value;
             let quote_asset_amount = get_output_price_with_reserves(
                 &position.direction,
@@ -644,9 +1559,13 @@ pub fn trigger_mutiple_tp_sl(
                 tmp_reserve.quote_asset_reserve,
                 tmp_reserve.base_asset_reserve,
             )?;
-            let close_price = quote_asset_amount
-                .checked_mul(config.decimals)?
-                .checked_div(base_asset_amount)?;
+            let (close_price, quote_asset_limit) = get_tp_sl_close_price(
+                deps.as_ref(),
+                position,
+                base_asset_amount,
+                quote_asset_amount,
+                do_tp,
+            )?;
 
             let stop_loss = position.stop_loss.unwrap_or_default();
             let take_profit = position.take_profit.unwrap_or_default();
@@ -671,17 +1590,43 @@ pub fn trigger_mutiple_tp_sl(
                 tp_sl_action == "trigger_stop_loss"
             };
 
-            if tp_sl_flag {
+            // positions whose close would slip past the limit are left open
+            if tp_sl_flag
+                && is_within_quote_asset_limit(
+                    &position.side,
+                    quote_asset_amount,
+                    quote_asset_limit,
+                )
+            {
                 let _ = update_reserve(
                     &mut tmp_reserve,
                     quote_asset_amount,
                     base_asset_amount,
                     &position.direction,
                 );
+
+                let keeper_reward = calc_keeper_reward(
+                    &config.keeper_reward,
+                    config.decimals,
+                    quote_asset_amount,
+                    keeper_rewards,
+                )?;
+                if !keeper_reward.is_zero() {
+                    keeper_rewards = keeper_rewards.checked_add(keeper_reward)?;
+                    store_tmp_keeper_reward(
+                        deps.storage,
+                        position.position_id,
+                        &TmpKeeperReward {
+                            keeper: info.sender.clone(),
+                            amount: keeper_reward,
+                        },
+                    )?;
+                }
+
                 msgs.push(internal_close_position(
                     deps.storage,
                     position,
-                    Uint128::zero(),
+                    quote_asset_limit,
                     CLOSE_POSITION_REPLY_ID,
                 )?);
             }
@@ -703,86 +1648,542 @@ pub fn trigger_mutiple_tp_sl(
         ]))
 }
 
+#[allow(clippy::too_many_arguments)]
 pub fn liquidate(
     deps: DepsMut,
-    _env: Env,
+    env: Env,
     info: MessageInfo,
     vamm: String,
     position_id: u64,
     quote_asset_limit: Uint128,
+    deadline: Option<u64>,
+    worst_price: Option<Uint128>,
 ) -> StdResult<Response> {
+    require_before_deadline(&env, deadline)?;
+
     let config = read_config(deps.storage)?;
     let state = read_state(deps.storage)?;
     require_not_paused(state.pause)?;
     // validate address inputs
     let vamm = deps.api.addr_validate(&vamm)?;
 
-    // read the position for the trader from vamm
-    let vamm_key = keccak_256(vamm.as_bytes());
-    let position = read_position(deps.storage, &vamm_key, position_id)?;
+    // read the position for the trader from vamm
+    let vamm_key = keccak_256(vamm.as_bytes());
+    let position = read_position(deps.storage, &vamm_key, position_id)?;
+
+    // check if the trader is protected from liquidation by the whitelist
+    if whitelist_capabilities(deps.as_ref(), &position.trader)?.liquidation_protection
+        && info.sender != position.trader
+    {
+        return Err(StdError::generic_err("trader is whitelisted"));
+    }
+
+    // store the liquidator
+    store_tmp_liquidator(deps.storage, &info.sender)?;
+
+    // retrieve the existing margin ratio of the position at the mark price policy of the vamm
+    let margin_ratio = query_margin_ratio(deps.as_ref(), &position)?;
+
+    require_vamm(deps.as_ref(), &config.insurance_fund, &vamm)?;
+    require_insufficient_margin(margin_ratio, config.maintenance_margin_ratio)?;
+
+    // check the position isn't zero
+    require_position_not_zero(position.size.value)?;
+
+    // if the insurance fund cannot cover the bad debt, deleverage the opposite side instead
+    if needs_auto_deleverage(deps.as_ref(), &position)? {
+        return Ok(auto_deleverage(deps, env, &position)?.add_attributes(vec![
+            ("margin_ratio", &margin_ratio.to_string()),
+            (
+                "maintenance_margin_ratio",
+                &config.maintenance_margin_ratio.to_string(),
+            ),
+        ]));
+    }
+
+    // checked when the position is liquidated through the vAMM
+    if let Some(worst_price) = worst_price {
+        store_tmp_worst_price(deps.storage, position_id, worst_price)?;
+    }
+
+    // the backstop pool takes over the position first, the vAMM is only used if it fails
+    let msg = match &config.backstop_pool {
+        Some(backstop_pool) => {
+            store_tmp_liquidation(
+                deps.storage,
+                &TmpLiquidationInfo {
+                    vamm: vamm.clone(),
+                    position_id,
+                    quote_asset_limit,
+                },
+            )?;
+
+            SubMsg::reply_always(
+                BackstopPoolController(backstop_pool.clone())
+                    .absorb(vamm.to_string(), position_id)?,
+                BACKSTOP_POOL_REPLY_ID,
+            )
+        }
+        None => liquidation_msg(deps, &position, margin_ratio, quote_asset_limit)?,
+    };
+
+    Ok(Response::new().add_submessage(msg).add_attributes(vec![
+        ("action", "liquidate"),
+        ("vamm", vamm.as_ref()),
+        ("pair", &position.pair),
+        ("position_id", &position_id.to_string()),
+        ("margin_ratio", &margin_ratio.to_string()),
+        (
+            "maintenance_margin_ratio",
+            &config.maintenance_margin_ratio.to_string(),
+        ),
+        ("trader", &position.trader.as_ref()),
+    ]))
+}
+
+/// Liquidates the position through the vAMM, partially if the margin ratio
+/// is above the liquidation fee, else get rekt
+pub fn liquidation_msg(
+    deps: DepsMut,
+    position: &Position,
+    margin_ratio: Integer,
+    quote_asset_limit: Uint128,
+) -> StdResult<SubMsg> {
+    let config = read_config(deps.storage)?;
+
+    let partial_position_size = if margin_ratio.value <= config.liquidation_fee {
+        None
+    } else if !config.target_margin_ratio.is_zero() {
+        calc_target_liquidation_size(deps.as_ref(), position, config.target_margin_ratio)?
+    } else if !config.partial_liquidation_ratio.is_zero() {
+        Some(
+            position
+                .size
+                .value
+                .checked_mul(config.partial_liquidation_ratio)?
+                .checked_div(config.decimals)?,
+        )
+    } else {
+        None
+    };
+
+    match partial_position_size {
+        Some(partial_position_size) => partial_liquidation(
+            deps,
+            &position.vamm,
+            position,
+            quote_asset_limit,
+            partial_position_size,
+        ),
+        None => internal_close_position(
+            deps.storage,
+            position,
+            quote_asset_limit,
+            LIQUIDATION_REPLY_ID,
+        ),
+    }
+}
+
+/// Finds the smallest size to close that brings the margin ratio of the position at the mark
+/// price policy back to the target, returns None if only closing the whole position is enough
+fn calc_target_liquidation_size(
+    deps: Deps,
+    position: &Position,
+    target_margin_ratio: Uint128,
+) -> StdResult<Option<Uint128>> {
+    let config = read_config(deps.storage)?;
+    let vamm_state = VammController(position.vamm.clone()).state(&deps.querier)?;
+
+    // the partial liquidation realizes its share of the pnl at the mark price policy
+    let PositionUnrealizedPnlResponse {
+        position_notional,
+        unrealized_pnl,
+    } = get_mark_price_notional_unrealized_pnl(deps, position)?;
+
+    // books the close of the size as the partial liquidation reply does, with the size closed on
+    // the vamm curve, and checks the margin ratio of what is left of the position
+    let is_healthy = |size: Uint128| -> StdResult<bool> {
+        if size >= position.size.value {
+            return Ok(true);
+        }
+
+        let closed_notional = get_output_price_with_reserves(
+            &position.direction,
+            size,
+            vamm_state.quote_asset_reserve,
+            vamm_state.base_asset_reserve,
+        )?;
+        let mut reserve = TmpReserveInfo {
+            quote_asset_reserve: vamm_state.quote_asset_reserve,
+            base_asset_reserve: vamm_state.base_asset_reserve,
+        };
+        update_reserve(&mut reserve, closed_notional, size, &position.direction)?;
+
+        let realized_pnl = (unrealized_pnl * Integer::new_positive(size))
+            / Integer::new_positive(position.size.value);
+        let liquidation_penalty = closed_notional
+            .checked_mul(config.liquidation_fee)?
+            .checked_div(config.decimals)?;
+
+        let mut remaining = position.clone();
+        remaining.size = match position.direction {
+            Direction::AddToAmm => position.size - Integer::new_positive(size),
+            Direction::RemoveFromAmm => position.size + Integer::new_positive(size),
+        };
+        remaining.margin = match position
+            .margin
+            .checked_sub(realized_pnl.value)
+            .and_then(|margin| margin.checked_sub(liquidation_penalty))
+        {
+            Ok(margin) => margin,
+            Err(_) => return Ok(false),
+        };
+        remaining.notional = match position.direction {
+            Direction::AddToAmm => position
+                .notional
+                .checked_sub(closed_notional)
+                .and_then(|notional| notional.checked_sub(realized_pnl.value)),
+            Direction::RemoveFromAmm => realized_pnl
+                .value
+                .checked_add(position.notional)
+                .and_then(|notional| notional.checked_sub(closed_notional)),
+        }
+        .unwrap_or_default();
+
+        // the size left is valued at the less healthy of the spot price moved by the close and
+        // the mark price, so that the target holds whichever of them the policy reads
+        let remaining_size = remaining.size.value;
+        let spot_notional = get_output_price_with_reserves(
+            &position.direction,
+            remaining_size,
+            reserve.quote_asset_reserve,
+            reserve.base_asset_reserve,
+        )?;
+        let mark_notional = position_notional.multiply_ratio(remaining_size, position.size.value);
+        let (remaining_notional, remaining_pnl) = match position.direction {
+            Direction::AddToAmm => {
+                let notional = spot_notional.min(mark_notional);
+                (
+                    notional,
+                    Integer::new_positive(notional) - Integer::new_positive(remaining.notional),
+                )
+            }
+            Direction::RemoveFromAmm => {
+                let notional = spot_notional.max(mark_notional);
+                (
+                    notional,
+                    Integer::new_positive(remaining.notional) - Integer::new_positive(notional),
+                )
+            }
+        };
+
+        let remain_margin =
+            calc_remain_margin_with_funding_payment(deps, &remaining, remaining_pnl)?;
+        if remain_margin.margin <= remain_margin.bad_debt {
+            return Ok(false);
+        }
+
+        Ok(remain_margin
+            .margin
+            .checked_sub(remain_margin.bad_debt)?
+            .checked_mul(config.decimals)?
+            >= remaining_notional.checked_mul(target_margin_ratio)?)
+    };
+
+    // the margin ratio grows with the size closed while it is above the liquidation fee
+    let mut low = Uint128::zero();
+    let mut high = position.size.value;
+    while high.checked_sub(low)? > Uint128::from(1u64) {
+        let mid = low + (high - low) / Uint128::from(2u64);
+        if is_healthy(mid)? {
+            high = mid;
+        } else {
+            low = mid;
+        }
+    }
+
+    if high == position.size.value {
+        Ok(None)
+    } else {
+        Ok(Some(high))
+    }
+}
+
+/// Allows a registered backstop liquidator to take over a position at the
+/// mark price instead of closing it through the vAMM
+pub fn backstop_liquidate(
+    mut deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    vamm: String,
+    position_id: u64,
+    margin_amount: Uint128,
+) -> StdResult<Response> {
+    let config = read_config(deps.storage)?;
+    let mut state = read_state(deps.storage)?;
+    require_not_paused(state.pause)?;
+    require_non_zero_input(margin_amount)?;
+    // validate address inputs
+    let vamm = deps.api.addr_validate(&vamm)?;
+    let liquidator = info.sender.clone();
+
+    let is_backstop_pool = config.backstop_pool.as_ref() == Some(&liquidator);
+    if !is_backstop_pool
+        && !BACKSTOP_LIQUIDATORS.query_hook(deps.as_ref(), liquidator.to_string())?
+    {
+        return Err(StdError::generic_err("unauthorized"));
+    }
+
+    // read the position for the trader from vamm
+    let vamm_key = keccak_256(vamm.as_bytes());
+    let position = read_position(deps.storage, &vamm_key, position_id)?;
+
+    if position.trader == liquidator {
+        return Err(StdError::generic_err("Cannot take over own position"));
+    }
+
+    // check if the trader is protected from liquidation by the whitelist
+    if whitelist_capabilities(deps.as_ref(), &position.trader)?.liquidation_protection {
+        return Err(StdError::generic_err("trader is whitelisted"));
+    }
+
+    require_vamm(deps.as_ref(), &config.insurance_fund, &vamm)?;
+
+    // retrieve the existing margin ratio of the position
+    let margin_ratio = query_margin_ratio(deps.as_ref(), &position)?;
+    require_insufficient_margin(margin_ratio, config.maintenance_margin_ratio)?;
+
+    // check the position isn't zero
+    require_position_not_zero(position.size.value)?;
+
+    // the position is taken over at the mark price policy of the vamm
+    let PositionUnrealizedPnlResponse {
+        position_notional,
+        unrealized_pnl,
+    } = get_mark_price_notional_unrealized_pnl(deps.as_ref(), &position)?;
+    require_non_zero_input(position_notional)?;
+
+    // the liquidator has to post enough margin to open the position
+    let vamm_controller = VammController(vamm.clone());
+    let vamm_config = vamm_controller.config(&deps.querier)?;
+    require_additional_margin(
+        Integer::new_positive(
+            margin_amount
+                .checked_mul(config.decimals)?
+                .checked_div(position_notional)?,
+        ),
+        Uint128::max(
+            config.initial_margin_ratio,
+            vamm_config.initial_margin_ratio,
+        ),
+    )?;
+
+    // the backstop pool takes over the position at a discount to the mark price
+    let (takeover_notional, unrealized_pnl) = if is_backstop_pool {
+        let discount = position_notional
+            .checked_mul(config.backstop_discount)?
+            .checked_div(config.decimals)?;
+        match position.direction {
+            Direction::AddToAmm => {
+                let notional = position_notional.checked_sub(discount)?;
+                (
+                    notional,
+                    Integer::new_positive(notional) - Integer::new_positive(position.notional),
+                )
+            }
+            Direction::RemoveFromAmm => {
+                let notional = position_notional.checked_add(discount)?;
+                (
+                    notional,
+                    Integer::new_positive(position.notional) - Integer::new_positive(notional),
+                )
+            }
+        }
+    } else {
+        (position_notional, unrealized_pnl)
+    };
 
-    // check if the trader is on the whitelist
-    if WHITELIST.query_hook(deps.as_ref(), position.trader.to_string())?
-        && info.sender != position.trader
-    {
-        return Err(StdError::generic_err("trader is whitelisted"));
-    }
+    let RemainMarginResponse {
+        funding_payment,
+        margin,
+        bad_debt,
+        latest_premium_fraction,
+    } = calc_remain_margin_with_funding_payment(deps.as_ref(), &position, unrealized_pnl)?;
+
+    // if the insurance fund cannot cover the bad debt, deleverage the opposite side instead, before
+    // any margin of the liquidator is collected
+    if exceeds_insurance_fund(deps.as_ref(), bad_debt)? {
+        let mut response = auto_deleverage(deps.branch(), env, &position)?;
+
+        // native margin comes with the message and is sent straight back
+        let sent = get_asset(info, config.eligible_collateral.clone()).amount;
+        if !sent.is_zero() {
+            response = response.add_submessage(execute_transfer(deps.storage, &liquidator, sent)?);
+        }
 
-    // store the liquidator
-    store_tmp_liquidator(deps.storage, &info.sender)?;
+        return Ok(response.add_attributes(vec![("margin_ratio", &margin_ratio.to_string())]));
+    }
 
-    // retrieve the existing margin ratio of the position
-    let margin_ratio = query_margin_ratio(deps.as_ref(), &position)?;
+    // calculate liquidation penalty, any rounding dust of the split goes to the insurance fund. The
+    // backstop pool is paid by the discount it takes the position over at, so a discounted
+    // takeover charges the trader no penalty on top of it
+    let liquidation_penalty = if is_backstop_pool && !config.backstop_discount.is_zero() {
+        Uint128::zero()
+    } else {
+        Uint128::min(
+            position_notional
+                .checked_mul(config.liquidation_fee)?
+                .checked_div(config.decimals)?,
+            margin,
+        )
+    };
+    let LiquidationFees {
+        liquidator: liquidation_fee,
+        fee_pool: fee_pool_fee,
+        ..
+    } = calc_liquidation_fees(&config, liquidation_penalty)?;
+    let insurance_fee = liquidation_penalty
+        .checked_sub(liquidation_fee)?
+        .checked_sub(fee_pool_fee)?;
+    let trader_amount = margin.checked_sub(liquidation_penalty)?;
 
-    // let vamm_controller = VammController(vamm.clone());
+    let mut msgs: Vec<SubMsg> = vec![];
 
-    // if vamm_controller.is_over_spread_limit(&deps.querier)? {
-    //     let oracle_margin_ratio =
-    //         get_margin_ratio_calc_option(deps.as_ref(), &position, PnlCalcOption::Oracle)?;
+    match config.eligible_collateral.clone() {
+        AssetInfo::NativeToken { .. } => {
+            let token = Asset {
+                info: config.eligible_collateral.clone(),
+                amount: margin_amount,
+            };
 
-    //     if oracle_margin_ratio.checked_sub(margin_ratio)? > Integer::zero() {
-    //         margin_ratio = oracle_margin_ratio
-    //     }
-    // }
+            token.assert_sent_native_token_balance(&info)?;
+        }
 
-    require_vamm(deps.as_ref(), &config.insurance_fund, &vamm)?;
-    require_insufficient_margin(margin_ratio, config.maintenance_margin_ratio)?;
+        AssetInfo::Token { .. } => {
+            msgs.push(execute_transfer_from(
+                deps.storage,
+                &liquidator,
+                &env.contract.address,
+                margin_amount,
+            )?);
+        }
+    };
 
-    // check the position isn't zero
-    require_position_not_zero(position.size.value)?;
+    record_realized_pnl(
+        deps.storage,
+        &env,
+        &position.trader,
+        position.subaccount_id,
+        unrealized_pnl,
+    )?;
 
-    // first see if this is a partial liquidation, else get rekt
-    let msg = if margin_ratio.value > config.liquidation_fee
-        && !config.partial_liquidation_ratio.is_zero()
-    {
-        partial_liquidation(
-            deps,
-            &vamm,
-            &position,
-            quote_asset_limit,
-            config.decimals,
-            config.partial_liquidation_ratio,
-        )?
+    // the insurance fund is only used for the residual bad debt
+    let pre_paid_shortfall = if !bad_debt.is_zero() {
+        realize_bad_debt(deps.as_ref(), bad_debt, &mut msgs, &mut state)?
     } else {
-        internal_close_position(
+        Uint128::zero()
+    };
+
+    if !insurance_fee.is_zero() {
+        msgs.push(execute_transfer_to_insurance_fund(
+            deps.as_ref(),
+            env.clone(),
+            insurance_fee,
+        )?);
+    }
+
+    if !fee_pool_fee.is_zero() {
+        msgs.push(execute_transfer(
             deps.storage,
-            &position,
-            quote_asset_limit,
-            LIQUIDATION_REPLY_ID,
-        )?
+            &config.fee_pool,
+            fee_pool_fee,
+        )?);
+    }
+
+    let mut unfunded_bad_debt = Uint128::zero();
+    if !liquidation_fee.is_zero() {
+        unfunded_bad_debt += withdraw(
+            deps.branch(),
+            env.clone(),
+            &mut state,
+            &liquidator,
+            config.eligible_collateral.clone(),
+            liquidation_fee,
+            insurance_fee.checked_add(fee_pool_fee)?,
+            pre_paid_shortfall,
+            &mut msgs,
+        )?;
+    }
+
+    if !trader_amount.is_zero() {
+        unfunded_bad_debt += withdraw_to_subaccount(
+            deps.branch(),
+            env.clone(),
+            &mut state,
+            &position.trader,
+            position.subaccount_id,
+            config.eligible_collateral.clone(),
+            trader_amount,
+            liquidation_penalty,
+            pre_paid_shortfall,
+            &mut msgs,
+        )?;
+    }
+
+    // rebase the position on the mark price under the new owner
+    let new_position = Position {
+        trader: liquidator.clone(),
+        margin: margin_amount,
+        notional: takeover_notional,
+        entry_price: takeover_notional
+            .checked_mul(config.decimals)?
+            .checked_div(position.size.value)?,
+        take_profit: None,
+        stop_loss: None,
+        tp_sl_trigger: TpSlTrigger::default(),
+        trailing_stop: None,
+        take_profit_levels: vec![],
+        last_updated_premium_fraction: latest_premium_fraction,
+        block_time: env.block.time.seconds(),
+        subaccount_id: 0,
+        ..position.clone()
     };
 
-    Ok(Response::new().add_submessage(msg).add_attributes(vec![
-        ("action", "liquidate"),
+    // a takeover adds no exposure to the market, so neither the open interest caps nor the base
+    // asset holding cap of the liquidator hold it back, a large position is exactly what a
+    // backstop has to be able to take over
+    rebase_open_interest_notional(
+        deps.branch(),
+        &mut state,
+        vamm.clone(),
+        position.side,
+        Integer::new_positive(takeover_notional) - Integer::new_positive(position.notional),
+    )?;
+
+    store_state(deps.storage, &state)?;
+    remove_position(deps.storage, &vamm_key, &position)?;
+    store_position(deps.storage, &vamm_key, &new_position, true)?;
+    update_adl_candidate(deps.branch(), &new_position)?;
+
+    Ok(Response::new().add_submessages(msgs).add_attributes(vec![
+        ("action", "backstop_liquidate"),
         ("vamm", vamm.as_ref()),
         ("pair", &position.pair),
         ("position_id", &position_id.to_string()),
         ("margin_ratio", &margin_ratio.to_string()),
-        (
-            "maintenance_margin_ratio",
-            &config.maintenance_margin_ratio.to_string(),
-        ),
-        ("trader", &position.trader.as_ref()),
+        ("trader", position.trader.as_ref()),
+        ("liquidator", liquidator.as_ref()),
+        ("position_notional", &position_notional.to_string()),
+        ("takeover_notional", &takeover_notional.to_string()),
+        ("pnl", &unrealized_pnl.to_string()),
+        ("funding_payment", &funding_payment.to_string()),
+        ("liquidation_fee", &liquidation_fee.to_string()),
+        ("insurance_fee", &insurance_fee.to_string()),
+        ("fee_pool_fee", &fee_pool_fee.to_string()),
+        ("withdraw_amount", &trader_amount.to_string()),
+        ("bad_debt", &bad_debt.to_string()),
+        ("margin_amount", &margin_amount.to_string()),
+        ("unfunded_bad_debt", &unfunded_bad_debt.to_string()),
     ]))
 }
 
@@ -790,7 +2191,7 @@ pub fn liquidate(
 pub fn pay_funding(
     deps: DepsMut,
     _env: Env,
-    _info: MessageInfo,
+    info: MessageInfo,
     vamm: String,
 ) -> StdResult<Response> {
     // validate address inputs
@@ -804,15 +2205,40 @@ pub fn pay_funding(
         PAY_FUNDING_REPLY_ID,
     );
 
+    // the keeper is paid by the insurance fund once the funding is settled, up to what the fund
+    // holds so that the reward never blocks the settlement
+    let keeper_reward = calc_keeper_reward(
+        &config.keeper_reward,
+        config.decimals,
+        Uint128::zero(),
+        Uint128::zero(),
+    )?;
+    let keeper_reward = if keeper_reward.is_zero() {
+        keeper_reward
+    } else {
+        keeper_reward.min(query_insurance_fund_balance(deps.as_ref())?)
+    };
+    let mut msgs: Vec<SubMsg> = vec![];
+    if !keeper_reward.is_zero() {
+        msgs.push(execute_insurance_fund_withdrawal(
+            deps.as_ref(),
+            keeper_reward,
+        )?);
+        msgs.extend(execute_payout(deps.storage, &info.sender, keeper_reward)?);
+    }
+
     Ok(Response::new()
         .add_submessage(funding_msg)
+        .add_submessages(msgs)
         .add_attribute("action", "pay_funding")
-        .add_attribute("vamm", vamm.to_string()))
+        .add_attribute("vamm", vamm.to_string())
+        .add_attribute("keeper", info.sender.to_string())
+        .add_attribute("keeper_reward", keeper_reward.to_string()))
 }
 
 /// Enables a user to directly deposit margin into their position
 pub fn deposit_margin(
-    deps: DepsMut,
+    mut deps: DepsMut,
     env: Env,
     info: MessageInfo,
     vamm: String,
@@ -820,7 +2246,7 @@ pub fn deposit_margin(
     amount: Uint128,
 ) -> StdResult<Response> {
     let vamm = deps.api.addr_validate(&vamm)?;
-    let trader = info.sender.clone();
+    let depositor = info.sender.clone();
 
     let state = read_state(deps.storage)?;
     require_not_paused(state.pause)?;
@@ -842,26 +2268,29 @@ pub fn deposit_margin(
         }
 
         AssetInfo::Token { .. } => {
-            let msg = execute_transfer_from(deps.storage, &trader, &env.contract.address, amount)?;
+            let msg =
+                execute_transfer_from(deps.storage, &depositor, &env.contract.address, amount)?;
             response = response.add_submessage(msg);
         }
     };
     let vamm_key = keccak_256(vamm.as_bytes());
-    // read the position for the trader from vamm
+    // read the position from vamm, anyone can top up the margin of a position
     let mut position = read_position(deps.storage, &vamm_key, position_id)?;
 
-    if position.trader != trader {
-        return Err(StdError::generic_err("Unauthorized"));
-    }
+    // neither the depositor nor the trader can be blocked
+    require_not_blocked(deps.storage, &depositor)?;
+    require_not_blocked(deps.storage, &position.trader)?;
 
     position.margin = position.margin.checked_add(amount)?;
 
     store_position(deps.storage, &vamm_key, &position, false)?;
+    update_adl_candidate(deps.branch(), &position)?;
 
     Ok(response.add_attributes([
         ("action", "deposit_margin"),
         ("position_id", &position_id.to_string()),
-        ("trader", trader.as_str()),
+        ("trader", position.trader.as_str()),
+        ("depositor", depositor.as_str()),
         ("deposit_amount", &amount.to_string()),
         ("vamm", vamm.as_str()),
     ]))
@@ -869,7 +2298,7 @@ pub fn deposit_margin(
 
 /// Enables a user to directly withdraw excess margin from their position
 pub fn withdraw_margin(
-    deps: DepsMut,
+    mut deps: DepsMut,
     env: Env,
     info: MessageInfo,
     vamm: String,
@@ -915,18 +2344,22 @@ pub fn withdraw_margin(
 
     let fees = position.spread_fee.checked_add(position.toll_fee)?;
     // withdraw margin
-    let msgs = withdraw(
-        deps.as_ref(),
+    let mut msgs: Vec<SubMsg> = vec![];
+    let unfunded_bad_debt = withdraw_to_subaccount(
+        deps.branch(),
         env,
         &mut state,
         &trader,
+        position.subaccount_id,
         config.eligible_collateral,
         amount,
         fees,
         Uint128::zero(),
+        &mut msgs,
     )?;
 
     store_position(deps.storage, &vamm_key, &position, false)?;
+    update_adl_candidate(deps.branch(), &position)?;
     store_state(deps.storage, &state)?;
 
     Ok(Response::new().add_submessages(msgs).add_attributes(vec![
@@ -944,6 +2377,273 @@ pub fn withdraw_margin(
         ),
         ("bad_debt", &remain_margin.bad_debt.to_string()),
         ("vamm", vamm.as_str()),
+        ("unfunded_bad_debt", &unfunded_bad_debt.to_string()),
+    ]))
+}
+
+/// Moves the margin of a position in or out so that it has the given leverage at the mark price,
+/// pending funding is settled first
+pub fn set_leverage(
+    mut deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    vamm: String,
+    position_id: u64,
+    leverage: Uint128,
+) -> StdResult<Response> {
+    let vamm = deps.api.addr_validate(&vamm)?;
+    let trader = info.sender.clone();
+
+    let config = read_config(deps.storage)?;
+    require_vamm(deps.as_ref(), &config.insurance_fund, &vamm)?;
+    let mut state = read_state(deps.storage)?;
+    require_not_paused(state.pause)?;
+    require_non_zero_input(leverage)?;
+
+    if leverage < config.decimals {
+        return Err(StdError::generic_err("Leverage must be greater than 1"));
+    }
+
+    let vamm_config = VammController(vamm.clone()).config(&deps.querier)?;
+    let margin_ratio = config
+        .decimals
+        .checked_mul(config.decimals)?
+        .checked_div(leverage)?;
+    require_additional_margin(
+        Integer::from(margin_ratio),
+        Uint128::max(
+            config.initial_margin_ratio,
+            vamm_config.initial_margin_ratio,
+        ),
+    )?;
+
+    let vamm_key = keccak_256(vamm.as_bytes());
+    let mut position = read_position(deps.storage, &vamm_key, position_id)?;
+
+    if position.trader != trader {
+        return Err(StdError::generic_err("Unauthorized"));
+    }
+
+    // settle the pending funding into the margin
+    let remain_margin =
+        calc_remain_margin_with_funding_payment(deps.as_ref(), &position, Integer::zero())?;
+    require_bad_debt(remain_margin.bad_debt)?;
+    position.margin = remain_margin.margin;
+    position.last_updated_premium_fraction = remain_margin.latest_premium_fraction;
+
+    let PositionUnrealizedPnlResponse {
+        position_notional,
+        unrealized_pnl: _,
+    } = get_mark_price_notional_unrealized_pnl(deps.as_ref(), &position)?;
+    let target_margin = position_notional
+        .checked_mul(config.decimals)?
+        .checked_div(leverage)?;
+
+    let sent = get_asset(info, config.eligible_collateral.clone()).amount;
+    let mut msgs: Vec<SubMsg> = vec![];
+    let mut unfunded_bad_debt = Uint128::zero();
+
+    let margin_delta = if target_margin > position.margin {
+        // a blocked address cannot add collateral
+        require_not_blocked(deps.storage, &trader)?;
+
+        let amount = target_margin.checked_sub(position.margin)?;
+        match config.eligible_collateral {
+            AssetInfo::NativeToken { .. } => {
+                // the exact amount is only known here, what is sent over it is refunded
+                let refund = sent
+                    .checked_sub(amount)
+                    .map_err(|_| StdError::generic_err("sent funds are insufficient"))?;
+                if !refund.is_zero() {
+                    msgs.extend(execute_payout(deps.storage, &trader, refund)?);
+                }
+            }
+            AssetInfo::Token { .. } => {
+                msgs.push(execute_transfer_from(
+                    deps.storage,
+                    &trader,
+                    &env.contract.address,
+                    amount,
+                )?);
+            }
+        }
+        position.margin = target_margin;
+        store_position(deps.storage, &vamm_key, &position, false)?;
+        update_adl_candidate(deps.branch(), &position)?;
+
+        Integer::new_positive(amount)
+    } else {
+        let amount = position.margin.checked_sub(target_margin)?;
+        if !sent.is_zero() {
+            msgs.extend(execute_payout(deps.storage, &trader, sent)?);
+        }
+        store_position(deps.storage, &vamm_key, &position, false)?;
+
+        if !amount.is_zero() {
+            // check if margin is sufficient
+            let free_collateral =
+                query_free_collateral(deps.as_ref(), vamm.to_string(), position_id)?;
+            if free_collateral
+                .checked_sub(Integer::new_positive(amount))?
+                .is_negative()
+            {
+                return Err(StdError::generic_err("Insufficient collateral"));
+            }
+
+            let fees = position.spread_fee.checked_add(position.toll_fee)?;
+            unfunded_bad_debt = withdraw_to_subaccount(
+                deps.branch(),
+                env,
+                &mut state,
+                &trader,
+                position.subaccount_id,
+                config.eligible_collateral,
+                amount,
+                fees,
+                Uint128::zero(),
+                &mut msgs,
+            )?;
+
+            position.margin = target_margin;
+            store_position(deps.storage, &vamm_key, &position, false)?;
+            update_adl_candidate(deps.branch(), &position)?;
+            store_state(deps.storage, &state)?;
+        }
+
+        Integer::new_negative(amount)
+    };
+
+    Ok(Response::new().add_submessages(msgs).add_attributes(vec![
+        ("action", "set_leverage"),
+        ("position_id", &position_id.to_string()),
+        ("trader", trader.as_ref()),
+        ("leverage", &leverage.to_string()),
+        ("margin_delta", &margin_delta.to_string()),
+        (
+            "funding_payment",
+            &remain_margin.funding_payment.to_string(),
+        ),
+        ("vamm", vamm.as_str()),
+        ("unfunded_bad_debt", &unfunded_bad_debt.to_string()),
+    ]))
+}
+
+/// Pays out part of the unrealized profit of a position while it stays open, the profit is
+/// measured at the least favourable of the mark price and the twap so that it cannot be inflated
+/// by moving the spot price
+pub fn realize_pnl(
+    mut deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    vamm: String,
+    position_id: u64,
+    amount: Uint128,
+) -> StdResult<Response> {
+    let vamm = deps.api.addr_validate(&vamm)?;
+    let trader = info.sender;
+
+    let config = read_config(deps.storage)?;
+    require_vamm(deps.as_ref(), &config.insurance_fund, &vamm)?;
+    let mut state = read_state(deps.storage)?;
+    require_not_paused(state.pause)?;
+    require_non_zero_input(amount)?;
+
+    let vamm_key = keccak_256(vamm.as_bytes());
+    let position = read_position(deps.storage, &vamm_key, position_id)?;
+
+    if position.trader != trader {
+        return Err(StdError::generic_err("Unauthorized"));
+    }
+
+    // settle the pending funding into the margin
+    let remain_margin =
+        calc_remain_margin_with_funding_payment(deps.as_ref(), &position, Integer::zero())?;
+    require_bad_debt(remain_margin.bad_debt)?;
+
+    let mark = get_mark_price_notional_unrealized_pnl(deps.as_ref(), &position)?;
+    let twap = get_position_notional_unrealized_pnl(deps.as_ref(), &position, PnlCalcOption::Twap)?;
+    let PositionUnrealizedPnlResponse {
+        position_notional,
+        unrealized_pnl,
+    } = if twap.unrealized_pnl < mark.unrealized_pnl {
+        twap
+    } else {
+        mark
+    };
+
+    if unrealized_pnl < Integer::new_positive(amount) {
+        return Err(StdError::generic_err("Insufficient unrealized profit"));
+    }
+
+    // the margin ratio left once the amount is paid out
+    let vamm_config = VammController(vamm.clone()).config(&deps.querier)?;
+    let margin_ratio = (Integer::new_positive(remain_margin.margin) + unrealized_pnl
+        - Integer::new_positive(amount))
+        * Integer::new_positive(config.decimals)
+        / Integer::new_positive(position_notional);
+    require_additional_margin(
+        margin_ratio,
+        Uint128::max(
+            config.initial_margin_ratio,
+            vamm_config.initial_margin_ratio,
+        ),
+    )?;
+
+    // move the open notional towards the mark by the realized amount
+    let notional = match position.side {
+        Side::Buy => position.notional.checked_add(amount)?,
+        Side::Sell => position.notional.checked_sub(amount)?,
+    };
+    let new_position = Position {
+        margin: remain_margin.margin,
+        notional,
+        entry_price: notional
+            .checked_mul(config.decimals)?
+            .checked_div(position.size.value)?,
+        last_updated_premium_fraction: remain_margin.latest_premium_fraction,
+        ..position.clone()
+    };
+
+    // the basis reset adds no exposure so it is not held to the open interest caps
+    rebase_open_interest_notional(
+        deps.branch(),
+        &mut state,
+        vamm.clone(),
+        position.side,
+        Integer::new_positive(notional) - Integer::new_positive(position.notional),
+    )?;
+
+    let mut msgs: Vec<SubMsg> = vec![];
+    let unfunded_bad_debt = withdraw_to_subaccount(
+        deps.branch(),
+        env,
+        &mut state,
+        &trader,
+        position.subaccount_id,
+        config.eligible_collateral,
+        amount,
+        Uint128::zero(),
+        Uint128::zero(),
+        &mut msgs,
+    )?;
+
+    store_state(deps.storage, &state)?;
+    remove_position(deps.storage, &vamm_key, &position)?;
+    store_position(deps.storage, &vamm_key, &new_position, true)?;
+    update_adl_candidate(deps.branch(), &new_position)?;
+
+    Ok(Response::new().add_submessages(msgs).add_attributes(vec![
+        ("action", "realize_pnl"),
+        ("position_id", &position_id.to_string()),
+        ("trader", trader.as_ref()),
+        ("amount", &amount.to_string()),
+        ("entry_price", &new_position.entry_price.to_string()),
+        (
+            "funding_payment",
+            &remain_margin.funding_payment.to_string(),
+        ),
+        ("vamm", vamm.as_str()),
+        ("unfunded_bad_debt", &unfunded_bad_debt.to_string()),
     ]))
 }
 
@@ -992,6 +2692,8 @@ pub fn internal_close_position(
             stop_loss: position.stop_loss,
             spread_fee: position.spread_fee,
             toll_fee: position.toll_fee,
+            subaccount_id: position.subaccount_id,
+            is_increase: false,
         },
     )?;
 
@@ -1010,18 +2712,10 @@ fn partial_liquidation(
     vamm: &Addr,
     position: &Position,
     quote_asset_limit: Uint128,
-    decimals: Uint128,
-    partial_liquidation_ratio: Uint128,
+    partial_position_size: Uint128,
 ) -> StdResult<SubMsg> {
-    let partial_position_size = position
-        .size
-        .value
-        .checked_mul(partial_liquidation_ratio)?
-        .checked_div(decimals)?;
-
-    let partial_asset_limit = quote_asset_limit
-        .checked_mul(partial_liquidation_ratio)?
-        .checked_div(decimals)?;
+    let partial_asset_limit =
+        quote_asset_limit.multiply_ratio(partial_position_size, position.size.value);
 
     let vamm_controller = VammController(vamm.clone());
 
@@ -1031,10 +2725,11 @@ fn partial_liquidation(
         partial_position_size,
     )?;
 
+    // the pnl is taken at the mark price policy that the liquidation is sized and checked at
     let PositionUnrealizedPnlResponse {
         position_notional: _,
         unrealized_pnl,
-    } = get_position_notional_unrealized_pnl(deps.as_ref(), position, PnlCalcOption::SpotPrice)?;
+    } = get_mark_price_notional_unrealized_pnl(deps.as_ref(), position)?;
 
     let side = position_to_side(position.size);
 
@@ -1056,6 +2751,8 @@ fn partial_liquidation(
             stop_loss: position.stop_loss,
             spread_fee: position.spread_fee,
             toll_fee: position.toll_fee,
+            subaccount_id: position.subaccount_id,
+            is_increase: false,
         },
     )?;
 
@@ -1,7 +1,13 @@
+mod account;
+mod adl;
+mod backfill;
+mod blocklist;
 pub mod contract;
 mod error;
 mod handle;
+mod limits;
 mod messages;
+mod order;
 mod query;
 mod reply;
 mod state;
@@ -0,0 +1,317 @@
+use cosmwasm_std::{
+    Addr, Deps, DepsMut, Env, MessageInfo, Response, StdError, StdResult, Storage, Uint128,
+};
+use margined_common::integer::Integer;
+use margined_perp::margined_engine::{
+    Position, PositionFilter, TraderLimits, TraderLimitsResponse,
+};
+use margined_utils::contracts::helpers::InsuranceFundController;
+
+use crate::{
+    query::query_positions,
+    state::{
+        read_config, read_daily_loss, read_daily_losses, read_pending_self_limits,
+        read_self_limits, read_trader_limits, remove_pending_self_limits, store_daily_loss,
+        store_pending_self_limits, store_self_limits, store_trader_limits, DailyLoss,
+        PendingLimits, MAX_LIMIT,
+    },
+    utils::keccak_256,
+};
+
+const SECONDS_PER_DAY: u64 = 86_400;
+
+// Sets the limits of a trader, can only be called by the owner
+pub fn update_trader_limits(
+    deps: DepsMut,
+    info: MessageInfo,
+    trader: String,
+    limits: TraderLimits,
+) -> StdResult<Response> {
+    let config = read_config(deps.storage)?;
+
+    // check permission
+    if info.sender != config.owner {
+        return Err(StdError::generic_err("unauthorized"));
+    }
+
+    let trader = deps.api.addr_validate(&trader)?;
+    store_trader_limits(deps.storage, &trader, &limits)?;
+
+    Ok(Response::default().add_attributes(vec![
+        ("action", "update_trader_limits"),
+        ("trader", trader.as_str()),
+    ]))
+}
+
+// Sets the limits that the sender puts on the positions of one of their sub-accounts, tighter
+// limits apply at once while looser ones only apply from the next UTC day
+pub fn update_self_limits(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    limits: TraderLimits,
+    subaccount_id: Option<u32>,
+) -> StdResult<Response> {
+    let subaccount_id = subaccount_id.unwrap_or_default();
+    let current = self_limits(deps.storage, &env, &info.sender, subaccount_id)?;
+
+    // a self-exclusion cannot be lifted within the day it is in place
+    let tightened = TraderLimits {
+        max_positions_per_market: tightest(
+            limits.max_positions_per_market,
+            current.max_positions_per_market,
+        ),
+        max_total_notional: tightest(limits.max_total_notional, current.max_total_notional),
+        max_leverage: tightest(limits.max_leverage, current.max_leverage),
+        daily_loss_limit: tightest(limits.daily_loss_limit, current.daily_loss_limit),
+    };
+    store_self_limits(deps.storage, &info.sender, subaccount_id, &tightened)?;
+
+    if tightened == limits {
+        remove_pending_self_limits(deps.storage, &info.sender, subaccount_id);
+    } else {
+        store_pending_self_limits(
+            deps.storage,
+            &info.sender,
+            subaccount_id,
+            &PendingLimits {
+                day: current_day(&env) + 1,
+                limits,
+            },
+        )?;
+    }
+
+    Ok(Response::default().add_attributes(vec![
+        ("action", "update_self_limits"),
+        ("trader", info.sender.as_str()),
+        ("subaccount_id", &subaccount_id.to_string()),
+    ]))
+}
+
+pub fn query_trader_limits(
+    deps: Deps,
+    env: Env,
+    trader: String,
+    subaccount_id: Option<u32>,
+) -> StdResult<TraderLimitsResponse> {
+    let trader = deps.api.addr_validate(&trader)?;
+    let subaccount_id = subaccount_id.unwrap_or_default();
+    let pending_self_limits = read_pending_self_limits(deps.storage, &trader, subaccount_id)?
+        .filter(|pending| pending.day > current_day(&env))
+        .map(|pending| pending.limits);
+
+    Ok(TraderLimitsResponse {
+        limits: read_trader_limits(deps.storage, &trader)?,
+        self_limits: self_limits(deps.storage, &env, &trader, subaccount_id)?,
+        pending_self_limits,
+        daily_realized_loss: trader_daily_realized_loss(deps.storage, &env, &trader)?,
+        subaccount_daily_realized_loss: daily_realized_loss(
+            deps.storage,
+            &env,
+            &trader,
+            subaccount_id,
+        )?,
+    })
+}
+
+/// Checks a position being opened or increased against the limits set by the owner, which apply
+/// to all the positions of the trader, and the limits the trader set on the sub-account
+#[allow(clippy::too_many_arguments)]
+pub fn require_trader_limits(
+    deps: Deps,
+    env: &Env,
+    trader: &Addr,
+    subaccount_id: u32,
+    vamm: &Addr,
+    is_new_position: bool,
+    leverage: Uint128,
+    open_notional: Uint128,
+) -> StdResult<()> {
+    let limits = read_trader_limits(deps.storage, trader)?;
+    require_limits(
+        deps,
+        &limits,
+        trader_daily_realized_loss(deps.storage, env, trader)?,
+        PositionFilter::Trader(trader.to_string()),
+        vamm,
+        is_new_position,
+        leverage,
+        open_notional,
+    )?;
+
+    let self_limits = self_limits(deps.storage, env, trader, subaccount_id)?;
+    require_limits(
+        deps,
+        &self_limits,
+        daily_realized_loss(deps.storage, env, trader, subaccount_id)?,
+        PositionFilter::Subaccount(trader.to_string(), subaccount_id),
+        vamm,
+        is_new_position,
+        leverage,
+        open_notional,
+    )
+}
+
+#[allow(clippy::too_many_arguments)]
+fn require_limits(
+    deps: Deps,
+    limits: &TraderLimits,
+    daily_loss: Uint128,
+    filter: PositionFilter,
+    vamm: &Addr,
+    is_new_position: bool,
+    leverage: Uint128,
+    open_notional: Uint128,
+) -> StdResult<()> {
+    if let Some(daily_loss_limit) = limits.daily_loss_limit {
+        if daily_loss >= daily_loss_limit {
+            return Err(StdError::generic_err("Daily loss limit is reached"));
+        }
+    }
+
+    if let Some(max_leverage) = limits.max_leverage {
+        if leverage > max_leverage {
+            return Err(StdError::generic_err("Leverage exceeds the trader limit"));
+        }
+    }
+
+    if let Some(max_positions) = limits.max_positions_per_market {
+        let positions = filtered_positions(deps.storage, vamm, &filter)?;
+        if is_new_position && positions.len() as u32 >= max_positions {
+            return Err(StdError::generic_err(
+                "Open positions exceed the trader limit",
+            ));
+        }
+    }
+
+    if let Some(max_total_notional) = limits.max_total_notional {
+        let config = read_config(deps.storage)?;
+        let insurance_fund = config
+            .insurance_fund
+            .ok_or_else(|| StdError::generic_err("insurance fund is not registered"))?;
+        let vamms = InsuranceFundController(insurance_fund)
+            .all_vamms(&deps.querier, None)?
+            .vamm_list;
+
+        let mut total_notional = open_notional;
+        for vamm in vamms.iter() {
+            for position in filtered_positions(deps.storage, vamm, &filter)? {
+                total_notional = total_notional.checked_add(position.notional)?;
+            }
+        }
+
+        if total_notional > max_total_notional {
+            return Err(StdError::generic_err(
+                "Total notional exceeds the trader limit",
+            ));
+        }
+    }
+
+    Ok(())
+}
+
+/// Adds the loss of a close to the loss realized by the sub-account in the current day
+pub fn record_realized_pnl(
+    storage: &mut dyn Storage,
+    env: &Env,
+    trader: &Addr,
+    subaccount_id: u32,
+    realized_pnl: Integer,
+) -> StdResult<()> {
+    if !realized_pnl.is_negative() {
+        return Ok(());
+    }
+
+    let day = current_day(env);
+    let loss = daily_realized_loss(storage, env, trader, subaccount_id)?
+        .checked_add(realized_pnl.value)?;
+
+    store_daily_loss(storage, trader, subaccount_id, &DailyLoss { day, loss })
+}
+
+fn current_day(env: &Env) -> u64 {
+    env.block.time.seconds() / SECONDS_PER_DAY
+}
+
+/// the self limits in place, the pending looser limits once their day has come
+fn self_limits(
+    storage: &dyn Storage,
+    env: &Env,
+    trader: &Addr,
+    subaccount_id: u32,
+) -> StdResult<TraderLimits> {
+    match read_pending_self_limits(storage, trader, subaccount_id)? {
+        Some(pending) if pending.day <= current_day(env) => Ok(pending.limits),
+        _ => read_self_limits(storage, trader, subaccount_id),
+    }
+}
+
+// no limit is looser than any limit
+fn tightest<T: Ord>(limit: Option<T>, other: Option<T>) -> Option<T> {
+    match (limit, other) {
+        (Some(limit), Some(other)) => Some(limit.min(other)),
+        (limit, other) => limit.or(other),
+    }
+}
+
+// the loss of a previous day no longer counts
+fn loss_of_today(env: &Env, daily_loss: DailyLoss) -> Uint128 {
+    if daily_loss.day == current_day(env) {
+        daily_loss.loss
+    } else {
+        Uint128::zero()
+    }
+}
+
+fn daily_realized_loss(
+    storage: &dyn Storage,
+    env: &Env,
+    trader: &Addr,
+    subaccount_id: u32,
+) -> StdResult<Uint128> {
+    Ok(loss_of_today(
+        env,
+        read_daily_loss(storage, trader, subaccount_id)?,
+    ))
+}
+
+/// the loss realized today by all the sub-accounts of the trader
+fn trader_daily_realized_loss(
+    storage: &dyn Storage,
+    env: &Env,
+    trader: &Addr,
+) -> StdResult<Uint128> {
+    let mut loss = Uint128::zero();
+    for daily_loss in read_daily_losses(storage, trader)? {
+        loss = loss.checked_add(loss_of_today(env, daily_loss))?;
+    }
+
+    Ok(loss)
+}
+
+fn filtered_positions(
+    storage: &dyn Storage,
+    vamm: &Addr,
+    filter: &PositionFilter,
+) -> StdResult<Vec<Position>> {
+    let vamm_key = keccak_256(vamm.as_bytes());
+    let mut positions: Vec<Position> = vec![];
+
+    loop {
+        let page = query_positions(
+            storage,
+            &vamm_key,
+            None,
+            filter.clone(),
+            positions.last().map(|position| position.position_id),
+            Some(MAX_LIMIT),
+            None,
+        )?;
+        let is_last_page = page.len() < MAX_LIMIT as usize;
+        positions.extend(page);
+
+        if is_last_page {
+            return Ok(positions);
+        }
+    }
+}
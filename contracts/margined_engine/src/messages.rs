@@ -1,8 +1,12 @@
-use cosmwasm_std::{Addr, Deps, Env, StdError, StdResult, Storage, SubMsg, Uint128};
+use cosmwasm_std::{Addr, Deps, DepsMut, Env, StdError, StdResult, Storage, SubMsg, Uint128};
 
 use crate::{
-    contract::TRANSFER_FAILURE_REPLY_ID,
-    state::{read_config, State},
+    account::credit_trader_balance,
+    contract::{PAYOUT_REPLY_ID, PAYOUT_REPLY_ID_RANGE, TRANSFER_FAILURE_REPLY_ID},
+    state::{
+        increase_last_payout_id, is_blocked, read_config, read_credit_proceeds, read_escrowed,
+        read_total_liabilities, store_escrowed, store_tmp_payout, State, TmpPayout,
+    },
 };
 
 use margined_common::{asset::AssetInfo, messages::wasm_execute};
@@ -38,6 +42,44 @@ pub fn execute_transfer(
     Ok(SubMsg::reply_on_error(msg, TRANSFER_FAILURE_REPLY_ID))
 }
 
+// Pays out to a trader or keeper, a failed transfer is credited to the claimable balance of the
+// receiver by the reply so that it cannot block a close or a liquidation. The payout of a
+// blocked receiver is escrowed instead of sent
+pub fn execute_payout(
+    storage: &mut dyn Storage,
+    receiver: &Addr,
+    amount: Uint128,
+) -> StdResult<Option<SubMsg>> {
+    if is_blocked(storage, receiver)? {
+        let escrowed = read_escrowed(storage, receiver)?.checked_add(amount)?;
+        store_escrowed(storage, receiver, escrowed)?;
+
+        return Ok(None);
+    }
+
+    let config = read_config(storage)?;
+
+    let msg = config
+        .eligible_collateral
+        .into_msg(receiver.to_string(), amount, None)?;
+
+    // every payout in flight has its own reply id so that the reply finds its receiver and
+    // amount. A payout that goes through does not reply, its tmp payout is left for the next
+    // payout of the same id to overwrite, so the ids cycle through the range to bound the tmp
+    // payouts kept. A message pays out far less than the range so none of its payouts share an id
+    let reply_id = PAYOUT_REPLY_ID + increase_last_payout_id(storage)? % PAYOUT_REPLY_ID_RANGE;
+    store_tmp_payout(
+        storage,
+        reply_id,
+        &TmpPayout {
+            receiver: receiver.clone(),
+            amount,
+        },
+    )?;
+
+    Ok(Some(SubMsg::reply_on_error(msg, reply_id)))
+}
+
 pub fn execute_transfer_to_insurance_fund(
     deps: Deps,
     env: Env,
@@ -57,6 +99,17 @@ pub fn execute_transfer_to_insurance_fund(
     }
 }
 
+pub fn query_insurance_fund_balance(deps: Deps) -> StdResult<Uint128> {
+    let config = read_config(deps.storage)?;
+
+    match config.insurance_fund {
+        Some(insurance_fund) => config
+            .eligible_collateral
+            .query_balance(&deps.querier, insurance_fund),
+        None => Ok(Uint128::zero()),
+    }
+}
+
 pub fn execute_insurance_fund_withdrawal(deps: Deps, amount: Uint128) -> StdResult<SubMsg> {
     let config = read_config(deps.storage)?;
 
@@ -107,8 +160,11 @@ pub fn transfer_fees(
     Ok(messages)
 }
 
+/// Pays out an amount to the receiver, returns the part of it that neither the engine nor the
+/// insurance fund can fund, which is realized as bad debt instead of paid
+#[allow(clippy::too_many_arguments)]
 pub fn withdraw(
-    deps: Deps,
+    deps: DepsMut,
     env: Env,
     state: &mut State,
     receiver: &Addr,
@@ -116,21 +172,115 @@ pub fn withdraw(
     amount: Uint128,
     fees: Uint128,
     pre_paid_shortfall: Uint128,
-) -> StdResult<Vec<SubMsg>> {
-    let token_balance = eligible_collateral.query_balance(&deps.querier, env.contract.address)?;
+    messages: &mut Vec<SubMsg>,
+) -> StdResult<Uint128> {
+    let bad_debt = cover_shortfall(
+        deps.as_ref(),
+        env,
+        state,
+        eligible_collateral,
+        amount,
+        fees,
+        pre_paid_shortfall,
+        messages,
+    )?;
 
-    let mut messages: Vec<SubMsg> = vec![];
+    // nothing is paid out when none of the amount is funded
+    if bad_debt.is_zero() || bad_debt < amount {
+        messages.extend(execute_payout(
+            deps.storage,
+            receiver,
+            amount.checked_sub(bad_debt)?,
+        )?);
+    }
 
-    if token_balance.checked_add(pre_paid_shortfall)? < amount.checked_add(fees)? {
-        let shortfall = amount
-            .checked_add(fees)?
-            .checked_sub(token_balance.checked_add(pre_paid_shortfall)?)?;
+    Ok(bad_debt)
+}
+
+/// Pays out what a position returns to its trader, the proceeds of a sub-account, or of a trader
+/// that credits its proceeds, stay in the balance of the sub-account instead of being transferred.
+/// Returns the part of the amount realized as bad debt as `withdraw` does
+#[allow(clippy::too_many_arguments)]
+pub fn withdraw_to_subaccount(
+    deps: DepsMut,
+    env: Env,
+    state: &mut State,
+    trader: &Addr,
+    subaccount_id: u32,
+    eligible_collateral: AssetInfo,
+    amount: Uint128,
+    fees: Uint128,
+    pre_paid_shortfall: Uint128,
+    messages: &mut Vec<SubMsg>,
+) -> StdResult<Uint128> {
+    if subaccount_id == 0 && !read_credit_proceeds(deps.storage, trader)? {
+        return withdraw(
+            deps,
+            env,
+            state,
+            trader,
+            eligible_collateral,
+            amount,
+            fees,
+            pre_paid_shortfall,
+            messages,
+        );
+    }
+
+    let bad_debt = cover_shortfall(
+        deps.as_ref(),
+        env,
+        state,
+        eligible_collateral,
+        amount,
+        fees,
+        pre_paid_shortfall,
+        messages,
+    )?;
+    credit_trader_balance(
+        deps.storage,
+        trader,
+        subaccount_id,
+        amount.checked_sub(bad_debt)?,
+    )?;
 
+    Ok(bad_debt)
+}
+
+/// Withdraws from the insurance fund what the engine lacks to pay the amount and fees, returns
+/// the part of the amount that the insurance fund cannot cover either
+#[allow(clippy::too_many_arguments)]
+pub fn cover_shortfall(
+    deps: Deps,
+    env: Env,
+    state: &mut State,
+    eligible_collateral: AssetInfo,
+    amount: Uint128,
+    fees: Uint128,
+    pre_paid_shortfall: Uint128,
+    messages: &mut Vec<SubMsg>,
+) -> StdResult<Uint128> {
+    // free balances, order escrow and held payouts are owed to traders and cannot pay for this
+    let token_balance = eligible_collateral
+        .query_balance(&deps.querier, env.contract.address)?
+        .saturating_sub(read_total_liabilities(deps.storage)?);
+
+    if token_balance.checked_add(pre_paid_shortfall)? >= amount.checked_add(fees)? {
+        return Ok(Uint128::zero());
+    }
+
+    // only what the insurance fund holds is withdrawn, the rest of the amount is not funded by
+    // anything and is left unpaid as bad debt rather than owed as a claimable
+    let shortfall = amount
+        .checked_add(fees)?
+        .checked_sub(token_balance.checked_add(pre_paid_shortfall)?)?;
+    let covered = shortfall.min(query_insurance_fund_balance(deps)?);
+
+    if !covered.is_zero() {
         // add any shortfall to bad_debt
-        state.prepaid_bad_debt = state.prepaid_bad_debt.checked_add(shortfall)?;
-        messages.push(execute_insurance_fund_withdrawal(deps, shortfall)?);
+        state.prepaid_bad_debt = state.prepaid_bad_debt.checked_add(covered)?;
+        messages.push(execute_insurance_fund_withdrawal(deps, covered)?);
     }
 
-    messages.push(execute_transfer(deps.storage, receiver, amount)?);
-    Ok(messages)
+    Ok(shortfall.checked_sub(covered)?.min(amount))
 }
@@ -0,0 +1,461 @@
+use cosmwasm_std::{
+    Addr, Deps, DepsMut, Env, MessageInfo, Response, StdError, StdResult, Storage, SubMsg, Uint128,
+};
+use margined_common::asset::{Asset, AssetInfo};
+use margined_perp::{
+    margined_engine::{ConditionalOrder, ConditionalOrderMsg, Side, TwapOrder},
+    margined_vamm::CalcFeeResponse,
+};
+use margined_utils::contracts::helpers::VammController;
+
+use crate::{
+    handle::open_position_with_funds,
+    messages::{execute_payout, execute_transfer_from},
+    state::{
+        increase_last_order_id, may_read_position, read_conditional_orders, read_config,
+        read_order, read_state, read_twap_order, read_twap_orders, remove_order, remove_twap_order,
+        store_order, store_twap_order, twap_order_escrow, SentFunds,
+    },
+    utils::{get_asset, keccak_256, require_non_zero_input, require_not_paused, require_vamm},
+};
+
+fn validate_order(order: &ConditionalOrderMsg, decimals: Uint128) -> StdResult<()> {
+    require_non_zero_input(order.margin_amount)?;
+    require_non_zero_input(order.leverage)?;
+    require_non_zero_input(order.trigger_price)?;
+
+    if order.leverage < decimals {
+        return Err(StdError::generic_err("Leverage must be greater than 1"));
+    }
+
+    Ok(())
+}
+
+fn new_order(
+    storage: &mut dyn Storage,
+    vamm: &Addr,
+    trader: &Addr,
+    order: ConditionalOrderMsg,
+) -> StdResult<ConditionalOrder> {
+    Ok(ConditionalOrder {
+        order_id: increase_last_order_id(storage)?,
+        vamm: vamm.clone(),
+        trader: trader.clone(),
+        side: order.side,
+        margin_amount: order.margin_amount,
+        leverage: order.leverage,
+        trigger_price: order.trigger_price,
+        take_profit: order.take_profit,
+        stop_loss: order.stop_loss,
+        base_asset_limit: order.base_asset_limit,
+        oco_order_id: None,
+        subaccount_id: order.subaccount_id.unwrap_or_default(),
+    })
+}
+
+// Places a conditional order, and optionally its one-cancels-other sibling, escrowing the margin
+pub fn place_conditional_order(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    vamm: String,
+    order: ConditionalOrderMsg,
+    oco_order: Option<ConditionalOrderMsg>,
+) -> StdResult<Response> {
+    let vamm = deps.api.addr_validate(&vamm)?;
+    let config = read_config(deps.storage)?;
+    let state = read_state(deps.storage)?;
+    let trader = info.sender.clone();
+
+    require_not_paused(state.pause)?;
+    require_vamm(deps.as_ref(), &config.insurance_fund, &vamm)?;
+
+    validate_order(&order, config.decimals)?;
+    let mut escrow = order.margin_amount;
+    if let Some(oco_order) = &oco_order {
+        validate_order(oco_order, config.decimals)?;
+        escrow = escrow.checked_add(oco_order.margin_amount)?;
+    }
+
+    let mut msgs: Vec<SubMsg> = vec![];
+    match config.eligible_collateral {
+        AssetInfo::NativeToken { .. } => {
+            SentFunds {
+                asset: get_asset(info, config.eligible_collateral),
+                required: escrow,
+                escrowed: false,
+                from_balance: false,
+            }
+            .are_sufficient()?;
+        }
+        AssetInfo::Token { .. } => {
+            msgs.push(execute_transfer_from(
+                deps.storage,
+                &trader,
+                &env.contract.address,
+                escrow,
+            )?);
+        }
+    }
+
+    let mut first = new_order(deps.storage, &vamm, &trader, order)?;
+    let mut second = match oco_order {
+        Some(oco_order) => Some(new_order(deps.storage, &vamm, &trader, oco_order)?),
+        None => None,
+    };
+
+    if let Some(second) = second.as_mut() {
+        first.oco_order_id = Some(second.order_id);
+        second.oco_order_id = Some(first.order_id);
+        store_order(deps.storage, second)?;
+    }
+    store_order(deps.storage, &first)?;
+
+    Ok(Response::new().add_submessages(msgs).add_attributes(vec![
+        ("action", "place_conditional_order"),
+        ("vamm", vamm.as_ref()),
+        ("trader", trader.as_ref()),
+        ("order_id", &first.order_id.to_string()),
+        (
+            "oco_order_id",
+            &first.oco_order_id.unwrap_or_default().to_string(),
+        ),
+        ("escrow", &escrow.to_string()),
+    ]))
+}
+
+// Lets keepers open the position of a conditional order once the vamm price crosses its trigger
+pub fn execute_conditional_order(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    order_id: u64,
+) -> StdResult<Response> {
+    let config = read_config(deps.storage)?;
+    let order = read_order(deps.storage, order_id)?;
+
+    let spot_price = VammController(order.vamm.clone()).spot_price(&deps.querier)?;
+    let triggered = match order.side {
+        Side::Buy => spot_price >= order.trigger_price,
+        Side::Sell => spot_price <= order.trigger_price,
+    };
+    if !triggered {
+        return Err(StdError::generic_err("Trigger price is not reached"));
+    }
+
+    remove_order(deps.storage, &order)?;
+
+    // cancel the sibling and refund its escrowed margin
+    let mut msgs: Vec<SubMsg> = vec![];
+    if let Some(oco_order_id) = order.oco_order_id {
+        let oco_order = read_order(deps.storage, oco_order_id)?;
+        remove_order(deps.storage, &oco_order)?;
+        msgs.extend(execute_payout(
+            deps.storage,
+            &oco_order.trader,
+            oco_order.margin_amount,
+        )?);
+    }
+
+    // the position is validated and its fees are charged as if the trader opened it
+    let funds = SentFunds {
+        asset: Asset {
+            info: config.eligible_collateral,
+            amount: order.margin_amount,
+        },
+        required: Uint128::zero(),
+        escrowed: true,
+        from_balance: false,
+    };
+    let (response, _) = open_position_with_funds(
+        deps,
+        env,
+        order.trader,
+        order.subaccount_id,
+        funds,
+        None,
+        order.vamm.to_string(),
+        order.side,
+        order.margin_amount,
+        order.leverage,
+        order.take_profit,
+        order.stop_loss,
+        order.base_asset_limit,
+    )?;
+
+    Ok(response.add_submessages(msgs).add_attributes(vec![
+        ("order_id", &order_id.to_string()),
+        (
+            "cancelled_order_id",
+            &order.oco_order_id.unwrap_or_default().to_string(),
+        ),
+        ("keeper", info.sender.as_ref()),
+    ]))
+}
+
+// Cancels a conditional order of the trader and refunds its escrowed margin
+pub fn cancel_conditional_order(
+    deps: DepsMut,
+    info: MessageInfo,
+    order_id: u64,
+) -> StdResult<Response> {
+    let order = read_order(deps.storage, order_id)?;
+
+    if order.trader != info.sender {
+        return Err(StdError::generic_err("Unauthorized"));
+    }
+
+    remove_order(deps.storage, &order)?;
+
+    // the sibling stays as a standalone order
+    if let Some(oco_order_id) = order.oco_order_id {
+        let mut oco_order = read_order(deps.storage, oco_order_id)?;
+        oco_order.oco_order_id = None;
+        store_order(deps.storage, &oco_order)?;
+    }
+
+    let msgs = execute_payout(deps.storage, &order.trader, order.margin_amount)?;
+
+    Ok(Response::new().add_submessages(msgs).add_attributes(vec![
+        ("action", "cancel_conditional_order"),
+        ("order_id", &order_id.to_string()),
+        ("trader", order.trader.as_ref()),
+        ("refund", &order.margin_amount.to_string()),
+    ]))
+}
+
+pub fn query_conditional_order(deps: Deps, order_id: u64) -> StdResult<ConditionalOrder> {
+    read_order(deps.storage, order_id)
+}
+
+pub fn query_conditional_orders(
+    deps: Deps,
+    trader: String,
+    start_after: Option<u64>,
+    limit: Option<u32>,
+) -> StdResult<Vec<ConditionalOrder>> {
+    let trader = deps.api.addr_validate(&trader)?;
+    read_conditional_orders(deps.storage, &trader, start_after, limit)
+}
+
+// Splits the margin of a twap order in equal slices, the last slice takes the remainder
+fn twap_slice_margin(order: &TwapOrder) -> StdResult<Uint128> {
+    let slice_margin = order
+        .margin_amount
+        .checked_div(Uint128::from(order.slices))?;
+
+    if order.filled_slices + 1 == order.slices {
+        return Ok(order
+            .margin_amount
+            .checked_sub(slice_margin.checked_mul(Uint128::from(order.filled_slices))?)?);
+    }
+
+    Ok(slice_margin)
+}
+
+// Places a twap order that is filled in slices into one position, escrowing the margin
+#[allow(clippy::too_many_arguments)]
+pub fn place_twap_order(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    vamm: String,
+    side: Side,
+    margin_amount: Uint128,
+    leverage: Uint128,
+    slices: u64,
+    interval: u64,
+    price_limit: Uint128,
+    subaccount_id: Option<u32>,
+) -> StdResult<Response> {
+    let vamm = deps.api.addr_validate(&vamm)?;
+    let config = read_config(deps.storage)?;
+    let state = read_state(deps.storage)?;
+    let trader = info.sender.clone();
+
+    require_not_paused(state.pause)?;
+    require_vamm(deps.as_ref(), &config.insurance_fund, &vamm)?;
+    require_non_zero_input(leverage)?;
+
+    if leverage < config.decimals {
+        return Err(StdError::generic_err("Leverage must be greater than 1"));
+    }
+
+    if slices == 0 || margin_amount.checked_div(Uint128::from(slices))?.is_zero() {
+        return Err(StdError::generic_err("Invalid number of TWAP slices"));
+    }
+
+    let mut msgs: Vec<SubMsg> = vec![];
+    match config.eligible_collateral {
+        AssetInfo::NativeToken { .. } => {
+            SentFunds {
+                asset: get_asset(info, config.eligible_collateral),
+                required: margin_amount,
+                escrowed: false,
+                from_balance: false,
+            }
+            .are_sufficient()?;
+        }
+        AssetInfo::Token { .. } => {
+            msgs.push(execute_transfer_from(
+                deps.storage,
+                &trader,
+                &env.contract.address,
+                margin_amount,
+            )?);
+        }
+    }
+
+    let order = TwapOrder {
+        order_id: increase_last_order_id(deps.storage)?,
+        vamm: vamm.clone(),
+        trader: trader.clone(),
+        side,
+        margin_amount,
+        leverage,
+        slices,
+        filled_slices: 0,
+        interval,
+        price_limit,
+        position_id: None,
+        last_filled_at: 0,
+        subaccount_id: subaccount_id.unwrap_or_default(),
+    };
+    store_twap_order(deps.storage, &order)?;
+
+    Ok(Response::new().add_submessages(msgs).add_attributes(vec![
+        ("action", "place_twap_order"),
+        ("vamm", vamm.as_ref()),
+        ("trader", trader.as_ref()),
+        ("order_id", &order.order_id.to_string()),
+        ("slices", &slices.to_string()),
+        ("escrow", &margin_amount.to_string()),
+    ]))
+}
+
+// Lets keepers fill the next slice of a twap order once the interval since the last one passed
+pub fn execute_twap_order(
+    mut deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    order_id: u64,
+) -> StdResult<Response> {
+    let config = read_config(deps.storage)?;
+    let mut order = read_twap_order(deps.storage, order_id)?;
+    let now = env.block.time.seconds();
+
+    if order.filled_slices > 0 && now < order.last_filled_at + order.interval {
+        return Err(StdError::generic_err("TWAP slice interval has not passed"));
+    }
+
+    let slice_margin = twap_slice_margin(&order)?;
+
+    // the price limit of the slice is checked by the vamm as a limit on the base asset amount
+    let base_asset_limit = if order.price_limit.is_zero() {
+        Uint128::zero()
+    } else {
+        let vamm_controller = VammController(order.vamm.clone());
+        let notional = slice_margin
+            .checked_mul(order.leverage)?
+            .checked_div(config.decimals)?;
+        let CalcFeeResponse {
+            spread_fee,
+            toll_fee,
+        } = vamm_controller.calc_fee(&deps.querier, notional)?;
+        slice_margin
+            .checked_sub(spread_fee)?
+            .checked_sub(toll_fee)?
+            .checked_mul(order.leverage)?
+            .checked_div(order.price_limit)?
+    };
+
+    // a new position is opened if the trader closed the one of the previous slices, or no longer
+    // owns it once a backstop liquidator took it over
+    let vamm_key = keccak_256(order.vamm.as_bytes());
+    let position_id = match order.position_id {
+        Some(position_id) => may_read_position(deps.storage, &vamm_key, position_id)?
+            .filter(|position| {
+                position.trader == order.trader && position.subaccount_id == order.subaccount_id
+            })
+            .map(|position| position.position_id),
+        None => None,
+    };
+
+    let funds = SentFunds {
+        asset: Asset {
+            info: config.eligible_collateral,
+            amount: slice_margin,
+        },
+        required: Uint128::zero(),
+        escrowed: true,
+        from_balance: false,
+    };
+    let (response, position_id) = open_position_with_funds(
+        deps.branch(),
+        env,
+        order.trader.clone(),
+        order.subaccount_id,
+        funds,
+        position_id,
+        order.vamm.to_string(),
+        order.side,
+        slice_margin,
+        order.leverage,
+        None,
+        None,
+        base_asset_limit,
+    )?;
+
+    order.position_id = Some(position_id);
+    order.filled_slices += 1;
+    order.last_filled_at = now;
+
+    if order.filled_slices == order.slices {
+        remove_twap_order(deps.storage, &order)?;
+    } else {
+        store_twap_order(deps.storage, &order)?;
+    }
+
+    Ok(response.add_attributes(vec![
+        ("order_id", &order_id.to_string()),
+        ("filled_slices", &order.filled_slices.to_string()),
+        ("slice_margin", &slice_margin.to_string()),
+        ("keeper", info.sender.as_ref()),
+    ]))
+}
+
+// Cancels a twap order of the trader and refunds the margin of the unfilled slices
+pub fn cancel_twap_order(deps: DepsMut, info: MessageInfo, order_id: u64) -> StdResult<Response> {
+    let order = read_twap_order(deps.storage, order_id)?;
+
+    if order.trader != info.sender {
+        return Err(StdError::generic_err("Unauthorized"));
+    }
+
+    let refund = twap_order_escrow(&order)?;
+    remove_twap_order(deps.storage, &order)?;
+
+    let msgs = execute_payout(deps.storage, &order.trader, refund)?;
+
+    Ok(Response::new().add_submessages(msgs).add_attributes(vec![
+        ("action", "cancel_twap_order"),
+        ("order_id", &order_id.to_string()),
+        ("trader", order.trader.as_ref()),
+        ("filled_slices", &order.filled_slices.to_string()),
+        ("refund", &refund.to_string()),
+    ]))
+}
+
+pub fn query_twap_order(deps: Deps, order_id: u64) -> StdResult<TwapOrder> {
+    read_twap_order(deps.storage, order_id)
+}
+
+pub fn query_twap_orders(
+    deps: Deps,
+    trader: String,
+    start_after: Option<u64>,
+    limit: Option<u32>,
+) -> StdResult<Vec<TwapOrder>> {
+    let trader = deps.api.addr_validate(&trader)?;
+    read_twap_orders(deps.storage, &trader, start_after, limit)
+}
@@ -1,8 +1,9 @@
 use cosmwasm_std::{Deps, Order, StdError, StdResult, Storage, Uint128};
 use margined_common::integer::Integer;
 use margined_perp::margined_engine::{
-    ConfigResponse, LastPositionIdResponse, PauserResponse, PnlCalcOption, Position,
-    PositionFilter, PositionTpSlResponse, PositionUnrealizedPnlResponse, Side, StateResponse,
+    ConfigResponse, LastPositionIdResponse, MarkPricePolicy, MarketStateResponse, PauserResponse,
+    PnlCalcOption, Position, PositionFilter, PositionTpSlResponse, PositionUnrealizedPnlResponse,
+    Side, StateResponse, TakeProfitLevel, WhitelistCapabilities,
 };
 use margined_utils::{
     contracts::helpers::{InsuranceFundController, VammController},
@@ -13,14 +14,16 @@ use crate::{
     contract::PAUSER,
     state::{
         read_config, read_last_position_id, read_position, read_positions,
-        read_positions_with_indexer, read_state, read_vamm_map, TmpReserveInfo,
-        PREFIX_POSITION_BY_PRICE, PREFIX_POSITION_BY_SIDE, PREFIX_POSITION_BY_TRADER,
+        read_positions_with_indexer, read_state, read_total_liabilities, read_vamm_map,
+        TmpReserveInfo, PREFIX_POSITION_BY_PRICE, PREFIX_POSITION_BY_SIDE,
+        PREFIX_POSITION_BY_SUBACCOUNT, PREFIX_POSITION_BY_TRADER,
     },
     tick::query_ticks,
     utils::{
-        calc_funding_payment, calc_remain_margin_with_funding_payment, calculate_tp_sl_spread,
-        check_tp_sl_price, get_position_notional_unrealized_pnl, keccak_256, position_is_bad_debt,
-        position_is_liquidated,
+        calc_funding_payment, calculate_tp_sl_spread, check_tp_sl_price,
+        get_margin_ratio_calc_option, get_mark_price_notional_unrealized_pnl,
+        get_position_notional_unrealized_pnl, get_tp_sl_close_price, is_within_quote_asset_limit,
+        keccak_256, position_is_bad_debt, position_is_liquidated, whitelist_capabilities,
     },
 };
 
@@ -39,6 +42,7 @@ pub fn query_state(deps: Deps) -> StdResult<StateResponse> {
         open_interest_notional: state.open_interest_notional,
         bad_debt: state.prepaid_bad_debt,
         pause: state.pause,
+        total_liabilities: read_total_liabilities(deps.storage)?,
     })
 }
 
@@ -51,6 +55,16 @@ pub fn query_pauser(deps: Deps) -> StdResult<PauserResponse> {
     }
 }
 
+/// Queries the checks a whitelisted address bypasses
+pub fn query_whitelist_capabilities(
+    deps: Deps,
+    address: String,
+) -> StdResult<WhitelistCapabilities> {
+    let address = deps.api.addr_validate(&address)?;
+
+    whitelist_capabilities(deps, &address)
+}
+
 /// Queries user position
 pub fn query_position(deps: Deps, vamm: String, position_id: u64) -> StdResult<Position> {
     // if vamm and trader are not correct, vamm_key will throw not found error
@@ -87,6 +101,21 @@ pub fn query_positions(
             limit,
             order_by,
         )?,
+        PositionFilter::Subaccount(trader_addr, subaccount_id) => {
+            read_positions_with_indexer::<Side>(
+                storage,
+                &[
+                    PREFIX_POSITION_BY_SUBACCOUNT,
+                    vamm_key,
+                    trader_addr.as_bytes(),
+                    &subaccount_id.to_be_bytes(),
+                ],
+                direction_filter,
+                start_after,
+                limit,
+                order_by,
+            )?
+        }
         PositionFilter::Price(price) => {
             let price_key = price.to_be_bytes();
             read_positions_with_indexer::<Side>(
@@ -210,26 +239,31 @@ pub fn query_trader_position_with_funding_payment(
     Ok(position)
 }
 
-/// Queries the margin ratio of a trader
+/// Queries the margin ratio of a trader at the mark price policy of the vamm
 pub fn query_margin_ratio(deps: Deps, position: &Position) -> StdResult<Integer> {
     if position.size.is_zero() {
         return Ok(Integer::zero());
     }
 
-    let PositionUnrealizedPnlResponse {
-        position_notional,
-        unrealized_pnl,
-    } = get_position_notional_unrealized_pnl(deps, position, PnlCalcOption::SpotPrice)?;
-
-    let remain_margin = calc_remain_margin_with_funding_payment(deps, position, unrealized_pnl)?;
+    let vamm_map = read_vamm_map(deps.storage, &position.vamm)?;
 
-    let config = read_config(deps.storage)?;
-    let margin_ratio = ((Integer::new_positive(remain_margin.margin)
-        - Integer::new_positive(remain_margin.bad_debt))
-        * Integer::new_positive(config.decimals))
-        / Integer::new_positive(position_notional);
+    match vamm_map.mark_price_policy {
+        MarkPricePolicy::SpotPrice => {
+            get_margin_ratio_calc_option(deps, position, PnlCalcOption::SpotPrice)
+        }
+        MarkPricePolicy::Twap => get_margin_ratio_calc_option(deps, position, PnlCalcOption::Twap),
+        MarkPricePolicy::Oracle => {
+            get_margin_ratio_calc_option(deps, position, PnlCalcOption::Oracle)
+        }
+        MarkPricePolicy::MaxHealth => {
+            let spot_margin_ratio =
+                get_margin_ratio_calc_option(deps, position, PnlCalcOption::SpotPrice)?;
+            let oracle_margin_ratio =
+                get_margin_ratio_calc_option(deps, position, PnlCalcOption::Oracle)?;
 
-    Ok(margin_ratio)
+            Ok(Integer::max(spot_margin_ratio, oracle_margin_ratio))
+        }
+    }
 }
 
 /// Queries the withdrawable collateral of a trader
@@ -237,11 +271,11 @@ pub fn query_free_collateral(deps: Deps, vamm: String, position_id: u64) -> StdR
     // retrieve the latest position
     let position = query_trader_position_with_funding_payment(deps, vamm.clone(), position_id)?;
 
-    // get trader's unrealized PnL and choose the least beneficial one for the trader
+    // get trader's unrealized PnL at the mark price and twap, choose the least beneficial one
     let PositionUnrealizedPnlResponse {
-        position_notional: spot_notional,
-        unrealized_pnl: spot_pnl,
-    } = get_position_notional_unrealized_pnl(deps, &position, PnlCalcOption::SpotPrice)?;
+        position_notional: mark_notional,
+        unrealized_pnl: mark_pnl,
+    } = get_mark_price_notional_unrealized_pnl(deps, &position)?;
     let PositionUnrealizedPnlResponse {
         position_notional: twap_notional,
         unrealized_pnl: twap_pnl,
@@ -251,15 +285,15 @@ pub fn query_free_collateral(deps: Deps, vamm: String, position_id: u64) -> StdR
     let PositionUnrealizedPnlResponse {
         position_notional,
         unrealized_pnl,
-    } = if spot_pnl.abs() > twap_pnl.abs() {
+    } = if mark_pnl.abs() > twap_pnl.abs() {
         PositionUnrealizedPnlResponse {
             position_notional: twap_notional,
             unrealized_pnl: twap_pnl,
         }
     } else {
         PositionUnrealizedPnlResponse {
-            position_notional: spot_notional,
-            unrealized_pnl: spot_pnl,
+            position_notional: mark_notional,
+            unrealized_pnl: mark_pnl,
         }
     };
 
@@ -351,9 +385,13 @@ pub fn query_position_is_tpsl(
                 tmp_reserve.quote_asset_reserve,
                 tmp_reserve.base_asset_reserve,
             )?;
-            let close_price = quote_asset_amount
-                .checked_mul(config.decimals)?
-                .checked_div(base_asset_amount)?;
+            let (close_price, quote_asset_limit) = get_tp_sl_close_price(
+                deps,
+                position,
+                base_asset_amount,
+                quote_asset_amount,
+                do_tp,
+            )?;
 
             let stop_loss = position.stop_loss.unwrap_or_default();
             let take_profit = position.take_profit.unwrap_or_default();
@@ -378,7 +416,13 @@ pub fn query_position_is_tpsl(
                 tp_sl_action == "trigger_stop_loss"
             };
 
-            if tp_sl_flag {
+            if tp_sl_flag
+                && is_within_quote_asset_limit(
+                    &position.side,
+                    quote_asset_amount,
+                    quote_asset_limit,
+                )
+            {
                 return Ok(PositionTpSlResponse { is_tpsl: true });
             }
         }
@@ -405,8 +449,7 @@ pub fn query_position_is_bad_debt(deps: Deps, position_id: u64, vamm: String) ->
 pub fn query_position_is_liquidated(deps: Deps, position_id: u64, vamm: String) -> StdResult<bool> {
     let config = read_config(deps.storage)?;
     let vamm_key = keccak_256(vamm.as_bytes());
-    let vamm_addr = deps.api.addr_validate(&vamm)?;
-    let vamm_controller = VammController(vamm_addr.clone());
+    let vamm_controller = VammController(deps.api.addr_validate(&vamm)?);
     let position = read_position(deps.storage, &vamm_key, position_id)?;
     let is_liquidated = position_is_liquidated(
         deps,
@@ -416,3 +459,42 @@ pub fn query_position_is_liquidated(deps: Deps, position_id: u64, vamm: String)
     )?;
     Ok(is_liquidated)
 }
+
+/// Queries the remaining take profit levels of a position
+pub fn query_take_profit_levels(
+    deps: Deps,
+    vamm: String,
+    position_id: u64,
+) -> StdResult<Vec<TakeProfitLevel>> {
+    let vamm_key = keccak_256(vamm.as_bytes());
+    let position = read_position(deps.storage, &vamm_key, position_id)?;
+
+    Ok(position.take_profit_levels)
+}
+
+/// Queries the open interest of each side of a market and its caps
+pub fn query_market_state(deps: Deps, vamm: String) -> StdResult<MarketStateResponse> {
+    let vamm = deps.api.addr_validate(&vamm)?;
+    let vamm_map = read_vamm_map(deps.storage, &vamm)?;
+    let open_interest_notional_cap = VammController(vamm)
+        .config(&deps.querier)?
+        .open_interest_notional_cap;
+
+    Ok(MarketStateResponse {
+        open_interest_notional: vamm_map
+            .long_open_interest_notional
+            .checked_add(vamm_map.short_open_interest_notional)?,
+        long_open_interest_notional: vamm_map.long_open_interest_notional,
+        short_open_interest_notional: vamm_map.short_open_interest_notional,
+        open_interest_notional_cap,
+        long_open_interest_cap: vamm_map.long_open_interest_cap,
+        short_open_interest_cap: vamm_map.short_open_interest_cap,
+    })
+}
+
+/// Queries the mark price policy of a vamm
+pub fn query_mark_price_policy(deps: Deps, vamm: String) -> StdResult<MarkPricePolicy> {
+    let vamm_map = read_vamm_map(deps.storage, &deps.api.addr_validate(&vamm)?)?;
+
+    Ok(vamm_map.mark_price_policy)
+}
@@ -2,39 +2,67 @@ use cosmwasm_std::{DepsMut, Env, Response, StdError, StdResult, SubMsg, Uint128}
 use margined_utils::contracts::helpers::VammController;
 
 use crate::{
+    account::credit_trader_balance,
+    adl::update_adl_candidate,
+    handle::liquidation_msg,
+    limits::record_realized_pnl,
     messages::{
-        execute_insurance_fund_withdrawal, execute_transfer, execute_transfer_from,
-        execute_transfer_to_insurance_fund, transfer_fees, withdraw,
+        execute_insurance_fund_withdrawal, execute_payout, execute_transfer, execute_transfer_from,
+        execute_transfer_to_insurance_fund, transfer_fees, withdraw, withdraw_to_subaccount,
     },
+    query::query_margin_ratio,
     state::{
-        append_cumulative_premium_fraction, enter_restriction_mode, read_config, read_position,
-        read_sent_funds, read_state, read_tmp_liquidator, read_tmp_swap, remove_position,
-        remove_sent_funds, remove_tmp_liquidator, remove_tmp_swap, store_position, store_state,
-        State,
+        append_cumulative_premium_fraction, enter_restriction_mode, may_read_position,
+        read_claimable, read_config, read_position, read_sent_funds, read_state,
+        read_tmp_keeper_reward, read_tmp_liquidation, read_tmp_liquidator, read_tmp_payout,
+        read_tmp_swap, remove_position, remove_sent_funds, remove_tmp_keeper_reward,
+        remove_tmp_liquidation, remove_tmp_liquidator, remove_tmp_payout, remove_tmp_swap,
+        remove_tmp_worst_price, store_claimable, store_position, store_state, State,
     },
     utils::{
-        calc_remain_margin_with_funding_payment, check_base_asset_holding_cap, keccak_256,
-        realize_bad_debt, side_to_direction, update_open_interest_notional,
+        calc_liquidation_fees, calc_remain_margin_with_funding_payment,
+        check_base_asset_holding_cap, keccak_256, realize_bad_debt, require_worst_price,
+        side_to_direction, update_open_interest_notional, LiquidationFees,
     },
 };
 
 use margined_common::{asset::AssetInfo, integer::Integer};
 use margined_perp::{
-    margined_engine::{Position, RemainMarginResponse, Side},
+    margined_engine::{Position, RemainMarginResponse, Side, TpSlTrigger},
     margined_vamm::Direction,
 };
 
 // Updates position after successful execution of the swap
 pub fn open_position_reply(
-    deps: DepsMut,
+    mut deps: DepsMut,
     env: Env,
     input: Uint128,
     output: Uint128,
     position_id: u64,
+    exact_margin: bool,
 ) -> StdResult<Response> {
     let mut swap = read_tmp_swap(deps.storage, &position_id.to_be_bytes())?;
+    let vamm_key = keccak_256(swap.vamm.as_bytes());
+
+    require_worst_price(
+        deps.storage,
+        position_id,
+        swap.side == Side::Buy,
+        input,
+        output,
+    )?;
+
+    // the slices of a twap order after the first are added to its position
+    let existing_position = if swap.is_increase {
+        let position = may_read_position(deps.storage, &vamm_key, position_id)?
+            .ok_or_else(|| StdError::generic_err("Position not found"))?;
+        remove_position(deps.storage, &vamm_key, &position)?;
+        Some(position)
+    } else {
+        None
+    };
 
-    let mut position = Position {
+    let mut position = existing_position.unwrap_or(Position {
         position_id: swap.position_id,
         vamm: swap.vamm.clone(),
         trader: swap.trader.clone(),
@@ -47,11 +75,15 @@ pub fn open_position_reply(
         entry_price: Uint128::zero(),
         take_profit: swap.take_profit,
         stop_loss: swap.stop_loss,
+        tp_sl_trigger: TpSlTrigger::default(),
+        trailing_stop: None,
+        take_profit_levels: vec![],
         last_updated_premium_fraction: Integer::zero(),
         spread_fee: swap.spread_fee,
         toll_fee: swap.toll_fee,
         block_time: env.block.time.seconds(),
-    };
+        subaccount_id: swap.subaccount_id,
+    });
 
     // depending on the direction the output is positive or negative
     let signed_output = match &swap.side {
@@ -63,19 +95,24 @@ pub fn open_position_reply(
     let config = read_config(deps.storage)?;
 
     update_open_interest_notional(
-        &deps.as_ref(),
+        deps.branch(),
         &mut state,
         swap.vamm.clone(),
+        swap.side,
         Integer::new_positive(input),
         swap.trader.clone(),
     )?;
 
     // define variables that differ across increase and decrease scenario
-    // calculate margin needed given swap
-    let swap_margin = swap
-        .open_notional
-        .checked_mul(config.decimals)?
-        .checked_div(swap.leverage)?;
+    // calculate margin needed given swap, an open by size knows its margin and only derives
+    // its leverage from it
+    let swap_margin = if exact_margin {
+        swap.margin_amount
+    } else {
+        swap.open_notional
+            .checked_mul(config.decimals)?
+            .checked_div(swap.leverage)?
+    };
 
     swap.margin_to_vault = swap
         .margin_to_vault
@@ -92,7 +129,7 @@ pub fn open_position_reply(
     } = calc_remain_margin_with_funding_payment(deps.as_ref(), &position, margin_delta)?;
 
     // set the new position
-    position.notional = swap.open_notional;
+    position.notional = position.notional.checked_add(swap.open_notional)?;
     position.size += signed_output;
     position.margin = margin;
     position.last_updated_premium_fraction = latest_premium_fraction;
@@ -101,9 +138,13 @@ pub fn open_position_reply(
         .checked_mul(config.decimals)?
         .checked_div(position.size.value)?;
     position.block_time = env.block.time.seconds();
+    if swap.is_increase {
+        position.spread_fee = position.spread_fee.checked_add(swap.spread_fee)?;
+        position.toll_fee = position.toll_fee.checked_add(swap.toll_fee)?;
+    }
 
-    let vamm_key = keccak_256(position.vamm.as_bytes());
     store_position(deps.storage, &vamm_key, &position, true)?;
+    update_adl_candidate(deps.branch(), &position)?;
 
     // check the new position doesn't exceed any caps
     check_base_asset_holding_cap(
@@ -119,10 +160,7 @@ pub fn open_position_reply(
     // create transfer messages depending on PnL
     if swap.margin_to_vault.is_positive() {
         match config.eligible_collateral {
-            AssetInfo::NativeToken { .. } => {
-                funds.required = funds.required.checked_add(swap_margin)?;
-            }
-            AssetInfo::Token { .. } => {
+            AssetInfo::Token { .. } if !funds.escrowed => {
                 msgs.push(execute_transfer_from(
                     deps.storage,
                     &swap.trader,
@@ -130,16 +168,19 @@ pub fn open_position_reply(
                     swap.margin_to_vault.value,
                 )?);
             }
+            _ => {
+                funds.required = funds.required.checked_add(swap_margin)?;
+            }
         }
     };
 
-    // create messages to pay for toll and spread fees, check flag is true if this follows a reverse
+    // create messages to pay for toll and spread fees, escrowed fees are paid by the engine
     let mut fees_messages = transfer_fees(
         deps.as_ref(),
-        swap.trader,
+        swap.trader.clone(),
         swap.spread_fee,
         swap.toll_fee,
-        true,
+        !funds.escrowed,
     )?;
     // add the fee transfer messages
     msgs.append(&mut fees_messages);
@@ -150,8 +191,26 @@ pub fn open_position_reply(
         .checked_add(swap.spread_fee)?
         .checked_add(swap.toll_fee)?;
 
-    // check if native tokens are sufficient
-    if let AssetInfo::NativeToken { .. } = config.eligible_collateral {
+    if funds.from_balance {
+        // credit back what is left of the balance of the sub-account
+        let refund = funds
+            .asset
+            .amount
+            .checked_sub(funds.required)
+            .map_err(|_| StdError::generic_err("Insufficient trader balance"))?;
+        credit_trader_balance(deps.storage, &swap.trader, swap.subaccount_id, refund)?;
+    } else if funds.escrowed {
+        // refund the escrowed margin that was lost to rounding
+        let refund = funds
+            .asset
+            .amount
+            .checked_sub(funds.required)
+            .map_err(|_| StdError::generic_err("sent funds are insufficient"))?;
+        if !refund.is_zero() {
+            msgs.extend(execute_payout(deps.storage, &swap.trader, refund)?);
+        }
+    } else if let AssetInfo::NativeToken { .. } = config.eligible_collateral {
+        // check if native tokens are sufficient
         funds.are_sufficient()?;
     }
 
@@ -170,9 +229,9 @@ pub fn open_position_reply(
 
 // Closes position after successful execution of the swap
 pub fn close_position_reply(
-    deps: DepsMut,
+    mut deps: DepsMut,
     env: Env,
-    _input: Uint128,
+    input: Uint128,
     output: Uint128,
     position_id: u64,
 ) -> StdResult<Response> {
@@ -180,6 +239,14 @@ pub fn close_position_reply(
     let vamm_key = keccak_256(swap.vamm.as_bytes());
     let position = read_position(deps.storage, &vamm_key, position_id)?;
 
+    require_worst_price(
+        deps.storage,
+        position_id,
+        position.side == Side::Sell,
+        output,
+        input,
+    )?;
+
     let margin_delta = match &position.direction {
         Direction::AddToAmm => {
             Integer::new_positive(output) - Integer::new_positive(swap.open_notional)
@@ -196,6 +263,14 @@ pub fn close_position_reply(
         latest_premium_fraction: _,
     } = calc_remain_margin_with_funding_payment(deps.as_ref(), &position, margin_delta)?;
 
+    record_realized_pnl(
+        deps.storage,
+        &env,
+        &swap.trader,
+        swap.subaccount_id,
+        margin_delta,
+    )?;
+
     let mut msgs: Vec<SubMsg> = vec![];
     let mut withdraw_amount = Integer::new_positive(margin).checked_add(swap.unrealized_pnl)?;
     let mut spread_fee = Uint128::zero();
@@ -228,19 +303,44 @@ pub fn close_position_reply(
         return Err(StdError::generic_err("Cannot close position - bad debt"));
     }
 
+    // the keeper that triggered the close is paid from what is left of the margin
+    let keeper_reward = read_tmp_keeper_reward(deps.storage, position_id)?;
+    let mut keeper_reward_amount = Uint128::zero();
+    if let Some(keeper_reward) = &keeper_reward {
+        keeper_reward_amount = Uint128::min(keeper_reward.amount, withdraw_amount.value);
+        withdraw_amount.value = withdraw_amount.value.checked_sub(keeper_reward_amount)?;
+        remove_tmp_keeper_reward(deps.storage, position_id);
+    }
+
     let mut state = read_state(deps.storage)?;
+    let mut unfunded_bad_debt = Uint128::zero();
     if !withdraw_amount.is_zero() {
         let config = read_config(deps.storage)?;
-        msgs.append(&mut withdraw(
-            deps.as_ref(),
+        let fees = spread_fee
+            .checked_add(toll_fee)?
+            .checked_add(keeper_reward_amount)?;
+        unfunded_bad_debt = withdraw_to_subaccount(
+            deps.branch(),
             env,
             &mut state,
             &swap.trader,
+            position.subaccount_id,
             config.eligible_collateral,
             withdraw_amount.value,
-            spread_fee.checked_add(toll_fee)?,
+            fees,
             Uint128::zero(),
-        )?);
+            &mut msgs,
+        )?;
+    }
+
+    if let Some(keeper_reward) = &keeper_reward {
+        if !keeper_reward_amount.is_zero() {
+            msgs.extend(execute_payout(
+                deps.storage,
+                &keeper_reward.keeper,
+                keeper_reward_amount,
+            )?);
+        }
     }
 
     if !spread_fee.is_zero() && !toll_fee.is_zero() {
@@ -258,9 +358,10 @@ pub fn close_position_reply(
         margin_delta + Integer::new_positive(bad_debt) + Integer::new_positive(position.notional);
 
     update_open_interest_notional(
-        &deps.as_ref(),
+        deps.branch(),
         &mut state,
         swap.vamm,
+        position.side,
         value.invert_sign(),
         swap.trader,
     )?;
@@ -285,13 +386,15 @@ pub fn close_position_reply(
         ("funding_payment", &funding_payment.to_string()),
         ("bad_debt", &bad_debt.to_string()),
         ("withdraw_amount", &withdraw_amount.value.to_string()),
+        ("keeper_reward", &keeper_reward_amount.to_string()),
         ("trader", &position.trader.as_ref()),
+        ("unfunded_bad_debt", &unfunded_bad_debt.to_string()),
     ]))
 }
 
 // Partially closes position
 pub fn partial_close_position_reply(
-    deps: DepsMut,
+    mut deps: DepsMut,
     env: Env,
     input: Uint128,
     output: Uint128,
@@ -301,11 +404,20 @@ pub fn partial_close_position_reply(
     let vamm_key = keccak_256(swap.vamm.as_bytes());
     let mut position = read_position(deps.storage, &vamm_key, position_id)?;
 
+    require_worst_price(
+        deps.storage,
+        position_id,
+        position.side == Side::Sell,
+        input,
+        output,
+    )?;
+
     let mut state: State = read_state(deps.storage)?;
     update_open_interest_notional(
-        &deps.as_ref(),
+        deps.branch(),
         &mut state,
         swap.vamm.clone(),
+        position.side,
         Integer::new_negative(input),
         swap.trader.clone(),
     )?;
@@ -330,6 +442,14 @@ pub fn partial_close_position_reply(
         latest_premium_fraction,
     } = calc_remain_margin_with_funding_payment(deps.as_ref(), &position, realized_pnl)?;
 
+    record_realized_pnl(
+        deps.storage,
+        &env,
+        &swap.trader,
+        swap.subaccount_id,
+        realized_pnl,
+    )?;
+
     let unrealized_pnl_after = swap.unrealized_pnl - realized_pnl;
 
     let remaining_notional = if position.size > Integer::zero() {
@@ -342,7 +462,7 @@ pub fn partial_close_position_reply(
     };
 
     // calculate the fees
-    let fees_messages = transfer_fees(
+    let mut msgs = transfer_fees(
         deps.as_ref(),
         swap.trader,
         swap.spread_fee,
@@ -350,14 +470,30 @@ pub fn partial_close_position_reply(
         false,
     )?;
 
+    // the keeper that triggered a take profit level is paid from the margin
+    let mut keeper_reward_amount = Uint128::zero();
+    if let Some(keeper_reward) = read_tmp_keeper_reward(deps.storage, position_id)? {
+        keeper_reward_amount = Uint128::min(keeper_reward.amount, margin);
+        remove_tmp_keeper_reward(deps.storage, position_id);
+
+        if !keeper_reward_amount.is_zero() {
+            msgs.extend(execute_payout(
+                deps.storage,
+                &keeper_reward.keeper,
+                keeper_reward_amount,
+            )?);
+        }
+    }
+
     // set the new position
     position.size += signed_output;
-    position.margin = margin;
+    position.margin = margin.checked_sub(keeper_reward_amount)?;
     position.notional = remaining_notional.value;
     position.last_updated_premium_fraction = latest_premium_fraction;
     position.block_time = env.block.time.seconds();
 
     store_position(deps.storage, &vamm_key, &position, false)?;
+    update_adl_candidate(deps.branch(), &position)?;
     store_state(deps.storage, &state)?;
 
     // to prevent attacker to leverage the bad debt to withdraw extra token from insurance fund
@@ -368,31 +504,30 @@ pub fn partial_close_position_reply(
     // remove the tmp position
     remove_tmp_swap(deps.storage, &position_id.to_be_bytes());
 
-    Ok(Response::new()
-        .add_submessages(fees_messages)
-        .add_attributes(vec![
-            ("action", "partial_close_position_reply"),
-            (
-                "take_profit",
-                &position.take_profit.unwrap_or_default().to_string(),
-            ),
-            (
-                "stop_loss",
-                &position.stop_loss.unwrap_or_default().to_string(),
-            ),
-            ("pnl", &unrealized_pnl_after.to_string()),
-            ("spread_fee", &swap.spread_fee.to_string()),
-            ("toll_fee", &swap.toll_fee.to_string()),
-            ("funding_payment", &funding_payment.to_string()),
-            ("bad_debt", &bad_debt.to_string()),
-        ]))
+    Ok(Response::new().add_submessages(msgs).add_attributes(vec![
+        ("action", "partial_close_position_reply"),
+        (
+            "take_profit",
+            &position.take_profit.unwrap_or_default().to_string(),
+        ),
+        (
+            "stop_loss",
+            &position.stop_loss.unwrap_or_default().to_string(),
+        ),
+        ("pnl", &unrealized_pnl_after.to_string()),
+        ("spread_fee", &swap.spread_fee.to_string()),
+        ("toll_fee", &swap.toll_fee.to_string()),
+        ("funding_payment", &funding_payment.to_string()),
+        ("bad_debt", &bad_debt.to_string()),
+        ("keeper_reward", &keeper_reward_amount.to_string()),
+    ]))
 }
 
 // Liquidates position after successful execution of the swap
 pub fn liquidate_reply(
-    deps: DepsMut,
+    mut deps: DepsMut,
     env: Env,
-    _input: Uint128,
+    input: Uint128,
     output: Uint128,
     position_id: u64,
 ) -> StdResult<Response> {
@@ -401,6 +536,14 @@ pub fn liquidate_reply(
     let vamm_key = keccak_256(swap.vamm.as_bytes());
     let position = read_position(deps.storage, &vamm_key, position_id)?;
 
+    require_worst_price(
+        deps.storage,
+        position_id,
+        position.side == Side::Sell,
+        output,
+        input,
+    )?;
+
     // calculate delta from trade and whether it was profitable or a loss
     let margin_delta = match &position.direction {
         Direction::RemoveFromAmm => {
@@ -414,6 +557,14 @@ pub fn liquidate_reply(
     let mut remain_margin =
         calc_remain_margin_with_funding_payment(deps.as_ref(), &position, margin_delta)?;
 
+    record_realized_pnl(
+        deps.storage,
+        &env,
+        &position.trader,
+        position.subaccount_id,
+        margin_delta,
+    )?;
+
     let config = read_config(deps.storage)?;
 
     let liquidator = match config.operator {
@@ -426,7 +577,8 @@ pub fn liquidate_reply(
         .checked_mul(config.liquidation_fee)?
         .checked_div(config.decimals)?;
 
-    let liquidation_fee = liquidation_penalty.checked_div(Uint128::from(2u64))?;
+    let fees = calc_liquidation_fees(&config, liquidation_penalty)?;
+    let liquidation_fee = fees.liquidator;
 
     if liquidation_fee > remain_margin.margin {
         let bad_debt = liquidation_fee.checked_sub(remain_margin.margin)?;
@@ -438,6 +590,13 @@ pub fn liquidate_reply(
         remain_margin.margin = remain_margin.margin.checked_sub(liquidation_fee)?;
     }
 
+    // the fee pool and then the insurance fund are paid their share of the penalty out of the
+    // remaining margin, what is left of the margin after the penalty goes to the insurance fund
+    let fee_pool_fee = Uint128::min(fees.fee_pool, remain_margin.margin);
+    remain_margin.margin = remain_margin.margin.checked_sub(fee_pool_fee)?;
+    let insurance_fee = Uint128::min(fees.insurance_fund, remain_margin.margin);
+    remain_margin.margin = remain_margin.margin.checked_sub(insurance_fee)?;
+
     let mut msgs: Vec<SubMsg> = vec![];
 
     let mut state = read_state(deps.storage)?;
@@ -447,11 +606,20 @@ pub fn liquidate_reply(
         Uint128::zero()
     };
 
-    // any remaining margin goes to the insurance contract
-    if !remain_margin.margin.is_zero() {
+    if !fee_pool_fee.is_zero() {
+        msgs.push(execute_transfer(
+            deps.storage,
+            &config.fee_pool,
+            fee_pool_fee,
+        )?);
+    }
+
+    // the share of the insurance fund and any remaining margin go to the insurance contract
+    let insurance_amount = insurance_fee.checked_add(remain_margin.margin)?;
+    if !insurance_amount.is_zero() {
         let msg = match config.insurance_fund {
             Some(insurance_fund) => {
-                execute_transfer(deps.storage, &insurance_fund, remain_margin.margin)?
+                execute_transfer(deps.storage, &insurance_fund, insurance_amount)?
             }
             None => return Err(StdError::generic_err("insurance fund is not registered")),
         };
@@ -459,8 +627,8 @@ pub fn liquidate_reply(
         msgs.push(msg);
     }
 
-    msgs.append(&mut withdraw(
-        deps.as_ref(),
+    let unfunded_bad_debt = withdraw(
+        deps.branch(),
         env.clone(),
         &mut state,
         &liquidator,
@@ -468,7 +636,8 @@ pub fn liquidate_reply(
         liquidation_fee,
         Uint128::zero(),
         pre_paid_shortfall,
-    )?);
+        &mut msgs,
+    )?;
 
     store_state(deps.storage, &state)?;
 
@@ -489,22 +658,27 @@ pub fn liquidate_reply(
             "stop_loss",
             &position.stop_loss.unwrap_or_default().to_string(),
         ),
+        ("liquidation_penalty", &liquidation_penalty.to_string()),
         ("liquidation_fee", &liquidation_fee.to_string()),
+        ("fee_pool_fee", &fee_pool_fee.to_string()),
+        ("insurance_fee", &insurance_fee.to_string()),
+        ("remaining_margin", &remain_margin.margin.to_string()),
         ("pnl", &margin_delta.to_string()),
         (
             "funding_payment",
             &remain_margin.funding_payment.to_string(),
         ),
         ("bad_debt", &remain_margin.bad_debt.to_string()),
+        ("unfunded_bad_debt", &unfunded_bad_debt.to_string()),
     ]))
 }
 
 // Partially liquidates the position
 pub fn partial_liquidation_reply(
-    deps: DepsMut,
+    mut deps: DepsMut,
     env: Env,
-    input: Uint128,
-    output: Uint128,
+    base_asset_amount: Uint128,
+    quote_asset_amount: Uint128,
     position_id: u64,
 ) -> StdResult<Response> {
     let swap = read_tmp_swap(deps.storage, &position_id.to_be_bytes())?;
@@ -513,26 +687,58 @@ pub fn partial_liquidation_reply(
     let mut position = read_position(deps.storage, &vamm_key, position_id)?;
     let config = read_config(deps.storage)?;
 
+    require_worst_price(
+        deps.storage,
+        position_id,
+        position.side == Side::Sell,
+        quote_asset_amount,
+        base_asset_amount,
+    )?;
+
     let liquidator = match config.operator {
         Some(addr) => addr,
         None => read_tmp_liquidator(deps.storage)?,
     };
 
-    // calculate delta from trade and whether it was profitable or a loss
-    let realized_pnl = (swap.unrealized_pnl
-        * Integer::new_positive(config.partial_liquidation_ratio))
-        / Integer::new_positive(config.decimals);
+    // calculate delta from trade and whether it was profitable or a loss, the pnl is realized
+    // for the size the swap closed, which is not the size asked when the close was capped at
+    // the open notional of the position
+    let realized_pnl =
+        if config.target_margin_ratio.is_zero() && base_asset_amount == swap.margin_amount {
+            (swap.unrealized_pnl * Integer::new_positive(config.partial_liquidation_ratio))
+                / Integer::new_positive(config.decimals)
+        } else {
+            (swap.unrealized_pnl * Integer::new_positive(base_asset_amount))
+                / Integer::new_positive(position.size.value)
+        };
 
-    let liquidation_penalty = output
+    record_realized_pnl(
+        deps.storage,
+        &env,
+        &position.trader,
+        position.subaccount_id,
+        realized_pnl,
+    )?;
+
+    let liquidation_penalty = quote_asset_amount
         .checked_mul(config.liquidation_fee)?
         .checked_div(config.decimals)?;
 
-    let liquidation_fee = liquidation_penalty.checked_div(Uint128::from(2u64))?;
+    // the insurance fund takes the rounding of the split, so that the whole penalty taken from
+    // the margin is paid out
+    let LiquidationFees {
+        liquidator: liquidation_fee,
+        fee_pool: fee_pool_fee,
+        ..
+    } = calc_liquidation_fees(&config, liquidation_penalty)?;
+    let insurance_fee = liquidation_penalty
+        .checked_sub(liquidation_fee)?
+        .checked_sub(fee_pool_fee)?;
 
     if position.size < Integer::zero() {
-        position.size += Integer::new_positive(input);
+        position.size += Integer::new_positive(base_asset_amount);
     } else {
-        position.size += Integer::new_negative(input);
+        position.size += Integer::new_negative(base_asset_amount);
     }
 
     // reduce the traders margin
@@ -550,30 +756,39 @@ pub fn partial_liquidation_reply(
             negative: false, ..
         } => position
             .notional
-            .checked_sub(swap.open_notional)?
+            .checked_sub(quote_asset_amount)?
             .checked_sub(realized_pnl.value)?,
         Integer { negative: true, .. } => realized_pnl
             .value
             .checked_add(position.notional)?
-            .checked_sub(swap.open_notional)?,
+            .checked_sub(quote_asset_amount)?,
     };
 
     let mut messages: Vec<SubMsg> = vec![];
     let mut state = read_state(deps.storage)?;
-    if !liquidation_fee.is_zero() {
+    if !insurance_fee.is_zero() {
         let msg = match config.insurance_fund {
-            Some(insurance_fund) => {
-                execute_transfer(deps.storage, &insurance_fund, liquidation_fee)?
-            }
+            Some(insurance_fund) => execute_transfer(deps.storage, &insurance_fund, insurance_fee)?,
             None => return Err(StdError::generic_err("insurance fund is not registered")),
         };
 
         messages.push(msg);
+    }
+
+    if !fee_pool_fee.is_zero() {
+        messages.push(execute_transfer(
+            deps.storage,
+            &config.fee_pool,
+            fee_pool_fee,
+        )?);
+    }
 
+    let mut unfunded_bad_debt = Uint128::zero();
+    if !liquidation_fee.is_zero() {
         // calculate token balance that should be remaining once
         // insurance fees have been paid
-        messages.append(&mut withdraw(
-            deps.as_ref(),
+        unfunded_bad_debt = withdraw(
+            deps.branch(),
             env.clone(),
             &mut state,
             &liquidator,
@@ -581,10 +796,12 @@ pub fn partial_liquidation_reply(
             liquidation_fee,
             Uint128::zero(),
             Uint128::zero(),
-        )?);
+            &mut messages,
+        )?;
     }
 
     store_position(deps.storage, &vamm_key, &position, false)?;
+    update_adl_candidate(deps.branch(), &position)?;
     store_state(deps.storage, &state)?;
 
     remove_tmp_swap(deps.storage, &position_id.to_be_bytes());
@@ -597,7 +814,10 @@ pub fn partial_liquidation_reply(
         .add_attributes(vec![
             ("action", "partial_liquidation_reply"),
             ("liquidation_fee", &liquidation_fee.to_string()),
+            ("insurance_fee", &insurance_fee.to_string()),
+            ("fee_pool_fee", &fee_pool_fee.to_string()),
             ("pnl", &realized_pnl.to_string()),
+            ("unfunded_bad_debt", &unfunded_bad_debt.to_string()),
         ]))
 }
 
@@ -637,3 +857,55 @@ pub fn pay_funding_reply(
         ("funding_payment", &funding_payment.to_string()),
     ]))
 }
+
+// Clears the liquidation once the backstop pool has taken over the position
+pub fn backstop_pool_reply(deps: DepsMut) -> StdResult<Response> {
+    let liquidation = read_tmp_liquidation(deps.storage)?;
+
+    remove_tmp_liquidation(deps.storage);
+    remove_tmp_liquidator(deps.storage);
+    // the worst price only applies to liquidations through the vAMM
+    remove_tmp_worst_price(deps.storage, liquidation.position_id);
+
+    Ok(Response::new().add_attributes(vec![
+        ("action", "backstop_pool_reply"),
+        ("vamm", liquidation.vamm.as_ref()),
+        ("position_id", &liquidation.position_id.to_string()),
+    ]))
+}
+
+// Falls back to liquidating through the vAMM when the backstop pool cannot absorb the position
+pub fn backstop_pool_failure_reply(deps: DepsMut) -> StdResult<Response> {
+    let liquidation = read_tmp_liquidation(deps.storage)?;
+    remove_tmp_liquidation(deps.storage);
+
+    let vamm_key = keccak_256(liquidation.vamm.as_bytes());
+    let position = read_position(deps.storage, &vamm_key, liquidation.position_id)?;
+    let margin_ratio = query_margin_ratio(deps.as_ref(), &position)?;
+
+    let msg = liquidation_msg(deps, &position, margin_ratio, liquidation.quote_asset_limit)?;
+
+    Ok(Response::new().add_submessage(msg).add_attributes(vec![
+        ("action", "backstop_pool_failure_reply"),
+        ("vamm", liquidation.vamm.as_ref()),
+        ("position_id", &liquidation.position_id.to_string()),
+    ]))
+}
+
+// Credits a failed payout to the claimable balance of the receiver instead of reverting the
+// close or liquidation that paid it
+pub fn payout_reply(deps: DepsMut, reply_id: u64, error: String) -> StdResult<Response> {
+    let payout = read_tmp_payout(deps.storage, reply_id)?;
+    remove_tmp_payout(deps.storage, reply_id);
+
+    let claimable = read_claimable(deps.storage, &payout.receiver)?.checked_add(payout.amount)?;
+    store_claimable(deps.storage, &payout.receiver, claimable)?;
+
+    Ok(Response::new().add_attributes(vec![
+        ("action", "payout_failure"),
+        ("receiver", payout.receiver.as_ref()),
+        ("amount", &payout.amount.to_string()),
+        ("claimable", &claimable.to_string()),
+        ("error", &error),
+    ]))
+}
@@ -1,33 +1,67 @@
 use cosmwasm_schema::cw_serde;
 use cosmwasm_schema::serde::{de::DeserializeOwned, Serialize};
 use cosmwasm_std::{
-    from_slice, to_vec, Addr, Order as OrderBy, StdError, StdResult, Storage, Uint128,
+    from_slice, to_vec, Addr, Binary, Order as OrderBy, StdError, StdResult, Storage, Uint128,
 };
 use cosmwasm_storage::{singleton, singleton_read, Bucket, ReadonlyBucket};
 use std::cmp::Ordering;
 
 use margined_common::{asset::Asset, integer::Integer};
-use margined_perp::margined_engine::{ConfigResponse, Position, Side};
+use margined_perp::margined_engine::{
+    BackfillStage, ConditionalOrder, ConfigResponse, EscrowResponse, MarkPricePolicy, Position,
+    Side, TraderLimits, TwapOrder, WhitelistCapabilities,
+};
 
-use crate::utils::calc_range_start;
+use crate::utils::{calc_range_start, keccak_256};
 
 // settings for pagination
 pub const MAX_LIMIT: u32 = 100;
 pub const DEFAULT_LIMIT: u32 = 10;
+// max take profit levels of a position
+pub const MAX_TAKE_PROFIT_LEVELS: usize = 10;
+// max positions of a side that are ranked for auto-deleveraging
+pub const MAX_ADL_CANDIDATES: usize = 50;
 
 pub static KEY_CONFIG: &[u8] = b"config";
 pub static KEY_STATE: &[u8] = b"state";
 pub static KEY_SENT_FUNDS: &[u8] = b"sent-funds";
 pub static KEY_TMP_SWAP: &[u8] = b"tmp-swap";
 pub static KEY_TMP_LIQUIDATOR: &[u8] = b"tmp-liquidator";
+pub static KEY_TMP_LIQUIDATION: &[u8] = b"tmp-liquidation";
+pub static KEY_TMP_KEEPER_REWARD: &[u8] = b"tmp-keeper-reward";
+pub static KEY_TMP_PAYOUT: &[u8] = b"tmp-payout";
+pub static KEY_TMP_WORST_PRICE: &[u8] = b"tmp-worst-price";
 pub static KEY_VAMM_MAP: &[u8] = b"vamm-map";
 pub static KEY_LAST_POSITION_ID: &[u8] = b"last_position_id";
+pub static KEY_LAST_ORDER_ID: &[u8] = b"last_order_id";
+pub static KEY_LAST_PAYOUT_ID: &[u8] = b"last_payout_id";
+pub static KEY_TOTAL_LIABILITIES: &[u8] = b"total_liabilities";
+pub static KEY_STORAGE_VERSION: &[u8] = b"storage_version";
+pub static KEY_BACKFILL: &[u8] = b"backfill";
 
 static PREFIX_POSITION: &[u8] = b"position"; // prefix position
 pub static PREFIX_POSITION_BY_SIDE: &[u8] = b"position_by_direction"; // position from the direction
 pub static PREFIX_POSITION_BY_PRICE: &[u8] = b"position_by_price"; // position from the price
 pub static PREFIX_POSITION_BY_TRADER: &[u8] = b"position_by_trader"; // position from a trader
+pub static PREFIX_POSITION_BY_SUBACCOUNT: &[u8] = b"position_by_subaccount"; // position from a sub-account
 pub static PREFIX_TICK: &[u8] = b"tick"; // this is tick with value is the total positions
+static PREFIX_ADL_CANDIDATES: &[u8] = b"adl_candidates"; // positions of a side ranked for deleveraging
+static PREFIX_TRADER_BALANCE: &[u8] = b"trader_balance"; // free collateral of a trader
+static PREFIX_CLAIMABLE: &[u8] = b"claimable"; // payouts that failed to be transferred
+static PREFIX_BLOCKLIST: &[u8] = b"blocklist"; // addresses blocked from trading and payouts
+static PREFIX_ESCROW: &[u8] = b"escrow"; // payouts held while the receiver is blocked
+static PREFIX_CREDIT_PROCEEDS: &[u8] = b"credit_proceeds"; // traders crediting close proceeds to the balance
+static PREFIX_TRADER_LIMITS: &[u8] = b"trader_limits"; // limits set by the owner on a trader
+static PREFIX_SELF_LIMITS: &[u8] = b"self_limits"; // limits set by a trader on themselves
+static PREFIX_PENDING_SELF_LIMITS: &[u8] = b"pending_self_limits"; // looser self limits of the next day
+static PREFIX_DAILY_LOSS: &[u8] = b"daily_loss"; // loss realized by a trader in a day
+static PREFIX_WHITELIST_CAPABILITIES: &[u8] = b"whitelist_capabilities"; // what a whitelisted address bypasses
+static PREFIX_SIGNING_KEY: &[u8] = b"signing_key"; // public key signing the orders of a trader
+static PREFIX_USED_NONCE: &[u8] = b"used_nonce"; // nonces of signed orders of a trader
+static PREFIX_ORDER: &[u8] = b"order"; // prefix conditional order
+static PREFIX_ORDER_BY_TRADER: &[u8] = b"order_by_trader"; // conditional order from a trader
+static PREFIX_TWAP_ORDER: &[u8] = b"twap_order"; // prefix twap order
+static PREFIX_TWAP_ORDER_BY_TRADER: &[u8] = b"twap_order_by_trader"; // twap order from a trader
 
 pub type Config = ConfigResponse;
 
@@ -74,6 +108,60 @@ pub fn read_state(storage: &dyn Storage) -> StdResult<State> {
     }
 }
 
+/// Reads the version of the stored data, zero before versions were kept
+pub fn read_storage_version(storage: &dyn Storage) -> StdResult<u32> {
+    Ok(singleton_read(storage, KEY_STORAGE_VERSION)
+        .may_load()?
+        .unwrap_or_default())
+}
+
+pub fn store_storage_version(storage: &mut dyn Storage, version: u32) -> StdResult<()> {
+    singleton(storage, KEY_STORAGE_VERSION).save(&version)
+}
+
+#[cw_serde]
+pub struct Backfill {
+    pub stages: Vec<BackfillStage>, // stages left, the first one is in progress
+    pub start_after: Option<Binary>, // key of the last entry read by the stage in progress
+    pub seen_vamms: Vec<Addr>,      // vamms whose open interest is already reset
+}
+
+pub fn store_backfill(storage: &mut dyn Storage, backfill: &Backfill) -> StdResult<()> {
+    singleton(storage, KEY_BACKFILL).save(backfill)
+}
+
+pub fn remove_backfill(storage: &mut dyn Storage) {
+    singleton::<Backfill>(storage, KEY_BACKFILL).remove()
+}
+
+pub fn read_backfill(storage: &dyn Storage) -> StdResult<Option<Backfill>> {
+    singleton_read(storage, KEY_BACKFILL).may_load()
+}
+
+/// Collateral the engine holds for traders outside of their positions: free balances,
+/// the margin escrowed by orders and payouts that are claimable or escrowed. It is kept
+/// apart from the state so that a state read before a payout cannot overwrite it
+pub fn read_total_liabilities(storage: &dyn Storage) -> StdResult<Uint128> {
+    Ok(singleton_read(storage, KEY_TOTAL_LIABILITIES)
+        .may_load()?
+        .unwrap_or_default())
+}
+
+fn update_total_liabilities(
+    storage: &mut dyn Storage,
+    previous: Uint128,
+    current: Uint128,
+) -> StdResult<()> {
+    let total = read_total_liabilities(storage)?
+        .checked_add(current)?
+        .checked_sub(previous)?;
+    store_total_liabilities(storage, total)
+}
+
+pub fn store_total_liabilities(storage: &mut dyn Storage, total: Uint128) -> StdResult<()> {
+    singleton(storage, KEY_TOTAL_LIABILITIES).save(&total)
+}
+
 pub fn store_position(
     storage: &mut dyn Storage,
     key: &[u8],
@@ -103,6 +191,17 @@ pub fn store_position(
     )
     .save(position_id_key, &position.side)?;
 
+    Bucket::multilevel(
+        storage,
+        &[
+            PREFIX_POSITION_BY_SUBACCOUNT,
+            key,
+            position.trader.as_bytes(),
+            &position.subaccount_id.to_be_bytes(),
+        ],
+    )
+    .save(position_id_key, &position.side)?;
+
     Bucket::multilevel(
         storage,
         &[PREFIX_POSITION_BY_SIDE, key, &position.side.as_bytes()],
@@ -115,6 +214,26 @@ pub fn store_position(
     Ok(total_tick_orders)
 }
 
+/// Reads the ids of the positions of a side that are ranked for auto-deleveraging, highest rank
+/// first
+pub fn read_adl_candidates(storage: &dyn Storage, key: &[u8], side: &Side) -> StdResult<Vec<u64>> {
+    Ok(
+        ReadonlyBucket::multilevel(storage, &[PREFIX_ADL_CANDIDATES, key])
+            .may_load(side.as_bytes())?
+            .unwrap_or_default(),
+    )
+}
+
+pub fn store_adl_candidates(
+    storage: &mut dyn Storage,
+    key: &[u8],
+    side: &Side,
+    candidates: &[u64],
+) -> StdResult<()> {
+    Bucket::multilevel(storage, &[PREFIX_ADL_CANDIDATES, key])
+        .save(side.as_bytes(), &candidates.to_vec())
+}
+
 pub fn remove_position(
     storage: &mut dyn Storage,
     key: &[u8],
@@ -148,6 +267,17 @@ pub fn remove_position(
     )
     .remove(position_id_key);
 
+    Bucket::<Side>::multilevel(
+        storage,
+        &[
+            PREFIX_POSITION_BY_SUBACCOUNT,
+            key,
+            position.trader.as_bytes(),
+            &position.subaccount_id.to_be_bytes(),
+        ],
+    )
+    .remove(position_id_key);
+
     Bucket::<Side>::multilevel(
         storage,
         &[PREFIX_POSITION_BY_SIDE, key, &position.side.as_bytes()],
@@ -157,6 +287,12 @@ pub fn remove_position(
     Bucket::<Side>::multilevel(storage, &[PREFIX_POSITION_BY_PRICE, key, &price_key])
         .remove(position_id_key);
 
+    let mut candidates = read_adl_candidates(storage, key, &position.side)?;
+    if candidates.contains(&position.position_id) {
+        candidates.retain(|position_id| *position_id != position.position_id);
+        store_adl_candidates(storage, key, &position.side, &candidates)?;
+    }
+
     // return total orders belong to the tick
     Ok(total_tick_orders)
 }
@@ -165,6 +301,15 @@ pub fn read_position(storage: &dyn Storage, key: &[u8], position_id: u64) -> Std
     ReadonlyBucket::multilevel(storage, &[PREFIX_POSITION, key]).load(&position_id.to_be_bytes())
 }
 
+pub fn may_read_position(
+    storage: &dyn Storage,
+    key: &[u8],
+    position_id: u64,
+) -> StdResult<Option<Position>> {
+    ReadonlyBucket::multilevel(storage, &[PREFIX_POSITION, key])
+        .may_load(&position_id.to_be_bytes())
+}
+
 /// read_positions_with_indexer: namespace is PREFIX + KEY + INDEXER
 pub fn read_positions_with_indexer<T: Serialize + DeserializeOwned>(
     storage: &dyn Storage,
@@ -217,12 +362,301 @@ pub fn read_positions(
         .collect()
 }
 
+/// the main account keeps the key of the trader, other sub-accounts are nested under the trader so
+/// that the length prefix of the trader keeps their keys apart
+fn save_by_subaccount<T: Serialize + DeserializeOwned>(
+    storage: &mut dyn Storage,
+    namespace: &[u8],
+    trader: &Addr,
+    subaccount_id: u32,
+    data: &T,
+) -> StdResult<()> {
+    match subaccount_id {
+        0 => Bucket::new(storage, namespace).save(trader.as_bytes(), data),
+        _ => Bucket::multilevel(storage, &[namespace, trader.as_bytes()])
+            .save(&subaccount_id.to_be_bytes(), data),
+    }
+}
+
+fn remove_by_subaccount<T: Serialize + DeserializeOwned>(
+    storage: &mut dyn Storage,
+    namespace: &[u8],
+    trader: &Addr,
+    subaccount_id: u32,
+) {
+    match subaccount_id {
+        0 => Bucket::<T>::new(storage, namespace).remove(trader.as_bytes()),
+        _ => Bucket::<T>::multilevel(storage, &[namespace, trader.as_bytes()])
+            .remove(&subaccount_id.to_be_bytes()),
+    }
+}
+
+fn may_load_by_subaccount<T: Serialize + DeserializeOwned>(
+    storage: &dyn Storage,
+    namespace: &[u8],
+    trader: &Addr,
+    subaccount_id: u32,
+) -> StdResult<Option<T>> {
+    match subaccount_id {
+        0 => ReadonlyBucket::new(storage, namespace).may_load(trader.as_bytes()),
+        _ => ReadonlyBucket::multilevel(storage, &[namespace, trader.as_bytes()])
+            .may_load(&subaccount_id.to_be_bytes()),
+    }
+}
+
+/// reads the entries of every sub-account of the trader, the main account first
+fn read_all_subaccounts<T: Serialize + DeserializeOwned>(
+    storage: &dyn Storage,
+    namespace: &[u8],
+    trader: &Addr,
+) -> StdResult<Vec<T>> {
+    let mut entries: Vec<T> = ReadonlyBucket::new(storage, namespace)
+        .may_load(trader.as_bytes())?
+        .into_iter()
+        .collect();
+    for item in ReadonlyBucket::<T>::multilevel(storage, &[namespace, trader.as_bytes()]).range(
+        None,
+        None,
+        OrderBy::Ascending,
+    ) {
+        entries.push(item?.1);
+    }
+
+    Ok(entries)
+}
+
+pub fn store_trader_balance(
+    storage: &mut dyn Storage,
+    trader: &Addr,
+    subaccount_id: u32,
+    balance: Uint128,
+) -> StdResult<()> {
+    let previous = read_trader_balance(storage, trader, subaccount_id)?;
+    update_total_liabilities(storage, previous, balance)?;
+
+    save_by_subaccount(
+        storage,
+        PREFIX_TRADER_BALANCE,
+        trader,
+        subaccount_id,
+        &balance,
+    )
+}
+
+pub fn read_trader_balance(
+    storage: &dyn Storage,
+    trader: &Addr,
+    subaccount_id: u32,
+) -> StdResult<Uint128> {
+    Ok(
+        may_load_by_subaccount(storage, PREFIX_TRADER_BALANCE, trader, subaccount_id)?
+            .unwrap_or_default(),
+    )
+}
+
+pub fn store_credit_proceeds(
+    storage: &mut dyn Storage,
+    trader: &Addr,
+    credit_proceeds: bool,
+) -> StdResult<()> {
+    let mut bucket = Bucket::new(storage, PREFIX_CREDIT_PROCEEDS);
+    if credit_proceeds {
+        bucket.save(trader.as_bytes(), &true)
+    } else {
+        bucket.remove(trader.as_bytes());
+        Ok(())
+    }
+}
+
+pub fn read_credit_proceeds(storage: &dyn Storage, trader: &Addr) -> StdResult<bool> {
+    Ok(ReadonlyBucket::<bool>::new(storage, PREFIX_CREDIT_PROCEEDS)
+        .may_load(trader.as_bytes())?
+        .is_some())
+}
+
+pub fn store_signing_key(
+    storage: &mut dyn Storage,
+    trader: &Addr,
+    public_key: &Binary,
+) -> StdResult<()> {
+    Bucket::new(storage, PREFIX_SIGNING_KEY).save(trader.as_bytes(), public_key)
+}
+
+pub fn remove_signing_key(storage: &mut dyn Storage, trader: &Addr) {
+    Bucket::<Binary>::new(storage, PREFIX_SIGNING_KEY).remove(trader.as_bytes())
+}
+
+pub fn read_signing_key(storage: &dyn Storage, trader: &Addr) -> StdResult<Option<Binary>> {
+    ReadonlyBucket::new(storage, PREFIX_SIGNING_KEY).may_load(trader.as_bytes())
+}
+
+pub fn store_used_nonce(storage: &mut dyn Storage, trader: &Addr, nonce: u64) -> StdResult<()> {
+    Bucket::multilevel(storage, &[PREFIX_USED_NONCE, trader.as_bytes()])
+        .save(&nonce.to_be_bytes(), &true)
+}
+
+pub fn is_nonce_used(storage: &dyn Storage, trader: &Addr, nonce: u64) -> StdResult<bool> {
+    Ok(
+        ReadonlyBucket::<bool>::multilevel(storage, &[PREFIX_USED_NONCE, trader.as_bytes()])
+            .may_load(&nonce.to_be_bytes())?
+            .is_some(),
+    )
+}
+
+// the counter starts at zero for engines deployed before conditional orders
+pub fn increase_last_order_id(storage: &mut dyn Storage) -> StdResult<u64> {
+    let mut last_order_id = singleton(storage, KEY_LAST_ORDER_ID);
+    let order_id = last_order_id.may_load()?.unwrap_or(0u64) + 1;
+    last_order_id.save(&order_id)?;
+    Ok(order_id)
+}
+
+pub fn store_order(storage: &mut dyn Storage, order: &ConditionalOrder) -> StdResult<()> {
+    let order_id_key = &order.order_id.to_be_bytes();
+    let previous = ReadonlyBucket::<ConditionalOrder>::new(storage, PREFIX_ORDER)
+        .may_load(order_id_key)?
+        .map(|order| order.margin_amount)
+        .unwrap_or_default();
+    update_total_liabilities(storage, previous, order.margin_amount)?;
+
+    Bucket::new(storage, PREFIX_ORDER).save(order_id_key, order)?;
+    Bucket::multilevel(storage, &[PREFIX_ORDER_BY_TRADER, order.trader.as_bytes()])
+        .save(order_id_key, &order.side)
+}
+
+pub fn remove_order(storage: &mut dyn Storage, order: &ConditionalOrder) -> StdResult<()> {
+    let order_id_key = &order.order_id.to_be_bytes();
+    if let Some(stored) =
+        ReadonlyBucket::<ConditionalOrder>::new(storage, PREFIX_ORDER).may_load(order_id_key)?
+    {
+        update_total_liabilities(storage, stored.margin_amount, Uint128::zero())?;
+    }
+
+    Bucket::<ConditionalOrder>::new(storage, PREFIX_ORDER).remove(order_id_key);
+    Bucket::<Side>::multilevel(storage, &[PREFIX_ORDER_BY_TRADER, order.trader.as_bytes()])
+        .remove(order_id_key);
+    Ok(())
+}
+
+pub fn read_order(storage: &dyn Storage, order_id: u64) -> StdResult<ConditionalOrder> {
+    ReadonlyBucket::new(storage, PREFIX_ORDER)
+        .load(&order_id.to_be_bytes())
+        .map_err(|_| StdError::generic_err("Conditional order not found"))
+}
+
+/// read_orders_by_trader: reads the orders under PREFIX from the index of the trader
+fn read_orders_by_trader<T: Serialize + DeserializeOwned>(
+    storage: &dyn Storage,
+    namespaces: (&[u8], &[u8]),
+    trader: &Addr,
+    start_after: Option<u64>,
+    limit: Option<u32>,
+) -> StdResult<Vec<T>> {
+    let limit = limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT) as usize;
+    let start = calc_range_start(start_after.map(|id| id.to_be_bytes().to_vec()));
+
+    let order_indexer: ReadonlyBucket<Side> =
+        ReadonlyBucket::multilevel(storage, &[namespaces.1, trader.as_bytes()]);
+    let order_bucket = ReadonlyBucket::new(storage, namespaces.0);
+
+    order_indexer
+        .range(start.as_deref(), None, OrderBy::Ascending)
+        .take(limit)
+        .map(|item| order_bucket.load(&item?.0))
+        .collect()
+}
+
+pub fn read_conditional_orders(
+    storage: &dyn Storage,
+    trader: &Addr,
+    start_after: Option<u64>,
+    limit: Option<u32>,
+) -> StdResult<Vec<ConditionalOrder>> {
+    read_orders_by_trader(
+        storage,
+        (PREFIX_ORDER, PREFIX_ORDER_BY_TRADER),
+        trader,
+        start_after,
+        limit,
+    )
+}
+
+/// Margin of the unfilled slices of a twap order that the engine holds in escrow
+pub fn twap_order_escrow(order: &TwapOrder) -> StdResult<Uint128> {
+    let filled_margin = order
+        .margin_amount
+        .checked_div(Uint128::from(order.slices))?
+        .checked_mul(Uint128::from(order.filled_slices))?;
+
+    Ok(order.margin_amount.checked_sub(filled_margin)?)
+}
+
+pub fn store_twap_order(storage: &mut dyn Storage, order: &TwapOrder) -> StdResult<()> {
+    let order_id_key = &order.order_id.to_be_bytes();
+    let previous = match ReadonlyBucket::<TwapOrder>::new(storage, PREFIX_TWAP_ORDER)
+        .may_load(order_id_key)?
+    {
+        Some(stored) => twap_order_escrow(&stored)?,
+        None => Uint128::zero(),
+    };
+    update_total_liabilities(storage, previous, twap_order_escrow(order)?)?;
+
+    Bucket::new(storage, PREFIX_TWAP_ORDER).save(order_id_key, order)?;
+    Bucket::multilevel(
+        storage,
+        &[PREFIX_TWAP_ORDER_BY_TRADER, order.trader.as_bytes()],
+    )
+    .save(order_id_key, &order.side)
+}
+
+pub fn remove_twap_order(storage: &mut dyn Storage, order: &TwapOrder) -> StdResult<()> {
+    let order_id_key = &order.order_id.to_be_bytes();
+    if let Some(stored) =
+        ReadonlyBucket::<TwapOrder>::new(storage, PREFIX_TWAP_ORDER).may_load(order_id_key)?
+    {
+        update_total_liabilities(storage, twap_order_escrow(&stored)?, Uint128::zero())?;
+    }
+
+    Bucket::<TwapOrder>::new(storage, PREFIX_TWAP_ORDER).remove(order_id_key);
+    Bucket::<Side>::multilevel(
+        storage,
+        &[PREFIX_TWAP_ORDER_BY_TRADER, order.trader.as_bytes()],
+    )
+    .remove(order_id_key);
+    Ok(())
+}
+
+pub fn read_twap_order(storage: &dyn Storage, order_id: u64) -> StdResult<TwapOrder> {
+    ReadonlyBucket::new(storage, PREFIX_TWAP_ORDER)
+        .load(&order_id.to_be_bytes())
+        .map_err(|_| StdError::generic_err("TWAP order not found"))
+}
+
+pub fn read_twap_orders(
+    storage: &dyn Storage,
+    trader: &Addr,
+    start_after: Option<u64>,
+    limit: Option<u32>,
+) -> StdResult<Vec<TwapOrder>> {
+    read_orders_by_trader(
+        storage,
+        (PREFIX_TWAP_ORDER, PREFIX_TWAP_ORDER_BY_TRADER),
+        trader,
+        start_after,
+        limit,
+    )
+}
+
 /// Used to monitor that transferred native tokens are sufficient when opening a
 /// new position or relevant operations
 #[cw_serde]
 pub struct SentFunds {
     pub asset: Asset,
     pub required: Uint128,
+    // the asset is already held by the engine, e.g. the margin of a conditional order
+    pub escrowed: bool,
+    // the escrowed asset is the balance of the trader, what is left of it is credited back
+    pub from_balance: bool,
 }
 
 impl SentFunds {
@@ -270,6 +704,10 @@ pub struct TmpSwapInfo {
     pub stop_loss: Option<Uint128>,   // stop loss price of position
     pub spread_fee: Uint128,          // spread fee
     pub toll_fee: Uint128,            // toll fee
+    #[serde(default)]
+    pub subaccount_id: u32, // sub-account of the trader owning the position
+    #[serde(default)]
+    pub is_increase: bool, // adds to the stored position, set for the twap slices after the first
 }
 
 pub fn store_tmp_swap(storage: &mut dyn Storage, swap: &TmpSwapInfo) -> StdResult<()> {
@@ -302,6 +740,332 @@ pub fn read_tmp_liquidator(storage: &dyn Storage) -> StdResult<Addr> {
     }
 }
 
+#[cw_serde]
+pub struct TmpLiquidationInfo {
+    pub vamm: Addr,
+    pub position_id: u64,
+    pub quote_asset_limit: Uint128,
+}
+
+pub fn store_tmp_liquidation(
+    storage: &mut dyn Storage,
+    liquidation: &TmpLiquidationInfo,
+) -> StdResult<()> {
+    storage.set(KEY_TMP_LIQUIDATION, &to_vec(liquidation)?);
+    Ok(())
+}
+
+pub fn remove_tmp_liquidation(storage: &mut dyn Storage) {
+    storage.remove(KEY_TMP_LIQUIDATION)
+}
+
+pub fn read_tmp_liquidation(storage: &dyn Storage) -> StdResult<TmpLiquidationInfo> {
+    match storage.get(KEY_TMP_LIQUIDATION) {
+        Some(data) => from_slice(&data),
+        None => Err(StdError::generic_err("Liquidation not found")),
+    }
+}
+
+/// Reward of the keeper that triggered the close of a position, paid from its margin
+#[cw_serde]
+pub struct TmpKeeperReward {
+    pub keeper: Addr,
+    pub amount: Uint128,
+}
+
+pub fn store_tmp_keeper_reward(
+    storage: &mut dyn Storage,
+    position_id: u64,
+    reward: &TmpKeeperReward,
+) -> StdResult<()> {
+    Bucket::new(storage, KEY_TMP_KEEPER_REWARD).save(&position_id.to_be_bytes(), reward)
+}
+
+pub fn remove_tmp_keeper_reward(storage: &mut dyn Storage, position_id: u64) {
+    Bucket::<'_, TmpKeeperReward>::new(storage, KEY_TMP_KEEPER_REWARD)
+        .remove(&position_id.to_be_bytes())
+}
+
+pub fn read_tmp_keeper_reward(
+    storage: &dyn Storage,
+    position_id: u64,
+) -> StdResult<Option<TmpKeeperReward>> {
+    ReadonlyBucket::new(storage, KEY_TMP_KEEPER_REWARD).may_load(&position_id.to_be_bytes())
+}
+
+pub fn store_tmp_worst_price(
+    storage: &mut dyn Storage,
+    position_id: u64,
+    worst_price: Uint128,
+) -> StdResult<()> {
+    Bucket::new(storage, KEY_TMP_WORST_PRICE).save(&position_id.to_be_bytes(), &worst_price)
+}
+
+pub fn remove_tmp_worst_price(storage: &mut dyn Storage, position_id: u64) {
+    Bucket::<'_, Uint128>::new(storage, KEY_TMP_WORST_PRICE).remove(&position_id.to_be_bytes())
+}
+
+pub fn read_tmp_worst_price(storage: &dyn Storage, position_id: u64) -> StdResult<Option<Uint128>> {
+    ReadonlyBucket::new(storage, KEY_TMP_WORST_PRICE).may_load(&position_id.to_be_bytes())
+}
+
+#[cw_serde]
+pub struct TmpPayout {
+    pub receiver: Addr,
+    pub amount: Uint128,
+}
+
+pub fn increase_last_payout_id(storage: &mut dyn Storage) -> StdResult<u64> {
+    let mut last_payout_id = singleton(storage, KEY_LAST_PAYOUT_ID);
+    let payout_id = last_payout_id.may_load()?.unwrap_or(0u64) + 1;
+    last_payout_id.save(&payout_id)?;
+    Ok(payout_id)
+}
+
+pub fn store_tmp_payout(
+    storage: &mut dyn Storage,
+    reply_id: u64,
+    payout: &TmpPayout,
+) -> StdResult<()> {
+    Bucket::new(storage, KEY_TMP_PAYOUT).save(&reply_id.to_be_bytes(), payout)
+}
+
+pub fn remove_tmp_payout(storage: &mut dyn Storage, reply_id: u64) {
+    Bucket::<'_, TmpPayout>::new(storage, KEY_TMP_PAYOUT).remove(&reply_id.to_be_bytes())
+}
+
+pub fn read_tmp_payout(storage: &dyn Storage, reply_id: u64) -> StdResult<TmpPayout> {
+    ReadonlyBucket::new(storage, KEY_TMP_PAYOUT).load(&reply_id.to_be_bytes())
+}
+
+pub fn store_claimable(
+    storage: &mut dyn Storage,
+    address: &Addr,
+    amount: Uint128,
+) -> StdResult<()> {
+    let previous = read_claimable(storage, address)?;
+    update_total_liabilities(storage, previous, amount)?;
+
+    let mut bucket = Bucket::new(storage, PREFIX_CLAIMABLE);
+    if amount.is_zero() {
+        bucket.remove(address.as_bytes());
+        Ok(())
+    } else {
+        bucket.save(address.as_bytes(), &amount)
+    }
+}
+
+pub fn read_claimable(storage: &dyn Storage, address: &Addr) -> StdResult<Uint128> {
+    Ok(ReadonlyBucket::new(storage, PREFIX_CLAIMABLE)
+        .may_load(address.as_bytes())?
+        .unwrap_or_default())
+}
+
+pub fn store_blocked(storage: &mut dyn Storage, address: &Addr) -> StdResult<()> {
+    Bucket::new(storage, PREFIX_BLOCKLIST).save(address.as_bytes(), &true)
+}
+
+pub fn remove_blocked(storage: &mut dyn Storage, address: &Addr) {
+    Bucket::<bool>::new(storage, PREFIX_BLOCKLIST).remove(address.as_bytes())
+}
+
+pub fn is_blocked(storage: &dyn Storage, address: &Addr) -> StdResult<bool> {
+    Ok(ReadonlyBucket::<bool>::new(storage, PREFIX_BLOCKLIST)
+        .may_load(address.as_bytes())?
+        .is_some())
+}
+
+pub fn read_blocklist(
+    storage: &dyn Storage,
+    start_after: Option<Addr>,
+    limit: Option<u32>,
+) -> StdResult<Vec<Addr>> {
+    let limit = limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT) as usize;
+    let start = calc_range_start(start_after.map(|address| address.as_bytes().to_vec()));
+
+    ReadonlyBucket::<bool>::new(storage, PREFIX_BLOCKLIST)
+        .range(start.as_deref(), None, OrderBy::Ascending)
+        .take(limit)
+        .map(|item| {
+            let (key, _) = item?;
+            Ok(Addr::unchecked(String::from_utf8(key)?))
+        })
+        .collect()
+}
+
+pub fn store_escrowed(storage: &mut dyn Storage, address: &Addr, amount: Uint128) -> StdResult<()> {
+    let previous = read_escrowed(storage, address)?;
+    update_total_liabilities(storage, previous, amount)?;
+
+    let mut bucket = Bucket::new(storage, PREFIX_ESCROW);
+    if amount.is_zero() {
+        bucket.remove(address.as_bytes());
+        Ok(())
+    } else {
+        bucket.save(address.as_bytes(), &amount)
+    }
+}
+
+pub fn read_escrowed(storage: &dyn Storage, address: &Addr) -> StdResult<Uint128> {
+    Ok(ReadonlyBucket::new(storage, PREFIX_ESCROW)
+        .may_load(address.as_bytes())?
+        .unwrap_or_default())
+}
+
+pub fn read_escrows(
+    storage: &dyn Storage,
+    start_after: Option<Addr>,
+    limit: Option<u32>,
+) -> StdResult<Vec<EscrowResponse>> {
+    let limit = limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT) as usize;
+    let start = calc_range_start(start_after.map(|address| address.as_bytes().to_vec()));
+
+    ReadonlyBucket::<Uint128>::new(storage, PREFIX_ESCROW)
+        .range(start.as_deref(), None, OrderBy::Ascending)
+        .take(limit)
+        .map(|item| {
+            let (key, amount) = item?;
+            Ok(EscrowResponse {
+                address: Addr::unchecked(String::from_utf8(key)?),
+                amount,
+            })
+        })
+        .collect()
+}
+
+pub fn store_trader_limits(
+    storage: &mut dyn Storage,
+    trader: &Addr,
+    limits: &TraderLimits,
+) -> StdResult<()> {
+    Bucket::new(storage, PREFIX_TRADER_LIMITS).save(trader.as_bytes(), limits)
+}
+
+pub fn read_trader_limits(storage: &dyn Storage, trader: &Addr) -> StdResult<TraderLimits> {
+    Ok(ReadonlyBucket::new(storage, PREFIX_TRADER_LIMITS)
+        .may_load(trader.as_bytes())?
+        .unwrap_or_default())
+}
+
+pub fn store_self_limits(
+    storage: &mut dyn Storage,
+    trader: &Addr,
+    subaccount_id: u32,
+    limits: &TraderLimits,
+) -> StdResult<()> {
+    save_by_subaccount(storage, PREFIX_SELF_LIMITS, trader, subaccount_id, limits)
+}
+
+pub fn read_self_limits(
+    storage: &dyn Storage,
+    trader: &Addr,
+    subaccount_id: u32,
+) -> StdResult<TraderLimits> {
+    Ok(
+        may_load_by_subaccount(storage, PREFIX_SELF_LIMITS, trader, subaccount_id)?
+            .unwrap_or_default(),
+    )
+}
+
+#[cw_serde]
+pub struct PendingLimits {
+    pub day: u64, // days since the unix epoch from which the limits apply
+    pub limits: TraderLimits,
+}
+
+pub fn store_pending_self_limits(
+    storage: &mut dyn Storage,
+    trader: &Addr,
+    subaccount_id: u32,
+    pending: &PendingLimits,
+) -> StdResult<()> {
+    save_by_subaccount(
+        storage,
+        PREFIX_PENDING_SELF_LIMITS,
+        trader,
+        subaccount_id,
+        pending,
+    )
+}
+
+pub fn remove_pending_self_limits(storage: &mut dyn Storage, trader: &Addr, subaccount_id: u32) {
+    remove_by_subaccount::<PendingLimits>(
+        storage,
+        PREFIX_PENDING_SELF_LIMITS,
+        trader,
+        subaccount_id,
+    )
+}
+
+pub fn read_pending_self_limits(
+    storage: &dyn Storage,
+    trader: &Addr,
+    subaccount_id: u32,
+) -> StdResult<Option<PendingLimits>> {
+    may_load_by_subaccount(storage, PREFIX_PENDING_SELF_LIMITS, trader, subaccount_id)
+}
+
+pub fn store_whitelist_capabilities(
+    storage: &mut dyn Storage,
+    address: &Addr,
+    capabilities: &WhitelistCapabilities,
+) -> StdResult<()> {
+    Bucket::new(storage, PREFIX_WHITELIST_CAPABILITIES).save(address.as_bytes(), capabilities)
+}
+
+pub fn remove_whitelist_capabilities(storage: &mut dyn Storage, address: &Addr) {
+    Bucket::<WhitelistCapabilities>::new(storage, PREFIX_WHITELIST_CAPABILITIES)
+        .remove(address.as_bytes())
+}
+
+// entries whitelisted before capabilities existed keep every capability
+pub fn read_whitelist_capabilities(
+    storage: &dyn Storage,
+    address: &Addr,
+) -> StdResult<WhitelistCapabilities> {
+    Ok(ReadonlyBucket::new(storage, PREFIX_WHITELIST_CAPABILITIES)
+        .may_load(address.as_bytes())?
+        .unwrap_or_else(WhitelistCapabilities::all))
+}
+
+#[cw_serde]
+#[derive(Default)]
+pub struct DailyLoss {
+    pub day: u64, // days since the unix epoch
+    pub loss: Uint128,
+}
+
+pub fn store_daily_loss(
+    storage: &mut dyn Storage,
+    trader: &Addr,
+    subaccount_id: u32,
+    daily_loss: &DailyLoss,
+) -> StdResult<()> {
+    save_by_subaccount(
+        storage,
+        PREFIX_DAILY_LOSS,
+        trader,
+        subaccount_id,
+        daily_loss,
+    )
+}
+
+pub fn read_daily_loss(
+    storage: &dyn Storage,
+    trader: &Addr,
+    subaccount_id: u32,
+) -> StdResult<DailyLoss> {
+    Ok(
+        may_load_by_subaccount(storage, PREFIX_DAILY_LOSS, trader, subaccount_id)?
+            .unwrap_or_default(),
+    )
+}
+
+/// Reads the daily losses of every sub-account of the trader
+pub fn read_daily_losses(storage: &dyn Storage, trader: &Addr) -> StdResult<Vec<DailyLoss>> {
+    read_all_subaccounts(storage, PREFIX_DAILY_LOSS, trader)
+}
+
 #[cw_serde]
 pub struct TmpReserveInfo {
     pub quote_asset_reserve: Uint128,
@@ -313,6 +1077,17 @@ pub struct TmpReserveInfo {
 pub struct VammMap {
     pub last_restriction_block: u64,
     pub cumulative_premium_fractions: Vec<Integer>,
+    #[serde(default)]
+    pub mark_price_policy: MarkPricePolicy,
+    #[serde(default)]
+    pub long_open_interest_notional: Uint128,
+    #[serde(default)]
+    pub short_open_interest_notional: Uint128,
+    // caps of each side on top of the cap of the vamm, zero is uncapped
+    #[serde(default)]
+    pub long_open_interest_cap: Uint128,
+    #[serde(default)]
+    pub short_open_interest_cap: Uint128,
 }
 
 pub fn store_vamm_map(storage: &mut dyn Storage, vamm: Addr, vamm_map: &VammMap) -> StdResult<()> {
@@ -330,6 +1105,97 @@ pub fn read_vamm_map(storage: &dyn Storage, vamm: &Addr) -> StdResult<VammMap> {
     }
 }
 
+/// Indexes a page of the positions stored before sub-accounts existed under the main account of
+/// their trader
+pub fn backfill_position_by_subaccount(
+    storage: &mut dyn Storage,
+    positions: Vec<Position>,
+) -> StdResult<()> {
+    for position in positions {
+        Bucket::multilevel(
+            storage,
+            &[
+                PREFIX_POSITION_BY_SUBACCOUNT,
+                &keccak_256(position.vamm.as_bytes()),
+                position.trader.as_bytes(),
+                &position.subaccount_id.to_be_bytes(),
+            ],
+        )
+        .save(&position.position_id.to_be_bytes(), &position.side)?;
+    }
+
+    Ok(())
+}
+
+/// Reads a page of the positions of every vamm along with their storage keys
+pub fn read_positions_page(
+    storage: &dyn Storage,
+    start_after: Option<Vec<u8>>,
+    limit: usize,
+) -> StdResult<Vec<(Vec<u8>, Position)>> {
+    // ranging the position prefix without a vamm key reads the positions of every vamm
+    ReadonlyBucket::<Position>::new(storage, PREFIX_POSITION)
+        .range(
+            calc_range_start(start_after).as_deref(),
+            None,
+            OrderBy::Ascending,
+        )
+        .take(limit)
+        .collect()
+}
+
+/// Adds the notional of a page of positions to the open interest of their side, the open interest
+/// of a vamm is reset the first time one of its positions is seen
+pub fn seed_open_interest_notional(
+    storage: &mut dyn Storage,
+    positions: Vec<Position>,
+    seen_vamms: &mut Vec<Addr>,
+) -> StdResult<()> {
+    let mut vamm_maps: Vec<(Addr, VammMap)> = vec![];
+    for position in positions {
+        if position.size.is_zero() {
+            continue;
+        }
+
+        let index = match vamm_maps
+            .iter()
+            .position(|(vamm, _)| vamm == &position.vamm)
+        {
+            Some(index) => index,
+            None => {
+                let mut vamm_map = read_vamm_map(storage, &position.vamm)?;
+                if !seen_vamms.contains(&position.vamm) {
+                    vamm_map.long_open_interest_notional = Uint128::zero();
+                    vamm_map.short_open_interest_notional = Uint128::zero();
+                    seen_vamms.push(position.vamm.clone());
+                }
+                vamm_maps.push((position.vamm.clone(), vamm_map));
+                vamm_maps.len() - 1
+            }
+        };
+
+        let vamm_map = &mut vamm_maps[index].1;
+        match position.side {
+            Side::Buy => {
+                vamm_map.long_open_interest_notional = vamm_map
+                    .long_open_interest_notional
+                    .checked_add(position.notional)?
+            }
+            Side::Sell => {
+                vamm_map.short_open_interest_notional = vamm_map
+                    .short_open_interest_notional
+                    .checked_add(position.notional)?
+            }
+        }
+    }
+
+    for (vamm, vamm_map) in vamm_maps {
+        store_vamm_map(storage, vamm, &vamm_map)?;
+    }
+
+    Ok(())
+}
+
 /// Accumulates the premium fractions at each settlement payment so that eventually users take
 /// their P&L
 pub fn append_cumulative_premium_fraction(
@@ -355,6 +1221,32 @@ pub fn append_cumulative_premium_fraction(
     store_vamm_map(storage, vamm, &vamm_map)
 }
 
+pub fn store_mark_price_policy(
+    storage: &mut dyn Storage,
+    vamm: Addr,
+    policy: MarkPricePolicy,
+) -> StdResult<()> {
+    let mut vamm_map = read_vamm_map(storage, &vamm)?;
+
+    vamm_map.mark_price_policy = policy;
+
+    store_vamm_map(storage, vamm, &vamm_map)
+}
+
+pub fn store_open_interest_caps(
+    storage: &mut dyn Storage,
+    vamm: Addr,
+    long_cap: Uint128,
+    short_cap: Uint128,
+) -> StdResult<()> {
+    let mut vamm_map = read_vamm_map(storage, &vamm)?;
+
+    vamm_map.long_open_interest_cap = long_cap;
+    vamm_map.short_open_interest_cap = short_cap;
+
+    store_vamm_map(storage, vamm, &vamm_map)
+}
+
 pub fn enter_restriction_mode(
     storage: &mut dyn Storage,
     vamm: Addr,
@@ -0,0 +1,485 @@
+use cosmwasm_std::{StdError, Uint128};
+use margined_common::{asset::AssetInfo, integer::Integer};
+use margined_perp::{
+    margined_engine::Side, margined_insurance_fund::ExecuteMsg as InsuranceFundExecuteMsg,
+};
+use margined_utils::{
+    cw_multi_test::Executor,
+    testing::{to_decimals, SimpleScenario},
+};
+
+use crate::testing::new_simple_scenario;
+
+#[test]
+fn test_auto_deleverage_when_insurance_fund_is_empty() {
+    let SimpleScenario {
+        mut router,
+        owner,
+        alice,
+        bob,
+        carol,
+        david,
+        engine,
+        usdc,
+        vamm,
+        insurance_fund,
+        ..
+    } = new_simple_scenario();
+
+    // alice goes long, bob and david short and push the price down
+    let msg = engine
+        .open_position(
+            vamm.addr().to_string(),
+            Side::Buy,
+            to_decimals(20u64),
+            to_decimals(10u64),
+            None,
+            None,
+            to_decimals(0u64),
+            vec![],
+        )
+        .unwrap();
+    router.execute(alice.clone(), msg).unwrap();
+
+    let msg = engine
+        .open_position(
+            vamm.addr().to_string(),
+            Side::Sell,
+            to_decimals(20u64),
+            to_decimals(10u64),
+            None,
+            None,
+            to_decimals(0u64),
+            vec![],
+        )
+        .unwrap();
+    router.execute(bob.clone(), msg).unwrap();
+
+    let msg = engine
+        .open_position(
+            vamm.addr().to_string(),
+            Side::Sell,
+            to_decimals(20u64),
+            to_decimals(10u64),
+            None,
+            None,
+            to_decimals(0u64),
+            vec![],
+        )
+        .unwrap();
+    router.execute(david.clone(), msg).unwrap();
+
+    router.update_block(|block| {
+        block.time = block.time.plus_seconds(15);
+        block.height += 1;
+    });
+
+    // bob is in profit so he is first in the short queue
+    let ranking = engine
+        .get_adl_ranking(&router.wrap(), vamm.addr().to_string(), Side::Sell, None)
+        .unwrap();
+    assert_eq!(ranking.ranks.len(), 2);
+    assert_eq!(ranking.ranks[0].position_id, 2u64);
+    assert_eq!(ranking.ranks[0].trader, bob);
+    assert_eq!(
+        ranking.ranks[0].unrealized_pnl,
+        Integer::new_positive(76_923_076_922u128)
+    );
+    assert_eq!(ranking.ranks[1].position_id, 3u64);
+    assert_eq!(
+        ranking.ranks[1].unrealized_pnl,
+        Integer::new_negative(6u128)
+    );
+
+    let rank = engine
+        .get_adl_rank(&router.wrap(), vamm.addr().to_string(), 3u64)
+        .unwrap();
+    assert_eq!(rank.rank, 2u32);
+
+    // drain the insurance fund so the bad debt cannot be covered
+    router
+        .execute_contract(
+            owner.clone(),
+            insurance_fund.addr(),
+            &InsuranceFundExecuteMsg::WithdrawFund {
+                token: AssetInfo::Token {
+                    contract_addr: usdc.addr(),
+                },
+                amount: to_decimals(5000u64),
+            },
+            &[],
+        )
+        .unwrap();
+
+    let engine_balance = usdc.balance(&router.wrap(), engine.addr()).unwrap();
+    assert_eq!(engine_balance, to_decimals(60u64));
+
+    // alice is liquidated against bob at the bankruptcy price, 180 / 16.666666666
+    let msg = engine
+        .liquidate(vamm.addr().to_string(), 1, to_decimals(0u64))
+        .unwrap();
+    let res = router.execute(carol.clone(), msg).unwrap();
+    let event = res
+        .events
+        .iter()
+        .find(|event| {
+            event
+                .attributes
+                .iter()
+                .any(|attr| attr.key == "action" && attr.value == "auto_deleverage")
+        })
+        .unwrap();
+    assert!(event
+        .attributes
+        .iter()
+        .any(|attr| attr.key == "adl_position_id" && attr.value == "2"));
+
+    // alice's position is gone and the vamm was not touched
+    assert!(engine
+        .position(&router.wrap(), vamm.addr().to_string(), 1)
+        .is_err());
+    let vamm_state = vamm.state(&router.wrap()).unwrap();
+    assert_eq!(vamm_state.quote_asset_reserve, to_decimals(800u64));
+    assert_eq!(
+        vamm_state.base_asset_reserve,
+        Uint128::from(125_000_000_002u128)
+    );
+
+    // bob keeps the dust of his position and realizes 20 USDC of profit
+    let position = engine
+        .position(&router.wrap(), vamm.addr().to_string(), 2)
+        .unwrap();
+    assert_eq!(position.size, Integer::new_negative(1u128));
+    assert_eq!(position.notional, Uint128::from(12u128));
+    assert_eq!(position.margin, Uint128::from(39_999_999_988u128));
+
+    // david is untouched
+    let position = engine
+        .position(&router.wrap(), vamm.addr().to_string(), 3)
+        .unwrap();
+    assert_eq!(position.size, Integer::new_negative(25_000_000_001u128));
+    assert_eq!(position.margin, to_decimals(20u64));
+
+    // no funds moved and the insurance fund was not called
+    let engine_balance = usdc.balance(&router.wrap(), engine.addr()).unwrap();
+    assert_eq!(engine_balance, to_decimals(60u64));
+    let insurance_balance = usdc.balance(&router.wrap(), insurance_fund.addr()).unwrap();
+    assert_eq!(insurance_balance, Uint128::zero());
+    let carol_balance = usdc.balance(&router.wrap(), carol.clone()).unwrap();
+    assert_eq!(carol_balance, Uint128::zero());
+}
+
+#[test]
+fn test_auto_deleverage_realizes_unfilled_size_as_bad_debt() {
+    let SimpleScenario {
+        mut router,
+        owner,
+        alice,
+        bob,
+        carol,
+        david,
+        engine,
+        usdc,
+        vamm,
+        insurance_fund,
+        ..
+    } = new_simple_scenario();
+
+    // alice goes long, bob shorts less than her size and david pushes the price down with a
+    // short that cannot take a loss at her bankruptcy price
+    let msg = engine
+        .open_position(
+            vamm.addr().to_string(),
+            Side::Buy,
+            to_decimals(20u64),
+            to_decimals(10u64),
+            None,
+            None,
+            to_decimals(0u64),
+            vec![],
+        )
+        .unwrap();
+    router.execute(alice.clone(), msg).unwrap();
+
+    let msg = engine
+        .open_position(
+            vamm.addr().to_string(),
+            Side::Sell,
+            to_decimals(10u64),
+            to_decimals(10u64),
+            None,
+            None,
+            to_decimals(0u64),
+            vec![],
+        )
+        .unwrap();
+    router.execute(bob.clone(), msg).unwrap();
+
+    let msg = engine
+        .open_position(
+            vamm.addr().to_string(),
+            Side::Sell,
+            to_decimals(16u64),
+            Uint128::from(18_750_000_000u128),
+            None,
+            None,
+            to_decimals(0u64),
+            vec![],
+        )
+        .unwrap();
+    router.execute(david.clone(), msg).unwrap();
+
+    router.update_block(|block| {
+        block.time = block.time.plus_seconds(15);
+        block.height += 1;
+    });
+
+    // drain the insurance fund so the bad debt cannot be covered
+    router
+        .execute_contract(
+            owner.clone(),
+            insurance_fund.addr(),
+            &InsuranceFundExecuteMsg::WithdrawFund {
+                token: AssetInfo::Token {
+                    contract_addr: usdc.addr(),
+                },
+                amount: to_decimals(5000u64),
+            },
+            &[],
+        )
+        .unwrap();
+
+    let david_position = engine
+        .position(&router.wrap(), vamm.addr().to_string(), 3)
+        .unwrap();
+
+    // bob takes what he can, david is skipped and the rest is bad debt instead of an error
+    let msg = engine
+        .liquidate(vamm.addr().to_string(), 1, to_decimals(0u64))
+        .unwrap();
+    let res = router.execute(carol.clone(), msg).unwrap();
+    let event = res
+        .events
+        .iter()
+        .find(|event| {
+            event
+                .attributes
+                .iter()
+                .any(|attr| attr.key == "action" && attr.value == "auto_deleverage")
+        })
+        .unwrap();
+    let attribute = |key: &str| {
+        event
+            .attributes
+            .iter()
+            .find(|attr| attr.key == key)
+            .unwrap()
+            .value
+            .parse::<u128>()
+            .map(Uint128::from)
+            .unwrap()
+    };
+    assert_eq!(attribute("adl_position_id"), Uint128::from(2u128));
+    assert!(!attribute("unfilled_size").is_zero());
+    assert!(!attribute("bad_debt").is_zero());
+
+    // alice and bob are closed, david is untouched
+    assert!(engine
+        .position(&router.wrap(), vamm.addr().to_string(), 1)
+        .is_err());
+    assert!(engine
+        .position(&router.wrap(), vamm.addr().to_string(), 2)
+        .is_err());
+    let position = engine
+        .position(&router.wrap(), vamm.addr().to_string(), 3)
+        .unwrap();
+    assert_eq!(position, david_position);
+}
+
+#[test]
+fn test_backstop_liquidation_auto_deleverages_when_insurance_fund_is_empty() {
+    let SimpleScenario {
+        mut router,
+        owner,
+        alice,
+        bob,
+        david,
+        engine,
+        usdc,
+        vamm,
+        insurance_fund,
+        ..
+    } = new_simple_scenario();
+
+    // alice goes long and bob pushes the price down
+    let msg = engine
+        .open_position(
+            vamm.addr().to_string(),
+            Side::Buy,
+            to_decimals(20u64),
+            to_decimals(10u64),
+            None,
+            None,
+            to_decimals(0u64),
+            vec![],
+        )
+        .unwrap();
+    router.execute(alice.clone(), msg).unwrap();
+
+    let msg = engine
+        .open_position(
+            vamm.addr().to_string(),
+            Side::Sell,
+            to_decimals(40u64),
+            to_decimals(10u64),
+            None,
+            None,
+            to_decimals(0u64),
+            vec![],
+        )
+        .unwrap();
+    router.execute(bob.clone(), msg).unwrap();
+
+    router.update_block(|block| {
+        block.time = block.time.plus_seconds(15);
+        block.height += 1;
+    });
+
+    let msg = engine.add_backstop_liquidator(david.to_string()).unwrap();
+    router.execute(owner.clone(), msg).unwrap();
+
+    // drain the insurance fund so the bad debt cannot be covered
+    router
+        .execute_contract(
+            owner.clone(),
+            insurance_fund.addr(),
+            &InsuranceFundExecuteMsg::WithdrawFund {
+                token: AssetInfo::Token {
+                    contract_addr: usdc.addr(),
+                },
+                amount: to_decimals(5000u64),
+            },
+            &[],
+        )
+        .unwrap();
+
+    // david has funds and an allowance for the engine, none of the margin he posts is pulled
+    // when the position is deleveraged
+    let david_balance_before = usdc.balance(&router.wrap(), david.clone()).unwrap();
+    let msg = engine
+        .backstop_liquidate(vamm.addr().to_string(), 1, to_decimals(100u64), vec![])
+        .unwrap();
+    let res = router.execute(david.clone(), msg).unwrap();
+    assert!(res.events.iter().any(|event| event
+        .attributes
+        .iter()
+        .any(|attr| attr.key == "action" && attr.value == "auto_deleverage")));
+
+    assert!(engine
+        .position(&router.wrap(), vamm.addr().to_string(), 1)
+        .is_err());
+    let david_balance = usdc.balance(&router.wrap(), david.clone()).unwrap();
+    assert_eq!(david_balance, david_balance_before);
+    assert!(!res.events.iter().any(|event| event
+        .attributes
+        .iter()
+        .any(|attr| attr.key == "action" && attr.value == "transfer_from")));
+}
+
+#[test]
+fn test_adl_candidates_are_capped_and_ranked_by_keepers() {
+    let SimpleScenario {
+        mut router,
+        alice,
+        bob,
+        carol,
+        engine,
+        vamm,
+        ..
+    } = new_simple_scenario();
+
+    // alice fills the candidates of the short side
+    for _ in 0..50 {
+        let msg = engine
+            .open_position(
+                vamm.addr().to_string(),
+                Side::Sell,
+                to_decimals(1u64),
+                to_decimals(1u64),
+                None,
+                None,
+                to_decimals(0u64),
+                vec![],
+            )
+            .unwrap();
+        router.execute(alice.clone(), msg).unwrap();
+    }
+
+    // bob opens with more leverage and carol pushes the price down
+    let msg = engine
+        .open_position(
+            vamm.addr().to_string(),
+            Side::Sell,
+            to_decimals(1u64),
+            to_decimals(10u64),
+            None,
+            None,
+            to_decimals(0u64),
+            vec![],
+        )
+        .unwrap();
+    router.execute(bob.clone(), msg).unwrap();
+
+    let msg = engine
+        .open_position(
+            vamm.addr().to_string(),
+            Side::Sell,
+            to_decimals(50u64),
+            to_decimals(5u64),
+            None,
+            None,
+            to_decimals(0u64),
+            vec![],
+        )
+        .unwrap();
+    router.execute(carol, msg).unwrap();
+
+    // the candidates are full so bob is not ranked
+    let err = engine
+        .get_adl_rank(&router.wrap(), vamm.addr().to_string(), 51u64)
+        .unwrap_err();
+    assert!(err
+        .to_string()
+        .contains("Position is outside of the ADL ranking"));
+
+    let msg = engine
+        .update_adl_candidates(vamm.addr().to_string(), Side::Buy, vec![51u64])
+        .unwrap();
+    let err = router.execute(alice.clone(), msg).unwrap_err();
+    assert_eq!(
+        StdError::generic_err("Position is on the wrong side"),
+        err.downcast().unwrap()
+    );
+
+    // a keeper proposes bob, who outranks the positions of alice
+    let msg = engine
+        .update_adl_candidates(vamm.addr().to_string(), Side::Sell, vec![51u64])
+        .unwrap();
+    router.execute(alice, msg).unwrap();
+
+    let rank = engine
+        .get_adl_rank(&router.wrap(), vamm.addr().to_string(), 51u64)
+        .unwrap();
+    assert_eq!(rank.rank, 1u32);
+    assert_eq!(rank.trader, bob);
+
+    let ranking = engine
+        .get_adl_ranking(
+            &router.wrap(),
+            vamm.addr().to_string(),
+            Side::Sell,
+            Some(100),
+        )
+        .unwrap();
+    assert_eq!(ranking.ranks.len(), 50);
+}
@@ -0,0 +1,221 @@
+use cosmwasm_std::{StdError, Uint128};
+use margined_perp::margined_engine::{MarkPricePolicy, Side};
+use margined_utils::{
+    cw_multi_test::Executor,
+    testing::{to_decimals, SimpleScenario},
+};
+
+use crate::testing::new_simple_scenario;
+
+#[test]
+fn test_backstop_liquidator_takes_over_position() {
+    let SimpleScenario {
+        mut router,
+        owner,
+        alice,
+        bob,
+        david,
+        engine,
+        usdc,
+        vamm,
+        insurance_fund,
+        ..
+    } = new_simple_scenario();
+
+    // alice goes long and bob shorts the price down
+    let msg = engine
+        .open_position(
+            vamm.addr().to_string(),
+            Side::Buy,
+            to_decimals(20u64),
+            to_decimals(10u64),
+            None,
+            None,
+            to_decimals(0u64),
+            vec![],
+        )
+        .unwrap();
+    router.execute(alice.clone(), msg).unwrap();
+
+    let msg = engine
+        .open_position(
+            vamm.addr().to_string(),
+            Side::Sell,
+            to_decimals(6u64),
+            to_decimals(5u64),
+            None,
+            None,
+            to_decimals(0u64),
+            vec![],
+        )
+        .unwrap();
+    router.execute(bob.clone(), msg).unwrap();
+
+    router.update_block(|block| {
+        block.time = block.time.plus_seconds(15);
+        block.height += 1;
+    });
+
+    // set maintenance ratio as 10% to allow liquidation
+    let msg = engine
+        .set_margin_ratios(Uint128::from(100_000_000u128))
+        .unwrap();
+    router.execute(owner.clone(), msg).unwrap();
+
+    // only registered backstop liquidators can take over positions
+    let msg = engine
+        .backstop_liquidate(vamm.addr().to_string(), 1, to_decimals(20u64), vec![])
+        .unwrap();
+    let err = router.execute(david.clone(), msg).unwrap_err();
+    assert_eq!(
+        StdError::generic_err("unauthorized"),
+        err.downcast().unwrap()
+    );
+
+    let msg = engine.add_backstop_liquidator(david.to_string()).unwrap();
+    router.execute(owner.clone(), msg).unwrap();
+    assert!(engine
+        .is_backstop_liquidator(&router.wrap(), david.to_string())
+        .unwrap());
+
+    // the liquidator has to post the initial margin
+    let msg = engine
+        .backstop_liquidate(vamm.addr().to_string(), 1, to_decimals(10u64), vec![])
+        .unwrap();
+    let err = router.execute(david.clone(), msg).unwrap_err();
+    assert_eq!(
+        StdError::generic_err("Position is undercollateralized"),
+        err.downcast().unwrap()
+    );
+
+    let vamm_state_before = vamm.state(&router.wrap()).unwrap();
+    let alice_balance_before = usdc.balance(&router.wrap(), alice.clone()).unwrap();
+    let david_balance_before = usdc.balance(&router.wrap(), david.clone()).unwrap();
+
+    let msg = engine
+        .backstop_liquidate(vamm.addr().to_string(), 1, to_decimals(20u64), vec![])
+        .unwrap();
+    router.execute(david.clone(), msg).unwrap();
+
+    // the vamm is untouched
+    let vamm_state = vamm.state(&router.wrap()).unwrap();
+    assert_eq!(vamm_state, vamm_state_before);
+
+    // david now owns the position rebased at the mark price
+    let position = engine
+        .position(&router.wrap(), vamm.addr().to_string(), 1)
+        .unwrap();
+    assert_eq!(position.trader, david);
+    assert_eq!(position.margin, to_decimals(20u64));
+    assert_eq!(position.notional, Uint128::from(190_920_502_083u128));
+    assert_eq!(position.size.value, Uint128::from(16_666_666_666u128));
+    assert_eq!(position.take_profit, None);
+    assert_eq!(position.stop_loss, None);
+
+    // alice receives her remaining margin, 10.920502083, less the 5% penalty of 9.546025104
+    let alice_balance = usdc.balance(&router.wrap(), alice.clone()).unwrap();
+    assert_eq!(
+        alice_balance,
+        alice_balance_before + Uint128::from(1_374_476_979u128)
+    );
+
+    // david posts 20 USDC and receives half of the penalty
+    let david_balance = usdc.balance(&router.wrap(), david.clone()).unwrap();
+    assert_eq!(
+        david_balance,
+        david_balance_before - to_decimals(20u64) + Uint128::from(4_773_012_552u128)
+    );
+
+    // the insurance fund receives the other half
+    let insurance_balance = usdc.balance(&router.wrap(), insurance_fund.addr()).unwrap();
+    assert_eq!(
+        insurance_balance,
+        to_decimals(5000u64) + Uint128::from(4_773_012_552u128)
+    );
+
+    let engine_balance = usdc.balance(&router.wrap(), engine.addr()).unwrap();
+    assert_eq!(engine_balance, Uint128::from(35_079_497_917u128));
+}
+
+#[test]
+fn test_backstop_liquidator_takes_over_position_at_mark_price_policy() {
+    let SimpleScenario {
+        mut router,
+        owner,
+        alice,
+        bob,
+        david,
+        engine,
+        vamm,
+        pricefeed,
+        ..
+    } = new_simple_scenario();
+
+    let msg = engine
+        .open_position(
+            vamm.addr().to_string(),
+            Side::Buy,
+            to_decimals(20u64),
+            to_decimals(10u64),
+            None,
+            None,
+            to_decimals(0u64),
+            vec![],
+        )
+        .unwrap();
+    router.execute(alice.clone(), msg).unwrap();
+
+    let msg = engine
+        .open_position(
+            vamm.addr().to_string(),
+            Side::Sell,
+            to_decimals(6u64),
+            to_decimals(5u64),
+            None,
+            None,
+            to_decimals(0u64),
+            vec![],
+        )
+        .unwrap();
+    router.execute(bob.clone(), msg).unwrap();
+
+    router.update_block(|block| {
+        block.time = block.time.plus_seconds(15);
+        block.height += 1;
+    });
+
+    let msg = engine
+        .set_margin_ratios(Uint128::from(100_000_000u128))
+        .unwrap();
+    router.execute(owner.clone(), msg).unwrap();
+
+    // the market is marked at the index price of 11.5
+    let msg = pricefeed
+        .append_price(
+            "ETH".to_string(),
+            Uint128::from(11_500_000_000u128),
+            1_000_000_000u64,
+        )
+        .unwrap();
+    router.execute(owner.clone(), msg).unwrap();
+    let msg = engine
+        .update_mark_price_policy(vamm.addr().to_string(), MarkPricePolicy::Oracle)
+        .unwrap();
+    router.execute(owner.clone(), msg).unwrap();
+
+    let msg = engine.add_backstop_liquidator(david.to_string()).unwrap();
+    router.execute(owner.clone(), msg).unwrap();
+
+    let msg = engine
+        .backstop_liquidate(vamm.addr().to_string(), 1, to_decimals(20u64), vec![])
+        .unwrap();
+    router.execute(david.clone(), msg).unwrap();
+
+    // david owns the position rebased at the index notional instead of the vamm one
+    let position = engine
+        .position(&router.wrap(), vamm.addr().to_string(), 1)
+        .unwrap();
+    assert_eq!(position.trader, david);
+    assert_eq!(position.size.value, Uint128::from(16_666_666_666u128));
+    assert_eq!(position.notional, Uint128::from(191_666_666_659u128));
+}
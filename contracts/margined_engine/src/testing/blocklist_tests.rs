@@ -0,0 +1,278 @@
+use cosmwasm_std::{StdError, Uint128};
+use margined_perp::margined_engine::{ConditionalOrderMsg, Side};
+use margined_utils::{
+    cw_multi_test::Executor,
+    testing::{to_decimals, SimpleScenario},
+};
+
+use crate::testing::new_simple_scenario;
+
+#[test]
+fn test_add_remove_blocklist() {
+    let SimpleScenario {
+        mut router,
+        owner,
+        alice,
+        bob,
+        carol,
+        engine,
+        ..
+    } = new_simple_scenario();
+
+    // only the owner can block
+    let msg = engine.add_blocklist(alice.to_string()).unwrap();
+    let err = router.execute(bob.clone(), msg).unwrap_err();
+    assert_eq!(
+        StdError::generic_err("unauthorized"),
+        err.downcast().unwrap()
+    );
+
+    for address in [&carol, &alice, &bob] {
+        let msg = engine.add_blocklist(address.to_string()).unwrap();
+        router.execute(owner.clone(), msg).unwrap();
+    }
+
+    let msg = engine.add_blocklist(alice.to_string()).unwrap();
+    let err = router.execute(owner.clone(), msg).unwrap_err();
+    assert_eq!(
+        StdError::generic_err("address is already blocked"),
+        err.downcast().unwrap()
+    );
+
+    assert!(engine
+        .is_blocked(&router.wrap(), alice.to_string())
+        .unwrap());
+
+    // the blocklist is paginated in address order
+    let blocklist = engine.get_blocklist(&router.wrap(), None, Some(2)).unwrap();
+    assert_eq!(blocklist, vec![alice.clone(), bob.clone()]);
+
+    let blocklist = engine
+        .get_blocklist(&router.wrap(), Some(bob.to_string()), Some(2))
+        .unwrap();
+    assert_eq!(blocklist, vec![carol.clone()]);
+
+    let msg = engine.remove_blocklist(bob.to_string()).unwrap();
+    router.execute(owner.clone(), msg).unwrap();
+
+    assert!(!engine.is_blocked(&router.wrap(), bob.to_string()).unwrap());
+
+    let msg = engine.remove_blocklist(bob.to_string()).unwrap();
+    let err = router.execute(owner, msg).unwrap_err();
+    assert_eq!(
+        StdError::generic_err("address is not blocked"),
+        err.downcast().unwrap()
+    );
+
+    let blocklist = engine.get_blocklist(&router.wrap(), None, None).unwrap();
+    assert_eq!(blocklist, vec![alice, carol]);
+}
+
+#[test]
+fn test_blocked_address_cannot_open_or_deposit_margin() {
+    let SimpleScenario {
+        mut router,
+        owner,
+        alice,
+        bob,
+        engine,
+        vamm,
+        ..
+    } = new_simple_scenario();
+
+    let msg = engine
+        .open_position(
+            vamm.addr().to_string(),
+            Side::Buy,
+            to_decimals(6u64),
+            to_decimals(10u64),
+            None,
+            None,
+            to_decimals(0u64),
+            vec![],
+        )
+        .unwrap();
+    router.execute(alice.clone(), msg).unwrap();
+
+    let msg = engine.add_blocklist(alice.to_string()).unwrap();
+    router.execute(owner, msg).unwrap();
+
+    let msg = engine
+        .open_position(
+            vamm.addr().to_string(),
+            Side::Buy,
+            to_decimals(6u64),
+            to_decimals(10u64),
+            None,
+            None,
+            to_decimals(0u64),
+            vec![],
+        )
+        .unwrap();
+    let err = router.execute(alice.clone(), msg).unwrap_err();
+    assert_eq!(
+        StdError::generic_err("address is blocked"),
+        err.downcast().unwrap()
+    );
+
+    let msg = engine
+        .deposit_margin(vamm.addr().to_string(), 1, to_decimals(1u64), vec![])
+        .unwrap();
+    let err = router.execute(alice.clone(), msg).unwrap_err();
+    assert_eq!(
+        StdError::generic_err("address is blocked"),
+        err.downcast().unwrap()
+    );
+
+    // lowering the leverage adds collateral too
+    let msg = engine
+        .set_leverage(vamm.addr().to_string(), 1, to_decimals(5u64), vec![])
+        .unwrap();
+    let err = router.execute(alice, msg).unwrap_err();
+    assert_eq!(
+        StdError::generic_err("address is blocked"),
+        err.downcast().unwrap()
+    );
+
+    // nor can anyone top up the position of a blocked trader
+    let msg = engine
+        .deposit_margin(vamm.addr().to_string(), 1, to_decimals(1u64), vec![])
+        .unwrap();
+    let err = router.execute(bob, msg).unwrap_err();
+    assert_eq!(
+        StdError::generic_err("address is blocked"),
+        err.downcast().unwrap()
+    );
+}
+
+#[test]
+fn test_blocked_payout_is_escrowed() {
+    let SimpleScenario {
+        mut router,
+        owner,
+        alice,
+        engine,
+        usdc,
+        vamm,
+        ..
+    } = new_simple_scenario();
+
+    let msg = engine
+        .open_position(
+            vamm.addr().to_string(),
+            Side::Buy,
+            to_decimals(6u64),
+            to_decimals(10u64),
+            None,
+            None,
+            to_decimals(0u64),
+            vec![],
+        )
+        .unwrap();
+    router.execute(alice.clone(), msg).unwrap();
+
+    let msg = engine.add_blocklist(alice.to_string()).unwrap();
+    router.execute(owner.clone(), msg).unwrap();
+
+    // the position of a blocked trader can still be closed, the proceeds are escrowed
+    let alice_balance = usdc.balance(&router.wrap(), alice.clone()).unwrap();
+
+    let msg = engine
+        .close_position(vamm.addr().to_string(), 1, to_decimals(0u64))
+        .unwrap();
+    router.execute(alice.clone(), msg).unwrap();
+
+    assert_eq!(
+        usdc.balance(&router.wrap(), alice.clone()).unwrap(),
+        alice_balance
+    );
+
+    let escrows = engine.get_escrows(&router.wrap(), None, None).unwrap();
+    assert_eq!(escrows.len(), 1);
+    assert_eq!(escrows[0].address, alice);
+    let escrowed = escrows[0].amount;
+    assert!(!escrowed.is_zero());
+
+    let msg = engine.claim().unwrap();
+    let err = router.execute(alice.clone(), msg).unwrap_err();
+    assert_eq!(
+        StdError::generic_err("address is blocked"),
+        err.downcast().unwrap()
+    );
+
+    // unblocking releases the escrow to the claimable balance
+    let msg = engine.remove_blocklist(alice.to_string()).unwrap();
+    router.execute(owner, msg).unwrap();
+
+    let escrows = engine.get_escrows(&router.wrap(), None, None).unwrap();
+    assert!(escrows.is_empty());
+
+    let claimable = engine
+        .get_claimable_balance(&router.wrap(), alice.to_string())
+        .unwrap();
+    assert_eq!(claimable, escrowed);
+
+    let msg = engine.claim().unwrap();
+    router.execute(alice.clone(), msg).unwrap();
+
+    assert_eq!(
+        usdc.balance(&router.wrap(), alice.clone()).unwrap(),
+        alice_balance.checked_add(escrowed).unwrap()
+    );
+    assert_eq!(
+        engine
+            .get_claimable_balance(&router.wrap(), alice.to_string())
+            .unwrap(),
+        Uint128::zero()
+    );
+}
+
+#[test]
+fn test_order_refund_to_blocked_trader_is_escrowed() {
+    let SimpleScenario {
+        mut router,
+        owner,
+        alice,
+        usdc,
+        engine,
+        vamm,
+        ..
+    } = new_simple_scenario();
+
+    let msg = engine
+        .place_conditional_order(
+            vamm.addr().to_string(),
+            ConditionalOrderMsg {
+                side: Side::Buy,
+                margin_amount: to_decimals(6u64),
+                leverage: to_decimals(10u64),
+                trigger_price: to_decimals(11u64),
+                take_profit: None,
+                stop_loss: None,
+                base_asset_limit: to_decimals(0u64),
+                subaccount_id: None,
+            },
+            None,
+            vec![],
+        )
+        .unwrap();
+    router.execute(alice.clone(), msg).unwrap();
+
+    let msg = engine.add_blocklist(alice.to_string()).unwrap();
+    router.execute(owner, msg).unwrap();
+
+    let alice_balance = usdc.balance(&router.wrap(), alice.clone()).unwrap();
+
+    // the refund of the cancelled order is held like any other payout to a blocked address
+    let msg = engine.cancel_conditional_order(1).unwrap();
+    router.execute(alice.clone(), msg).unwrap();
+
+    assert_eq!(
+        usdc.balance(&router.wrap(), alice.clone()).unwrap(),
+        alice_balance
+    );
+    let escrows = engine.get_escrows(&router.wrap(), None, None).unwrap();
+    assert_eq!(escrows.len(), 1);
+    assert_eq!(escrows[0].address, alice);
+    assert_eq!(escrows[0].amount, to_decimals(6u64));
+}
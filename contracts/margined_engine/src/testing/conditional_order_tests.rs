@@ -0,0 +1,149 @@
+use cosmwasm_std::{StdError, Uint128};
+use margined_perp::margined_engine::{ConditionalOrderMsg, Side};
+use margined_utils::{
+    cw_multi_test::Executor,
+    testing::{to_decimals, SimpleScenario},
+};
+
+use crate::testing::new_simple_scenario;
+
+fn stop_entry(side: Side, trigger_price: u64) -> ConditionalOrderMsg {
+    ConditionalOrderMsg {
+        side,
+        margin_amount: to_decimals(6u64),
+        leverage: to_decimals(10u64),
+        trigger_price: to_decimals(trigger_price),
+        take_profit: None,
+        stop_loss: None,
+        base_asset_limit: to_decimals(0u64),
+        subaccount_id: None,
+    }
+}
+
+#[test]
+fn test_place_and_cancel_conditional_order() {
+    let SimpleScenario {
+        mut router,
+        alice,
+        bob,
+        usdc,
+        engine,
+        vamm,
+        ..
+    } = new_simple_scenario();
+
+    let msg = engine
+        .place_conditional_order(
+            vamm.addr().to_string(),
+            stop_entry(Side::Buy, 11u64),
+            None,
+            vec![],
+        )
+        .unwrap();
+    router.execute(alice.clone(), msg).unwrap();
+
+    // the margin is escrowed by the engine
+    let alice_balance = usdc.balance(&router.wrap(), alice.clone()).unwrap();
+    assert_eq!(alice_balance, Uint128::from(4_994_000_000_000u128));
+    let engine_balance = usdc.balance(&router.wrap(), engine.addr()).unwrap();
+    assert_eq!(engine_balance, to_decimals(6u64));
+
+    let orders = engine
+        .get_conditional_orders(&router.wrap(), alice.to_string(), None, None)
+        .unwrap();
+    assert_eq!(orders.len(), 1);
+    assert_eq!(orders[0].order_id, 1);
+    assert_eq!(orders[0].trigger_price, to_decimals(11u64));
+
+    let msg = engine.cancel_conditional_order(1).unwrap();
+    let err = router.execute(bob, msg).unwrap_err();
+    assert_eq!(
+        StdError::generic_err("Unauthorized"),
+        err.downcast().unwrap()
+    );
+
+    let msg = engine.cancel_conditional_order(1).unwrap();
+    router.execute(alice.clone(), msg).unwrap();
+
+    let alice_balance = usdc.balance(&router.wrap(), alice.clone()).unwrap();
+    assert_eq!(alice_balance, Uint128::from(5_000_000_000_000u128));
+    let orders = engine
+        .get_conditional_orders(&router.wrap(), alice.to_string(), None, None)
+        .unwrap();
+    assert!(orders.is_empty());
+}
+
+#[test]
+fn test_oco_stop_entry_executes_and_cancels_sibling() {
+    let SimpleScenario {
+        mut router,
+        alice,
+        bob,
+        carol,
+        usdc,
+        engine,
+        vamm,
+        ..
+    } = new_simple_scenario();
+
+    // breakout entries on both sides of the price of 10
+    let msg = engine
+        .place_conditional_order(
+            vamm.addr().to_string(),
+            stop_entry(Side::Buy, 11u64),
+            Some(stop_entry(Side::Sell, 9u64)),
+            vec![],
+        )
+        .unwrap();
+    router.execute(alice.clone(), msg).unwrap();
+
+    let alice_balance = usdc.balance(&router.wrap(), alice.clone()).unwrap();
+    assert_eq!(alice_balance, Uint128::from(4_988_000_000_000u128));
+
+    let order = engine.get_conditional_order(&router.wrap(), 2).unwrap();
+    assert_eq!(order.oco_order_id, Some(1));
+
+    let msg = engine.execute_conditional_order(1).unwrap();
+    let err = router.execute(carol.clone(), msg).unwrap_err();
+    assert_eq!(
+        StdError::generic_err("Trigger price is not reached"),
+        err.downcast().unwrap()
+    );
+
+    // bob pushes the price to 11.236
+    let msg = engine
+        .open_position(
+            vamm.addr().to_string(),
+            Side::Buy,
+            to_decimals(6u64),
+            to_decimals(10u64),
+            None,
+            None,
+            to_decimals(0u64),
+            vec![],
+        )
+        .unwrap();
+    router.execute(bob, msg).unwrap();
+
+    let msg = engine.execute_conditional_order(1).unwrap();
+    router.execute(carol, msg).unwrap();
+
+    let position = engine
+        .position(&router.wrap(), vamm.addr().to_string(), 2)
+        .unwrap();
+    assert_eq!(position.trader, alice);
+    assert_eq!(position.side, Side::Buy);
+    assert_eq!(position.margin, to_decimals(6u64));
+    assert_eq!(position.notional, to_decimals(60u64));
+
+    // the sibling is cancelled and its margin refunded
+    let alice_balance = usdc.balance(&router.wrap(), alice.clone()).unwrap();
+    assert_eq!(alice_balance, Uint128::from(4_994_000_000_000u128));
+    let engine_balance = usdc.balance(&router.wrap(), engine.addr()).unwrap();
+    assert_eq!(engine_balance, to_decimals(12u64));
+
+    let orders = engine
+        .get_conditional_orders(&router.wrap(), alice.to_string(), None, None)
+        .unwrap();
+    assert!(orders.is_empty());
+}
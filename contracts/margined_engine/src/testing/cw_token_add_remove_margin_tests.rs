@@ -57,6 +57,61 @@ fn test_add_margin() {
     );
 }
 
+#[test]
+fn test_add_margin_from_another_sender() {
+    let SimpleScenario {
+        mut router,
+        alice,
+        bob,
+        engine,
+        vamm,
+        usdc,
+        ..
+    } = new_simple_scenario();
+
+    let msg = engine
+        .open_position(
+            vamm.addr().to_string(),
+            Side::Buy,
+            to_decimals(60u64),
+            to_decimals(10u64),
+            None,
+            None,
+            to_decimals(0u64),
+            vec![],
+        )
+        .unwrap();
+    router.execute(alice.clone(), msg).unwrap();
+
+    let msg = engine
+        .deposit_margin(vamm.addr().to_string(), 1, to_decimals(80u64), vec![])
+        .unwrap();
+    let res = router.execute(bob.clone(), msg).unwrap();
+    assert!(res.events.iter().any(|event| event
+        .attributes
+        .iter()
+        .any(|attr| attr.key == "depositor" && attr.value == bob.as_str())));
+
+    let bob_balance = usdc.balance(&router.wrap(), bob.clone()).unwrap();
+    assert_eq!(bob_balance, to_decimals(4_920u64));
+
+    let alice_position = engine
+        .position(&router.wrap(), vamm.addr().to_string(), 1)
+        .unwrap();
+    assert_eq!(alice_position.trader, alice);
+    assert_eq!(alice_position.margin, to_decimals(140u64));
+
+    // the depositor cannot take the margin back
+    let msg = engine
+        .withdraw_margin(vamm.addr().to_string(), 1, to_decimals(80u64))
+        .unwrap();
+    let err = router.execute(bob, msg).unwrap_err();
+    assert_eq!(
+        err.source().unwrap().to_string(),
+        "Generic error: Unauthorized"
+    );
+}
+
 #[test]
 fn test_add_margin_insufficent_balance() {
     let SimpleScenario {
@@ -0,0 +1,164 @@
+use cosmwasm_std::{StdError, Uint128};
+use margined_perp::margined_engine::{KeeperReward, Side};
+use margined_utils::{
+    cw_multi_test::Executor,
+    testing::{to_decimals, SimpleScenario},
+};
+
+use crate::testing::new_simple_scenario;
+
+#[test]
+fn test_update_keeper_reward() {
+    let SimpleScenario {
+        mut router,
+        owner,
+        alice,
+        engine,
+        ..
+    } = new_simple_scenario();
+
+    let keeper_reward = KeeperReward {
+        flat: to_decimals(1u64),
+        notional_ratio: Uint128::from(10_000_000u128),
+        max_per_call: Uint128::from(1_500_000_000u128),
+    };
+
+    let msg = engine.update_keeper_reward(keeper_reward.clone()).unwrap();
+    let err = router.execute(alice, msg).unwrap_err();
+    assert_eq!(
+        StdError::generic_err("unauthorized"),
+        err.downcast().unwrap()
+    );
+
+    let msg = engine.update_keeper_reward(keeper_reward.clone()).unwrap();
+    router.execute(owner, msg).unwrap();
+
+    let config = engine.config(&router.wrap()).unwrap();
+    assert_eq!(config.keeper_reward, keeper_reward);
+}
+
+#[test]
+fn test_take_profit_pays_keeper_from_position() {
+    let SimpleScenario {
+        mut router,
+        owner,
+        alice,
+        bob,
+        carol,
+        usdc,
+        engine,
+        vamm,
+        ..
+    } = new_simple_scenario();
+
+    // 1 flat plus 1% of the closed notional, at most 1.5 per call
+    let msg = engine
+        .update_keeper_reward(KeeperReward {
+            flat: to_decimals(1u64),
+            notional_ratio: Uint128::from(10_000_000u128),
+            max_per_call: Uint128::from(1_500_000_000u128),
+        })
+        .unwrap();
+    router.execute(owner, msg).unwrap();
+
+    let msg = engine
+        .open_position(
+            vamm.addr().to_string(),
+            Side::Buy,
+            to_decimals(6u64),
+            to_decimals(10u64),
+            Some(to_decimals(11u64)),
+            None,
+            to_decimals(0u64),
+            vec![],
+        )
+        .unwrap();
+    router.execute(alice.clone(), msg).unwrap();
+
+    let msg = engine
+        .open_position(
+            vamm.addr().to_string(),
+            Side::Buy,
+            to_decimals(6u64),
+            to_decimals(10u64),
+            None,
+            None,
+            to_decimals(0u64),
+            vec![],
+        )
+        .unwrap();
+    router.execute(bob, msg).unwrap();
+
+    let carol_balance = usdc.balance(&router.wrap(), carol.clone()).unwrap();
+
+    // alice closes 66.770759397 of notional, so the reward of 1.667707593 is capped
+    let msg = engine
+        .trigger_tp_sl(vamm.addr().to_string(), 1, true)
+        .unwrap();
+    router.execute(carol.clone(), msg).unwrap();
+
+    let carol_reward = usdc
+        .balance(&router.wrap(), carol.clone())
+        .unwrap()
+        .checked_sub(carol_balance)
+        .unwrap();
+    assert_eq!(carol_reward, Uint128::from(1_500_000_000u128));
+
+    // 6 of margin plus 6.770759397 of profit minus the reward
+    let alice_balance = usdc.balance(&router.wrap(), alice).unwrap();
+    assert_eq!(alice_balance, Uint128::from(5_005_270_759_397u128));
+}
+
+#[test]
+fn test_pay_funding_caps_keeper_reward_at_insurance_fund() {
+    let SimpleScenario {
+        mut router,
+        owner,
+        carol,
+        usdc,
+        engine,
+        vamm,
+        pricefeed,
+        insurance_fund,
+        ..
+    } = new_simple_scenario();
+
+    let insurance_balance = usdc.balance(&router.wrap(), insurance_fund.addr()).unwrap();
+
+    // the flat reward is more than the insurance fund holds
+    let msg = engine
+        .update_keeper_reward(KeeperReward {
+            flat: insurance_balance.checked_add(to_decimals(1u64)).unwrap(),
+            notional_ratio: Uint128::zero(),
+            max_per_call: Uint128::zero(),
+        })
+        .unwrap();
+    router.execute(owner.clone(), msg).unwrap();
+
+    let msg = pricefeed
+        .append_price("ETH".to_string(), to_decimals(10u64), 1_000_000_000)
+        .unwrap();
+    router.execute(owner, msg).unwrap();
+
+    // move to the next funding time
+    router.update_block(|block| {
+        block.time = block.time.plus_seconds(86_400u64);
+        block.height += 1;
+    });
+
+    let carol_balance = usdc.balance(&router.wrap(), carol.clone()).unwrap();
+
+    // the funding is settled and the keeper is paid what the fund holds
+    let msg = engine.pay_funding(vamm.addr().to_string()).unwrap();
+    router.execute(carol.clone(), msg).unwrap();
+
+    let carol_reward = usdc
+        .balance(&router.wrap(), carol)
+        .unwrap()
+        .checked_sub(carol_balance)
+        .unwrap();
+    assert_eq!(carol_reward, insurance_balance);
+
+    let insurance_balance = usdc.balance(&router.wrap(), insurance_fund.addr()).unwrap();
+    assert_eq!(insurance_balance, Uint128::zero());
+}
@@ -0,0 +1,311 @@
+use cosmwasm_std::{StdError, Uint128};
+use margined_perp::margined_engine::{LiquidationFeeSplit, Side};
+use margined_utils::{
+    cw_multi_test::Executor,
+    testing::{to_decimals, SimpleScenario},
+};
+
+use crate::testing::new_simple_scenario;
+
+#[test]
+fn test_update_liquidation_fee_split() {
+    let SimpleScenario {
+        mut router,
+        owner,
+        alice,
+        engine,
+        ..
+    } = new_simple_scenario();
+
+    let split = LiquidationFeeSplit {
+        liquidator: Uint128::from(250_000_000u128),
+        insurance_fund: Uint128::from(500_000_000u128),
+        fee_pool: Uint128::from(250_000_000u128),
+    };
+
+    // only the owner can update the split
+    let msg = engine
+        .update_liquidation_fee_split(split.clone(), None)
+        .unwrap();
+    let err = router.execute(alice, msg).unwrap_err();
+    assert_eq!(
+        StdError::generic_err("unauthorized"),
+        err.downcast().unwrap()
+    );
+
+    // the shares have to add up to the whole penalty
+    let msg = engine
+        .update_liquidation_fee_split(
+            LiquidationFeeSplit {
+                fee_pool: Uint128::from(300_000_000u128),
+                ..split.clone()
+            },
+            None,
+        )
+        .unwrap();
+    let err = router.execute(owner.clone(), msg).unwrap_err();
+    assert_eq!(
+        StdError::generic_err("Invalid liquidation fee split"),
+        err.downcast().unwrap()
+    );
+
+    let msg = engine
+        .update_liquidation_fee_split(split.clone(), Some(to_decimals(2u64)))
+        .unwrap();
+    router.execute(owner, msg).unwrap();
+
+    let config = engine.config(&router.wrap()).unwrap();
+    assert_eq!(config.liquidation_fee_split, split);
+    assert_eq!(config.max_liquidator_fee, Some(to_decimals(2u64)));
+}
+
+#[test]
+fn test_liquidation_fee_is_split_with_capped_liquidator_reward() {
+    let SimpleScenario {
+        mut router,
+        owner,
+        alice,
+        bob,
+        carol,
+        engine,
+        usdc,
+        vamm,
+        fee_pool,
+        insurance_fund,
+        ..
+    } = new_simple_scenario();
+
+    let msg = engine
+        .update_liquidation_fee_split(
+            LiquidationFeeSplit {
+                liquidator: Uint128::from(250_000_000u128),
+                insurance_fund: Uint128::from(500_000_000u128),
+                fee_pool: Uint128::from(250_000_000u128),
+            },
+            Some(to_decimals(2u64)),
+        )
+        .unwrap();
+    router.execute(owner.clone(), msg).unwrap();
+
+    // alice goes long and bob shorts the price down
+    let msg = engine
+        .open_position(
+            vamm.addr().to_string(),
+            Side::Buy,
+            to_decimals(20u64),
+            to_decimals(10u64),
+            None,
+            None,
+            to_decimals(0u64),
+            vec![],
+        )
+        .unwrap();
+    router.execute(alice.clone(), msg).unwrap();
+
+    let msg = engine
+        .open_position(
+            vamm.addr().to_string(),
+            Side::Sell,
+            to_decimals(6u64),
+            to_decimals(5u64),
+            None,
+            None,
+            to_decimals(0u64),
+            vec![],
+        )
+        .unwrap();
+    router.execute(bob.clone(), msg).unwrap();
+
+    router.update_block(|block| {
+        block.time = block.time.plus_seconds(15);
+        block.height += 1;
+    });
+
+    // set maintenance ratio as 10% to allow liquidation
+    let msg = engine
+        .set_margin_ratios(Uint128::from(100_000_000u128))
+        .unwrap();
+    router.execute(owner.clone(), msg).unwrap();
+
+    let msg = engine
+        .liquidate(vamm.addr().to_string(), 1, to_decimals(0u64))
+        .unwrap();
+    let res = router.execute(carol.clone(), msg).unwrap();
+
+    let event = res
+        .events
+        .iter()
+        .find(|event| {
+            event
+                .attributes
+                .iter()
+                .any(|attr| attr.key == "action" && attr.value == "liquidation_reply")
+        })
+        .unwrap();
+    let attribute = |key: &str| {
+        event
+            .attributes
+            .iter()
+            .find(|attr| attr.key == key)
+            .unwrap()
+            .value
+            .clone()
+    };
+    assert_eq!(attribute("liquidation_fee"), "2000000000");
+    assert_eq!(attribute("fee_pool_fee"), "2386506276");
+    assert_eq!(attribute("insurance_fee"), "5159518828");
+    assert_eq!(attribute("remaining_margin"), "1374476979");
+
+    // the penalty of 9.546025104 is a quarter each for the liquidator and the fee pool,
+    // the liquidator reward is capped at 2 USDC
+    let carol_balance = usdc.balance(&router.wrap(), carol.clone()).unwrap();
+    assert_eq!(carol_balance, to_decimals(2u64));
+
+    let fee_pool_balance = usdc.balance(&router.wrap(), fee_pool.addr()).unwrap();
+    assert_eq!(fee_pool_balance, Uint128::from(2_386_506_276u128));
+
+    // the insurance fund takes half of the penalty, the 0.386506276 over the liquidator cap and
+    // the 1.374476979 of margin left out of 10.920502083
+    let insurance_balance = usdc.balance(&router.wrap(), insurance_fund.addr()).unwrap();
+    assert_eq!(
+        insurance_balance,
+        to_decimals(5000u64) + Uint128::from(6_533_995_807u128)
+    );
+}
+
+#[test]
+fn test_liquidation_fee_legs_are_paid_in_order_out_of_remaining_margin() {
+    let SimpleScenario {
+        mut router,
+        owner,
+        alice,
+        bob,
+        carol,
+        engine,
+        usdc,
+        vamm,
+        fee_pool,
+        insurance_fund,
+        ..
+    } = new_simple_scenario();
+
+    let msg = engine
+        .update_liquidation_fee_split(
+            LiquidationFeeSplit {
+                liquidator: Uint128::from(250_000_000u128),
+                insurance_fund: Uint128::from(500_000_000u128),
+                fee_pool: Uint128::from(250_000_000u128),
+            },
+            None,
+        )
+        .unwrap();
+    router.execute(owner.clone(), msg).unwrap();
+
+    // a penalty of 10% is more than the margin left at liquidation
+    let msg = engine
+        .update_config(
+            None,
+            None,
+            None,
+            None,
+            None,
+            None,
+            None,
+            Some(Uint128::from(100_000_000u128)),
+            None,
+        )
+        .unwrap();
+    router.execute(owner.clone(), msg).unwrap();
+
+    // alice goes long and bob shorts the price down
+    let msg = engine
+        .open_position(
+            vamm.addr().to_string(),
+            Side::Buy,
+            to_decimals(20u64),
+            to_decimals(10u64),
+            None,
+            None,
+            to_decimals(0u64),
+            vec![],
+        )
+        .unwrap();
+    router.execute(alice.clone(), msg).unwrap();
+
+    let msg = engine
+        .open_position(
+            vamm.addr().to_string(),
+            Side::Sell,
+            to_decimals(6u64),
+            to_decimals(5u64),
+            None,
+            None,
+            to_decimals(0u64),
+            vec![],
+        )
+        .unwrap();
+    router.execute(bob.clone(), msg).unwrap();
+
+    router.update_block(|block| {
+        block.time = block.time.plus_seconds(15);
+        block.height += 1;
+    });
+
+    // set maintenance ratio as 10% to allow liquidation
+    let msg = engine
+        .set_margin_ratios(Uint128::from(100_000_000u128))
+        .unwrap();
+    router.execute(owner.clone(), msg).unwrap();
+
+    let msg = engine
+        .liquidate(vamm.addr().to_string(), 1, to_decimals(0u64))
+        .unwrap();
+    let res = router.execute(carol.clone(), msg).unwrap();
+
+    let event = res
+        .events
+        .iter()
+        .find(|event| {
+            event
+                .attributes
+                .iter()
+                .any(|attr| attr.key == "action" && attr.value == "liquidation_reply")
+        })
+        .unwrap();
+    let attribute = |key: &str| {
+        event
+            .attributes
+            .iter()
+            .find(|attr| attr.key == key)
+            .unwrap()
+            .value
+            .parse::<u128>()
+            .map(Uint128::from)
+            .unwrap()
+    };
+    let liquidation_penalty = attribute("liquidation_penalty");
+    let liquidation_fee = attribute("liquidation_fee");
+    let fee_pool_fee = attribute("fee_pool_fee");
+    let insurance_fee = attribute("insurance_fee");
+
+    // the liquidator and the fee pool are paid their quarter in full, the insurance fund gets
+    // what is left of the 10.920502083 of margin instead of half of the penalty
+    assert_eq!(liquidation_fee, fee_pool_fee);
+    assert!(liquidation_penalty > Uint128::from(10_920_502_083u128));
+    assert!(insurance_fee < liquidation_penalty - liquidation_fee - fee_pool_fee);
+    assert_eq!(
+        liquidation_fee + fee_pool_fee + insurance_fee,
+        Uint128::from(10_920_502_083u128)
+    );
+    assert_eq!(attribute("remaining_margin"), Uint128::zero());
+    assert_eq!(attribute("bad_debt"), Uint128::zero());
+
+    let carol_balance = usdc.balance(&router.wrap(), carol.clone()).unwrap();
+    assert_eq!(carol_balance, liquidation_fee);
+
+    let fee_pool_balance = usdc.balance(&router.wrap(), fee_pool.addr()).unwrap();
+    assert_eq!(fee_pool_balance, fee_pool_fee);
+
+    let insurance_balance = usdc.balance(&router.wrap(), insurance_fund.addr()).unwrap();
+    assert_eq!(insurance_balance, to_decimals(5000u64) + insurance_fee);
+}
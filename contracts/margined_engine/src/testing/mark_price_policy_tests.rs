@@ -0,0 +1,259 @@
+use cosmwasm_std::{StdError, Uint128};
+use margined_common::integer::Integer;
+use margined_perp::margined_engine::{MarkPricePolicy, Side};
+use margined_utils::{
+    cw_multi_test::Executor,
+    testing::{to_decimals, SimpleScenario},
+};
+
+use crate::testing::new_simple_scenario;
+
+#[test]
+fn test_update_mark_price_policy() {
+    let SimpleScenario {
+        mut router,
+        owner,
+        alice,
+        engine,
+        vamm,
+        ..
+    } = new_simple_scenario();
+
+    let policy = engine
+        .get_mark_price_policy(&router.wrap(), vamm.addr().to_string())
+        .unwrap();
+    assert_eq!(policy, MarkPricePolicy::SpotPrice);
+
+    let msg = engine
+        .update_mark_price_policy(vamm.addr().to_string(), MarkPricePolicy::Oracle)
+        .unwrap();
+    let err = router.execute(alice, msg).unwrap_err();
+    assert_eq!(
+        StdError::generic_err("unauthorized"),
+        err.downcast().unwrap()
+    );
+
+    let msg = engine
+        .update_mark_price_policy(vamm.addr().to_string(), MarkPricePolicy::Oracle)
+        .unwrap();
+    router.execute(owner, msg).unwrap();
+
+    let policy = engine
+        .get_mark_price_policy(&router.wrap(), vamm.addr().to_string())
+        .unwrap();
+    assert_eq!(policy, MarkPricePolicy::Oracle);
+}
+
+#[test]
+fn test_max_health_mark_price_prevents_spot_liquidation() {
+    let SimpleScenario {
+        mut router,
+        owner,
+        alice,
+        bob,
+        carol,
+        engine,
+        vamm,
+        pricefeed,
+        ..
+    } = new_simple_scenario();
+
+    // alice goes long and bob shorts the spot price down
+    let msg = engine
+        .open_position(
+            vamm.addr().to_string(),
+            Side::Buy,
+            to_decimals(20u64),
+            to_decimals(10u64),
+            None,
+            None,
+            to_decimals(0u64),
+            vec![],
+        )
+        .unwrap();
+    router.execute(alice.clone(), msg).unwrap();
+
+    let msg = engine
+        .open_position(
+            vamm.addr().to_string(),
+            Side::Sell,
+            to_decimals(6u64),
+            to_decimals(5u64),
+            None,
+            None,
+            to_decimals(0u64),
+            vec![],
+        )
+        .unwrap();
+    router.execute(bob.clone(), msg).unwrap();
+
+    router.update_block(|block| {
+        block.time = block.time.plus_seconds(15);
+        block.height += 1;
+    });
+
+    // the oracle still prices the market at 13
+    let msg = pricefeed
+        .append_price("ETH".to_string(), to_decimals(13u64), 1_000_000_000u64)
+        .unwrap();
+    router.execute(owner.clone(), msg).unwrap();
+
+    let msg = engine
+        .set_margin_ratios(Uint128::from(100_000_000u128))
+        .unwrap();
+    router.execute(owner.clone(), msg).unwrap();
+
+    let margin_ratio = engine
+        .get_margin_ratio(&router.wrap(), vamm.addr().to_string(), 1)
+        .unwrap();
+    assert_eq!(margin_ratio, Integer::new_positive(57_199_211u128));
+
+    let msg = engine
+        .update_mark_price_policy(vamm.addr().to_string(), MarkPricePolicy::MaxHealth)
+        .unwrap();
+    router.execute(owner.clone(), msg).unwrap();
+
+    // (20 + 16.666666658) / 216.666666658 at the oracle price
+    let margin_ratio = engine
+        .get_margin_ratio(&router.wrap(), vamm.addr().to_string(), 1)
+        .unwrap();
+    assert_eq!(margin_ratio, Integer::new_positive(169_230_769u128));
+
+    let msg = engine
+        .liquidate(vamm.addr().to_string(), 1, to_decimals(0u64))
+        .unwrap();
+    let err = router.execute(carol.clone(), msg).unwrap_err();
+    assert_eq!(
+        StdError::generic_err("Position is overcollateralized"),
+        err.downcast().unwrap()
+    );
+
+    // back on spot the position can be liquidated
+    let msg = engine
+        .update_mark_price_policy(vamm.addr().to_string(), MarkPricePolicy::SpotPrice)
+        .unwrap();
+    router.execute(owner.clone(), msg).unwrap();
+
+    let msg = engine
+        .liquidate(vamm.addr().to_string(), 1, to_decimals(0u64))
+        .unwrap();
+    router.execute(carol, msg).unwrap();
+}
+
+#[test]
+fn test_oracle_mark_price_triggers_take_profit() {
+    let SimpleScenario {
+        mut router,
+        owner,
+        alice,
+        engine,
+        vamm,
+        pricefeed,
+        ..
+    } = new_simple_scenario();
+
+    let msg = engine
+        .open_position(
+            vamm.addr().to_string(),
+            Side::Buy,
+            to_decimals(6u64),
+            to_decimals(10u64),
+            Some(to_decimals(11u64)),
+            Some(to_decimals(5u64)),
+            to_decimals(0u64),
+            vec![],
+        )
+        .unwrap();
+    router.execute(alice.clone(), msg).unwrap();
+
+    let msg = pricefeed
+        .append_price("ETH".to_string(), to_decimals(12u64), 1_000_000_000u64)
+        .unwrap();
+    router.execute(owner.clone(), msg).unwrap();
+
+    // closing on the vamm would not reach the take profit
+    let msg = engine
+        .trigger_tp_sl(vamm.addr().to_string(), 1, true)
+        .unwrap();
+    router.execute(alice.clone(), msg).unwrap();
+    engine
+        .position(&router.wrap(), vamm.addr().to_string(), 1)
+        .unwrap();
+
+    let msg = engine
+        .update_mark_price_policy(vamm.addr().to_string(), MarkPricePolicy::Oracle)
+        .unwrap();
+    router.execute(owner, msg).unwrap();
+
+    let tp_sl_status = engine
+        .get_tp_sl_status(&router.wrap(), vamm.addr().to_string(), Side::Buy, true, 10)
+        .unwrap();
+    assert!(tp_sl_status.is_tpsl);
+
+    let msg = engine
+        .trigger_tp_sl(vamm.addr().to_string(), 1, true)
+        .unwrap();
+    router.execute(alice, msg).unwrap();
+
+    let err = engine
+        .position(&router.wrap(), vamm.addr().to_string(), 1)
+        .unwrap_err();
+    assert_eq!(
+        StdError::GenericErr {
+            msg: "Querier contract error: margined_perp::margined_engine::Position not found"
+                .to_string()
+        },
+        err
+    );
+}
+
+#[test]
+fn test_max_health_take_profit_is_reached_at_both_prices() {
+    let SimpleScenario {
+        mut router,
+        owner,
+        alice,
+        engine,
+        vamm,
+        pricefeed,
+        ..
+    } = new_simple_scenario();
+
+    let msg = engine
+        .open_position(
+            vamm.addr().to_string(),
+            Side::Buy,
+            to_decimals(6u64),
+            to_decimals(10u64),
+            Some(to_decimals(11u64)),
+            Some(to_decimals(5u64)),
+            to_decimals(0u64),
+            vec![],
+        )
+        .unwrap();
+    router.execute(alice.clone(), msg).unwrap();
+
+    let msg = engine
+        .update_mark_price_policy(vamm.addr().to_string(), MarkPricePolicy::MaxHealth)
+        .unwrap();
+    router.execute(owner.clone(), msg).unwrap();
+
+    // only the index price reaches the take profit
+    let msg = pricefeed
+        .append_price("ETH".to_string(), to_decimals(12u64), 1_000_000_000u64)
+        .unwrap();
+    router.execute(owner, msg).unwrap();
+
+    let tp_sl_status = engine
+        .get_tp_sl_status(&router.wrap(), vamm.addr().to_string(), Side::Buy, true, 10)
+        .unwrap();
+    assert!(!tp_sl_status.is_tpsl);
+
+    let msg = engine
+        .trigger_tp_sl(vamm.addr().to_string(), 1, true)
+        .unwrap();
+    router.execute(alice, msg).unwrap();
+    engine
+        .position(&router.wrap(), vamm.addr().to_string(), 1)
+        .unwrap();
+}
@@ -0,0 +1,121 @@
+use cosmwasm_std::{StdError, Uint128};
+use margined_perp::margined_engine::Side;
+use margined_utils::{
+    cw_multi_test::Executor,
+    testing::{to_decimals, SimpleScenario},
+};
+
+use crate::testing::new_simple_scenario;
+
+#[test]
+fn test_open_interest_is_tracked_per_side() {
+    let SimpleScenario {
+        mut router,
+        owner,
+        alice,
+        bob,
+        engine,
+        vamm,
+        ..
+    } = new_simple_scenario();
+
+    let msg = engine
+        .update_open_interest_caps(
+            vamm.addr().to_string(),
+            to_decimals(100u64),
+            Uint128::zero(),
+        )
+        .unwrap();
+    let err = router.execute(alice.clone(), msg).unwrap_err();
+    assert_eq!(
+        StdError::generic_err("unauthorized"),
+        err.downcast().unwrap()
+    );
+
+    let msg = engine
+        .update_open_interest_caps(
+            vamm.addr().to_string(),
+            to_decimals(100u64),
+            Uint128::zero(),
+        )
+        .unwrap();
+    router.execute(owner, msg).unwrap();
+
+    let msg = engine
+        .open_position(
+            vamm.addr().to_string(),
+            Side::Buy,
+            to_decimals(6u64),
+            to_decimals(10u64),
+            None,
+            None,
+            to_decimals(0u64),
+            vec![],
+        )
+        .unwrap();
+    router.execute(alice.clone(), msg).unwrap();
+
+    // the long side is capped at 100
+    let msg = engine
+        .open_position(
+            vamm.addr().to_string(),
+            Side::Buy,
+            to_decimals(6u64),
+            to_decimals(10u64),
+            None,
+            None,
+            to_decimals(0u64),
+            vec![],
+        )
+        .unwrap();
+    let err = router.execute(bob.clone(), msg).unwrap_err();
+    assert_eq!(
+        StdError::generic_err("long open interest exceeds cap"),
+        err.downcast().unwrap()
+    );
+
+    let msg = engine
+        .open_position(
+            vamm.addr().to_string(),
+            Side::Sell,
+            to_decimals(6u64),
+            to_decimals(10u64),
+            None,
+            None,
+            to_decimals(0u64),
+            vec![],
+        )
+        .unwrap();
+    router.execute(bob, msg).unwrap();
+
+    let market_state = engine
+        .get_market_state(&router.wrap(), vamm.addr().to_string())
+        .unwrap();
+    assert_eq!(market_state.open_interest_notional, to_decimals(120u64));
+    assert_eq!(market_state.long_open_interest_notional, to_decimals(60u64));
+    assert_eq!(
+        market_state.short_open_interest_notional,
+        to_decimals(60u64)
+    );
+    assert_eq!(market_state.long_open_interest_cap, to_decimals(100u64));
+    assert_eq!(market_state.short_open_interest_cap, Uint128::zero());
+
+    // closing the long frees its side of the market
+    let msg = engine
+        .close_position(vamm.addr().to_string(), 1, to_decimals(0u64))
+        .unwrap();
+    router.execute(alice, msg).unwrap();
+
+    let market_state = engine
+        .get_market_state(&router.wrap(), vamm.addr().to_string())
+        .unwrap();
+    // what is left is the loss to the rounding of the vamm
+    assert_eq!(
+        market_state.long_open_interest_notional,
+        Uint128::from(6u128)
+    );
+    assert_eq!(
+        market_state.short_open_interest_notional,
+        to_decimals(60u64)
+    );
+}
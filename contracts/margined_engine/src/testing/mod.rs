@@ -1,4 +1,8 @@
+mod auto_deleverage_tests;
+mod backstop_liquidation_tests;
 mod bad_debt_tests;
+mod blocklist_tests;
+mod conditional_order_tests;
 mod cw_token_add_remove_margin_tests;
 mod cw_token_liquidation_frontrun_hack_tests;
 mod cw_token_liquidation_tests;
@@ -6,8 +10,12 @@ mod cw_token_pay_funding_tests;
 mod cw_token_position_fee_tests;
 mod fee_calculation_tests;
 mod fluctuation_tests;
+mod keeper_reward_tests;
+mod liquidation_fee_split_tests;
 mod margin_engine_tests;
 mod margin_ratio_tests;
+mod mark_price_policy_tests;
+mod market_open_interest_tests;
 mod native_token_add_remove_margin_tests;
 mod native_token_liquidation_frontrun_hack_tests;
 mod native_token_liquidation_tests;
@@ -15,12 +23,23 @@ mod native_token_pay_funding_tests;
 mod native_token_position_fee_tests;
 mod open_interest_notional_tests;
 mod pausable_tests;
+mod payout_tests;
 mod personal_position_tests;
+mod position_by_size_tests;
 mod position_liquidation_tests;
 mod position_tests;
 mod position_upper_bound_tests;
+mod price_limit_tests;
+mod realize_pnl_tests;
+mod set_leverage_tests;
+mod signed_order_tests;
+mod subaccount_tests;
+mod target_margin_ratio_tests;
 mod tests;
 mod tp_sl_test;
+mod trader_balance_tests;
+mod trader_limits_tests;
+mod twap_order_tests;
 mod whitelist_tests;
 
 mod gas_integration_tests;
@@ -0,0 +1,203 @@
+use crate::contract::{execute, instantiate, query, reply, PAYOUT_REPLY_ID};
+use crate::messages::execute_payout;
+use crate::testing::new_native_token_scenario;
+use cosmwasm_std::testing::{mock_dependencies, mock_env, mock_info};
+use cosmwasm_std::{
+    coins, from_binary, Addr, Attribute, BankMsg, Coin, CosmosMsg, Reply, ReplyOn, StdError,
+    SubMsgResult, Uint128,
+};
+use margined_common::asset::NATIVE_DENOM;
+use margined_perp::margined_engine::{ExecuteMsg, InstantiateMsg, PnlCalcOption, QueryMsg, Side};
+use margined_utils::{
+    cw_multi_test::Executor,
+    testing::{to_decimals, NativeTokenScenario},
+};
+
+const OWNER: &str = "owner";
+const ALICE: &str = "alice";
+
+#[test]
+fn test_failed_payout_is_claimable() {
+    let mut deps = mock_dependencies();
+    let msg = InstantiateMsg {
+        pauser: OWNER.to_string(),
+        operator: None,
+        insurance_fund: Some("insurance_fund".to_string()),
+        fee_pool: "fee_pool".to_string(),
+        eligible_collateral: NATIVE_DENOM.to_string(),
+        initial_margin_ratio: Uint128::from(50_000u128),
+        maintenance_margin_ratio: Uint128::from(50_000u128),
+        tp_sl_spread: Uint128::from(50_000u128),
+        liquidation_fee: Uint128::from(100u128),
+    };
+    instantiate(deps.as_mut(), mock_env(), mock_info(OWNER, &[]), msg).unwrap();
+
+    let alice = Addr::unchecked(ALICE);
+
+    // a payout that goes through does not reply and leaves nothing to claim
+    let payout = execute_payout(deps.as_mut().storage, &alice, Uint128::from(100u128))
+        .unwrap()
+        .unwrap();
+    assert_eq!(payout.id, PAYOUT_REPLY_ID + 1);
+    assert_eq!(payout.reply_on, ReplyOn::Error);
+
+    let res = query(
+        deps.as_ref(),
+        mock_env(),
+        QueryMsg::ClaimableBalance {
+            address: ALICE.to_string(),
+        },
+    )
+    .unwrap();
+    assert_eq!(from_binary::<Uint128>(&res).unwrap(), Uint128::zero());
+
+    let err = execute(
+        deps.as_mut(),
+        mock_env(),
+        mock_info(ALICE, &[]),
+        ExecuteMsg::Claim {},
+    )
+    .unwrap_err();
+    assert_eq!(err, StdError::generic_err("Nothing to claim"));
+
+    // a payout that fails is credited instead of reverting
+    let payout = execute_payout(deps.as_mut().storage, &alice, Uint128::from(200u128))
+        .unwrap()
+        .unwrap();
+    assert_eq!(payout.id, PAYOUT_REPLY_ID + 2);
+    reply(
+        deps.as_mut(),
+        mock_env(),
+        Reply {
+            id: payout.id,
+            result: SubMsgResult::Err("recipient is blocked".to_string()),
+        },
+    )
+    .unwrap();
+
+    let res = query(
+        deps.as_ref(),
+        mock_env(),
+        QueryMsg::ClaimableBalance {
+            address: ALICE.to_string(),
+        },
+    )
+    .unwrap();
+    assert_eq!(
+        from_binary::<Uint128>(&res).unwrap(),
+        Uint128::from(200u128)
+    );
+
+    let res = execute(
+        deps.as_mut(),
+        mock_env(),
+        mock_info(ALICE, &[]),
+        ExecuteMsg::Claim {},
+    )
+    .unwrap();
+    assert_eq!(
+        res.messages[0].msg,
+        CosmosMsg::Bank(BankMsg::Send {
+            to_address: ALICE.to_string(),
+            amount: coins(200u128, NATIVE_DENOM),
+        })
+    );
+
+    let res = query(
+        deps.as_ref(),
+        mock_env(),
+        QueryMsg::ClaimableBalance {
+            address: ALICE.to_string(),
+        },
+    )
+    .unwrap();
+    assert_eq!(from_binary::<Uint128>(&res).unwrap(), Uint128::zero());
+}
+
+#[test]
+fn test_unfunded_payout_is_bad_debt_when_insurance_fund_is_empty() {
+    let NativeTokenScenario {
+        mut router,
+        alice,
+        engine,
+        vamm,
+        insurance_fund,
+        ..
+    } = new_native_token_scenario();
+
+    let msg = engine
+        .open_position(
+            vamm.addr().to_string(),
+            Side::Buy,
+            Uint128::from(60_000_000u64),
+            Uint128::from(10_000_000u64),
+            Some(to_decimals(10)),
+            Some(Uint128::zero()),
+            Uint128::zero(),
+            vec![Coin::new(60_000_000u128, NATIVE_DENOM)],
+        )
+        .unwrap();
+    router.execute(alice.clone(), msg).unwrap();
+
+    let pnl = engine
+        .get_unrealized_pnl(
+            &router.wrap(),
+            vamm.addr().to_string(),
+            1,
+            PnlCalcOption::SpotPrice,
+        )
+        .unwrap()
+        .unrealized_pnl;
+    let proceeds = if pnl.is_negative() {
+        Uint128::from(60_000_000u64).checked_sub(pnl.value).unwrap()
+    } else {
+        Uint128::from(60_000_000u64).checked_add(pnl.value).unwrap()
+    };
+
+    // neither the engine nor the insurance fund can pay the trader
+    router.init_modules(|router, _, storage| {
+        router
+            .bank
+            .init_balance(storage, &engine.addr(), vec![])
+            .unwrap();
+        router
+            .bank
+            .init_balance(storage, &insurance_fund.addr(), vec![])
+            .unwrap();
+    });
+    let alice_balance = router
+        .wrap()
+        .query_balance(&alice, NATIVE_DENOM)
+        .unwrap()
+        .amount;
+
+    // the close goes through and what cannot be funded is realized as bad debt
+    let msg = engine
+        .close_position(vamm.addr().to_string(), 1, Uint128::zero())
+        .unwrap();
+    let res = router.execute(alice.clone(), msg).unwrap();
+    let attrs: Vec<Attribute> = res.events.into_iter().flat_map(|e| e.attributes).collect();
+    let unfunded_bad_debt = attrs.iter().find(|a| a.key == "unfunded_bad_debt").unwrap();
+    assert_eq!(unfunded_bad_debt.value, proceeds.to_string());
+
+    engine
+        .position(&router.wrap(), vamm.addr().to_string(), 1)
+        .unwrap_err();
+    assert_eq!(
+        router
+            .wrap()
+            .query_balance(&alice, NATIVE_DENOM)
+            .unwrap()
+            .amount,
+        alice_balance
+    );
+
+    // nothing backs the unpaid proceeds so they are not owed as a claimable
+    let claimable = engine
+        .get_claimable_balance(&router.wrap(), alice.to_string())
+        .unwrap();
+    assert_eq!(claimable, Uint128::zero());
+
+    let state = engine.state(&router.wrap()).unwrap();
+    assert_eq!(state.total_liabilities, Uint128::zero());
+}
@@ -0,0 +1,146 @@
+use cosmwasm_std::{coins, StdError, Uint128};
+use margined_common::integer::Integer;
+use margined_perp::margined_engine::Side;
+use margined_utils::{
+    cw_multi_test::Executor,
+    testing::{to_decimals, NativeTokenScenario, SimpleScenario},
+};
+
+use crate::testing::{new_native_token_scenario, new_simple_scenario};
+
+#[test]
+fn test_open_long_position_by_size() {
+    let SimpleScenario {
+        mut router,
+        alice,
+        usdc,
+        engine,
+        vamm,
+        ..
+    } = new_simple_scenario();
+
+    let msg = engine
+        .open_position_by_size(
+            vamm.addr().to_string(),
+            Side::Buy,
+            to_decimals(5u64),
+            to_decimals(6u64),
+            vec![],
+        )
+        .unwrap();
+    router.execute(alice.clone(), msg).unwrap();
+
+    // 1000 * 100 / 95 - 1000 quote buys 5 base
+    let position = engine
+        .position(&router.wrap(), vamm.addr().to_string(), 1)
+        .unwrap();
+    assert_eq!(position.size, Integer::new_positive(to_decimals(5u64)));
+    assert_eq!(position.notional, Uint128::from(52_631_578_948u128));
+    assert_eq!(position.margin, to_decimals(6u64));
+
+    let alice_balance = usdc.balance(&router.wrap(), alice).unwrap();
+    assert_eq!(alice_balance, Uint128::from(4_994_000_000_000u128));
+}
+
+#[test]
+fn test_open_short_position_by_size() {
+    let SimpleScenario {
+        mut router,
+        alice,
+        engine,
+        vamm,
+        ..
+    } = new_simple_scenario();
+
+    let msg = engine
+        .open_position_by_size(
+            vamm.addr().to_string(),
+            Side::Sell,
+            to_decimals(5u64),
+            to_decimals(6u64),
+            vec![],
+        )
+        .unwrap();
+    router.execute(alice, msg).unwrap();
+
+    // selling 5 base gives 1000 - 1000 * 100 / 105 quote
+    let position = engine
+        .position(&router.wrap(), vamm.addr().to_string(), 1)
+        .unwrap();
+    assert_eq!(position.size, Integer::new_negative(to_decimals(5u64)));
+    assert_eq!(position.notional, Uint128::from(47_619_047_619u128));
+}
+
+#[test]
+fn test_open_position_by_size_breaks_initial_margin_ratio() {
+    let SimpleScenario {
+        mut router,
+        alice,
+        engine,
+        vamm,
+        ..
+    } = new_simple_scenario();
+
+    // 2 of margin for 52.63 notional is under the 0.05 initial margin ratio
+    let msg = engine
+        .open_position_by_size(
+            vamm.addr().to_string(),
+            Side::Buy,
+            to_decimals(5u64),
+            to_decimals(2u64),
+            vec![],
+        )
+        .unwrap();
+    let err = router.execute(alice.clone(), msg).unwrap_err();
+    assert_eq!(
+        StdError::generic_err("Position is undercollateralized"),
+        err.downcast().unwrap()
+    );
+
+    let msg = engine
+        .open_position_by_size(
+            vamm.addr().to_string(),
+            Side::Buy,
+            to_decimals(5u64),
+            to_decimals(60u64),
+            vec![],
+        )
+        .unwrap();
+    let err = router.execute(alice, msg).unwrap_err();
+    assert_eq!(
+        StdError::generic_err("Leverage must be greater than 1"),
+        err.downcast().unwrap()
+    );
+}
+
+#[test]
+fn test_open_position_by_size_with_native_token() {
+    let NativeTokenScenario {
+        mut router,
+        alice,
+        engine,
+        vamm,
+        ..
+    } = new_native_token_scenario();
+
+    // the margin does not divide the notional evenly, all of it is kept as margin
+    let msg = engine
+        .open_position_by_size(
+            vamm.addr().to_string(),
+            Side::Buy,
+            Uint128::from(5_000_000u128),
+            Uint128::from(6_000_000u128),
+            coins(6_000_000u128, "orai"),
+        )
+        .unwrap();
+    router.execute(alice.clone(), msg).unwrap();
+
+    let position = engine
+        .position(&router.wrap(), vamm.addr().to_string(), 1)
+        .unwrap();
+    assert_eq!(position.size, Integer::new_positive(5_000_000u128));
+    assert_eq!(position.margin, Uint128::from(6_000_000u128));
+
+    let alice_balance = router.wrap().query_balance(&alice, "orai").unwrap().amount;
+    assert_eq!(alice_balance, Uint128::from(4_994_000_000u128));
+}
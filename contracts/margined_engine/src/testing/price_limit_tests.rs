@@ -0,0 +1,119 @@
+use cosmwasm_std::StdError;
+use margined_perp::margined_engine::Side;
+use margined_utils::{
+    cw_multi_test::Executor,
+    testing::{to_decimals, SimpleScenario},
+};
+
+use crate::testing::new_simple_scenario;
+
+#[test]
+fn test_open_position_past_deadline() {
+    let SimpleScenario {
+        mut router,
+        alice,
+        engine,
+        vamm,
+        ..
+    } = new_simple_scenario();
+
+    let deadline = router.block_info().time.seconds() - 1;
+    let msg = engine
+        .open_position_with_limits(
+            vamm.addr().to_string(),
+            Side::Buy,
+            to_decimals(6u64),
+            to_decimals(10u64),
+            None,
+            None,
+            to_decimals(0u64),
+            Some(deadline),
+            None,
+            vec![],
+        )
+        .unwrap();
+    let err = router.execute(alice, msg).unwrap_err();
+    assert_eq!(
+        StdError::generic_err("Deadline has passed"),
+        err.downcast().unwrap()
+    );
+}
+
+#[test]
+fn test_open_and_close_position_worst_price() {
+    let SimpleScenario {
+        mut router,
+        alice,
+        engine,
+        vamm,
+        ..
+    } = new_simple_scenario();
+
+    // buying 5.66 base for 60 averages 10.6
+    let msg = engine
+        .open_position_with_limits(
+            vamm.addr().to_string(),
+            Side::Buy,
+            to_decimals(6u64),
+            to_decimals(10u64),
+            None,
+            None,
+            to_decimals(0u64),
+            None,
+            Some(10_500_000_000u128.into()),
+            vec![],
+        )
+        .unwrap();
+    let err = router.execute(alice.clone(), msg).unwrap_err();
+    assert_eq!(
+        StdError::generic_err("Average price 10600000000 is worse than 10500000000"),
+        err.downcast().unwrap()
+    );
+
+    let msg = engine
+        .open_position_with_limits(
+            vamm.addr().to_string(),
+            Side::Buy,
+            to_decimals(6u64),
+            to_decimals(10u64),
+            None,
+            None,
+            to_decimals(0u64),
+            None,
+            Some(to_decimals(11u64)),
+            vec![],
+        )
+        .unwrap();
+    router.execute(alice.clone(), msg).unwrap();
+
+    // selling it back averages just under 10.6
+    let msg = engine
+        .close_position_with_limits(
+            vamm.addr().to_string(),
+            1,
+            to_decimals(0u64),
+            None,
+            Some(to_decimals(11u64)),
+        )
+        .unwrap();
+    let err = router.execute(alice.clone(), msg).unwrap_err();
+    assert_eq!(
+        StdError::generic_err("Average price 10599999999 is worse than 11000000000"),
+        err.downcast().unwrap()
+    );
+
+    let msg = engine
+        .close_position_with_limits(
+            vamm.addr().to_string(),
+            1,
+            to_decimals(0u64),
+            None,
+            Some(10_500_000_000u128.into()),
+        )
+        .unwrap();
+    router.execute(alice, msg).unwrap();
+
+    assert!(engine
+        .position(&router.wrap(), vamm.addr().to_string(), 1)
+        .is_err());
+}
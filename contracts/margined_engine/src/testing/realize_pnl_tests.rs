@@ -0,0 +1,189 @@
+use cosmwasm_std::{StdError, Uint128};
+use margined_perp::margined_engine::Side;
+use margined_utils::{
+    cw_multi_test::Executor,
+    testing::{to_decimals, SimpleScenario},
+};
+
+use crate::testing::new_simple_scenario;
+
+#[test]
+fn test_realize_pnl() {
+    let SimpleScenario {
+        mut router,
+        alice,
+        bob,
+        usdc,
+        engine,
+        vamm,
+        ..
+    } = new_simple_scenario();
+
+    for trader in [&alice, &bob] {
+        let msg = engine
+            .open_position(
+                vamm.addr().to_string(),
+                Side::Buy,
+                to_decimals(6u64),
+                to_decimals(10u64),
+                None,
+                None,
+                to_decimals(0u64),
+                vec![],
+            )
+            .unwrap();
+        router.execute(trader.clone(), msg).unwrap();
+    }
+
+    // the twap catches up with the spot price once the interval has passed
+    router.update_block(|block| {
+        block.time = block.time.plus_seconds(1_000);
+        block.height += 1;
+    });
+
+    let msg = engine
+        .realize_pnl(vamm.addr().to_string(), 1, to_decimals(5u64))
+        .unwrap();
+    let err = router.execute(bob, msg).unwrap_err();
+    assert_eq!(
+        StdError::generic_err("Unauthorized"),
+        err.downcast().unwrap()
+    );
+
+    // alice has 6.77 of profit
+    let msg = engine
+        .realize_pnl(vamm.addr().to_string(), 1, to_decimals(7u64))
+        .unwrap();
+    let err = router.execute(alice.clone(), msg).unwrap_err();
+    assert_eq!(
+        StdError::generic_err("Insufficient unrealized profit"),
+        err.downcast().unwrap()
+    );
+
+    let msg = engine
+        .realize_pnl(vamm.addr().to_string(), 1, to_decimals(5u64))
+        .unwrap();
+    router.execute(alice.clone(), msg).unwrap();
+
+    let position = engine
+        .position(&router.wrap(), vamm.addr().to_string(), 1)
+        .unwrap();
+    assert_eq!(position.notional, to_decimals(65u64));
+    assert_eq!(position.margin, to_decimals(6u64));
+
+    let alice_balance = usdc.balance(&router.wrap(), alice).unwrap();
+    assert_eq!(alice_balance, to_decimals(4_999u64));
+}
+
+#[test]
+fn test_realize_pnl_ignores_spot_price_moves() {
+    let SimpleScenario {
+        mut router,
+        alice,
+        bob,
+        engine,
+        vamm,
+        ..
+    } = new_simple_scenario();
+
+    let msg = engine
+        .open_position(
+            vamm.addr().to_string(),
+            Side::Buy,
+            to_decimals(6u64),
+            to_decimals(10u64),
+            None,
+            None,
+            to_decimals(0u64),
+            vec![],
+        )
+        .unwrap();
+    router.execute(alice.clone(), msg).unwrap();
+
+    router.update_block(|block| {
+        block.time = block.time.plus_seconds(1_000);
+        block.height += 1;
+    });
+
+    // bob pumps the spot price, the twap has not moved yet
+    let msg = engine
+        .open_position(
+            vamm.addr().to_string(),
+            Side::Buy,
+            to_decimals(6u64),
+            to_decimals(10u64),
+            None,
+            None,
+            to_decimals(0u64),
+            vec![],
+        )
+        .unwrap();
+    router.execute(bob, msg).unwrap();
+
+    let msg = engine
+        .realize_pnl(vamm.addr().to_string(), 1, Uint128::from(1_000_000_000u128))
+        .unwrap();
+    let err = router.execute(alice, msg).unwrap_err();
+    assert_eq!(
+        StdError::generic_err("Insufficient unrealized profit"),
+        err.downcast().unwrap()
+    );
+}
+
+#[test]
+fn test_realize_pnl_is_not_held_to_open_interest_cap() {
+    let SimpleScenario {
+        mut router,
+        owner,
+        alice,
+        bob,
+        engine,
+        vamm,
+        ..
+    } = new_simple_scenario();
+
+    // the long side is capped at the open interest of both positions
+    let msg = engine
+        .update_open_interest_caps(
+            vamm.addr().to_string(),
+            to_decimals(120u64),
+            Uint128::zero(),
+        )
+        .unwrap();
+    router.execute(owner, msg).unwrap();
+
+    for trader in [&alice, &bob] {
+        let msg = engine
+            .open_position(
+                vamm.addr().to_string(),
+                Side::Buy,
+                to_decimals(6u64),
+                to_decimals(10u64),
+                None,
+                None,
+                to_decimals(0u64),
+                vec![],
+            )
+            .unwrap();
+        router.execute(trader.clone(), msg).unwrap();
+    }
+
+    router.update_block(|block| {
+        block.time = block.time.plus_seconds(1_000);
+        block.height += 1;
+    });
+
+    // the basis reset moves the open notional above the cap without adding exposure
+    let msg = engine
+        .realize_pnl(vamm.addr().to_string(), 1, to_decimals(5u64))
+        .unwrap();
+    router.execute(alice, msg).unwrap();
+
+    let market_state = engine
+        .get_market_state(&router.wrap(), vamm.addr().to_string())
+        .unwrap();
+    assert_eq!(
+        market_state.long_open_interest_notional,
+        to_decimals(125u64)
+    );
+}
@@ -0,0 +1,115 @@
+use cosmwasm_std::{StdError, Uint128};
+use margined_perp::margined_engine::Side;
+use margined_utils::{
+    cw_multi_test::Executor,
+    testing::{to_decimals, SimpleScenario},
+};
+
+use crate::testing::new_simple_scenario;
+
+#[test]
+fn test_set_leverage() {
+    let SimpleScenario {
+        mut router,
+        alice,
+        bob,
+        usdc,
+        engine,
+        vamm,
+        ..
+    } = new_simple_scenario();
+
+    let msg = engine
+        .open_position(
+            vamm.addr().to_string(),
+            Side::Buy,
+            to_decimals(6u64),
+            to_decimals(10u64),
+            None,
+            None,
+            to_decimals(0u64),
+            vec![],
+        )
+        .unwrap();
+    router.execute(alice.clone(), msg).unwrap();
+
+    let msg = engine
+        .set_leverage(vamm.addr().to_string(), 1, to_decimals(5u64), vec![])
+        .unwrap();
+    let err = router.execute(bob, msg).unwrap_err();
+    assert_eq!(
+        StdError::generic_err("Unauthorized"),
+        err.downcast().unwrap()
+    );
+
+    // the mark notional is 59.999999994 so 5x needs 11.999999998 of margin
+    let msg = engine
+        .set_leverage(vamm.addr().to_string(), 1, to_decimals(5u64), vec![])
+        .unwrap();
+    router.execute(alice.clone(), msg).unwrap();
+
+    let position = engine
+        .position(&router.wrap(), vamm.addr().to_string(), 1)
+        .unwrap();
+    assert_eq!(position.margin, Uint128::from(11_999_999_998u128));
+    let alice_balance = usdc.balance(&router.wrap(), alice.clone()).unwrap();
+    assert_eq!(alice_balance, Uint128::from(4_988_000_000_002u128));
+
+    // back to 10x withdraws the difference
+    let msg = engine
+        .set_leverage(vamm.addr().to_string(), 1, to_decimals(10u64), vec![])
+        .unwrap();
+    router.execute(alice.clone(), msg).unwrap();
+
+    let position = engine
+        .position(&router.wrap(), vamm.addr().to_string(), 1)
+        .unwrap();
+    assert_eq!(position.margin, Uint128::from(5_999_999_999u128));
+    let alice_balance = usdc.balance(&router.wrap(), alice.clone()).unwrap();
+    assert_eq!(alice_balance, Uint128::from(4_994_000_000_001u128));
+}
+
+#[test]
+fn test_set_leverage_checks_margin() {
+    let SimpleScenario {
+        mut router,
+        alice,
+        engine,
+        vamm,
+        ..
+    } = new_simple_scenario();
+
+    let msg = engine
+        .open_position(
+            vamm.addr().to_string(),
+            Side::Buy,
+            to_decimals(6u64),
+            to_decimals(10u64),
+            None,
+            None,
+            to_decimals(0u64),
+            vec![],
+        )
+        .unwrap();
+    router.execute(alice.clone(), msg).unwrap();
+
+    // 21x is under the 0.05 initial margin ratio
+    let msg = engine
+        .set_leverage(vamm.addr().to_string(), 1, to_decimals(21u64), vec![])
+        .unwrap();
+    let err = router.execute(alice.clone(), msg).unwrap_err();
+    assert_eq!(
+        StdError::generic_err("Position is undercollateralized"),
+        err.downcast().unwrap()
+    );
+
+    // 20x withdraws 3 while the loss leaves slightly less free collateral
+    let msg = engine
+        .set_leverage(vamm.addr().to_string(), 1, to_decimals(20u64), vec![])
+        .unwrap();
+    let err = router.execute(alice, msg).unwrap_err();
+    assert_eq!(
+        StdError::generic_err("Insufficient collateral"),
+        err.downcast().unwrap()
+    );
+}
@@ -0,0 +1,217 @@
+use cosmwasm_std::{Binary, StdError, Uint128};
+use k256::ecdsa::{signature::hazmat::PrehashSigner, Signature, SigningKey};
+use margined_perp::margined_engine::{Side, SignedOrder};
+use margined_utils::{
+    cw_multi_test::Executor,
+    testing::{to_decimals, SimpleScenario},
+};
+
+use crate::{account::signed_order_message, testing::new_simple_scenario, utils::keccak_256};
+
+fn signed_order(vamm: String, nonce: u64, expiry: u64) -> SignedOrder {
+    SignedOrder {
+        vamm,
+        side: Side::Buy,
+        margin_amount: to_decimals(6u64),
+        leverage: to_decimals(10u64),
+        base_asset_limit: to_decimals(0u64),
+        take_profit: None,
+        stop_loss: None,
+        nonce,
+        expiry,
+        subaccount_id: None,
+    }
+}
+
+#[test]
+fn test_deposit_and_withdraw_balance() {
+    let SimpleScenario {
+        mut router,
+        alice,
+        usdc,
+        engine,
+        ..
+    } = new_simple_scenario();
+
+    let msg = engine.deposit(to_decimals(100u64), vec![]).unwrap();
+    router.execute(alice.clone(), msg).unwrap();
+
+    let balance = engine
+        .get_trader_balance(&router.wrap(), alice.to_string())
+        .unwrap()
+        .balance;
+    assert_eq!(balance, to_decimals(100u64));
+    let alice_balance = usdc.balance(&router.wrap(), alice.clone()).unwrap();
+    assert_eq!(alice_balance, Uint128::from(4_900_000_000_000u128));
+
+    let msg = engine.withdraw(to_decimals(101u64)).unwrap();
+    let err = router.execute(alice.clone(), msg).unwrap_err();
+    assert_eq!(
+        StdError::generic_err("Insufficient trader balance"),
+        err.downcast().unwrap()
+    );
+
+    let msg = engine.withdraw(to_decimals(40u64)).unwrap();
+    router.execute(alice.clone(), msg).unwrap();
+
+    let balance = engine
+        .get_trader_balance(&router.wrap(), alice.to_string())
+        .unwrap()
+        .balance;
+    assert_eq!(balance, to_decimals(60u64));
+    let alice_balance = usdc.balance(&router.wrap(), alice.clone()).unwrap();
+    assert_eq!(alice_balance, Uint128::from(4_940_000_000_000u128));
+}
+
+#[test]
+fn test_signed_order_is_rejected() {
+    let SimpleScenario {
+        mut router,
+        alice,
+        carol,
+        engine,
+        vamm,
+        ..
+    } = new_simple_scenario();
+
+    let msg = engine.deposit(to_decimals(100u64), vec![]).unwrap();
+    router.execute(alice.clone(), msg).unwrap();
+
+    let expiry = router.block_info().time.seconds() + 100;
+
+    // expired orders are rejected before the signature is checked
+    let msg = engine
+        .execute_signed_order(
+            alice.to_string(),
+            signed_order(vamm.addr().to_string(), 1, 0),
+            Binary::from(vec![0u8; 64]),
+        )
+        .unwrap();
+    let err = router.execute(carol.clone(), msg).unwrap_err();
+    assert_eq!(
+        StdError::generic_err("Signed order has expired"),
+        err.downcast().unwrap()
+    );
+
+    let msg = engine
+        .execute_signed_order(
+            alice.to_string(),
+            signed_order(vamm.addr().to_string(), 1, expiry),
+            Binary::from(vec![0u8; 64]),
+        )
+        .unwrap();
+    let err = router.execute(carol.clone(), msg).unwrap_err();
+    assert_eq!(
+        StdError::generic_err("Trader has no signing key"),
+        err.downcast().unwrap()
+    );
+
+    let msg = engine
+        .update_signing_key(Some(Binary::from(vec![2u8; 32])))
+        .unwrap();
+    let err = router.execute(alice.clone(), msg).unwrap_err();
+    assert_eq!(
+        StdError::generic_err("Invalid public key"),
+        err.downcast().unwrap()
+    );
+
+    let msg = engine
+        .update_signing_key(Some(Binary::from(vec![2u8; 33])))
+        .unwrap();
+    router.execute(alice.clone(), msg).unwrap();
+
+    let msg = engine
+        .execute_signed_order(
+            alice.to_string(),
+            signed_order(vamm.addr().to_string(), 1, expiry),
+            Binary::from(vec![0u8; 64]),
+        )
+        .unwrap();
+    let err = router.execute(carol, msg).unwrap_err();
+    assert_eq!(
+        StdError::generic_err("Invalid signature"),
+        err.downcast().unwrap()
+    );
+
+    // nothing is debited and the nonce stays unused
+    let balance = engine
+        .get_trader_balance(&router.wrap(), alice.to_string())
+        .unwrap()
+        .balance;
+    assert_eq!(balance, to_decimals(100u64));
+    let used = engine
+        .is_nonce_used(&router.wrap(), alice.to_string(), 1)
+        .unwrap();
+    assert!(!used);
+}
+
+#[test]
+fn test_signed_order_cannot_be_replayed_for_another_trader() {
+    let SimpleScenario {
+        mut router,
+        alice,
+        bob,
+        carol,
+        engine,
+        vamm,
+        ..
+    } = new_simple_scenario();
+
+    // alice and bob register the same key
+    let signing_key = SigningKey::from_slice(&[7u8; 32]).unwrap();
+    let public_key = Binary::from(
+        signing_key
+            .verifying_key()
+            .to_encoded_point(true)
+            .as_bytes()
+            .to_vec(),
+    );
+    for trader in [&alice, &bob] {
+        let msg = engine.deposit(to_decimals(100u64), vec![]).unwrap();
+        router.execute(trader.clone(), msg).unwrap();
+        let msg = engine.update_signing_key(Some(public_key.clone())).unwrap();
+        router.execute(trader.clone(), msg).unwrap();
+    }
+
+    let expiry = router.block_info().time.seconds() + 100;
+    let order = signed_order(vamm.addr().to_string(), 1, expiry);
+    let message = signed_order_message(
+        &router.block_info().chain_id,
+        &engine.addr(),
+        &alice,
+        &order,
+    )
+    .unwrap();
+    let signature: Signature = signing_key.sign_prehash(&keccak_256(&message)).unwrap();
+    let signature = Binary::from(signature.to_bytes().to_vec());
+
+    let msg = engine
+        .execute_signed_order(alice.to_string(), order.clone(), signature.clone())
+        .unwrap();
+    router.execute(carol.clone(), msg).unwrap();
+
+    let position = engine
+        .position(&router.wrap(), vamm.addr().to_string(), 1)
+        .unwrap();
+    assert_eq!(position.trader, alice);
+
+    // the order of alice is not a valid order of bob
+    let msg = engine
+        .execute_signed_order(bob.to_string(), order, signature)
+        .unwrap();
+    let err = router.execute(carol, msg).unwrap_err();
+    assert_eq!(
+        StdError::generic_err("Invalid signature"),
+        err.downcast().unwrap()
+    );
+
+    let balance = engine
+        .get_trader_balance(&router.wrap(), bob.to_string())
+        .unwrap()
+        .balance;
+    assert_eq!(balance, to_decimals(100u64));
+    let used = engine
+        .is_nonce_used(&router.wrap(), bob.to_string(), 1)
+        .unwrap();
+    assert!(!used);
+}
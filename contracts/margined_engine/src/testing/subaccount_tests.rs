@@ -0,0 +1,289 @@
+use cosmwasm_std::{StdError, Uint128};
+use margined_perp::margined_engine::{PositionFilter, Side, TraderLimits};
+use margined_utils::{
+    cw_multi_test::Executor,
+    testing::{to_decimals, SimpleScenario},
+};
+
+use crate::testing::new_simple_scenario;
+
+#[test]
+fn test_transfer_between_subaccounts() {
+    let SimpleScenario {
+        mut router,
+        alice,
+        engine,
+        ..
+    } = new_simple_scenario();
+
+    let msg = engine.deposit(to_decimals(100u64), vec![]).unwrap();
+    router.execute(alice.clone(), msg).unwrap();
+
+    let msg = engine
+        .transfer_between_subaccounts(0, 0, to_decimals(50u64))
+        .unwrap();
+    let err = router.execute(alice.clone(), msg).unwrap_err();
+    assert_eq!(
+        StdError::generic_err("Sub-accounts must be different"),
+        err.downcast().unwrap()
+    );
+
+    let msg = engine
+        .transfer_between_subaccounts(0, 1, to_decimals(101u64))
+        .unwrap();
+    let err = router.execute(alice.clone(), msg).unwrap_err();
+    assert_eq!(
+        StdError::generic_err("Insufficient trader balance"),
+        err.downcast().unwrap()
+    );
+
+    let msg = engine
+        .transfer_between_subaccounts(0, 1, to_decimals(40u64))
+        .unwrap();
+    router.execute(alice.clone(), msg).unwrap();
+
+    let balance = engine
+        .get_trader_balance(&router.wrap(), alice.to_string())
+        .unwrap();
+    assert_eq!(balance.balance, to_decimals(60u64));
+    let balance = engine
+        .get_subaccount_balance(&router.wrap(), alice.to_string(), 1)
+        .unwrap();
+    assert_eq!(balance.balance, to_decimals(40u64));
+
+    // the main account cannot withdraw what belongs to the sub-account
+    let msg = engine.withdraw(to_decimals(61u64)).unwrap();
+    let err = router.execute(alice, msg).unwrap_err();
+    assert_eq!(
+        StdError::generic_err("Insufficient trader balance"),
+        err.downcast().unwrap()
+    );
+}
+
+#[test]
+fn test_open_and_close_position_in_subaccount() {
+    let SimpleScenario {
+        mut router,
+        alice,
+        engine,
+        vamm,
+        ..
+    } = new_simple_scenario();
+
+    let msg = engine.deposit(to_decimals(100u64), vec![]).unwrap();
+    router.execute(alice.clone(), msg).unwrap();
+    let msg = engine
+        .transfer_between_subaccounts(0, 1, to_decimals(50u64))
+        .unwrap();
+    router.execute(alice.clone(), msg).unwrap();
+
+    let msg = engine
+        .open_position_from_subaccount(
+            vamm.addr().to_string(),
+            1,
+            Side::Buy,
+            to_decimals(6u64),
+            to_decimals(10u64),
+            to_decimals(0u64),
+        )
+        .unwrap();
+    router.execute(alice.clone(), msg).unwrap();
+
+    let position = engine
+        .position(&router.wrap(), vamm.addr().to_string(), 1)
+        .unwrap();
+    assert_eq!(position.trader, alice);
+    assert_eq!(position.subaccount_id, 1);
+
+    let balance = engine
+        .get_subaccount_balance(&router.wrap(), alice.to_string(), 1)
+        .unwrap();
+    assert_eq!(balance.balance, to_decimals(44u64));
+    let balance = engine
+        .get_trader_balance(&router.wrap(), alice.to_string())
+        .unwrap();
+    assert_eq!(balance.balance, to_decimals(50u64));
+
+    let positions = engine
+        .get_positions(
+            &router.wrap(),
+            vamm.addr().to_string(),
+            PositionFilter::Subaccount(alice.to_string(), 1),
+            None,
+            None,
+            None,
+            None,
+        )
+        .unwrap();
+    assert_eq!(positions.len(), 1);
+    let positions = engine
+        .get_positions(
+            &router.wrap(),
+            vamm.addr().to_string(),
+            PositionFilter::Subaccount(alice.to_string(), 0),
+            None,
+            None,
+            None,
+            None,
+        )
+        .unwrap();
+    assert!(positions.is_empty());
+    let positions = engine
+        .get_positions(
+            &router.wrap(),
+            vamm.addr().to_string(),
+            PositionFilter::Trader(alice.to_string()),
+            None,
+            None,
+            None,
+            None,
+        )
+        .unwrap();
+    assert_eq!(positions.len(), 1);
+
+    // the proceeds go back to the sub-account without opting in
+    let msg = engine
+        .close_position(vamm.addr().to_string(), 1, to_decimals(0u64))
+        .unwrap();
+    router.execute(alice.clone(), msg).unwrap();
+
+    let balance = engine
+        .get_subaccount_balance(&router.wrap(), alice.to_string(), 1)
+        .unwrap();
+    assert_eq!(balance.balance, Uint128::from(49_999_999_994u128));
+    let positions = engine
+        .get_positions(
+            &router.wrap(),
+            vamm.addr().to_string(),
+            PositionFilter::Subaccount(alice.to_string(), 1),
+            None,
+            None,
+            None,
+            None,
+        )
+        .unwrap();
+    assert!(positions.is_empty());
+}
+
+#[test]
+fn test_withdraw_margin_of_subaccount_position_is_credited_to_subaccount() {
+    let SimpleScenario {
+        mut router,
+        alice,
+        usdc,
+        engine,
+        vamm,
+        ..
+    } = new_simple_scenario();
+
+    // the margin is paid from the wallet, the position belongs to the sub-account
+    let msg = engine
+        .open_position_for_subaccount(
+            vamm.addr().to_string(),
+            2,
+            Side::Buy,
+            to_decimals(6u64),
+            to_decimals(5u64),
+            to_decimals(0u64),
+            vec![],
+        )
+        .unwrap();
+    router.execute(alice.clone(), msg).unwrap();
+
+    let position = engine
+        .position(&router.wrap(), vamm.addr().to_string(), 1)
+        .unwrap();
+    assert_eq!(position.subaccount_id, 2);
+    let alice_balance = usdc.balance(&router.wrap(), alice.clone()).unwrap();
+
+    let msg = engine
+        .withdraw_margin(vamm.addr().to_string(), 1, to_decimals(1u64))
+        .unwrap();
+    router.execute(alice.clone(), msg).unwrap();
+
+    let balance = engine
+        .get_subaccount_balance(&router.wrap(), alice.to_string(), 2)
+        .unwrap();
+    assert_eq!(balance.balance, to_decimals(1u64));
+    let balance = engine
+        .get_trader_balance(&router.wrap(), alice.to_string())
+        .unwrap();
+    assert!(balance.balance.is_zero());
+    assert_eq!(usdc.balance(&router.wrap(), alice).unwrap(), alice_balance);
+}
+
+#[test]
+fn test_self_limits_are_kept_per_subaccount() {
+    let SimpleScenario {
+        mut router,
+        alice,
+        engine,
+        vamm,
+        ..
+    } = new_simple_scenario();
+
+    let msg = engine
+        .update_subaccount_self_limits(
+            1,
+            TraderLimits {
+                max_positions_per_market: Some(1),
+                ..TraderLimits::default()
+            },
+        )
+        .unwrap();
+    router.execute(alice.clone(), msg).unwrap();
+
+    let limits = engine
+        .get_subaccount_limits(&router.wrap(), alice.to_string(), 1)
+        .unwrap();
+    assert_eq!(limits.self_limits.max_positions_per_market, Some(1));
+    let limits = engine
+        .get_trader_limits(&router.wrap(), alice.to_string())
+        .unwrap();
+    assert_eq!(limits.self_limits, TraderLimits::default());
+
+    let msg = engine
+        .open_position_for_subaccount(
+            vamm.addr().to_string(),
+            1,
+            Side::Buy,
+            to_decimals(6u64),
+            to_decimals(5u64),
+            to_decimals(0u64),
+            vec![],
+        )
+        .unwrap();
+    router.execute(alice.clone(), msg).unwrap();
+
+    let msg = engine
+        .open_position_for_subaccount(
+            vamm.addr().to_string(),
+            1,
+            Side::Buy,
+            to_decimals(6u64),
+            to_decimals(5u64),
+            to_decimals(0u64),
+            vec![],
+        )
+        .unwrap();
+    let err = router.execute(alice.clone(), msg).unwrap_err();
+    assert_eq!(
+        StdError::generic_err("Open positions exceed the trader limit"),
+        err.downcast().unwrap()
+    );
+
+    // the limits of the sub-account do not apply to the main account
+    let msg = engine
+        .open_position(
+            vamm.addr().to_string(),
+            Side::Buy,
+            to_decimals(6u64),
+            to_decimals(5u64),
+            None,
+            None,
+            to_decimals(0u64),
+            vec![],
+        )
+        .unwrap();
+    router.execute(alice, msg).unwrap();
+}
@@ -0,0 +1,158 @@
+use cosmwasm_std::{StdError, Uint128};
+use margined_common::integer::Integer;
+use margined_perp::margined_engine::Side;
+use margined_utils::{
+    cw_multi_test::Executor,
+    testing::{to_decimals, SimpleScenario},
+};
+
+use crate::testing::new_simple_scenario;
+
+#[test]
+fn test_target_margin_ratio_must_be_above_maintenance_and_liquidation_fee() {
+    let SimpleScenario {
+        mut router,
+        owner,
+        engine,
+        ..
+    } = new_simple_scenario();
+
+    let msg = engine
+        .set_target_margin_ratio(Uint128::from(50_000_000u128))
+        .unwrap();
+    let err = router.execute(owner.clone(), msg).unwrap_err();
+    assert_eq!(
+        StdError::generic_err(
+            "Target margin ratio must be above the maintenance margin ratio and the liquidation fee"
+        ),
+        err.downcast().unwrap()
+    );
+
+    let msg = engine
+        .set_target_margin_ratio(Uint128::from(150_000_000u128))
+        .unwrap();
+    router.execute(owner.clone(), msg).unwrap();
+
+    let config = engine.config(&router.wrap()).unwrap();
+    assert_eq!(config.target_margin_ratio, Uint128::from(150_000_000u128));
+
+    // the target is checked again when the maintenance margin ratio or the liquidation fee moves
+    let msg = engine
+        .set_margin_ratios(Uint128::from(200_000_000u128))
+        .unwrap();
+    let err = router.execute(owner.clone(), msg).unwrap_err();
+    assert_eq!(
+        StdError::generic_err(
+            "Target margin ratio must be above the maintenance margin ratio and the liquidation fee"
+        ),
+        err.downcast().unwrap()
+    );
+
+    let msg = engine
+        .set_liquidation_fee(Uint128::from(150_000_000u128))
+        .unwrap();
+    let err = router.execute(owner, msg).unwrap_err();
+    assert_eq!(
+        StdError::generic_err(
+            "Target margin ratio must be above the maintenance margin ratio and the liquidation fee"
+        ),
+        err.downcast().unwrap()
+    );
+}
+
+#[test]
+fn test_partial_liquidation_restores_target_margin_ratio() {
+    let SimpleScenario {
+        mut router,
+        owner,
+        alice,
+        bob,
+        carol,
+        engine,
+        usdc,
+        vamm,
+        insurance_fund,
+        ..
+    } = new_simple_scenario();
+
+    // alice goes long and bob shorts the price down
+    let msg = engine
+        .open_position(
+            vamm.addr().to_string(),
+            Side::Buy,
+            to_decimals(20u64),
+            to_decimals(10u64),
+            None,
+            None,
+            to_decimals(0u64),
+            vec![],
+        )
+        .unwrap();
+    router.execute(alice.clone(), msg).unwrap();
+
+    let msg = engine
+        .open_position(
+            vamm.addr().to_string(),
+            Side::Sell,
+            to_decimals(6u64),
+            to_decimals(5u64),
+            None,
+            None,
+            to_decimals(0u64),
+            vec![],
+        )
+        .unwrap();
+    router.execute(bob.clone(), msg).unwrap();
+
+    router.update_block(|block| {
+        block.time = block.time.plus_seconds(15);
+        block.height += 1;
+    });
+
+    // set maintenance ratio as 10% to allow liquidation, and restore positions to 15%
+    let msg = engine
+        .set_margin_ratios(Uint128::from(100_000_000u128))
+        .unwrap();
+    router.execute(owner.clone(), msg).unwrap();
+    let msg = engine
+        .set_target_margin_ratio(Uint128::from(150_000_000u128))
+        .unwrap();
+    router.execute(owner.clone(), msg).unwrap();
+
+    let margin_ratio = engine
+        .get_margin_ratio(&router.wrap(), vamm.addr().to_string(), 1)
+        .unwrap();
+    assert_eq!(margin_ratio, Integer::new_positive(57_199_211u128));
+
+    let size_before = engine
+        .position(&router.wrap(), vamm.addr().to_string(), 1)
+        .unwrap()
+        .size;
+
+    let msg = engine
+        .liquidate(vamm.addr().to_string(), 1, to_decimals(0u64))
+        .unwrap();
+    router.execute(carol.clone(), msg).unwrap();
+
+    // the size is closed on the vamm curve of the 1000/100 reserves, whose slippage moves the
+    // price of what is left, and the ratio of what is left still reaches the target
+    let position = engine
+        .position(&router.wrap(), vamm.addr().to_string(), 1)
+        .unwrap();
+    assert!(position.size.value < size_before.value);
+    assert!(!position.size.is_zero());
+
+    let margin_ratio = engine
+        .get_margin_ratio(&router.wrap(), vamm.addr().to_string(), 1)
+        .unwrap();
+    assert!(margin_ratio >= Integer::new_positive(150_000_000u128));
+
+    // the penalty is split between carol and the insurance fund, which takes the rounding
+    let carol_balance = usdc.balance(&router.wrap(), carol.clone()).unwrap();
+    assert!(!carol_balance.is_zero());
+
+    let insurance_balance = usdc.balance(&router.wrap(), insurance_fund.addr()).unwrap();
+    let insurance_fee = insurance_balance - to_decimals(5000u64);
+    assert!(insurance_fee >= carol_balance);
+    assert!(insurance_fee - carol_balance <= Uint128::from(1u128));
+}
@@ -1,10 +1,20 @@
-use crate::contract::{execute, instantiate, query};
-use cosmwasm_std::testing::{mock_dependencies, mock_env, mock_info};
-use cosmwasm_std::{from_binary, Addr, Uint128};
+use crate::contract::{execute, instantiate, migrate, query};
+use crate::state::{
+    read_vamm_map, store_claimable, store_position, KEY_CONFIG, KEY_TOTAL_LIABILITIES,
+    PREFIX_POSITION_BY_SUBACCOUNT,
+};
+use crate::utils::keccak_256;
+use cosmwasm_schema::cw_serde;
+use cosmwasm_std::testing::{
+    mock_dependencies, mock_env, mock_info, MockApi, MockQuerier, MockStorage,
+};
+use cosmwasm_std::{from_binary, to_vec, Addr, OwnedDeps, StdError, Storage, Uint128};
+use cosmwasm_storage::{Bucket, ReadonlyBucket};
 use margined_common::asset::{AssetInfo, NATIVE_DENOM};
 use margined_common::integer::Integer;
 use margined_perp::margined_engine::{
-    ConfigResponse, ExecuteMsg, InstantiateMsg, PauserResponse, QueryMsg,
+    BackfillResponse, BackfillStage, ConfigResponse, ExecuteMsg, InstantiateMsg, KeeperReward,
+    LiquidationFeeSplit, MigrateMsg, PauserResponse, Position, QueryMsg, Side, StateResponse,
 };
 
 const OWNER: &str = "owner";
@@ -53,8 +63,317 @@ fn test_instantiation() {
             partial_liquidation_ratio: Uint128::zero(),
             tp_sl_spread: Uint128::from(50_000u128),
             liquidation_fee: Uint128::from(100u128),
+            backstop_pool: None,
+            backstop_discount: Uint128::zero(),
+            liquidation_fee_split: LiquidationFeeSplit {
+                liquidator: Uint128::from(500_000u128),
+                insurance_fund: Uint128::from(500_000u128),
+                fee_pool: Uint128::zero(),
+            },
+            max_liquidator_fee: None,
+            target_margin_ratio: Uint128::zero(),
+            keeper_reward: KeeperReward::default(),
+        }
+    );
+}
+
+// config as stored by engines deployed before the upgrade
+#[cw_serde]
+struct LegacyConfig {
+    owner: Addr,
+    insurance_fund: Option<Addr>,
+    fee_pool: Addr,
+    eligible_collateral: AssetInfo,
+    decimals: Uint128,
+    initial_margin_ratio: Uint128,
+    maintenance_margin_ratio: Uint128,
+    partial_liquidation_ratio: Uint128,
+    tp_sl_spread: Uint128,
+    liquidation_fee: Uint128,
+    operator: Option<Addr>,
+}
+
+#[test]
+fn test_migrate_config_stored_before_upgrade() {
+    let mut deps = mock_dependencies();
+    let legacy_config = LegacyConfig {
+        owner: Addr::unchecked(OWNER),
+        insurance_fund: Some(Addr::unchecked(INSURANCE_FUND)),
+        fee_pool: Addr::unchecked(FEE_POOL),
+        eligible_collateral: AssetInfo::NativeToken {
+            denom: NATIVE_DENOM.to_string(),
+        },
+        decimals: Uint128::from(10u128.pow(6u32)),
+        initial_margin_ratio: Uint128::from(50_000u128),
+        maintenance_margin_ratio: Uint128::from(50_000u128),
+        partial_liquidation_ratio: Uint128::zero(),
+        tp_sl_spread: Uint128::from(50_000u128),
+        liquidation_fee: Uint128::from(100u128),
+        operator: None,
+    };
+    deps.storage
+        .set(KEY_CONFIG, &to_vec(&legacy_config).unwrap());
+
+    migrate(deps.as_mut(), mock_env(), MigrateMsg {}).unwrap();
+
+    let res = query(deps.as_ref(), mock_env(), QueryMsg::Config {}).unwrap();
+    let config: ConfigResponse = from_binary(&res).unwrap();
+    assert_eq!(
+        config,
+        ConfigResponse {
+            owner: legacy_config.owner,
+            operator: None,
+            insurance_fund: legacy_config.insurance_fund,
+            fee_pool: legacy_config.fee_pool,
+            eligible_collateral: legacy_config.eligible_collateral,
+            decimals: legacy_config.decimals,
+            initial_margin_ratio: legacy_config.initial_margin_ratio,
+            maintenance_margin_ratio: legacy_config.maintenance_margin_ratio,
+            partial_liquidation_ratio: Uint128::zero(),
+            tp_sl_spread: legacy_config.tp_sl_spread,
+            liquidation_fee: legacy_config.liquidation_fee,
+            backstop_pool: None,
+            backstop_discount: Uint128::zero(),
+            liquidation_fee_split: LiquidationFeeSplit {
+                liquidator: Uint128::from(500_000u128),
+                insurance_fund: Uint128::from(500_000u128),
+                fee_pool: Uint128::zero(),
+            },
+            max_liquidator_fee: None,
+            target_margin_ratio: Uint128::zero(),
+            keeper_reward: KeeperReward::default(),
+        }
+    );
+}
+
+// runs the pending backfill until no stage is left
+fn run_backfill(deps: &mut OwnedDeps<MockStorage, MockApi, MockQuerier>, limit: Option<u32>) {
+    loop {
+        let res = query(deps.as_ref(), mock_env(), QueryMsg::Backfill {}).unwrap();
+        let backfill: BackfillResponse = from_binary(&res).unwrap();
+        if backfill.stages.is_empty() {
+            break;
         }
+
+        execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info(OWNER, &[]),
+            ExecuteMsg::Backfill { limit },
+        )
+        .unwrap();
+    }
+}
+
+#[test]
+fn test_migrate_seeds_open_interest_from_positions() {
+    let mut deps = mock_dependencies();
+    let msg = InstantiateMsg {
+        pauser: OWNER.to_string(),
+        operator: None,
+        insurance_fund: Some(INSURANCE_FUND.to_string()),
+        fee_pool: FEE_POOL.to_string(),
+        eligible_collateral: NATIVE_DENOM.to_string(),
+        initial_margin_ratio: Uint128::from(50_000u128), // 0.05
+        maintenance_margin_ratio: Uint128::from(50_000u128), // 0.05
+        tp_sl_spread: Uint128::from(50_000u128),         // 0.05
+        liquidation_fee: Uint128::from(100u128),
+    };
+    let info = mock_info(OWNER, &[]);
+    instantiate(deps.as_mut(), mock_env(), info, msg).unwrap();
+
+    // positions opened before the open interest of each side was tracked
+    let vamm = Addr::unchecked("vamm");
+    let vamm_key = keccak_256(vamm.as_bytes());
+    for (position_id, side, size, notional) in [
+        (1u64, Side::Buy, Integer::new_positive(10u64), 100u64),
+        (2u64, Side::Buy, Integer::new_positive(5u64), 60u64),
+        (3u64, Side::Sell, Integer::new_negative(3u64), 30u64),
+    ] {
+        let position = Position {
+            position_id,
+            vamm: vamm.clone(),
+            trader: Addr::unchecked("trader"),
+            side,
+            size,
+            notional: Uint128::from(notional),
+            ..Position::default()
+        };
+        store_position(deps.as_mut().storage, &vamm_key, &position, true).unwrap();
+    }
+
+    migrate(deps.as_mut(), mock_env(), MigrateMsg {}).unwrap();
+
+    // the engine stays paused until the stored data is rebuilt
+    let res = query(deps.as_ref(), mock_env(), QueryMsg::Backfill {}).unwrap();
+    let backfill: BackfillResponse = from_binary(&res).unwrap();
+    assert_eq!(
+        backfill.stages,
+        vec![
+            BackfillStage::OpenInterest,
+            BackfillStage::PositionBySubaccount
+        ]
+    );
+
+    let info = mock_info(OWNER, &[]);
+    let err = execute(
+        deps.as_mut(),
+        mock_env(),
+        info.clone(),
+        ExecuteMsg::SetPause { pause: false },
+    )
+    .unwrap_err();
+    assert_eq!(err, StdError::generic_err("A backfill is pending"));
+
+    let err = execute(
+        deps.as_mut(),
+        mock_env(),
+        mock_info("trader", &[]),
+        ExecuteMsg::Backfill { limit: Some(2) },
+    )
+    .unwrap_err();
+    assert_eq!(err, StdError::generic_err("unauthorized"));
+
+    // the positions are read two at a time
+    for _ in 0..2 {
+        execute(
+            deps.as_mut(),
+            mock_env(),
+            info.clone(),
+            ExecuteMsg::Backfill { limit: Some(2) },
+        )
+        .unwrap();
+    }
+
+    let vamm_map = read_vamm_map(deps.as_ref().storage, &vamm).unwrap();
+    assert_eq!(vamm_map.long_open_interest_notional, Uint128::from(160u64));
+    assert_eq!(vamm_map.short_open_interest_notional, Uint128::from(30u64));
+
+    let res = query(deps.as_ref(), mock_env(), QueryMsg::Backfill {}).unwrap();
+    let backfill: BackfillResponse = from_binary(&res).unwrap();
+    assert_eq!(backfill.stages[0], BackfillStage::PositionBySubaccount);
+
+    run_backfill(&mut deps, None);
+
+    execute(
+        deps.as_mut(),
+        mock_env(),
+        info.clone(),
+        ExecuteMsg::SetPause { pause: false },
+    )
+    .unwrap();
+
+    // the backfill runs once, migrating again does not pause the engine
+    migrate(deps.as_mut(), mock_env(), MigrateMsg {}).unwrap();
+    let err = execute(
+        deps.as_mut(),
+        mock_env(),
+        info,
+        ExecuteMsg::Backfill { limit: None },
+    )
+    .unwrap_err();
+    assert_eq!(err, StdError::generic_err("No backfill is pending"));
+}
+
+#[test]
+fn test_migrate_backfills_positions_of_main_account() {
+    let mut deps = mock_dependencies();
+    let msg = InstantiateMsg {
+        pauser: OWNER.to_string(),
+        operator: None,
+        insurance_fund: Some(INSURANCE_FUND.to_string()),
+        fee_pool: FEE_POOL.to_string(),
+        eligible_collateral: NATIVE_DENOM.to_string(),
+        initial_margin_ratio: Uint128::from(50_000u128), // 0.05
+        maintenance_margin_ratio: Uint128::from(50_000u128), // 0.05
+        tp_sl_spread: Uint128::from(50_000u128),         // 0.05
+        liquidation_fee: Uint128::from(100u128),
+    };
+    let info = mock_info(OWNER, &[]);
+    instantiate(deps.as_mut(), mock_env(), info, msg).unwrap();
+
+    // a position stored before it was indexed by sub-account
+    let vamm = Addr::unchecked("vamm");
+    let vamm_key = keccak_256(vamm.as_bytes());
+    let position = Position {
+        position_id: 1,
+        vamm: vamm.clone(),
+        trader: Addr::unchecked("trader"),
+        side: Side::Buy,
+        size: Integer::new_positive(10u64),
+        notional: Uint128::from(100u64),
+        ..Position::default()
+    };
+    store_position(deps.as_mut().storage, &vamm_key, &position, true).unwrap();
+    let subaccount_index: &[&[u8]] = &[
+        PREFIX_POSITION_BY_SUBACCOUNT,
+        &vamm_key,
+        b"trader",
+        &0u32.to_be_bytes(),
+    ];
+    Bucket::<Side>::multilevel(deps.as_mut().storage, subaccount_index).remove(&1u64.to_be_bytes());
+
+    migrate(deps.as_mut(), mock_env(), MigrateMsg {}).unwrap();
+
+    run_backfill(&mut deps, None);
+
+    let side = ReadonlyBucket::<Side>::multilevel(deps.as_ref().storage, subaccount_index)
+        .load(&1u64.to_be_bytes())
+        .unwrap();
+    assert_eq!(side, Side::Buy);
+}
+
+#[test]
+fn test_migrate_starts_total_liabilities_at_zero() {
+    let mut deps = mock_dependencies();
+    let msg = InstantiateMsg {
+        pauser: OWNER.to_string(),
+        operator: None,
+        insurance_fund: Some(INSURANCE_FUND.to_string()),
+        fee_pool: FEE_POOL.to_string(),
+        eligible_collateral: NATIVE_DENOM.to_string(),
+        initial_margin_ratio: Uint128::from(50_000u128), // 0.05
+        maintenance_margin_ratio: Uint128::from(50_000u128), // 0.05
+        tp_sl_spread: Uint128::from(50_000u128),         // 0.05
+        liquidation_fee: Uint128::from(100u128),
+    };
+    let info = mock_info(OWNER, &[]);
+    instantiate(deps.as_mut(), mock_env(), info, msg).unwrap();
+
+    // the total was not tracked before the upgrade
+    deps.as_mut().storage.remove(KEY_TOTAL_LIABILITIES);
+
+    migrate(deps.as_mut(), mock_env(), MigrateMsg {}).unwrap();
+
+    // no liabilities were stored before the upgrade, only the positions are rebuilt
+    let res = query(deps.as_ref(), mock_env(), QueryMsg::Backfill {}).unwrap();
+    let backfill: BackfillResponse = from_binary(&res).unwrap();
+    assert_eq!(
+        backfill.stages,
+        vec![
+            BackfillStage::OpenInterest,
+            BackfillStage::PositionBySubaccount
+        ]
     );
+
+    let res = query(deps.as_ref(), mock_env(), QueryMsg::State {}).unwrap();
+    let state: StateResponse = from_binary(&res).unwrap();
+    assert_eq!(state.total_liabilities, Uint128::zero());
+
+    run_backfill(&mut deps, None);
+
+    // migrating again keeps the running total
+    store_claimable(
+        deps.as_mut().storage,
+        &Addr::unchecked("alice"),
+        Uint128::from(10u64),
+    )
+    .unwrap();
+    migrate(deps.as_mut(), mock_env(), MigrateMsg {}).unwrap();
+
+    let res = query(deps.as_ref(), mock_env(), QueryMsg::State {}).unwrap();
+    let state: StateResponse = from_binary(&res).unwrap();
+    assert_eq!(state.total_liabilities, Uint128::from(10u64));
 }
 
 #[test]
@@ -84,6 +403,7 @@ fn test_update_config() {
         partial_liquidation_ratio: None,
         tp_sl_spread: None,
         liquidation_fee: None,
+        target_margin_ratio: None,
     };
 
     let info = mock_info(OWNER, &[]);
@@ -107,6 +427,16 @@ fn test_update_config() {
             partial_liquidation_ratio: Uint128::zero(),
             tp_sl_spread: Uint128::from(50_000u128),
             liquidation_fee: Uint128::from(100u128),
+            backstop_pool: None,
+            backstop_discount: Uint128::zero(),
+            liquidation_fee_split: LiquidationFeeSplit {
+                liquidator: Uint128::from(500_000u128),
+                insurance_fund: Uint128::from(500_000u128),
+                fee_pool: Uint128::zero(),
+            },
+            max_liquidator_fee: None,
+            target_margin_ratio: Uint128::zero(),
+            keeper_reward: KeeperReward::default(),
         }
     );
 
@@ -120,6 +450,7 @@ fn test_update_config() {
         partial_liquidation_ratio: None,
         tp_sl_spread: None,
         liquidation_fee: None,
+        target_margin_ratio: None,
     };
 
     let info = mock_info(OWNER, &[]);
@@ -136,6 +467,7 @@ fn test_update_config() {
         partial_liquidation_ratio: None,
         tp_sl_spread: None,
         liquidation_fee: None,
+        target_margin_ratio: None,
     };
 
     let info = mock_info(OWNER, &[]);
@@ -5,7 +5,10 @@ use std::{
 
 use cosmwasm_std::{StdError, Uint128};
 
-use margined_perp::{margined_engine::Side, margined_vamm::Direction};
+use margined_perp::{
+    margined_engine::{KeeperReward, Side, TakeProfitLevel, TpSlTrigger, TrailDistance},
+    margined_vamm::Direction,
+};
 use margined_utils::{
     cw_multi_test::Executor,
     testing::{to_decimals, SimpleScenario},
@@ -1167,3 +1170,592 @@ fn test_multi_stoploss_short() {
             .unwrap()
     );
 }
+
+#[test]
+fn test_update_tp_sl_trigger() {
+    let SimpleScenario {
+        mut router,
+        alice,
+        bob,
+        engine,
+        vamm,
+        ..
+    } = new_simple_scenario();
+
+    let msg = engine
+        .open_position(
+            vamm.addr().to_string(),
+            Side::Buy,
+            to_decimals(6u64),
+            to_decimals(10u64),
+            Some(to_decimals(11u64)),
+            Some(to_decimals(5u64)),
+            to_decimals(0u64),
+            vec![],
+        )
+        .unwrap();
+    router.execute(alice.clone(), msg).unwrap();
+
+    let position = engine
+        .position(&router.wrap(), vamm.addr().to_string(), 1)
+        .unwrap();
+    assert_eq!(position.tp_sl_trigger, TpSlTrigger::MarkPrice);
+
+    let msg = engine
+        .update_tp_sl_trigger(vamm.addr().to_string(), 1, TpSlTrigger::IndexPrice)
+        .unwrap();
+    let err = router.execute(bob, msg).unwrap_err();
+    assert_eq!(
+        err.source().unwrap().to_string(),
+        "Generic error: Unauthorized".to_string()
+    );
+
+    let msg = engine
+        .update_tp_sl_trigger(vamm.addr().to_string(), 1, TpSlTrigger::IndexPrice)
+        .unwrap();
+    router.execute(alice, msg).unwrap();
+
+    let position = engine
+        .position(&router.wrap(), vamm.addr().to_string(), 1)
+        .unwrap();
+    assert_eq!(position.tp_sl_trigger, TpSlTrigger::IndexPrice);
+}
+
+#[test]
+fn test_index_price_takeprofit_respects_slippage_limit() {
+    let SimpleScenario {
+        mut router,
+        owner,
+        alice,
+        engine,
+        vamm,
+        pricefeed,
+        ..
+    } = new_simple_scenario();
+
+    let msg = engine
+        .open_position(
+            vamm.addr().to_string(),
+            Side::Buy,
+            to_decimals(6u64),
+            to_decimals(10u64),
+            Some(to_decimals(11u64)),
+            Some(to_decimals(5u64)),
+            to_decimals(0u64),
+            vec![],
+        )
+        .unwrap();
+    router.execute(alice.clone(), msg).unwrap();
+
+    let msg = engine
+        .update_tp_sl_trigger(vamm.addr().to_string(), 1, TpSlTrigger::IndexPrice)
+        .unwrap();
+    router.execute(alice.clone(), msg).unwrap();
+
+    // the index is above the take profit while the vamm closes at 10.599999999
+    let msg = pricefeed
+        .append_price("ETH".to_string(), to_decimals(12u64), 1_000_000_000u64)
+        .unwrap();
+    router.execute(owner.clone(), msg).unwrap();
+
+    // the vamm fill of 59.999999994 is more than 10% below the 67.924528296 at the index
+    let msg = vamm
+        .set_price_diff_limit_ratio(Uint128::from(100_000_000u128))
+        .unwrap();
+    router.execute(owner.clone(), msg).unwrap();
+
+    let tp_sl_status = engine
+        .get_tp_sl_status(&router.wrap(), vamm.addr().to_string(), Side::Buy, true, 10)
+        .unwrap();
+    assert!(!tp_sl_status.is_tpsl);
+
+    let msg = engine
+        .trigger_tp_sl(vamm.addr().to_string(), 1, true)
+        .unwrap();
+    router.execute(alice.clone(), msg).unwrap_err();
+
+    let msg = engine
+        .trigger_multiple_tp_sl(vamm.addr().to_string(), Side::Buy, true, 10)
+        .unwrap();
+    router.execute(alice.clone(), msg).unwrap();
+    engine
+        .position(&router.wrap(), vamm.addr().to_string(), 1)
+        .unwrap();
+
+    // within 15% the take profit closes the position
+    let msg = vamm
+        .set_price_diff_limit_ratio(Uint128::from(150_000_000u128))
+        .unwrap();
+    router.execute(owner, msg).unwrap();
+
+    let tp_sl_status = engine
+        .get_tp_sl_status(&router.wrap(), vamm.addr().to_string(), Side::Buy, true, 10)
+        .unwrap();
+    assert!(tp_sl_status.is_tpsl);
+
+    let msg = engine
+        .trigger_tp_sl(vamm.addr().to_string(), 1, true)
+        .unwrap();
+    router.execute(alice, msg).unwrap();
+
+    let err = engine
+        .position(&router.wrap(), vamm.addr().to_string(), 1)
+        .unwrap_err();
+    assert_eq!(
+        StdError::GenericErr {
+            msg: "Querier contract error: margined_perp::margined_engine::Position not found"
+                .to_string()
+        },
+        err
+    );
+}
+
+#[test]
+fn test_trailing_stop_ratchets_and_triggers() {
+    let SimpleScenario {
+        mut router,
+        owner,
+        alice,
+        bob,
+        engine,
+        vamm,
+        pricefeed,
+        ..
+    } = new_simple_scenario();
+
+    let msg = engine
+        .open_position(
+            vamm.addr().to_string(),
+            Side::Buy,
+            to_decimals(6u64),
+            to_decimals(10u64),
+            None,
+            None,
+            to_decimals(0u64),
+            vec![],
+        )
+        .unwrap();
+    router.execute(alice.clone(), msg).unwrap();
+
+    let msg = engine
+        .update_tp_sl_trigger(vamm.addr().to_string(), 1, TpSlTrigger::IndexPrice)
+        .unwrap();
+    router.execute(alice.clone(), msg).unwrap();
+
+    let msg = pricefeed
+        .append_price("ETH".to_string(), to_decimals(12u64), 1_000_000_000u64)
+        .unwrap();
+    router.execute(owner.clone(), msg).unwrap();
+
+    let msg = engine
+        .update_trailing_stop(
+            vamm.addr().to_string(),
+            1,
+            Some(TrailDistance::Absolute(to_decimals(1u64))),
+        )
+        .unwrap();
+    router.execute(alice.clone(), msg).unwrap();
+
+    let position = engine
+        .position(&router.wrap(), vamm.addr().to_string(), 1)
+        .unwrap();
+    assert_eq!(position.stop_loss, Some(to_decimals(11u64)));
+
+    // the stop follows the index up
+    let msg = pricefeed
+        .append_price("ETH".to_string(), to_decimals(14u64), 1_000_000_001u64)
+        .unwrap();
+    router.execute(owner.clone(), msg).unwrap();
+
+    let msg = engine
+        .ratchet_trailing_stop(vamm.addr().to_string(), 1)
+        .unwrap();
+    router.execute(bob.clone(), msg).unwrap();
+
+    let position = engine
+        .position(&router.wrap(), vamm.addr().to_string(), 1)
+        .unwrap();
+    assert_eq!(position.stop_loss, Some(to_decimals(13u64)));
+    assert_eq!(
+        position.trailing_stop.unwrap().water_mark,
+        to_decimals(14u64)
+    );
+
+    // but not down
+    let msg = pricefeed
+        .append_price(
+            "ETH".to_string(),
+            Uint128::from(12_500_000_000u128),
+            1_000_000_002u64,
+        )
+        .unwrap();
+    router.execute(owner.clone(), msg).unwrap();
+
+    let msg = engine
+        .ratchet_trailing_stop(vamm.addr().to_string(), 1)
+        .unwrap();
+    router.execute(bob.clone(), msg).unwrap();
+
+    let position = engine
+        .position(&router.wrap(), vamm.addr().to_string(), 1)
+        .unwrap();
+    assert_eq!(position.stop_loss, Some(to_decimals(13u64)));
+
+    let tp_sl_status = engine
+        .get_tp_sl_status(
+            &router.wrap(),
+            vamm.addr().to_string(),
+            Side::Buy,
+            false,
+            10,
+        )
+        .unwrap();
+    assert!(tp_sl_status.is_tpsl);
+
+    let msg = engine
+        .trigger_tp_sl(vamm.addr().to_string(), 1, false)
+        .unwrap();
+    let ret = router.execute(bob, msg).unwrap();
+    assert_eq!(ret.events[1].attributes[1].value, "trigger_stop_loss");
+
+    let err = engine
+        .position(&router.wrap(), vamm.addr().to_string(), 1)
+        .unwrap_err();
+    assert_eq!(
+        StdError::GenericErr {
+            msg: "Querier contract error: margined_perp::margined_engine::Position not found"
+                .to_string()
+        },
+        err
+    );
+}
+
+#[test]
+fn test_trailing_stop_keeps_tighter_stop_loss() {
+    let SimpleScenario {
+        mut router,
+        owner,
+        alice,
+        engine,
+        vamm,
+        pricefeed,
+        ..
+    } = new_simple_scenario();
+
+    let msg = engine
+        .open_position(
+            vamm.addr().to_string(),
+            Side::Buy,
+            to_decimals(6u64),
+            to_decimals(10u64),
+            None,
+            None,
+            to_decimals(0u64),
+            vec![],
+        )
+        .unwrap();
+    router.execute(alice.clone(), msg).unwrap();
+
+    let msg = engine
+        .update_tp_sl_trigger(vamm.addr().to_string(), 1, TpSlTrigger::IndexPrice)
+        .unwrap();
+    router.execute(alice.clone(), msg).unwrap();
+
+    let msg = pricefeed
+        .append_price("ETH".to_string(), to_decimals(12u64), 1_000_000_000u64)
+        .unwrap();
+    router.execute(owner, msg).unwrap();
+
+    let msg = engine
+        .update_trailing_stop(
+            vamm.addr().to_string(),
+            1,
+            Some(TrailDistance::Absolute(to_decimals(1u64))),
+        )
+        .unwrap();
+    router.execute(alice.clone(), msg).unwrap();
+
+    // a wider trail does not loosen the stop loss
+    let msg = engine
+        .update_trailing_stop(
+            vamm.addr().to_string(),
+            1,
+            Some(TrailDistance::Absolute(to_decimals(3u64))),
+        )
+        .unwrap();
+    router.execute(alice, msg).unwrap();
+
+    let position = engine
+        .position(&router.wrap(), vamm.addr().to_string(), 1)
+        .unwrap();
+    assert_eq!(position.stop_loss, Some(to_decimals(11u64)));
+    assert_eq!(
+        position.trailing_stop.unwrap().distance,
+        TrailDistance::Absolute(to_decimals(3u64))
+    );
+}
+
+#[test]
+fn test_take_profit_ladder() {
+    let SimpleScenario {
+        mut router,
+        owner,
+        alice,
+        bob,
+        engine,
+        vamm,
+        pricefeed,
+        ..
+    } = new_simple_scenario();
+
+    let msg = engine
+        .open_position(
+            vamm.addr().to_string(),
+            Side::Buy,
+            to_decimals(6u64),
+            to_decimals(10u64),
+            None,
+            None,
+            to_decimals(0u64),
+            vec![],
+        )
+        .unwrap();
+    router.execute(alice.clone(), msg).unwrap();
+
+    let msg = engine
+        .update_tp_sl_trigger(vamm.addr().to_string(), 1, TpSlTrigger::IndexPrice)
+        .unwrap();
+    router.execute(alice.clone(), msg).unwrap();
+
+    // the fractions have to cover the whole position
+    let msg = engine
+        .update_take_profit_levels(
+            vamm.addr().to_string(),
+            1,
+            vec![
+                TakeProfitLevel {
+                    price: to_decimals(11u64),
+                    fraction: Uint128::from(300_000_000u128),
+                },
+                TakeProfitLevel {
+                    price: to_decimals(12u64),
+                    fraction: Uint128::from(300_000_000u128),
+                },
+            ],
+        )
+        .unwrap();
+    let err = router.execute(alice.clone(), msg).unwrap_err();
+    assert_eq!(
+        err.source().unwrap().to_string(),
+        "Generic error: Take profit fractions must add up to the whole position".to_string()
+    );
+
+    let msg = engine
+        .update_take_profit_levels(
+            vamm.addr().to_string(),
+            1,
+            vec![
+                TakeProfitLevel {
+                    price: to_decimals(13u64),
+                    fraction: Uint128::from(400_000_000u128),
+                },
+                TakeProfitLevel {
+                    price: to_decimals(11u64),
+                    fraction: Uint128::from(300_000_000u128),
+                },
+                TakeProfitLevel {
+                    price: to_decimals(12u64),
+                    fraction: Uint128::from(300_000_000u128),
+                },
+            ],
+        )
+        .unwrap();
+    router.execute(alice.clone(), msg).unwrap();
+
+    // the first level closes 30% of the position
+    let msg = pricefeed
+        .append_price(
+            "ETH".to_string(),
+            Uint128::from(11_500_000_000u128),
+            1_000_000_000u64,
+        )
+        .unwrap();
+    router.execute(owner.clone(), msg).unwrap();
+
+    let msg = engine
+        .trigger_take_profit_level(vamm.addr().to_string(), 1)
+        .unwrap();
+    router.execute(bob.clone(), msg).unwrap();
+
+    let position = engine
+        .position(&router.wrap(), vamm.addr().to_string(), 1)
+        .unwrap();
+    assert_eq!(position.size.value, Uint128::from(3_962_264_151u128));
+
+    let levels = engine
+        .get_take_profit_levels(&router.wrap(), vamm.addr().to_string(), 1)
+        .unwrap();
+    assert_eq!(
+        levels,
+        vec![
+            TakeProfitLevel {
+                price: to_decimals(12u64),
+                fraction: Uint128::from(300_000_000u128),
+            },
+            TakeProfitLevel {
+                price: to_decimals(13u64),
+                fraction: Uint128::from(400_000_000u128),
+            },
+        ]
+    );
+
+    // the second level is not reached yet
+    let msg = engine
+        .trigger_take_profit_level(vamm.addr().to_string(), 1)
+        .unwrap();
+    let err = router.execute(bob.clone(), msg).unwrap_err();
+    assert_eq!(
+        err.source().unwrap().to_string(),
+        "Generic error: Take profit level is not reached".to_string()
+    );
+
+    let msg = pricefeed
+        .append_price(
+            "ETH".to_string(),
+            Uint128::from(12_500_000_000u128),
+            1_000_000_001u64,
+        )
+        .unwrap();
+    router.execute(owner.clone(), msg).unwrap();
+
+    let msg = engine
+        .trigger_take_profit_level(vamm.addr().to_string(), 1)
+        .unwrap();
+    router.execute(bob.clone(), msg).unwrap();
+
+    let position = engine
+        .position(&router.wrap(), vamm.addr().to_string(), 1)
+        .unwrap();
+    assert_eq!(position.size.value, Uint128::from(2_264_150_944u128));
+    assert_eq!(position.take_profit_levels.len(), 1);
+
+    // the last level closes the rest
+    let msg = pricefeed
+        .append_price(
+            "ETH".to_string(),
+            Uint128::from(13_500_000_000u128),
+            1_000_000_002u64,
+        )
+        .unwrap();
+    router.execute(owner, msg).unwrap();
+
+    let msg = engine
+        .trigger_take_profit_level(vamm.addr().to_string(), 1)
+        .unwrap();
+    router.execute(bob, msg).unwrap();
+
+    let err = engine
+        .position(&router.wrap(), vamm.addr().to_string(), 1)
+        .unwrap_err();
+    assert_eq!(
+        StdError::GenericErr {
+            msg: "Querier contract error: margined_perp::margined_engine::Position not found"
+                .to_string()
+        },
+        err
+    );
+}
+
+#[test]
+fn test_take_profit_ladder_triggers_in_batch_and_pays_keeper() {
+    let SimpleScenario {
+        mut router,
+        owner,
+        alice,
+        bob,
+        usdc,
+        engine,
+        vamm,
+        pricefeed,
+        ..
+    } = new_simple_scenario();
+
+    let msg = engine
+        .update_keeper_reward(KeeperReward {
+            flat: to_decimals(1u64),
+            notional_ratio: Uint128::zero(),
+            max_per_call: Uint128::zero(),
+        })
+        .unwrap();
+    router.execute(owner.clone(), msg).unwrap();
+
+    let msg = engine
+        .open_position(
+            vamm.addr().to_string(),
+            Side::Buy,
+            to_decimals(6u64),
+            to_decimals(10u64),
+            None,
+            None,
+            to_decimals(0u64),
+            vec![],
+        )
+        .unwrap();
+    router.execute(alice.clone(), msg).unwrap();
+
+    let msg = engine
+        .update_tp_sl_trigger(vamm.addr().to_string(), 1, TpSlTrigger::IndexPrice)
+        .unwrap();
+    router.execute(alice.clone(), msg).unwrap();
+
+    let msg = engine
+        .update_take_profit_levels(
+            vamm.addr().to_string(),
+            1,
+            vec![
+                TakeProfitLevel {
+                    price: to_decimals(11u64),
+                    fraction: Uint128::from(500_000_000u128),
+                },
+                TakeProfitLevel {
+                    price: to_decimals(13u64),
+                    fraction: Uint128::from(500_000_000u128),
+                },
+            ],
+        )
+        .unwrap();
+    router.execute(alice, msg).unwrap();
+
+    let msg = pricefeed
+        .append_price(
+            "ETH".to_string(),
+            Uint128::from(11_500_000_000u128),
+            1_000_000_000u64,
+        )
+        .unwrap();
+    router.execute(owner, msg).unwrap();
+
+    let bob_balance_before = usdc.balance(&router.wrap(), bob.clone()).unwrap();
+
+    // the batch trigger closes the share of the first level only
+    let msg = engine
+        .trigger_multiple_tp_sl(vamm.addr().to_string(), Side::Buy, true, 10)
+        .unwrap();
+    router.execute(bob.clone(), msg).unwrap();
+
+    let position = engine
+        .position(&router.wrap(), vamm.addr().to_string(), 1)
+        .unwrap();
+    assert_eq!(position.size.value, Uint128::from(2_830_188_679u128));
+    assert_eq!(
+        position.take_profit_levels,
+        vec![TakeProfitLevel {
+            price: to_decimals(13u64),
+            fraction: Uint128::from(500_000_000u128),
+        }]
+    );
+
+    // the keeper is paid out of the margin of the position
+    let bob_balance = usdc.balance(&router.wrap(), bob).unwrap();
+    assert_eq!(bob_balance, bob_balance_before + to_decimals(1u64));
+}
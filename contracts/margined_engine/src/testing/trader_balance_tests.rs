@@ -0,0 +1,187 @@
+use cosmwasm_std::{StdError, Uint128};
+use margined_perp::margined_engine::Side;
+use margined_utils::{
+    cw_multi_test::Executor,
+    testing::{to_decimals, SimpleScenario},
+};
+
+use crate::testing::new_simple_scenario;
+
+#[test]
+fn test_open_position_from_balance() {
+    let SimpleScenario {
+        mut router,
+        alice,
+        usdc,
+        engine,
+        vamm,
+        ..
+    } = new_simple_scenario();
+
+    let msg = engine.deposit(to_decimals(100u64), vec![]).unwrap();
+    router.execute(alice.clone(), msg).unwrap();
+
+    let msg = engine
+        .open_position_from_balance(
+            vamm.addr().to_string(),
+            Side::Buy,
+            to_decimals(101u64),
+            to_decimals(1u64),
+            None,
+            None,
+            to_decimals(0u64),
+        )
+        .unwrap();
+    let err = router.execute(alice.clone(), msg).unwrap_err();
+    assert_eq!(
+        StdError::generic_err("Insufficient trader balance"),
+        err.downcast().unwrap()
+    );
+
+    let msg = engine
+        .open_position_from_balance(
+            vamm.addr().to_string(),
+            Side::Buy,
+            to_decimals(6u64),
+            to_decimals(10u64),
+            None,
+            None,
+            to_decimals(0u64),
+        )
+        .unwrap();
+    router.execute(alice.clone(), msg).unwrap();
+
+    let position = engine
+        .position(&router.wrap(), vamm.addr().to_string(), 1)
+        .unwrap();
+    assert_eq!(position.trader, alice);
+    assert_eq!(position.margin, to_decimals(6u64));
+    assert_eq!(position.notional, to_decimals(60u64));
+
+    // the margin is taken from the balance without a transfer
+    let balance = engine
+        .get_trader_balance(&router.wrap(), alice.to_string())
+        .unwrap();
+    assert_eq!(balance.balance, to_decimals(94u64));
+    assert!(!balance.credit_proceeds);
+    let alice_balance = usdc.balance(&router.wrap(), alice.clone()).unwrap();
+    assert_eq!(alice_balance, Uint128::from(4_900_000_000_000u128));
+    let engine_balance = usdc.balance(&router.wrap(), engine.addr()).unwrap();
+    assert_eq!(engine_balance, to_decimals(100u64));
+}
+
+#[test]
+fn test_close_proceeds_are_credited_to_balance() {
+    let SimpleScenario {
+        mut router,
+        alice,
+        usdc,
+        engine,
+        vamm,
+        ..
+    } = new_simple_scenario();
+
+    let msg = engine.update_credit_proceeds(true).unwrap();
+    router.execute(alice.clone(), msg).unwrap();
+
+    let msg = engine
+        .open_position(
+            vamm.addr().to_string(),
+            Side::Buy,
+            to_decimals(6u64),
+            to_decimals(10u64),
+            None,
+            None,
+            to_decimals(0u64),
+            vec![],
+        )
+        .unwrap();
+    router.execute(alice.clone(), msg).unwrap();
+
+    let msg = engine
+        .close_position(vamm.addr().to_string(), 1, to_decimals(0u64))
+        .unwrap();
+    router.execute(alice.clone(), msg).unwrap();
+
+    // the margin less the rounding of the vamm stays in the engine
+    let balance = engine
+        .get_trader_balance(&router.wrap(), alice.to_string())
+        .unwrap();
+    assert_eq!(balance.balance, Uint128::from(5_999_999_994u128));
+    assert!(balance.credit_proceeds);
+    let alice_balance = usdc.balance(&router.wrap(), alice.clone()).unwrap();
+    assert_eq!(alice_balance, Uint128::from(4_994_000_000_000u128));
+
+    let msg = engine.withdraw(Uint128::from(5_999_999_994u128)).unwrap();
+    router.execute(alice.clone(), msg).unwrap();
+
+    let alice_balance = usdc.balance(&router.wrap(), alice).unwrap();
+    assert_eq!(alice_balance, Uint128::from(4_999_999_999_994u128));
+}
+
+#[test]
+fn test_trader_balances_do_not_cover_payouts() {
+    let SimpleScenario {
+        mut router,
+        alice,
+        bob,
+        carol,
+        usdc,
+        engine,
+        vamm,
+        insurance_fund,
+        ..
+    } = new_simple_scenario();
+
+    let msg = engine.deposit(to_decimals(100u64), vec![]).unwrap();
+    router.execute(alice.clone(), msg).unwrap();
+
+    let state = engine.state(&router.wrap()).unwrap();
+    assert_eq!(state.total_liabilities, to_decimals(100u64));
+
+    let msg = engine
+        .open_position(
+            vamm.addr().to_string(),
+            Side::Buy,
+            to_decimals(6u64),
+            to_decimals(10u64),
+            None,
+            None,
+            to_decimals(0u64),
+            vec![],
+        )
+        .unwrap();
+    router.execute(bob.clone(), msg).unwrap();
+
+    let msg = engine
+        .open_position(
+            vamm.addr().to_string(),
+            Side::Buy,
+            to_decimals(6u64),
+            to_decimals(10u64),
+            None,
+            None,
+            to_decimals(0u64),
+            vec![],
+        )
+        .unwrap();
+    router.execute(carol.clone(), msg).unwrap();
+
+    let insurance_balance = usdc.balance(&router.wrap(), insurance_fund.addr()).unwrap();
+
+    let msg = engine
+        .close_position(vamm.addr().to_string(), 1, to_decimals(0u64))
+        .unwrap();
+    router.execute(bob.clone(), msg).unwrap();
+
+    // the profit of bob is paid by the insurance fund, the balance of alice stays in the engine
+    let engine_balance = usdc.balance(&router.wrap(), engine.addr()).unwrap();
+    assert_eq!(engine_balance, to_decimals(100u64));
+    assert!(usdc.balance(&router.wrap(), insurance_fund.addr()).unwrap() < insurance_balance);
+
+    let msg = engine.withdraw(to_decimals(100u64)).unwrap();
+    router.execute(alice.clone(), msg).unwrap();
+
+    let state = engine.state(&router.wrap()).unwrap();
+    assert_eq!(state.total_liabilities, Uint128::zero());
+}
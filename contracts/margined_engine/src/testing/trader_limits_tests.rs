@@ -0,0 +1,412 @@
+use cosmwasm_std::{StdError, Uint128};
+use margined_perp::margined_engine::{Side, TraderLimits};
+use margined_utils::{
+    cw_multi_test::Executor,
+    testing::{to_decimals, SimpleScenario},
+};
+
+use crate::testing::new_simple_scenario;
+
+#[test]
+fn test_owner_trader_limits() {
+    let SimpleScenario {
+        mut router,
+        owner,
+        alice,
+        bob,
+        engine,
+        vamm,
+        ..
+    } = new_simple_scenario();
+
+    let limits = TraderLimits {
+        max_positions_per_market: Some(1),
+        max_leverage: Some(to_decimals(5u64)),
+        ..Default::default()
+    };
+
+    let msg = engine
+        .update_trader_limits(alice.to_string(), limits.clone())
+        .unwrap();
+    let err = router.execute(bob, msg).unwrap_err();
+    assert_eq!(
+        StdError::generic_err("unauthorized"),
+        err.downcast().unwrap()
+    );
+
+    let msg = engine
+        .update_trader_limits(alice.to_string(), limits.clone())
+        .unwrap();
+    router.execute(owner, msg).unwrap();
+
+    let response = engine
+        .get_trader_limits(&router.wrap(), alice.to_string())
+        .unwrap();
+    assert_eq!(response.limits, limits);
+    assert_eq!(response.self_limits, TraderLimits::default());
+
+    let msg = engine
+        .open_position(
+            vamm.addr().to_string(),
+            Side::Buy,
+            to_decimals(6u64),
+            to_decimals(10u64),
+            None,
+            None,
+            to_decimals(0u64),
+            vec![],
+        )
+        .unwrap();
+    let err = router.execute(alice.clone(), msg).unwrap_err();
+    assert_eq!(
+        StdError::generic_err("Leverage exceeds the trader limit"),
+        err.downcast().unwrap()
+    );
+
+    let msg = engine
+        .open_position(
+            vamm.addr().to_string(),
+            Side::Buy,
+            to_decimals(6u64),
+            to_decimals(5u64),
+            None,
+            None,
+            to_decimals(0u64),
+            vec![],
+        )
+        .unwrap();
+    router.execute(alice.clone(), msg).unwrap();
+
+    let msg = engine
+        .open_position(
+            vamm.addr().to_string(),
+            Side::Buy,
+            to_decimals(6u64),
+            to_decimals(5u64),
+            None,
+            None,
+            to_decimals(0u64),
+            vec![],
+        )
+        .unwrap();
+    let err = router.execute(alice, msg).unwrap_err();
+    assert_eq!(
+        StdError::generic_err("Open positions exceed the trader limit"),
+        err.downcast().unwrap()
+    );
+}
+
+#[test]
+fn test_self_max_total_notional() {
+    let SimpleScenario {
+        mut router,
+        alice,
+        engine,
+        vamm,
+        ..
+    } = new_simple_scenario();
+
+    let msg = engine
+        .update_self_limits(TraderLimits {
+            max_total_notional: Some(to_decimals(100u64)),
+            ..Default::default()
+        })
+        .unwrap();
+    router.execute(alice.clone(), msg).unwrap();
+
+    let msg = engine
+        .open_position(
+            vamm.addr().to_string(),
+            Side::Buy,
+            to_decimals(6u64),
+            to_decimals(10u64),
+            None,
+            None,
+            to_decimals(0u64),
+            vec![],
+        )
+        .unwrap();
+    router.execute(alice.clone(), msg).unwrap();
+
+    // 60 open and 50 more is over 100
+    let msg = engine
+        .open_position(
+            vamm.addr().to_string(),
+            Side::Sell,
+            to_decimals(5u64),
+            to_decimals(10u64),
+            None,
+            None,
+            to_decimals(0u64),
+            vec![],
+        )
+        .unwrap();
+    let err = router.execute(alice, msg).unwrap_err();
+    assert_eq!(
+        StdError::generic_err("Total notional exceeds the trader limit"),
+        err.downcast().unwrap()
+    );
+}
+
+#[test]
+fn test_self_daily_loss_limit() {
+    let SimpleScenario {
+        mut router,
+        alice,
+        bob,
+        engine,
+        vamm,
+        ..
+    } = new_simple_scenario();
+
+    let msg = engine
+        .update_self_limits(TraderLimits {
+            daily_loss_limit: Some(to_decimals(1u64)),
+            ..Default::default()
+        })
+        .unwrap();
+    router.execute(alice.clone(), msg).unwrap();
+
+    let msg = engine
+        .open_position(
+            vamm.addr().to_string(),
+            Side::Buy,
+            to_decimals(6u64),
+            to_decimals(10u64),
+            None,
+            None,
+            to_decimals(0u64),
+            vec![],
+        )
+        .unwrap();
+    router.execute(alice.clone(), msg).unwrap();
+
+    // bob pushes the price down before alice closes at a loss
+    let msg = engine
+        .open_position(
+            vamm.addr().to_string(),
+            Side::Sell,
+            to_decimals(6u64),
+            to_decimals(10u64),
+            None,
+            None,
+            to_decimals(0u64),
+            vec![],
+        )
+        .unwrap();
+    router.execute(bob, msg).unwrap();
+
+    let msg = engine
+        .close_position(vamm.addr().to_string(), 1, to_decimals(0u64))
+        .unwrap();
+    router.execute(alice.clone(), msg).unwrap();
+
+    let response = engine
+        .get_trader_limits(&router.wrap(), alice.to_string())
+        .unwrap();
+    assert!(response.daily_realized_loss > to_decimals(1u64));
+
+    let msg = engine
+        .open_position(
+            vamm.addr().to_string(),
+            Side::Buy,
+            to_decimals(6u64),
+            to_decimals(10u64),
+            None,
+            None,
+            to_decimals(0u64),
+            vec![],
+        )
+        .unwrap();
+    let err = router.execute(alice.clone(), msg).unwrap_err();
+    assert_eq!(
+        StdError::generic_err("Daily loss limit is reached"),
+        err.downcast().unwrap()
+    );
+
+    // the limit resets the next day
+    router.update_block(|block| {
+        block.time = block.time.plus_seconds(86_400);
+        block.height += 1;
+    });
+
+    let response = engine
+        .get_trader_limits(&router.wrap(), alice.to_string())
+        .unwrap();
+    assert!(response.daily_realized_loss.is_zero());
+
+    let msg = engine
+        .open_position(
+            vamm.addr().to_string(),
+            Side::Buy,
+            to_decimals(6u64),
+            to_decimals(10u64),
+            None,
+            None,
+            to_decimals(0u64),
+            vec![],
+        )
+        .unwrap();
+    router.execute(alice, msg).unwrap();
+}
+
+#[test]
+fn test_self_limits_are_loosened_from_the_next_day() {
+    let SimpleScenario {
+        mut router,
+        alice,
+        engine,
+        vamm,
+        ..
+    } = new_simple_scenario();
+
+    let limits = TraderLimits {
+        max_leverage: Some(to_decimals(5u64)),
+        ..Default::default()
+    };
+    let msg = engine.update_self_limits(limits.clone()).unwrap();
+    router.execute(alice.clone(), msg).unwrap();
+
+    // lifting the limit within the day leaves it in place until the next day
+    let msg = engine.update_self_limits(TraderLimits::default()).unwrap();
+    router.execute(alice.clone(), msg).unwrap();
+
+    let response = engine
+        .get_trader_limits(&router.wrap(), alice.to_string())
+        .unwrap();
+    assert_eq!(response.self_limits, limits);
+    assert_eq!(response.pending_self_limits, Some(TraderLimits::default()));
+
+    let msg = engine
+        .open_position(
+            vamm.addr().to_string(),
+            Side::Buy,
+            to_decimals(6u64),
+            to_decimals(10u64),
+            None,
+            None,
+            to_decimals(0u64),
+            vec![],
+        )
+        .unwrap();
+    let err = router.execute(alice.clone(), msg).unwrap_err();
+    assert_eq!(
+        StdError::generic_err("Leverage exceeds the trader limit"),
+        err.downcast().unwrap()
+    );
+
+    router.update_block(|block| {
+        block.time = block.time.plus_seconds(86_400);
+        block.height += 1;
+    });
+
+    let response = engine
+        .get_trader_limits(&router.wrap(), alice.to_string())
+        .unwrap();
+    assert_eq!(response.self_limits, TraderLimits::default());
+    assert_eq!(response.pending_self_limits, None);
+
+    let msg = engine
+        .open_position(
+            vamm.addr().to_string(),
+            Side::Buy,
+            to_decimals(6u64),
+            to_decimals(10u64),
+            None,
+            None,
+            to_decimals(0u64),
+            vec![],
+        )
+        .unwrap();
+    router.execute(alice.clone(), msg).unwrap();
+
+    // tightening applies at once
+    let msg = engine.update_self_limits(limits.clone()).unwrap();
+    router.execute(alice.clone(), msg).unwrap();
+
+    let response = engine
+        .get_trader_limits(&router.wrap(), alice.to_string())
+        .unwrap();
+    assert_eq!(response.self_limits, limits);
+    assert_eq!(response.pending_self_limits, None);
+}
+
+#[test]
+fn test_liquidation_loss_counts_to_daily_loss() {
+    let SimpleScenario {
+        mut router,
+        alice,
+        bob,
+        carol,
+        owner,
+        engine,
+        vamm,
+        pricefeed,
+        ..
+    } = new_simple_scenario();
+
+    let price = Uint128::from(10_000_000_000u128);
+    let timestamp = router.block_info().time.seconds();
+    let msg = pricefeed
+        .append_price("ETH".to_string(), price, timestamp)
+        .unwrap();
+    router.execute(owner.clone(), msg).unwrap();
+
+    router.update_block(|block| {
+        block.time = block.time.plus_seconds(900);
+        block.height += 1;
+    });
+
+    let msg = engine
+        .set_margin_ratios(Uint128::from(100_000_000u128))
+        .unwrap();
+    router.execute(owner.clone(), msg).unwrap();
+
+    let msg = engine
+        .set_partial_liquidation_ratio(Uint128::from(250_000_000u128))
+        .unwrap();
+    router.execute(owner.clone(), msg).unwrap();
+
+    let msg = engine
+        .open_position(
+            vamm.addr().to_string(),
+            Side::Buy,
+            to_decimals(25u64),
+            to_decimals(10u64),
+            None,
+            None,
+            to_decimals(0u64),
+            vec![],
+        )
+        .unwrap();
+    router.execute(alice.clone(), msg).unwrap();
+
+    router.update_block(|block| {
+        block.time = block.time.plus_seconds(15);
+        block.height += 1;
+    });
+
+    let msg = engine
+        .open_position(
+            vamm.addr().to_string(),
+            Side::Sell,
+            Uint128::from(45_180_722_890u128),
+            to_decimals(1u64),
+            None,
+            None,
+            to_decimals(0u64),
+            vec![],
+        )
+        .unwrap();
+    router.execute(bob, msg).unwrap();
+
+    let msg = engine
+        .liquidate(vamm.addr().to_string(), 1, to_decimals(0u64))
+        .unwrap();
+    router.execute(carol, msg).unwrap();
+
+    let response = engine
+        .get_trader_limits(&router.wrap(), alice.to_string())
+        .unwrap();
+    assert!(!response.daily_realized_loss.is_zero());
+}
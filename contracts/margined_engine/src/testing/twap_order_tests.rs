@@ -0,0 +1,235 @@
+use cosmwasm_std::{StdError, Uint128};
+use margined_common::integer::Integer;
+use margined_perp::margined_engine::Side;
+use margined_utils::{
+    cw_multi_test::Executor,
+    testing::{to_decimals, SimpleScenario},
+};
+
+use crate::testing::new_simple_scenario;
+
+#[test]
+fn test_twap_order_fills_slices_into_one_position() {
+    let SimpleScenario {
+        mut router,
+        alice,
+        carol,
+        usdc,
+        engine,
+        vamm,
+        ..
+    } = new_simple_scenario();
+
+    let msg = engine
+        .place_twap_order(
+            vamm.addr().to_string(),
+            Side::Buy,
+            to_decimals(18u64),
+            to_decimals(10u64),
+            3,
+            60,
+            Uint128::zero(),
+            vec![],
+        )
+        .unwrap();
+    router.execute(alice.clone(), msg).unwrap();
+
+    let alice_balance = usdc.balance(&router.wrap(), alice.clone()).unwrap();
+    assert_eq!(alice_balance, Uint128::from(4_982_000_000_000u128));
+
+    let msg = engine.execute_twap_order(1).unwrap();
+    router.execute(carol.clone(), msg).unwrap();
+
+    let msg = engine.execute_twap_order(1).unwrap();
+    let err = router.execute(carol.clone(), msg).unwrap_err();
+    assert_eq!(
+        StdError::generic_err("TWAP slice interval has not passed"),
+        err.downcast().unwrap()
+    );
+
+    for _ in 0..2 {
+        router.update_block(|block| {
+            block.time = block.time.plus_seconds(60);
+            block.height += 1;
+        });
+
+        let msg = engine.execute_twap_order(1).unwrap();
+        router.execute(carol.clone(), msg).unwrap();
+    }
+
+    // the slices of 60 notional are added to the first position
+    let position = engine
+        .position(&router.wrap(), vamm.addr().to_string(), 1)
+        .unwrap();
+    assert_eq!(position.trader, alice);
+    assert_eq!(position.size, Integer::new_positive(15_254_237_286u128));
+    assert_eq!(position.margin, to_decimals(18u64));
+    assert_eq!(position.notional, to_decimals(180u64));
+
+    // no other position is opened and the filled order is removed
+    assert!(engine
+        .position(&router.wrap(), vamm.addr().to_string(), 2)
+        .is_err());
+    assert!(engine.get_twap_order(&router.wrap(), 1).is_err());
+}
+
+#[test]
+fn test_twap_order_price_limit_and_cancel() {
+    let SimpleScenario {
+        mut router,
+        alice,
+        bob,
+        carol,
+        usdc,
+        engine,
+        vamm,
+        ..
+    } = new_simple_scenario();
+
+    let msg = engine
+        .place_twap_order(
+            vamm.addr().to_string(),
+            Side::Buy,
+            to_decimals(18u64),
+            to_decimals(10u64),
+            3,
+            60,
+            to_decimals(11u64),
+            vec![],
+        )
+        .unwrap();
+    router.execute(alice.clone(), msg).unwrap();
+
+    // the first slice fills at 10.6
+    let msg = engine.execute_twap_order(1).unwrap();
+    router.execute(carol.clone(), msg).unwrap();
+
+    router.update_block(|block| {
+        block.time = block.time.plus_seconds(60);
+        block.height += 1;
+    });
+
+    // the second slice would fill at 11.87
+    let msg = engine.execute_twap_order(1).unwrap();
+    let err = router.execute(carol, msg).unwrap_err();
+    assert_eq!(
+        StdError::generic_err("Less than minimum base asset amount limit"),
+        err.downcast().unwrap()
+    );
+
+    let order = engine.get_twap_order(&router.wrap(), 1).unwrap();
+    assert_eq!(order.filled_slices, 1);
+    assert_eq!(order.position_id, Some(1));
+
+    let msg = engine.cancel_twap_order(1).unwrap();
+    let err = router.execute(bob, msg).unwrap_err();
+    assert_eq!(
+        StdError::generic_err("Unauthorized"),
+        err.downcast().unwrap()
+    );
+
+    // the margin of the two unfilled slices is refunded
+    let msg = engine.cancel_twap_order(1).unwrap();
+    router.execute(alice.clone(), msg).unwrap();
+
+    let alice_balance = usdc.balance(&router.wrap(), alice.clone()).unwrap();
+    assert_eq!(alice_balance, Uint128::from(4_994_000_000_000u128));
+    let engine_balance = usdc.balance(&router.wrap(), engine.addr()).unwrap();
+    assert_eq!(engine_balance, to_decimals(6u64));
+
+    let orders = engine
+        .get_twap_orders(&router.wrap(), alice.to_string(), None, None)
+        .unwrap();
+    assert!(orders.is_empty());
+}
+
+#[test]
+fn test_twap_order_opens_new_position_after_backstop_takeover() {
+    let SimpleScenario {
+        mut router,
+        owner,
+        alice,
+        bob,
+        carol,
+        david,
+        engine,
+        vamm,
+        ..
+    } = new_simple_scenario();
+
+    let msg = engine
+        .place_twap_order(
+            vamm.addr().to_string(),
+            Side::Buy,
+            to_decimals(60u64),
+            to_decimals(10u64),
+            3,
+            60,
+            Uint128::zero(),
+            vec![],
+        )
+        .unwrap();
+    router.execute(alice.clone(), msg).unwrap();
+
+    let msg = engine.execute_twap_order(1).unwrap();
+    router.execute(carol.clone(), msg).unwrap();
+
+    // bob shorts the price down and david takes over the position of the first slice
+    let msg = engine
+        .open_position(
+            vamm.addr().to_string(),
+            Side::Sell,
+            to_decimals(6u64),
+            to_decimals(5u64),
+            None,
+            None,
+            to_decimals(0u64),
+            vec![],
+        )
+        .unwrap();
+    router.execute(bob, msg).unwrap();
+
+    router.update_block(|block| {
+        block.time = block.time.plus_seconds(60);
+        block.height += 1;
+    });
+
+    let msg = engine
+        .set_margin_ratios(Uint128::from(100_000_000u128))
+        .unwrap();
+    router.execute(owner.clone(), msg).unwrap();
+    let msg = engine.add_backstop_liquidator(david.to_string()).unwrap();
+    router.execute(owner, msg).unwrap();
+
+    let msg = engine
+        .backstop_liquidate(vamm.addr().to_string(), 1, to_decimals(20u64), vec![])
+        .unwrap();
+    router.execute(david.clone(), msg).unwrap();
+
+    router.update_block(|block| {
+        block.time = block.time.plus_seconds(1);
+        block.height += 1;
+    });
+
+    // the next slice opens a new position of alice instead of increasing the one of david
+    let msg = engine.execute_twap_order(1).unwrap();
+    router.execute(carol, msg).unwrap();
+
+    let position = engine
+        .position(&router.wrap(), vamm.addr().to_string(), 1)
+        .unwrap();
+    assert_eq!(position.trader, david);
+    assert_eq!(position.size, Integer::new_positive(16_666_666_666u128));
+
+    let position = engine
+        .position(&router.wrap(), vamm.addr().to_string(), 2)
+        .unwrap();
+    assert_eq!(position.trader, alice);
+    assert_eq!(position.size, Integer::new_positive(12_477_384_740u128));
+    assert_eq!(position.margin, to_decimals(20u64));
+    assert_eq!(position.notional, to_decimals(200u64));
+
+    let order = engine.get_twap_order(&router.wrap(), 1).unwrap();
+    assert_eq!(order.filled_slices, 2);
+    assert_eq!(order.position_id, Some(2));
+}
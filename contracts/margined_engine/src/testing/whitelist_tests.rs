@@ -1,5 +1,5 @@
 use cosmwasm_std::{Addr, StdError, Uint128};
-use margined_perp::margined_engine::Side;
+use margined_perp::margined_engine::{Side, WhitelistCapabilities};
 use margined_utils::{
     cw_multi_test::Executor,
     testing::{to_decimals, SimpleScenario},
@@ -546,3 +546,106 @@ fn test_whitelist_wont_stop_trading_if_reduce_pos() {
 
     assert!(res.is_ok())
 }
+
+#[test]
+fn test_whitelist_capabilities() {
+    let SimpleScenario {
+        mut router,
+        alice,
+        bob,
+        owner,
+        engine,
+        vamm,
+        ..
+    } = new_simple_scenario();
+
+    // add alice to whitelist, only bypassing the open interest cap
+    let capabilities = WhitelistCapabilities {
+        bypass_open_interest_cap: true,
+        ..Default::default()
+    };
+    let msg = engine
+        .add_whitelist_with_capabilities(alice.to_string(), capabilities.clone())
+        .unwrap();
+    router.execute(owner.clone(), msg).unwrap();
+
+    let response = engine
+        .get_whitelist_capabilities(&router.wrap(), alice.to_string())
+        .unwrap();
+    assert_eq!(response, capabilities);
+
+    // set base asset holding cap
+    let msg = vamm.set_base_asset_holding_cap(to_decimals(10u64)).unwrap();
+    router.execute(owner.clone(), msg).unwrap();
+
+    // open a short over the cap
+    let msg = engine
+        .open_position(
+            vamm.addr().to_string(),
+            Side::Sell,
+            to_decimals(100u64),
+            to_decimals(1u64),
+            None,
+            None,
+            to_decimals(0u64),
+            vec![],
+        )
+        .unwrap();
+    let err = router.execute(alice.clone(), msg).unwrap_err();
+    assert_eq!(
+        StdError::GenericErr {
+            msg: "base asset holding exceeds cap".to_string(),
+        },
+        err.downcast().unwrap()
+    );
+
+    // grant the holding cap bypass
+    let capabilities = WhitelistCapabilities {
+        bypass_holding_cap: true,
+        ..capabilities
+    };
+    let msg = engine
+        .update_whitelist_capabilities(alice.to_string(), capabilities.clone())
+        .unwrap();
+    router.execute(owner.clone(), msg).unwrap();
+
+    let msg = engine
+        .open_position(
+            vamm.addr().to_string(),
+            Side::Sell,
+            to_decimals(100u64),
+            to_decimals(1u64),
+            None,
+            None,
+            to_decimals(0u64),
+            vec![],
+        )
+        .unwrap();
+    router.execute(alice.clone(), msg).unwrap();
+
+    // bob is not whitelisted
+    let response = engine
+        .get_whitelist_capabilities(&router.wrap(), bob.to_string())
+        .unwrap();
+    assert_eq!(response, WhitelistCapabilities::default());
+
+    let msg = engine
+        .update_whitelist_capabilities(bob.to_string(), capabilities)
+        .unwrap();
+    let err = router.execute(owner.clone(), msg).unwrap_err();
+    assert_eq!(
+        StdError::GenericErr {
+            msg: "address is not whitelisted".to_string(),
+        },
+        err.downcast().unwrap()
+    );
+
+    // removing alice removes the capabilities
+    let msg = engine.remove_whitelist(alice.to_string()).unwrap();
+    router.execute(owner, msg).unwrap();
+
+    let response = engine
+        .get_whitelist_capabilities(&router.wrap(), alice.to_string())
+        .unwrap();
+    assert_eq!(response, WhitelistCapabilities::default());
+}
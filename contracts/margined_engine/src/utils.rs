@@ -1,6 +1,6 @@
 use cosmwasm_std::{
-    Addr, Deps, DepsMut, Env, MessageInfo, Response, StdError, StdResult, SubMsg, SubMsgResponse,
-    Uint128,
+    Addr, Deps, DepsMut, Env, MessageInfo, Response, StdError, StdResult, Storage, SubMsg,
+    SubMsgResponse, Uint128,
 };
 use margined_utils::{
     contracts::helpers::{InsuranceFundController, VammController},
@@ -16,15 +16,21 @@ use margined_common::{
     messages::{read_event, read_response},
 };
 use margined_perp::margined_engine::{
-    PnlCalcOption, Position, PositionUnrealizedPnlResponse, RemainMarginResponse, Side,
+    KeeperReward, MarkPricePolicy, PnlCalcOption, Position, PositionUnrealizedPnlResponse,
+    RemainMarginResponse, Side, TpSlTrigger, TrailDistance, WhitelistCapabilities,
 };
 use margined_perp::margined_vamm::Direction;
 
 use crate::{
-    contract::{PAUSER, WHITELIST},
-    messages::execute_insurance_fund_withdrawal,
+    backfill::require_no_backfill,
+    contract::{BACKSTOP_LIQUIDATORS, PAUSER, WHITELIST},
+    messages::{execute_insurance_fund_withdrawal, query_insurance_fund_balance},
     query::{query_cumulative_premium_fraction, query_margin_ratio},
-    state::{read_config, read_state, read_vamm_map, store_state, State, TmpReserveInfo},
+    state::{
+        read_config, read_state, read_tmp_worst_price, read_vamm_map, read_whitelist_capabilities,
+        remove_tmp_worst_price, remove_whitelist_capabilities, store_state, store_vamm_map,
+        store_whitelist_capabilities, Config, State, TmpReserveInfo,
+    },
 };
 
 pub fn keccak_256(input: &[u8]) -> Vec<u8> {
@@ -58,6 +64,69 @@ pub fn get_asset(info: MessageInfo, eligible_collateral: AssetInfo) -> Asset {
     }
 }
 
+pub struct LiquidationFees {
+    pub liquidator: Uint128,
+    pub insurance_fund: Uint128,
+    pub fee_pool: Uint128,
+}
+
+/// Splits the liquidation penalty between the liquidator, the insurance fund and the
+/// fee pool, the part of the liquidator reward above the cap goes to the insurance fund
+pub fn calc_liquidation_fees(
+    config: &Config,
+    liquidation_penalty: Uint128,
+) -> StdResult<LiquidationFees> {
+    let split = &config.liquidation_fee_split;
+
+    let mut liquidator = liquidation_penalty
+        .checked_mul(split.liquidator)?
+        .checked_div(config.decimals)?;
+    let mut insurance_fund = liquidation_penalty
+        .checked_mul(split.insurance_fund)?
+        .checked_div(config.decimals)?;
+    let fee_pool = liquidation_penalty
+        .checked_mul(split.fee_pool)?
+        .checked_div(config.decimals)?;
+
+    if let Some(max_liquidator_fee) = config.max_liquidator_fee {
+        if liquidator > max_liquidator_fee {
+            insurance_fund =
+                insurance_fund.checked_add(liquidator.checked_sub(max_liquidator_fee)?)?;
+            liquidator = max_liquidator_fee;
+        }
+    }
+
+    Ok(LiquidationFees {
+        liquidator,
+        insurance_fund,
+        fee_pool,
+    })
+}
+
+/// Calculates the reward of a keeper for closing the given notional, capped by what is left
+/// of the reward for the call
+pub fn calc_keeper_reward(
+    keeper_reward: &KeeperReward,
+    decimals: Uint128,
+    notional: Uint128,
+    paid: Uint128,
+) -> StdResult<Uint128> {
+    let reward = keeper_reward.flat.checked_add(
+        notional
+            .checked_mul(keeper_reward.notional_ratio)?
+            .checked_div(decimals)?,
+    )?;
+
+    if keeper_reward.max_per_call.is_zero() {
+        return Ok(reward);
+    }
+
+    Ok(Uint128::min(
+        reward,
+        keeper_reward.max_per_call.saturating_sub(paid),
+    ))
+}
+
 pub fn realize_bad_debt(
     deps: Deps,
     bad_debt: Uint128,
@@ -68,10 +137,15 @@ pub fn realize_bad_debt(
         // no need to move extra tokens because vault already prepay bad debt, only need to update the numbers
         state.prepaid_bad_debt = state.prepaid_bad_debt.checked_sub(bad_debt)?;
     } else {
-        // in order to realize all the bad debt vault need extra tokens from insuranceFund
-        let bad_debt_delta = bad_debt.checked_sub(state.prepaid_bad_debt)?;
-
-        messages.push(execute_insurance_fund_withdrawal(deps, bad_debt_delta)?);
+        // in order to realize all the bad debt vault need extra tokens from insuranceFund, the
+        // positions that would leave more than it holds are auto-deleveraged before getting here
+        let bad_debt_delta = bad_debt
+            .checked_sub(state.prepaid_bad_debt)?
+            .min(query_insurance_fund_balance(deps)?);
+
+        if !bad_debt_delta.is_zero() {
+            messages.push(execute_insurance_fund_withdrawal(deps, bad_debt_delta)?);
+        }
 
         state.prepaid_bad_debt = Uint128::zero();
 
@@ -83,34 +157,98 @@ pub fn realize_bad_debt(
 
 // this blocks trades if open interest is too high, required during the bootstrapping of the project
 pub fn update_open_interest_notional(
-    deps: &Deps,
+    deps: DepsMut,
     state: &mut State,
     vamm: Addr,
+    side: Side,
     amount: Integer,
     trader: Addr,
 ) -> StdResult<Response> {
-    let vamm_controller = VammController(vamm);
+    apply_open_interest_notional(deps, state, vamm, side, amount, Some(trader))
+}
+
+// moves the open interest with the open notional of a position that is rebased without trading,
+// the caps are not checked as no exposure is added
+pub fn rebase_open_interest_notional(
+    deps: DepsMut,
+    state: &mut State,
+    vamm: Addr,
+    side: Side,
+    amount: Integer,
+) -> StdResult<Response> {
+    apply_open_interest_notional(deps, state, vamm, side, amount, None)
+}
+
+fn apply_open_interest_notional(
+    deps: DepsMut,
+    state: &mut State,
+    vamm: Addr,
+    side: Side,
+    amount: Integer,
+    trader: Option<Addr>,
+) -> StdResult<Response> {
+    let vamm_controller = VammController(vamm.clone());
     let cap = vamm_controller
         .config(&deps.querier)?
         .open_interest_notional_cap;
 
-    let mut updated_open_interest =
-        amount.checked_add(Integer::new_positive(state.open_interest_notional))?;
+    // open interest is tracked for each side of the market
+    let mut vamm_map = read_vamm_map(deps.storage, &vamm)?;
+    let (open_interest, other_open_interest, side_cap) = match side {
+        Side::Buy => (
+            vamm_map.long_open_interest_notional,
+            vamm_map.short_open_interest_notional,
+            vamm_map.long_open_interest_cap,
+        ),
+        Side::Sell => (
+            vamm_map.short_open_interest_notional,
+            vamm_map.long_open_interest_notional,
+            vamm_map.short_open_interest_cap,
+        ),
+    };
 
+    let mut updated_open_interest = amount.checked_add(Integer::new_positive(open_interest))?;
     if updated_open_interest.is_negative() {
         updated_open_interest = Integer::zero();
     }
+    let market_open_interest = updated_open_interest
+        .value
+        .checked_add(other_open_interest)?;
+
+    // check if the caps have been exceeded - a whitelist capability bypasses
+    if let Some(trader) = trader.filter(|_| amount.is_positive()) {
+        let error = if !cap.is_zero() && market_open_interest > cap {
+            Some("open interest exceeds cap".to_string())
+        } else if !side_cap.is_zero() && updated_open_interest.value > side_cap {
+            let side = match side {
+                Side::Buy => "long",
+                Side::Sell => "short",
+            };
+            Some(format!("{} open interest exceeds cap", side))
+        } else {
+            None
+        };
 
-    // check if the cap has been exceeded - if trader address is in whitelist this bypasses
-    if (!cap.is_zero()
-        && amount.is_positive()
-        && updated_open_interest > Integer::new_positive(cap))
-        && !WHITELIST.query_hook(deps.to_owned(), trader.to_string())?
-    {
-        return Err(StdError::generic_err("open interest exceeds cap"));
+        if let Some(error) = error {
+            if !whitelist_capabilities(deps.as_ref(), &trader)?.bypass_open_interest_cap {
+                return Err(StdError::generic_err(error));
+            }
+        }
+    }
+
+    match side {
+        Side::Buy => vamm_map.long_open_interest_notional = updated_open_interest.value,
+        Side::Sell => vamm_map.short_open_interest_notional = updated_open_interest.value,
     }
+    store_vamm_map(deps.storage, vamm, &vamm_map)?;
 
-    state.open_interest_notional = updated_open_interest.value;
+    // the engine wide open interest is the sum over all markets
+    let mut updated_total =
+        amount.checked_add(Integer::new_positive(state.open_interest_notional))?;
+    if updated_total.is_negative() {
+        updated_total = Integer::zero();
+    }
+    state.open_interest_notional = updated_total.value;
 
     Ok(Response::new())
 }
@@ -127,9 +265,9 @@ pub fn check_base_asset_holding_cap(
         .config(&deps.querier)?
         .base_asset_holding_cap;
 
-    // check if the cap has been exceeded - if trader address is in whitelist this bypasses
+    // check if the cap has been exceeded - a whitelist capability bypasses
     if (!cap.is_zero() && size > cap)
-        && !WHITELIST.query_hook(deps.to_owned(), trader.to_string())?
+        && !whitelist_capabilities(deps.to_owned(), &trader)?.bypass_holding_cap
     {
         return Err(StdError::generic_err("base asset holding exceeds cap"));
     }
@@ -163,6 +301,35 @@ pub fn get_margin_ratio_calc_option(
     Ok(margin_ratio)
 }
 
+/// Returns the position notional and unrealized PnL at the mark price policy of the vamm,
+/// `MaxHealth` takes whichever of spot and oracle is the most beneficial for the trader
+pub fn get_mark_price_notional_unrealized_pnl(
+    deps: Deps,
+    position: &Position,
+) -> StdResult<PositionUnrealizedPnlResponse> {
+    let vamm_map = read_vamm_map(deps.storage, &position.vamm)?;
+
+    let calc_option = match vamm_map.mark_price_policy {
+        MarkPricePolicy::SpotPrice => PnlCalcOption::SpotPrice,
+        MarkPricePolicy::Twap => PnlCalcOption::Twap,
+        MarkPricePolicy::Oracle => PnlCalcOption::Oracle,
+        MarkPricePolicy::MaxHealth => {
+            let spot =
+                get_position_notional_unrealized_pnl(deps, position, PnlCalcOption::SpotPrice)?;
+            let oracle =
+                get_position_notional_unrealized_pnl(deps, position, PnlCalcOption::Oracle)?;
+
+            return Ok(if oracle.unrealized_pnl > spot.unrealized_pnl {
+                oracle
+            } else {
+                spot
+            });
+        }
+    };
+
+    get_position_notional_unrealized_pnl(deps, position, calc_option)
+}
+
 pub fn get_position_notional_unrealized_pnl(
     deps: Deps,
     position: &Position,
@@ -271,26 +438,99 @@ pub fn update_pauser(deps: DepsMut, info: MessageInfo, pauser: String) -> StdRes
         .map_err(|error| StdError::generic_err(error.to_string()))
 }
 
-// Adds an address to the whitelist for base asset holding cap
-pub fn add_whitelist(deps: DepsMut, info: MessageInfo, address: String) -> StdResult<Response> {
+// Adds an address to the whitelist with the checks it is allowed to bypass
+pub fn add_whitelist(
+    deps: DepsMut,
+    info: MessageInfo,
+    address: String,
+    capabilities: Option<WhitelistCapabilities>,
+) -> StdResult<Response> {
     // validate the address
     let valid_addr = deps.api.addr_validate(&address)?;
 
+    let capabilities = capabilities.unwrap_or_else(WhitelistCapabilities::all);
+    store_whitelist_capabilities(deps.storage, &valid_addr, &capabilities)?;
+
     WHITELIST
         .execute_add_hook(&PAUSER, deps, info, valid_addr)
         .map_err(|error| StdError::generic_err(error.to_string()))
 }
 
+// Changes the checks a whitelisted address is allowed to bypass
+pub fn update_whitelist_capabilities(
+    deps: DepsMut,
+    info: MessageInfo,
+    address: String,
+    capabilities: WhitelistCapabilities,
+) -> StdResult<Response> {
+    // validate the address
+    let valid_addr = deps.api.addr_validate(&address)?;
+
+    PAUSER
+        .assert_admin(deps.as_ref(), &info.sender)
+        .map_err(|error| StdError::generic_err(error.to_string()))?;
+
+    if !WHITELIST.query_hook(deps.as_ref(), valid_addr.to_string())? {
+        return Err(StdError::generic_err("address is not whitelisted"));
+    }
+
+    store_whitelist_capabilities(deps.storage, &valid_addr, &capabilities)?;
+
+    Ok(Response::new().add_attributes(vec![
+        ("action", "update_whitelist_capabilities"),
+        ("address", valid_addr.as_str()),
+    ]))
+}
+
 // Removes an address to the whitelist for base asset holding cap
 pub fn remove_whitelist(deps: DepsMut, info: MessageInfo, address: String) -> StdResult<Response> {
     // validate the address
     let valid_addr = deps.api.addr_validate(&address)?;
 
+    remove_whitelist_capabilities(deps.storage, &valid_addr);
+
     WHITELIST
         .execute_remove_hook(&PAUSER, deps, info, valid_addr)
         .map_err(|error| StdError::generic_err(error.to_string()))
 }
 
+// Returns the checks an address bypasses, none if it is not whitelisted
+pub fn whitelist_capabilities(deps: Deps, address: &Addr) -> StdResult<WhitelistCapabilities> {
+    if !WHITELIST.query_hook(deps, address.to_string())? {
+        return Ok(WhitelistCapabilities::default());
+    }
+
+    read_whitelist_capabilities(deps.storage, address)
+}
+
+// Adds an address to the liquidators allowed to take over positions
+pub fn add_backstop_liquidator(
+    deps: DepsMut,
+    info: MessageInfo,
+    address: String,
+) -> StdResult<Response> {
+    // validate the address
+    let valid_addr = deps.api.addr_validate(&address)?;
+
+    BACKSTOP_LIQUIDATORS
+        .execute_add_hook(&PAUSER, deps, info, valid_addr)
+        .map_err(|error| StdError::generic_err(error.to_string()))
+}
+
+// Removes an address from the liquidators allowed to take over positions
+pub fn remove_backstop_liquidator(
+    deps: DepsMut,
+    info: MessageInfo,
+    address: String,
+) -> StdResult<Response> {
+    // validate the address
+    let valid_addr = deps.api.addr_validate(&address)?;
+
+    BACKSTOP_LIQUIDATORS
+        .execute_remove_hook(&PAUSER, deps, info, valid_addr)
+        .map_err(|error| StdError::generic_err(error.to_string()))
+}
+
 pub fn set_pause(deps: DepsMut, _env: Env, info: MessageInfo, pause: bool) -> StdResult<Response> {
     let mut state = read_state(deps.storage)?;
 
@@ -300,6 +540,11 @@ pub fn set_pause(deps: DepsMut, _env: Env, info: MessageInfo, pause: bool) -> St
         return Err(StdError::generic_err("unauthorized"));
     }
 
+    // the engine stays paused until the data of the migration is rebuilt
+    if !pause {
+        require_no_backfill(deps.storage)?;
+    }
+
     state.pause = pause;
 
     store_state(deps.storage, &state)?;
@@ -379,7 +624,7 @@ pub fn require_not_restriction_mode(
     let vamm_map = read_vamm_map(deps.storage, vamm)?;
 
     if vamm_map.last_restriction_block == block_height
-        && !WHITELIST.query_hook(deps.to_owned(), trader.to_string())?
+        && !whitelist_capabilities(deps.to_owned(), trader)?.bypass_restriction_mode
     {
         return Err(StdError::generic_err("Only one action allowed"));
     }
@@ -396,6 +641,51 @@ pub fn require_not_paused(paused: bool) -> StdResult<Response> {
     Ok(Response::new())
 }
 
+// check the block time has not passed the deadline of the message
+pub fn require_before_deadline(env: &Env, deadline: Option<u64>) -> StdResult<Response> {
+    if let Some(deadline) = deadline {
+        if env.block.time.seconds() > deadline {
+            return Err(StdError::generic_err("Deadline has passed"));
+        }
+    }
+
+    Ok(Response::new())
+}
+
+// check the average price of a swap is not worse than the worst price given for the position,
+// buying the base asset is worse at a higher price and selling it at a lower price
+pub fn require_worst_price(
+    storage: &mut dyn Storage,
+    position_id: u64,
+    buys_base: bool,
+    quote_asset_amount: Uint128,
+    base_asset_amount: Uint128,
+) -> StdResult<Response> {
+    let worst_price = match read_tmp_worst_price(storage, position_id)? {
+        Some(worst_price) => worst_price,
+        None => return Ok(Response::new()),
+    };
+    remove_tmp_worst_price(storage, position_id);
+
+    if base_asset_amount.is_zero() {
+        return Ok(Response::new());
+    }
+
+    let config = read_config(storage)?;
+    let average_price = quote_asset_amount
+        .checked_mul(config.decimals)?
+        .checked_div(base_asset_amount)?;
+
+    if (buys_base && average_price > worst_price) || (!buys_base && average_price < worst_price) {
+        return Err(StdError::generic_err(format!(
+            "Average price {} is worse than {}",
+            average_price, worst_price
+        )));
+    }
+
+    Ok(Response::new())
+}
+
 // check an input is non-zero
 pub fn require_non_zero_input(input: Uint128) -> StdResult<Response> {
     if input.is_zero() {
@@ -435,6 +725,17 @@ pub fn parse_swap(response: &SubMsgResponse) -> StdResult<(Uint128, Uint128, u64
     }
 }
 
+/// Parses a swap into the base and the quote amounts exchanged, whichever of them was the input
+pub fn parse_swap_amounts(response: &SubMsgResponse) -> StdResult<(Uint128, Uint128, u64)> {
+    let (input, output, position_id) = parse_swap(response)?;
+    let wasm = read_response("wasm", response)?;
+
+    match read_event("type", wasm)? {
+        "input" => Ok((output, input, position_id)),
+        _ => Ok((input, output, position_id)),
+    }
+}
+
 pub fn parse_pay_funding(response: &SubMsgResponse) -> StdResult<(Integer, &str)> {
     // Find swap inputs and output events
     let wasm = read_response("wasm", response)?;
@@ -628,6 +929,166 @@ pub fn position_is_liquidated(
     }
 }
 
+/// Returns the price that take profit (`do_tp`) or stop loss is checked against, and the quote
+/// asset limit of the close. `quote_asset_amount` is the result of closing `base_asset_amount`
+/// of the position against the vamm reserves, when the trigger price is not that execution
+/// price the close may only deviate from it by the price diff limit of the vamm
+pub fn get_tp_sl_close_price(
+    deps: Deps,
+    position: &Position,
+    base_asset_amount: Uint128,
+    quote_asset_amount: Uint128,
+    do_tp: bool,
+) -> StdResult<(Uint128, Uint128)> {
+    let config = read_config(deps.storage)?;
+    let vamm_controller = VammController(position.vamm.clone());
+
+    let execution_price = quote_asset_amount
+        .checked_mul(config.decimals)?
+        .checked_div(base_asset_amount)?;
+
+    let price_source = match position.tp_sl_trigger {
+        TpSlTrigger::MarkPrice => read_vamm_map(deps.storage, &position.vamm)?.mark_price_policy,
+        TpSlTrigger::ExecutionPrice => MarkPricePolicy::SpotPrice,
+        TpSlTrigger::Twap => MarkPricePolicy::Twap,
+        TpSlTrigger::IndexPrice => MarkPricePolicy::Oracle,
+    };
+
+    let close_price = match price_source {
+        MarkPricePolicy::SpotPrice => execution_price,
+        MarkPricePolicy::Twap => {
+            let twap_notional = vamm_controller.output_twap(
+                &deps.querier,
+                position.direction.clone(),
+                base_asset_amount,
+            )?;
+
+            twap_notional
+                .checked_mul(config.decimals)?
+                .checked_div(base_asset_amount)?
+        }
+        MarkPricePolicy::Oracle => vamm_controller.underlying_price(&deps.querier)?,
+        MarkPricePolicy::MaxHealth => {
+            let oracle_price = vamm_controller.underlying_price(&deps.querier)?;
+
+            // a stop loss is checked at the healthiest price, longs are healthier at the higher
+            // price and shorts at the lower one, a take profit has to be reached at both prices
+            // so that it cannot be triggered by moving the vamm alone
+            match (&position.side, do_tp) {
+                (Side::Buy, false) | (Side::Sell, true) => {
+                    Uint128::max(execution_price, oracle_price)
+                }
+                (Side::Buy, true) | (Side::Sell, false) => {
+                    Uint128::min(execution_price, oracle_price)
+                }
+            }
+        }
+    };
+
+    if close_price == execution_price {
+        return Ok((close_price, Uint128::zero()));
+    }
+
+    let quote_asset_limit =
+        calc_trigger_quote_asset_limit(deps, position, close_price, base_asset_amount)?;
+
+    Ok((close_price, quote_asset_limit))
+}
+
+/// Returns the quote asset limit of closing `base_asset_amount` of a position triggered at
+/// `trigger_price`, the close may only deviate from it by the price diff limit of the vamm
+pub fn calc_trigger_quote_asset_limit(
+    deps: Deps,
+    position: &Position,
+    trigger_price: Uint128,
+    base_asset_amount: Uint128,
+) -> StdResult<Uint128> {
+    let config = read_config(deps.storage)?;
+    let vamm_config = VammController(position.vamm.clone()).config(&deps.querier)?;
+    if vamm_config.price_diff_limit_ratio.is_zero() {
+        return Ok(Uint128::zero());
+    }
+
+    let trigger_notional = trigger_price
+        .checked_mul(base_asset_amount)?
+        .checked_div(config.decimals)?;
+    let slippage = trigger_notional
+        .checked_mul(vamm_config.price_diff_limit_ratio)?
+        .checked_div(vamm_config.decimals)?;
+
+    // longs receive at least, and shorts pay at most, the limit
+    match position.side {
+        Side::Buy => Ok(trigger_notional.saturating_sub(slippage)),
+        Side::Sell => Ok(trigger_notional.checked_add(slippage)?),
+    }
+}
+
+/// Returns the price a trailing stop follows, the index price if the position triggers on it
+/// else the vamm twap, so that it cannot be ratcheted by moving the vamm
+pub fn get_trailing_price(deps: Deps, position: &Position) -> StdResult<Uint128> {
+    let vamm_controller = VammController(position.vamm.clone());
+
+    if position.tp_sl_trigger == TpSlTrigger::IndexPrice {
+        return vamm_controller.underlying_price(&deps.querier);
+    }
+
+    let config = read_config(deps.storage)?;
+    let twap_notional = vamm_controller.output_twap(
+        &deps.querier,
+        position.direction.clone(),
+        position.size.value,
+    )?;
+
+    Ok(twap_notional
+        .checked_mul(config.decimals)?
+        .checked_div(position.size.value)?)
+}
+
+/// Returns the stop loss that trails the water mark of a position by the distance
+pub fn calc_trailing_stop(
+    side: &Side,
+    water_mark: Uint128,
+    distance: &TrailDistance,
+    decimals: Uint128,
+) -> StdResult<Uint128> {
+    let distance = match distance {
+        TrailDistance::Absolute(amount) => *amount,
+        TrailDistance::Ratio(ratio) => water_mark.checked_mul(*ratio)?.checked_div(decimals)?,
+    };
+
+    match side {
+        Side::Buy => Ok(water_mark.saturating_sub(distance)),
+        Side::Sell => Ok(water_mark.checked_add(distance)?),
+    }
+}
+
+/// Returns the more protective of the current stop loss and the trailing one, a zero stop loss
+/// is unset
+pub fn more_protective_stop_loss(
+    side: &Side,
+    current: Option<Uint128>,
+    stop_loss: Uint128,
+) -> Uint128 {
+    match (side, current) {
+        (Side::Buy, Some(current)) => Uint128::max(current, stop_loss),
+        (Side::Sell, Some(current)) if !current.is_zero() => Uint128::min(current, stop_loss),
+        _ => stop_loss,
+    }
+}
+
+/// Checks that closing a position for `quote_asset_amount` respects the quote asset limit
+pub fn is_within_quote_asset_limit(
+    side: &Side,
+    quote_asset_amount: Uint128,
+    quote_asset_limit: Uint128,
+) -> bool {
+    quote_asset_limit.is_zero()
+        || match side {
+            Side::Buy => quote_asset_amount >= quote_asset_limit,
+            Side::Sell => quote_asset_amount <= quote_asset_limit,
+        }
+}
+
 pub fn require_is_not_over_price_diff_limit(
     deps: Deps,
     vamm_controller: &VammController,
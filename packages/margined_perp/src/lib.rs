@@ -1,3 +1,4 @@
+pub mod margined_backstop_pool;
 pub mod margined_engine;
 pub mod margined_fee_pool;
 pub mod margined_insurance_fund;
@@ -0,0 +1,103 @@
+use cosmwasm_schema::{cw_serde, QueryResponses};
+use margined_common::asset::AssetInfo;
+
+use cosmwasm_std::{Addr, Uint128};
+#[cw_serde]
+pub struct InstantiateMsg {
+    pub engine: String,
+    pub keeper: String,
+    pub margin_ratio: Uint128,
+}
+
+#[cw_serde]
+pub enum ExecuteMsg {
+    UpdateOwner {
+        owner: String,
+    },
+    UpdateConfig {
+        keeper: Option<String>,
+        margin_ratio: Option<Uint128>,
+    },
+    Deposit {
+        amount: Uint128,
+    },
+    Withdraw {
+        shares: Uint128,
+    },
+    Absorb {
+        vamm: String,
+        position_id: u64,
+    },
+    ClosePosition {
+        vamm: String,
+        position_id: u64,
+        quote_asset_limit: Uint128,
+    },
+    DepositMargin {
+        vamm: String,
+        position_id: u64,
+        amount: Uint128,
+    },
+    WithdrawMargin {
+        vamm: String,
+        position_id: u64,
+        amount: Uint128,
+    },
+}
+
+#[cw_serde]
+#[derive(QueryResponses)]
+pub enum QueryMsg {
+    #[returns(ConfigResponse)]
+    Config {},
+    #[returns(OwnerResponse)]
+    GetOwner {},
+    #[returns(StateResponse)]
+    State {},
+    #[returns(SharesResponse)]
+    Shares { address: String },
+    #[returns(PositionsResponse)]
+    Positions {},
+}
+
+#[cw_serde]
+pub struct MigrateMsg {}
+
+#[cw_serde]
+pub struct ConfigResponse {
+    pub engine: Addr,
+    pub keeper: Addr,
+    pub eligible_collateral: AssetInfo,
+    pub decimals: Uint128,
+    pub margin_ratio: Uint128,
+}
+
+#[cw_serde]
+pub struct OwnerResponse {
+    pub owner: Addr,
+}
+
+#[cw_serde]
+pub struct StateResponse {
+    pub total_shares: Uint128,
+    pub balance: Uint128,
+    pub positions_value: Uint128,
+    pub total_value: Uint128,
+}
+
+#[cw_serde]
+pub struct SharesResponse {
+    pub shares: Uint128,
+    pub value: Uint128,
+}
+
+#[cw_serde]
+pub struct PoolPosition {
+    pub vamm: Addr,
+    pub position_id: u64,
+}
+
+#[cw_serde]
+pub struct PositionsResponse {
+    pub positions: Vec<PoolPosition>,
+}
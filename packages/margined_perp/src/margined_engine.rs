@@ -1,6 +1,6 @@
 use crate::margined_vamm::Direction;
 use cosmwasm_schema::{cw_serde, QueryResponses};
-use cosmwasm_std::{Addr, SubMsg, Uint128};
+use cosmwasm_std::{Addr, Binary, SubMsg, Uint128};
 use margined_common::{asset::AssetInfo, integer::Integer};
 
 #[cw_serde]
@@ -26,11 +26,174 @@ pub enum PnlCalcOption {
     Oracle,
 }
 
+/// Price used to mark positions of a market for margin checks, liquidations and TP/SL triggers
+#[cw_serde]
+#[derive(Default)]
+pub enum MarkPricePolicy {
+    #[default]
+    SpotPrice,
+    Twap,
+    Oracle,
+    // the price at which the position is the healthiest between spot and oracle
+    MaxHealth,
+}
+
+/// Price that the take profit and stop loss of a position trigger on
+#[cw_serde]
+#[derive(Default)]
+pub enum TpSlTrigger {
+    // follows the mark price policy of the vamm
+    #[default]
+    MarkPrice,
+    ExecutionPrice,
+    Twap,
+    IndexPrice,
+}
+
+/// Distance of a trailing stop from the best price seen, either in price or as a ratio of it
+#[cw_serde]
+pub enum TrailDistance {
+    Absolute(Uint128),
+    Ratio(Uint128),
+}
+
+#[cw_serde]
+pub struct TrailingStop {
+    pub distance: TrailDistance,
+    // highest price seen for longs, lowest for shorts
+    pub water_mark: Uint128,
+}
+
+/// Take profit level of a ladder, the fractions of the levels add up to the decimals
+#[cw_serde]
+pub struct TakeProfitLevel {
+    pub price: Uint128,
+    pub fraction: Uint128,
+}
+
+/// Order that opens a position once the vamm price crosses its trigger price,
+/// upwards for buys and downwards for sells
+#[cw_serde]
+pub struct ConditionalOrderMsg {
+    pub side: Side,
+    pub margin_amount: Uint128,
+    pub leverage: Uint128,
+    pub trigger_price: Uint128,
+    pub take_profit: Option<Uint128>,
+    pub stop_loss: Option<Uint128>,
+    pub base_asset_limit: Uint128,
+    // sub-account of the sender owning the position, 0 is the main account
+    pub subaccount_id: Option<u32>,
+}
+
+#[cw_serde]
+pub struct ConditionalOrder {
+    pub order_id: u64,
+    pub vamm: Addr,
+    pub trader: Addr,
+    pub side: Side,
+    pub margin_amount: Uint128,
+    pub leverage: Uint128,
+    pub trigger_price: Uint128,
+    pub take_profit: Option<Uint128>,
+    pub stop_loss: Option<Uint128>,
+    pub base_asset_limit: Uint128,
+    // sibling order that is cancelled when this one is executed
+    pub oco_order_id: Option<u64>,
+    #[serde(default)]
+    pub subaccount_id: u32,
+}
+
+/// Order that is filled by keepers in equal slices into a single position
+#[cw_serde]
+pub struct TwapOrder {
+    pub order_id: u64,
+    pub vamm: Addr,
+    pub trader: Addr,
+    pub side: Side,
+    // margin of all the slices, escrowed until they are filled
+    pub margin_amount: Uint128,
+    pub leverage: Uint128,
+    pub slices: u64,
+    pub filled_slices: u64,
+    // minimum seconds between two slices
+    pub interval: u64,
+    // worst average price of a slice, zero is no limit
+    pub price_limit: Uint128,
+    pub position_id: Option<u64>,
+    pub last_filled_at: u64,
+    #[serde(default)]
+    pub subaccount_id: u32,
+}
+
+/// Reward paid to keepers that trigger TP/SL or settle funding, a flat fee plus a fraction
+/// of the closed notional, capped per call. All zero disables the reward
+#[cw_serde]
+#[derive(Default)]
+pub struct KeeperReward {
+    pub flat: Uint128,
+    pub notional_ratio: Uint128,
+    pub max_per_call: Uint128,
+}
+
+/// Limits on the positions a trader opens, set by the owner for risk or by the trader for
+/// self-exclusion. None is no limit
+#[cw_serde]
+#[derive(Default)]
+pub struct TraderLimits {
+    pub max_positions_per_market: Option<u32>,
+    pub max_total_notional: Option<Uint128>,
+    pub max_leverage: Option<Uint128>,
+    // realized loss in a UTC day after which no position is opened until the next day
+    pub daily_loss_limit: Option<Uint128>,
+}
+
+/// Checks that a whitelisted address is allowed to bypass, each one granted individually
+#[cw_serde]
+#[derive(Default)]
+pub struct WhitelistCapabilities {
+    pub bypass_open_interest_cap: bool,
+    pub bypass_holding_cap: bool,
+    pub bypass_restriction_mode: bool,
+    // only the trader can liquidate their positions
+    pub liquidation_protection: bool,
+}
+
+impl WhitelistCapabilities {
+    pub fn all() -> Self {
+        WhitelistCapabilities {
+            bypass_open_interest_cap: true,
+            bypass_holding_cap: true,
+            bypass_restriction_mode: true,
+            liquidation_protection: true,
+        }
+    }
+}
+
+/// Order signed off-chain by a trader and relayed by keepers, its margin is paid from the
+/// balance of the trader in the engine
+#[cw_serde]
+pub struct SignedOrder {
+    pub vamm: String,
+    pub side: Side,
+    pub margin_amount: Uint128,
+    pub leverage: Uint128,
+    pub base_asset_limit: Uint128,
+    pub take_profit: Option<Uint128>,
+    pub stop_loss: Option<Uint128>,
+    pub nonce: u64,
+    // block time in seconds after which the order cannot be executed
+    pub expiry: u64,
+    // sub-account of the trader paying the margin and owning the position, 0 is the main account
+    pub subaccount_id: Option<u32>,
+}
+
 #[cw_serde]
 pub enum PositionFilter {
-    Trader(String), // filter by trader
-    Price(Uint128), // filter by price
-    None,           // no filter
+    Trader(String),          // filter by trader
+    Subaccount(String, u32), // filter by trader and sub-account
+    Price(Uint128),          // filter by price
+    None,                    // no filter
 }
 
 #[cw_serde]
@@ -57,19 +220,67 @@ pub enum ExecuteMsg {
         partial_liquidation_ratio: Option<Uint128>,
         tp_sl_spread: Option<Uint128>,
         liquidation_fee: Option<Uint128>,
+        target_margin_ratio: Option<Uint128>,
     },
     UpdateOperator {
         operator: Option<String>,
     },
+    UpdateBackstopPool {
+        backstop_pool: Option<String>,
+        discount: Uint128,
+    },
+    UpdateLiquidationFeeSplit {
+        liquidation_fee_split: LiquidationFeeSplit,
+        max_liquidator_fee: Option<Uint128>,
+    },
+    UpdateMarkPricePolicy {
+        vamm: String,
+        policy: MarkPricePolicy,
+    },
+    UpdateOpenInterestCaps {
+        vamm: String,
+        long_cap: Uint128,
+        short_cap: Uint128,
+    },
+    UpdateTraderLimits {
+        trader: String,
+        limits: TraderLimits,
+    },
+    UpdateSelfLimits {
+        limits: TraderLimits,
+        // sub-account the limits apply to, 0 is the main account
+        subaccount_id: Option<u32>,
+    },
+    UpdateKeeperReward {
+        keeper_reward: KeeperReward,
+    },
     UpdatePauser {
         pauser: String,
     },
     AddWhitelist {
         address: String,
+        // None grants every capability
+        capabilities: Option<WhitelistCapabilities>,
+    },
+    UpdateWhitelistCapabilities {
+        address: String,
+        capabilities: WhitelistCapabilities,
     },
     RemoveWhitelist {
         address: String,
     },
+    AddBackstopLiquidator {
+        address: String,
+    },
+    RemoveBackstopLiquidator {
+        address: String,
+    },
+    AddBlocklist {
+        address: String,
+    },
+    RemoveBlocklist {
+        address: String,
+    },
     OpenPosition {
         vamm: String,
         side: Side,
@@ -78,6 +289,83 @@ pub enum ExecuteMsg {
         take_profit: Option<Uint128>,
         stop_loss: Option<Uint128>,
         base_asset_limit: Uint128,
+        // block time in seconds after which the message is rejected
+        deadline: Option<u64>,
+        // worst average price accepted for the swap
+        worst_price: Option<Uint128>,
+        // sub-account of the sender owning the position, 0 is the main account
+        subaccount_id: Option<u32>,
+    },
+    // opens a position of an exact base size with the margin paid by the trader
+    OpenPositionBySize {
+        vamm: String,
+        side: Side,
+        base_size: Uint128,
+        margin_amount: Uint128,
+        // sub-account of the sender owning the position, 0 is the main account
+        subaccount_id: Option<u32>,
+    },
+    OpenPositionFromBalance {
+        vamm: String,
+        side: Side,
+        margin_amount: Uint128,
+        leverage: Uint128,
+        take_profit: Option<Uint128>,
+        stop_loss: Option<Uint128>,
+        base_asset_limit: Uint128,
+        // sub-account of the sender paying the margin and owning the position, 0 is the main account
+        subaccount_id: Option<u32>,
+    },
+    TransferBetweenSubaccounts {
+        from_subaccount_id: u32,
+        to_subaccount_id: u32,
+        amount: Uint128,
+    },
+    Deposit {
+        amount: Uint128,
+    },
+    Withdraw {
+        amount: Uint128,
+    },
+    Claim {},
+    UpdateCreditProceeds {
+        credit_proceeds: bool,
+    },
+    UpdateSigningKey {
+        public_key: Option<Binary>,
+    },
+    ExecuteSignedOrder {
+        trader: String,
+        order: SignedOrder,
+        signature: Binary,
+    },
+    PlaceConditionalOrder {
+        vamm: String,
+        order: ConditionalOrderMsg,
+        oco_order: Option<ConditionalOrderMsg>,
+    },
+    ExecuteConditionalOrder {
+        order_id: u64,
+    },
+    CancelConditionalOrder {
+        order_id: u64,
+    },
+    PlaceTwapOrder {
+        vamm: String,
+        side: Side,
+        margin_amount: Uint128,
+        leverage: Uint128,
+        slices: u64,
+        interval: u64,
+        price_limit: Uint128,
+        // sub-account of the sender owning the position, 0 is the main account
+        subaccount_id: Option<u32>,
+    },
+    ExecuteTwapOrder {
+        order_id: u64,
+    },
+    CancelTwapOrder {
+        order_id: u64,
     },
     UpdateTpSl {
         vamm: String,
@@ -85,10 +373,35 @@ pub enum ExecuteMsg {
         take_profit: Option<Uint128>,
         stop_loss: Option<Uint128>,
     },
+    UpdateTpSlTrigger {
+        vamm: String,
+        position_id: u64,
+        tp_sl_trigger: TpSlTrigger,
+    },
+    UpdateTrailingStop {
+        vamm: String,
+        position_id: u64,
+        distance: Option<TrailDistance>,
+    },
+    RatchetTrailingStop {
+        vamm: String,
+        position_id: u64,
+    },
+    UpdateTakeProfitLevels {
+        vamm: String,
+        position_id: u64,
+        levels: Vec<TakeProfitLevel>,
+    },
+    TriggerTakeProfitLevel {
+        vamm: String,
+        position_id: u64,
+    },
     ClosePosition {
         vamm: String,
         position_id: u64,
         quote_asset_limit: Uint128,
+        deadline: Option<u64>,
+        worst_price: Option<Uint128>,
     },
     TriggerTpSl {
         vamm: String,
@@ -105,6 +418,21 @@ pub enum ExecuteMsg {
         vamm: String,
         position_id: u64,
         quote_asset_limit: Uint128,
+        deadline: Option<u64>,
+        worst_price: Option<Uint128>,
+    },
+    BackstopLiquidate {
+        vamm: String,
+        position_id: u64,
+        margin_amount: Uint128,
+    },
+    UpdateAdlCandidates {
+        vamm: String,
+        side: Side,
+        position_ids: Vec<u64>,
+    },
+    Backfill {
+        limit: Option<u32>,
     },
     PayFunding {
         vamm: String,
@@ -119,6 +447,16 @@ pub enum ExecuteMsg {
         position_id: u64,
         amount: Uint128,
     },
+    SetLeverage {
+        vamm: String,
+        position_id: u64,
+        leverage: Uint128,
+    },
+    RealizePnl {
+        vamm: String,
+        position_id: u64,
+        amount: Uint128,
+    },
     SetPause {
         pause: bool,
     },
@@ -140,6 +478,12 @@ pub enum QueryMsg {
     IsWhitelisted { address: String },
     #[returns(cw_controllers::HooksResponse)]
     GetWhitelist {},
+    #[returns(WhitelistCapabilities)]
+    WhitelistCapabilities { address: String },
+    #[returns(bool)]
+    IsBackstopLiquidator { address: String },
+    #[returns(cw_controllers::HooksResponse)]
+    GetBackstopLiquidators {},
     #[returns(Position)]
     Position { vamm: String, position_id: u64 },
     #[returns(Vec<Position>)]
@@ -151,6 +495,33 @@ pub enum QueryMsg {
         limit: Option<u32>,
         order_by: Option<i32>,
     },
+    #[returns(TraderBalanceResponse)]
+    TraderBalance {
+        trader: String,
+        subaccount_id: Option<u32>,
+    },
+    #[returns(Uint128)]
+    ClaimableBalance { address: String },
+    #[returns(Option<Binary>)]
+    SigningKey { trader: String },
+    #[returns(bool)]
+    IsNonceUsed { trader: String, nonce: u64 },
+    #[returns(ConditionalOrder)]
+    ConditionalOrder { order_id: u64 },
+    #[returns(Vec<ConditionalOrder>)]
+    ConditionalOrders {
+        trader: String,
+        start_after: Option<u64>,
+        limit: Option<u32>,
+    },
+    #[returns(TwapOrder)]
+    TwapOrder { order_id: u64 },
+    #[returns(Vec<TwapOrder>)]
+    TwapOrders {
+        trader: String,
+        start_after: Option<u64>,
+        limit: Option<u32>,
+    },
     #[returns(TickResponse)]
     Tick {
         vamm: String,
@@ -183,6 +554,29 @@ pub enum QueryMsg {
     },
     #[returns(Integer)]
     FreeCollateral { vamm: String, position_id: u64 },
+    #[returns(MarkPricePolicy)]
+    MarkPricePolicy { vamm: String },
+    #[returns(MarketStateResponse)]
+    MarketState { vamm: String },
+    #[returns(TraderLimitsResponse)]
+    TraderLimits {
+        trader: String,
+        subaccount_id: Option<u32>,
+    },
+    #[returns(bool)]
+    IsBlocked { address: String },
+    #[returns(Vec<Addr>)]
+    Blocklist {
+        start_after: Option<String>,
+        limit: Option<u32>,
+    },
+    #[returns(Vec<EscrowResponse>)]
+    Escrows {
+        start_after: Option<String>,
+        limit: Option<u32>,
+    },
+    #[returns(Vec<TakeProfitLevel>)]
+    TakeProfitLevels { vamm: String, position_id: u64 },
     #[returns(Uint128)]
     BalanceWithFundingPayment { position_id: u64 },
     #[returns(Position)]
@@ -200,6 +594,16 @@ pub enum QueryMsg {
     IsLiquidated { vamm: String, position_id: u64 },
     #[returns(LastPositionIdResponse)]
     LastPositionId {},
+    #[returns(AdlRankingResponse)]
+    AdlRanking {
+        vamm: String,
+        side: Side,
+        limit: Option<u32>,
+    },
+    #[returns(AdlRankResponse)]
+    AdlRank { vamm: String, position_id: u64 },
+    #[returns(BackfillResponse)]
+    Backfill {},
 }
 
 #[cw_serde]
@@ -215,6 +619,27 @@ pub struct ConfigResponse {
     pub tp_sl_spread: Uint128,
     pub liquidation_fee: Uint128,
     pub operator: Option<Addr>,
+    #[serde(default)]
+    pub backstop_pool: Option<Addr>,
+    #[serde(default)]
+    pub backstop_discount: Uint128,
+    #[serde(default)]
+    pub liquidation_fee_split: LiquidationFeeSplit,
+    #[serde(default)]
+    pub max_liquidator_fee: Option<Uint128>,
+    #[serde(default)]
+    pub target_margin_ratio: Uint128,
+    #[serde(default)]
+    pub keeper_reward: KeeperReward,
+}
+
+/// Shares of the liquidation penalty, they add up to the decimals of the collateral
+#[cw_serde]
+#[derive(Default)]
+pub struct LiquidationFeeSplit {
+    pub liquidator: Uint128,
+    pub insurance_fund: Uint128,
+    pub fee_pool: Uint128,
 }
 
 #[cw_serde]
@@ -222,6 +647,39 @@ pub struct StateResponse {
     pub open_interest_notional: Uint128,
     pub bad_debt: Uint128,
     pub pause: bool,
+    pub total_liabilities: Uint128,
+}
+
+#[cw_serde]
+pub struct MarketStateResponse {
+    pub open_interest_notional: Uint128,
+    pub long_open_interest_notional: Uint128,
+    pub short_open_interest_notional: Uint128,
+    // cap of the market set on the vamm, zero is uncapped
+    pub open_interest_notional_cap: Uint128,
+    pub long_open_interest_cap: Uint128,
+    pub short_open_interest_cap: Uint128,
+}
+
+#[cw_serde]
+pub struct EscrowResponse {
+    pub address: Addr,
+    // payouts held while the address is blocked
+    pub amount: Uint128,
+}
+
+#[cw_serde]
+pub struct TraderLimitsResponse {
+    // limits set by the owner
+    pub limits: TraderLimits,
+    // limits set by the trader
+    pub self_limits: TraderLimits,
+    // looser limits set by the trader that apply from the next UTC day
+    pub pending_self_limits: Option<TraderLimits>,
+    // loss realized by all the sub-accounts of the trader in the current UTC day
+    pub daily_realized_loss: Uint128,
+    // loss realized by the sub-account in the current UTC day
+    pub subaccount_daily_realized_loss: Uint128,
 }
 
 #[cw_serde]
@@ -250,6 +708,40 @@ pub struct PositionTpSlResponse {
     pub is_tpsl: bool,
 }
 
+#[cw_serde]
+pub struct TraderBalanceResponse {
+    pub balance: Uint128,
+    // whether the proceeds of closed positions are credited to the balance
+    pub credit_proceeds: bool,
+}
+
+#[cw_serde]
+pub struct AdlRankResponse {
+    pub position_id: u64,
+    pub trader: Addr,
+    pub rank: u32,
+    pub score: Integer,
+    pub unrealized_pnl: Integer,
+}
+
+#[cw_serde]
+pub struct AdlRankingResponse {
+    pub ranks: Vec<AdlRankResponse>,
+}
+
+/// Stored data that a migration rebuilds in batches, in the order they run
+#[cw_serde]
+pub enum BackfillStage {
+    OpenInterest,
+    PositionBySubaccount,
+}
+
+#[cw_serde]
+pub struct BackfillResponse {
+    // stages left to run, the first one is in progress
+    pub stages: Vec<BackfillStage>,
+}
+
 #[cw_serde]
 pub struct Position {
     pub position_id: u64,
@@ -264,10 +756,19 @@ pub struct Position {
     pub entry_price: Uint128,
     pub take_profit: Option<Uint128>,
     pub stop_loss: Option<Uint128>,
+    #[serde(default)]
+    pub tp_sl_trigger: TpSlTrigger,
+    #[serde(default)]
+    pub trailing_stop: Option<TrailingStop>,
+    #[serde(default)]
+    pub take_profit_levels: Vec<TakeProfitLevel>,
     pub spread_fee: Uint128,
     pub toll_fee: Uint128,
     pub last_updated_premium_fraction: Integer,
     pub block_time: u64,
+    // sub-account of the trader owning the position, 0 is the main account
+    #[serde(default)]
+    pub subaccount_id: u32,
 }
 
 impl Default for Position {
@@ -285,10 +786,14 @@ impl Default for Position {
             entry_price: Uint128::zero(),
             take_profit: None,
             stop_loss: Some(Uint128::zero()),
+            tp_sl_trigger: TpSlTrigger::default(),
+            trailing_stop: None,
+            take_profit_levels: vec![],
             last_updated_premium_fraction: Integer::zero(),
             spread_fee: Uint128::zero(),
             toll_fee: Uint128::zero(),
             block_time: 0u64,
+            subaccount_id: 0u32,
         }
     }
 }
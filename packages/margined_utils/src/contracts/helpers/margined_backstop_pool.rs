@@ -0,0 +1,129 @@
+use cosmwasm_schema::cw_serde;
+use margined_perp::margined_backstop_pool::{
+    ConfigResponse, ExecuteMsg, PositionsResponse, QueryMsg, SharesResponse, StateResponse,
+};
+
+use cosmwasm_std::{Addr, Coin, CosmosMsg, QuerierWrapper, StdResult, Uint128};
+
+use margined_common::messages::wasm_execute;
+
+/// BackstopPoolController is a wrapper around Addr that provides a lot of helpers
+/// for working with this.
+#[cw_serde]
+pub struct BackstopPoolController(pub Addr);
+
+impl BackstopPoolController {
+    pub fn addr(&self) -> Addr {
+        self.0.clone()
+    }
+
+    /////////////////////////
+    ///  Execute Messages ///
+    /////////////////////////
+
+    pub fn update_owner(&self, owner: String) -> StdResult<CosmosMsg> {
+        let msg = ExecuteMsg::UpdateOwner { owner };
+        wasm_execute(&self.0, &msg, vec![])
+    }
+
+    pub fn update_config(
+        &self,
+        keeper: Option<String>,
+        margin_ratio: Option<Uint128>,
+    ) -> StdResult<CosmosMsg> {
+        let msg = ExecuteMsg::UpdateConfig {
+            keeper,
+            margin_ratio,
+        };
+        wasm_execute(&self.0, &msg, vec![])
+    }
+
+    pub fn deposit(&self, amount: Uint128, funds: Vec<Coin>) -> StdResult<CosmosMsg> {
+        let msg = ExecuteMsg::Deposit { amount };
+        wasm_execute(&self.0, &msg, funds)
+    }
+
+    pub fn withdraw(&self, shares: Uint128) -> StdResult<CosmosMsg> {
+        let msg = ExecuteMsg::Withdraw { shares };
+        wasm_execute(&self.0, &msg, vec![])
+    }
+
+    pub fn absorb(&self, vamm: String, position_id: u64) -> StdResult<CosmosMsg> {
+        let msg = ExecuteMsg::Absorb { vamm, position_id };
+        wasm_execute(&self.0, &msg, vec![])
+    }
+
+    pub fn close_position(
+        &self,
+        vamm: String,
+        position_id: u64,
+        quote_asset_limit: Uint128,
+    ) -> StdResult<CosmosMsg> {
+        let msg = ExecuteMsg::ClosePosition {
+            vamm,
+            position_id,
+            quote_asset_limit,
+        };
+        wasm_execute(&self.0, &msg, vec![])
+    }
+
+    pub fn deposit_margin(
+        &self,
+        vamm: String,
+        position_id: u64,
+        amount: Uint128,
+    ) -> StdResult<CosmosMsg> {
+        let msg = ExecuteMsg::DepositMargin {
+            vamm,
+            position_id,
+            amount,
+        };
+        wasm_execute(&self.0, &msg, vec![])
+    }
+
+    pub fn withdraw_margin(
+        &self,
+        vamm: String,
+        position_id: u64,
+        amount: Uint128,
+    ) -> StdResult<CosmosMsg> {
+        let msg = ExecuteMsg::WithdrawMargin {
+            vamm,
+            position_id,
+            amount,
+        };
+        wasm_execute(&self.0, &msg, vec![])
+    }
+
+    //////////////////////
+    /// Query Messages ///
+    //////////////////////
+
+    /// get backstop pool configuration
+    pub fn config(&self, querier: &QuerierWrapper) -> StdResult<ConfigResponse> {
+        let msg = QueryMsg::Config {};
+
+        querier.query_wasm_smart(&self.0, &msg)
+    }
+
+    /// get backstop pool state
+    pub fn state(&self, querier: &QuerierWrapper) -> StdResult<StateResponse> {
+        let msg = QueryMsg::State {};
+
+        querier.query_wasm_smart(&self.0, &msg)
+    }
+
+    /// get the shares of a liquidity provider
+    pub fn shares(&self, querier: &QuerierWrapper, address: String) -> StdResult<SharesResponse> {
+        let msg = QueryMsg::Shares { address };
+
+        querier.query_wasm_smart(&self.0, &msg)
+    }
+
+    /// get the positions held by the pool
+    pub fn positions(&self, querier: &QuerierWrapper) -> StdResult<PositionsResponse> {
+        let msg = QueryMsg::Positions {};
+
+        querier.query_wasm_smart(&self.0, &msg)
+    }
+}
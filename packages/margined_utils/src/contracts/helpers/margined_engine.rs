@@ -1,11 +1,15 @@
 use cosmwasm_schema::cw_serde;
 use cw_controllers::HooksResponse;
 use margined_perp::margined_engine::{
-    ConfigResponse, ExecuteMsg, PnlCalcOption, Position, PositionFilter, PositionTpSlResponse,
-    PositionUnrealizedPnlResponse, QueryMsg, Side, StateResponse, TickResponse, TicksResponse,
+    AdlRankResponse, AdlRankingResponse, BackfillResponse, ConditionalOrder, ConditionalOrderMsg,
+    ConfigResponse, EscrowResponse, ExecuteMsg, KeeperReward, LiquidationFeeSplit, MarkPricePolicy,
+    MarketStateResponse, PnlCalcOption, Position, PositionFilter, PositionTpSlResponse,
+    PositionUnrealizedPnlResponse, QueryMsg, Side, SignedOrder, StateResponse, TakeProfitLevel,
+    TickResponse, TicksResponse, TpSlTrigger, TraderBalanceResponse, TraderLimits,
+    TraderLimitsResponse, TrailDistance, TwapOrder, WhitelistCapabilities,
 };
 
-use cosmwasm_std::{Addr, Coin, CosmosMsg, QuerierWrapper, StdResult, Uint128};
+use cosmwasm_std::{Addr, Binary, Coin, CosmosMsg, QuerierWrapper, StdResult, Uint128};
 
 use margined_common::integer::Integer;
 
@@ -32,6 +36,7 @@ impl EngineController {
         partial_liquidation_ratio: Option<Uint128>,
         tp_sl_spread: Option<Uint128>,
         liquidation_fee: Option<Uint128>,
+        target_margin_ratio: Option<Uint128>,
     ) -> StdResult<CosmosMsg> {
         wasm_execute(
             &self.0,
@@ -44,6 +49,7 @@ impl EngineController {
                 partial_liquidation_ratio,
                 tp_sl_spread,
                 liquidation_fee,
+                target_margin_ratio,
             },
             vec![],
         )
@@ -61,6 +67,7 @@ impl EngineController {
                 partial_liquidation_ratio: None,
                 tp_sl_spread: None,
                 liquidation_fee: None,
+                target_margin_ratio: None,
             },
             vec![],
         )
@@ -81,6 +88,7 @@ impl EngineController {
                 partial_liquidation_ratio: None,
                 tp_sl_spread: None,
                 liquidation_fee: None,
+                target_margin_ratio: None,
             },
             vec![],
         )
@@ -98,6 +106,7 @@ impl EngineController {
                 partial_liquidation_ratio: None,
                 tp_sl_spread: None,
                 liquidation_fee: None,
+                target_margin_ratio: None,
             },
             vec![],
         )
@@ -116,6 +125,7 @@ impl EngineController {
             partial_liquidation_ratio: Some(partial_liquidation_ratio),
             tp_sl_spread: None,
             liquidation_fee: None,
+            target_margin_ratio: None,
         };
         wasm_execute(&self.0, &msg, vec![])
     }
@@ -130,6 +140,22 @@ impl EngineController {
             partial_liquidation_ratio: None,
             tp_sl_spread: None,
             liquidation_fee: Some(liquidation_fee),
+            target_margin_ratio: None,
+        };
+        wasm_execute(&self.0, &msg, vec![])
+    }
+
+    pub fn set_target_margin_ratio(&self, target_margin_ratio: Uint128) -> StdResult<CosmosMsg> {
+        let msg = ExecuteMsg::UpdateConfig {
+            owner: None,
+            insurance_fund: None,
+            fee_pool: None,
+            initial_margin_ratio: None,
+            maintenance_margin_ratio: None,
+            partial_liquidation_ratio: None,
+            tp_sl_spread: None,
+            liquidation_fee: None,
+            target_margin_ratio: Some(target_margin_ratio),
         };
         wasm_execute(&self.0, &msg, vec![])
     }
@@ -149,6 +175,34 @@ impl EngineController {
         stop_loss: Option<Uint128>,
         base_asset_limit: Uint128,
         funds: Vec<Coin>,
+    ) -> StdResult<CosmosMsg> {
+        self.open_position_with_limits(
+            vamm,
+            side,
+            margin_amount,
+            leverage,
+            take_profit,
+            stop_loss,
+            base_asset_limit,
+            None,
+            None,
+            funds,
+        )
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    pub fn open_position_with_limits(
+        &self,
+        vamm: String,
+        side: Side,
+        margin_amount: Uint128,
+        leverage: Uint128,
+        take_profit: Option<Uint128>,
+        stop_loss: Option<Uint128>,
+        base_asset_limit: Uint128,
+        deadline: Option<u64>,
+        worst_price: Option<Uint128>,
+        funds: Vec<Coin>,
     ) -> StdResult<CosmosMsg> {
         let msg = ExecuteMsg::OpenPosition {
             vamm,
@@ -158,20 +212,239 @@ impl EngineController {
             base_asset_limit,
             take_profit,
             stop_loss,
+            deadline,
+            worst_price,
+            subaccount_id: None,
+        };
+        wasm_execute(&self.0, &msg, funds)
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    pub fn open_position_for_subaccount(
+        &self,
+        vamm: String,
+        subaccount_id: u32,
+        side: Side,
+        margin_amount: Uint128,
+        leverage: Uint128,
+        base_asset_limit: Uint128,
+        funds: Vec<Coin>,
+    ) -> StdResult<CosmosMsg> {
+        let msg = ExecuteMsg::OpenPosition {
+            vamm,
+            side,
+            margin_amount,
+            leverage,
+            base_asset_limit,
+            take_profit: None,
+            stop_loss: None,
+            deadline: None,
+            worst_price: None,
+            subaccount_id: Some(subaccount_id),
+        };
+        wasm_execute(&self.0, &msg, funds)
+    }
+
+    pub fn open_position_by_size(
+        &self,
+        vamm: String,
+        side: Side,
+        base_size: Uint128,
+        margin_amount: Uint128,
+        funds: Vec<Coin>,
+    ) -> StdResult<CosmosMsg> {
+        let msg = ExecuteMsg::OpenPositionBySize {
+            vamm,
+            side,
+            base_size,
+            margin_amount,
+            subaccount_id: None,
+        };
+        wasm_execute(&self.0, &msg, funds)
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    pub fn open_position_from_balance(
+        &self,
+        vamm: String,
+        side: Side,
+        margin_amount: Uint128,
+        leverage: Uint128,
+        take_profit: Option<Uint128>,
+        stop_loss: Option<Uint128>,
+        base_asset_limit: Uint128,
+    ) -> StdResult<CosmosMsg> {
+        let msg = ExecuteMsg::OpenPositionFromBalance {
+            vamm,
+            side,
+            margin_amount,
+            leverage,
+            base_asset_limit,
+            take_profit,
+            stop_loss,
+            subaccount_id: None,
         };
+        wasm_execute(&self.0, &msg, vec![])
+    }
+
+    pub fn open_position_from_subaccount(
+        &self,
+        vamm: String,
+        subaccount_id: u32,
+        side: Side,
+        margin_amount: Uint128,
+        leverage: Uint128,
+        base_asset_limit: Uint128,
+    ) -> StdResult<CosmosMsg> {
+        let msg = ExecuteMsg::OpenPositionFromBalance {
+            vamm,
+            side,
+            margin_amount,
+            leverage,
+            base_asset_limit,
+            take_profit: None,
+            stop_loss: None,
+            subaccount_id: Some(subaccount_id),
+        };
+        wasm_execute(&self.0, &msg, vec![])
+    }
+
+    pub fn transfer_between_subaccounts(
+        &self,
+        from_subaccount_id: u32,
+        to_subaccount_id: u32,
+        amount: Uint128,
+    ) -> StdResult<CosmosMsg> {
+        let msg = ExecuteMsg::TransferBetweenSubaccounts {
+            from_subaccount_id,
+            to_subaccount_id,
+            amount,
+        };
+        wasm_execute(&self.0, &msg, vec![])
+    }
+
+    pub fn deposit(&self, amount: Uint128, funds: Vec<Coin>) -> StdResult<CosmosMsg> {
+        let msg = ExecuteMsg::Deposit { amount };
         wasm_execute(&self.0, &msg, funds)
     }
 
+    pub fn withdraw(&self, amount: Uint128) -> StdResult<CosmosMsg> {
+        let msg = ExecuteMsg::Withdraw { amount };
+        wasm_execute(&self.0, &msg, vec![])
+    }
+
+    pub fn claim(&self) -> StdResult<CosmosMsg> {
+        let msg = ExecuteMsg::Claim {};
+        wasm_execute(&self.0, &msg, vec![])
+    }
+
+    pub fn update_credit_proceeds(&self, credit_proceeds: bool) -> StdResult<CosmosMsg> {
+        let msg = ExecuteMsg::UpdateCreditProceeds { credit_proceeds };
+        wasm_execute(&self.0, &msg, vec![])
+    }
+
+    pub fn update_signing_key(&self, public_key: Option<Binary>) -> StdResult<CosmosMsg> {
+        let msg = ExecuteMsg::UpdateSigningKey { public_key };
+        wasm_execute(&self.0, &msg, vec![])
+    }
+
+    pub fn execute_signed_order(
+        &self,
+        trader: String,
+        order: SignedOrder,
+        signature: Binary,
+    ) -> StdResult<CosmosMsg> {
+        let msg = ExecuteMsg::ExecuteSignedOrder {
+            trader,
+            order,
+            signature,
+        };
+        wasm_execute(&self.0, &msg, vec![])
+    }
+
+    pub fn place_conditional_order(
+        &self,
+        vamm: String,
+        order: ConditionalOrderMsg,
+        oco_order: Option<ConditionalOrderMsg>,
+        funds: Vec<Coin>,
+    ) -> StdResult<CosmosMsg> {
+        let msg = ExecuteMsg::PlaceConditionalOrder {
+            vamm,
+            order,
+            oco_order,
+        };
+        wasm_execute(&self.0, &msg, funds)
+    }
+
+    pub fn execute_conditional_order(&self, order_id: u64) -> StdResult<CosmosMsg> {
+        let msg = ExecuteMsg::ExecuteConditionalOrder { order_id };
+        wasm_execute(&self.0, &msg, vec![])
+    }
+
+    pub fn cancel_conditional_order(&self, order_id: u64) -> StdResult<CosmosMsg> {
+        let msg = ExecuteMsg::CancelConditionalOrder { order_id };
+        wasm_execute(&self.0, &msg, vec![])
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    pub fn place_twap_order(
+        &self,
+        vamm: String,
+        side: Side,
+        margin_amount: Uint128,
+        leverage: Uint128,
+        slices: u64,
+        interval: u64,
+        price_limit: Uint128,
+        funds: Vec<Coin>,
+    ) -> StdResult<CosmosMsg> {
+        let msg = ExecuteMsg::PlaceTwapOrder {
+            vamm,
+            side,
+            margin_amount,
+            leverage,
+            slices,
+            interval,
+            price_limit,
+            subaccount_id: None,
+        };
+        wasm_execute(&self.0, &msg, funds)
+    }
+
+    pub fn execute_twap_order(&self, order_id: u64) -> StdResult<CosmosMsg> {
+        let msg = ExecuteMsg::ExecuteTwapOrder { order_id };
+        wasm_execute(&self.0, &msg, vec![])
+    }
+
+    pub fn cancel_twap_order(&self, order_id: u64) -> StdResult<CosmosMsg> {
+        let msg = ExecuteMsg::CancelTwapOrder { order_id };
+        wasm_execute(&self.0, &msg, vec![])
+    }
+
     pub fn close_position(
         &self,
         vamm: String,
         position_id: u64,
         quote_asset_limit: Uint128,
+    ) -> StdResult<CosmosMsg> {
+        self.close_position_with_limits(vamm, position_id, quote_asset_limit, None, None)
+    }
+
+    pub fn close_position_with_limits(
+        &self,
+        vamm: String,
+        position_id: u64,
+        quote_asset_limit: Uint128,
+        deadline: Option<u64>,
+        worst_price: Option<Uint128>,
     ) -> StdResult<CosmosMsg> {
         let msg = ExecuteMsg::ClosePosition {
             vamm,
             position_id,
             quote_asset_limit,
+            deadline,
+            worst_price,
         };
         wasm_execute(&self.0, &msg, vec![])
     }
@@ -181,15 +454,62 @@ impl EngineController {
         vamm: String,
         position_id: u64,
         quote_asset_limit: Uint128,
+    ) -> StdResult<CosmosMsg> {
+        self.liquidate_with_limits(vamm, position_id, quote_asset_limit, None, None)
+    }
+
+    pub fn liquidate_with_limits(
+        &self,
+        vamm: String,
+        position_id: u64,
+        quote_asset_limit: Uint128,
+        deadline: Option<u64>,
+        worst_price: Option<Uint128>,
     ) -> StdResult<CosmosMsg> {
         let msg = ExecuteMsg::Liquidate {
             vamm,
             position_id,
             quote_asset_limit,
+            deadline,
+            worst_price,
         };
         wasm_execute(&self.0, &msg, vec![])
     }
 
+    pub fn backstop_liquidate(
+        &self,
+        vamm: String,
+        position_id: u64,
+        margin_amount: Uint128,
+        funds: Vec<Coin>,
+    ) -> StdResult<CosmosMsg> {
+        let msg = ExecuteMsg::BackstopLiquidate {
+            vamm,
+            position_id,
+            margin_amount,
+        };
+        wasm_execute(&self.0, &msg, funds)
+    }
+
+    pub fn update_adl_candidates(
+        &self,
+        vamm: String,
+        side: Side,
+        position_ids: Vec<u64>,
+    ) -> StdResult<CosmosMsg> {
+        let msg = ExecuteMsg::UpdateAdlCandidates {
+            vamm,
+            side,
+            position_ids,
+        };
+        wasm_execute(&self.0, &msg, vec![])
+    }
+
+    pub fn backfill(&self, limit: Option<u32>) -> StdResult<CosmosMsg> {
+        let msg = ExecuteMsg::Backfill { limit };
+        wasm_execute(&self.0, &msg, vec![])
+    }
+
     pub fn pay_funding(&self, vamm: String) -> StdResult<CosmosMsg> {
         let msg = ExecuteMsg::PayFunding { vamm };
         wasm_execute(&self.0, &msg, vec![])
@@ -224,6 +544,35 @@ impl EngineController {
         wasm_execute(&self.0, &msg, vec![])
     }
 
+    pub fn set_leverage(
+        &self,
+        vamm: String,
+        position_id: u64,
+        leverage: Uint128,
+        funds: Vec<Coin>,
+    ) -> StdResult<CosmosMsg> {
+        let msg = ExecuteMsg::SetLeverage {
+            vamm,
+            position_id,
+            leverage,
+        };
+        wasm_execute(&self.0, &msg, funds)
+    }
+
+    pub fn realize_pnl(
+        &self,
+        vamm: String,
+        position_id: u64,
+        amount: Uint128,
+    ) -> StdResult<CosmosMsg> {
+        let msg = ExecuteMsg::RealizePnl {
+            vamm,
+            position_id,
+            amount,
+        };
+        wasm_execute(&self.0, &msg, vec![])
+    }
+
     pub fn update_tp_sl(
         &self,
         vamm: String,
@@ -240,6 +589,62 @@ impl EngineController {
         wasm_execute(&self.0, &msg, vec![])
     }
 
+    pub fn update_tp_sl_trigger(
+        &self,
+        vamm: String,
+        position_id: u64,
+        tp_sl_trigger: TpSlTrigger,
+    ) -> StdResult<CosmosMsg> {
+        let msg = ExecuteMsg::UpdateTpSlTrigger {
+            vamm,
+            position_id,
+            tp_sl_trigger,
+        };
+        wasm_execute(&self.0, &msg, vec![])
+    }
+
+    pub fn update_trailing_stop(
+        &self,
+        vamm: String,
+        position_id: u64,
+        distance: Option<TrailDistance>,
+    ) -> StdResult<CosmosMsg> {
+        let msg = ExecuteMsg::UpdateTrailingStop {
+            vamm,
+            position_id,
+            distance,
+        };
+        wasm_execute(&self.0, &msg, vec![])
+    }
+
+    pub fn ratchet_trailing_stop(&self, vamm: String, position_id: u64) -> StdResult<CosmosMsg> {
+        let msg = ExecuteMsg::RatchetTrailingStop { vamm, position_id };
+        wasm_execute(&self.0, &msg, vec![])
+    }
+
+    pub fn update_take_profit_levels(
+        &self,
+        vamm: String,
+        position_id: u64,
+        levels: Vec<TakeProfitLevel>,
+    ) -> StdResult<CosmosMsg> {
+        let msg = ExecuteMsg::UpdateTakeProfitLevels {
+            vamm,
+            position_id,
+            levels,
+        };
+        wasm_execute(&self.0, &msg, vec![])
+    }
+
+    pub fn trigger_take_profit_level(
+        &self,
+        vamm: String,
+        position_id: u64,
+    ) -> StdResult<CosmosMsg> {
+        let msg = ExecuteMsg::TriggerTakeProfitLevel { vamm, position_id };
+        wasm_execute(&self.0, &msg, vec![])
+    }
+
     pub fn trigger_tp_sl(
         &self,
         vamm: String,
@@ -270,8 +675,116 @@ impl EngineController {
         wasm_execute(&self.0, &msg, vec![])
     }
 
+    pub fn update_backstop_pool(
+        &self,
+        backstop_pool: Option<String>,
+        discount: Uint128,
+    ) -> StdResult<CosmosMsg> {
+        let msg = ExecuteMsg::UpdateBackstopPool {
+            backstop_pool,
+            discount,
+        };
+        wasm_execute(&self.0, &msg, vec![])
+    }
+
+    pub fn update_liquidation_fee_split(
+        &self,
+        liquidation_fee_split: LiquidationFeeSplit,
+        max_liquidator_fee: Option<Uint128>,
+    ) -> StdResult<CosmosMsg> {
+        let msg = ExecuteMsg::UpdateLiquidationFeeSplit {
+            liquidation_fee_split,
+            max_liquidator_fee,
+        };
+        wasm_execute(&self.0, &msg, vec![])
+    }
+
+    pub fn update_keeper_reward(&self, keeper_reward: KeeperReward) -> StdResult<CosmosMsg> {
+        let msg = ExecuteMsg::UpdateKeeperReward { keeper_reward };
+        wasm_execute(&self.0, &msg, vec![])
+    }
+
+    pub fn update_trader_limits(
+        &self,
+        trader: String,
+        limits: TraderLimits,
+    ) -> StdResult<CosmosMsg> {
+        let msg = ExecuteMsg::UpdateTraderLimits { trader, limits };
+        wasm_execute(&self.0, &msg, vec![])
+    }
+
+    pub fn update_self_limits(&self, limits: TraderLimits) -> StdResult<CosmosMsg> {
+        let msg = ExecuteMsg::UpdateSelfLimits {
+            limits,
+            subaccount_id: None,
+        };
+        wasm_execute(&self.0, &msg, vec![])
+    }
+
+    pub fn update_subaccount_self_limits(
+        &self,
+        subaccount_id: u32,
+        limits: TraderLimits,
+    ) -> StdResult<CosmosMsg> {
+        let msg = ExecuteMsg::UpdateSelfLimits {
+            limits,
+            subaccount_id: Some(subaccount_id),
+        };
+        wasm_execute(&self.0, &msg, vec![])
+    }
+
+    pub fn update_mark_price_policy(
+        &self,
+        vamm: String,
+        policy: MarkPricePolicy,
+    ) -> StdResult<CosmosMsg> {
+        let msg = ExecuteMsg::UpdateMarkPricePolicy { vamm, policy };
+        wasm_execute(&self.0, &msg, vec![])
+    }
+
+    pub fn update_open_interest_caps(
+        &self,
+        vamm: String,
+        long_cap: Uint128,
+        short_cap: Uint128,
+    ) -> StdResult<CosmosMsg> {
+        let msg = ExecuteMsg::UpdateOpenInterestCaps {
+            vamm,
+            long_cap,
+            short_cap,
+        };
+        wasm_execute(&self.0, &msg, vec![])
+    }
+
     pub fn add_whitelist(&self, address: String) -> StdResult<CosmosMsg> {
-        let msg = ExecuteMsg::AddWhitelist { address };
+        let msg = ExecuteMsg::AddWhitelist {
+            address,
+            capabilities: None,
+        };
+        wasm_execute(&self.0, &msg, vec![])
+    }
+
+    pub fn add_whitelist_with_capabilities(
+        &self,
+        address: String,
+        capabilities: WhitelistCapabilities,
+    ) -> StdResult<CosmosMsg> {
+        let msg = ExecuteMsg::AddWhitelist {
+            address,
+            capabilities: Some(capabilities),
+        };
+        wasm_execute(&self.0, &msg, vec![])
+    }
+
+    pub fn update_whitelist_capabilities(
+        &self,
+        address: String,
+        capabilities: WhitelistCapabilities,
+    ) -> StdResult<CosmosMsg> {
+        let msg = ExecuteMsg::UpdateWhitelistCapabilities {
+            address,
+            capabilities,
+        };
         wasm_execute(&self.0, &msg, vec![])
     }
 
@@ -280,6 +793,26 @@ impl EngineController {
         wasm_execute(&self.0, &msg, vec![])
     }
 
+    pub fn add_backstop_liquidator(&self, address: String) -> StdResult<CosmosMsg> {
+        let msg = ExecuteMsg::AddBackstopLiquidator { address };
+        wasm_execute(&self.0, &msg, vec![])
+    }
+
+    pub fn remove_backstop_liquidator(&self, address: String) -> StdResult<CosmosMsg> {
+        let msg = ExecuteMsg::RemoveBackstopLiquidator { address };
+        wasm_execute(&self.0, &msg, vec![])
+    }
+
+    pub fn add_blocklist(&self, address: String) -> StdResult<CosmosMsg> {
+        let msg = ExecuteMsg::AddBlocklist { address };
+        wasm_execute(&self.0, &msg, vec![])
+    }
+
+    pub fn remove_blocklist(&self, address: String) -> StdResult<CosmosMsg> {
+        let msg = ExecuteMsg::RemoveBlocklist { address };
+        wasm_execute(&self.0, &msg, vec![])
+    }
+
     /// get margin engine configuration
     pub fn config(&self, querier: &QuerierWrapper) -> StdResult<ConfigResponse> {
         let msg = QueryMsg::Config {};
@@ -376,6 +909,57 @@ impl EngineController {
             .map(|res| res.hooks)
     }
 
+    /// get the backstop liquidators
+    pub fn get_backstop_liquidators(&self, querier: &QuerierWrapper) -> StdResult<Vec<String>> {
+        let msg = QueryMsg::GetBackstopLiquidators {};
+
+        querier
+            .query_wasm_smart::<HooksResponse>(&self.0, &msg)
+            .map(|res| res.hooks)
+    }
+
+    /// checks if the address supplied is a backstop liquidator
+    pub fn is_backstop_liquidator(
+        &self,
+        querier: &QuerierWrapper,
+        address: String,
+    ) -> StdResult<bool> {
+        let msg = QueryMsg::IsBackstopLiquidator { address };
+
+        querier.query_wasm_smart(&self.0, &msg)
+    }
+
+    /// checks if the address supplied is in the blocklist
+    pub fn is_blocked(&self, querier: &QuerierWrapper, address: String) -> StdResult<bool> {
+        let msg = QueryMsg::IsBlocked { address };
+
+        querier.query_wasm_smart(&self.0, &msg)
+    }
+
+    /// get the blocklist
+    pub fn get_blocklist(
+        &self,
+        querier: &QuerierWrapper,
+        start_after: Option<String>,
+        limit: Option<u32>,
+    ) -> StdResult<Vec<Addr>> {
+        let msg = QueryMsg::Blocklist { start_after, limit };
+
+        querier.query_wasm_smart(&self.0, &msg)
+    }
+
+    /// get the payouts escrowed for blocked addresses
+    pub fn get_escrows(
+        &self,
+        querier: &QuerierWrapper,
+        start_after: Option<String>,
+        limit: Option<u32>,
+    ) -> StdResult<Vec<EscrowResponse>> {
+        let msg = QueryMsg::Escrows { start_after, limit };
+
+        querier.query_wasm_smart(&self.0, &msg)
+    }
+
     /// checks if the address supplied is in the whitelist
     pub fn is_whitelist(&self, querier: &QuerierWrapper, address: String) -> StdResult<bool> {
         let msg = QueryMsg::IsWhitelisted { address };
@@ -383,6 +967,17 @@ impl EngineController {
         querier.query_wasm_smart(&self.0, &msg)
     }
 
+    /// get the checks a whitelisted address bypasses
+    pub fn get_whitelist_capabilities(
+        &self,
+        querier: &QuerierWrapper,
+        address: String,
+    ) -> StdResult<WhitelistCapabilities> {
+        let msg = QueryMsg::WhitelistCapabilities { address };
+
+        querier.query_wasm_smart(&self.0, &msg)
+    }
+
     /// get unrealized profit and loss for a position
     pub fn get_unrealized_pnl(
         &self,
@@ -412,6 +1007,183 @@ impl EngineController {
         querier.query_wasm_smart(&self.0, &msg)
     }
 
+    /// get mark price policy of a vamm
+    pub fn get_mark_price_policy(
+        &self,
+        querier: &QuerierWrapper,
+        vamm: String,
+    ) -> StdResult<MarkPricePolicy> {
+        let msg = QueryMsg::MarkPricePolicy { vamm };
+
+        querier.query_wasm_smart(&self.0, &msg)
+    }
+
+    /// get the open interest and caps of a market
+    pub fn get_market_state(
+        &self,
+        querier: &QuerierWrapper,
+        vamm: String,
+    ) -> StdResult<MarketStateResponse> {
+        let msg = QueryMsg::MarketState { vamm };
+
+        querier.query_wasm_smart(&self.0, &msg)
+    }
+
+    /// get the limits of a trader and the loss realized by the trader today
+    pub fn get_trader_limits(
+        &self,
+        querier: &QuerierWrapper,
+        trader: String,
+    ) -> StdResult<TraderLimitsResponse> {
+        let msg = QueryMsg::TraderLimits {
+            trader,
+            subaccount_id: None,
+        };
+
+        querier.query_wasm_smart(&self.0, &msg)
+    }
+
+    pub fn get_subaccount_limits(
+        &self,
+        querier: &QuerierWrapper,
+        trader: String,
+        subaccount_id: u32,
+    ) -> StdResult<TraderLimitsResponse> {
+        let msg = QueryMsg::TraderLimits {
+            trader,
+            subaccount_id: Some(subaccount_id),
+        };
+
+        querier.query_wasm_smart(&self.0, &msg)
+    }
+
+    /// get the balance of a trader in the engine
+    pub fn get_trader_balance(
+        &self,
+        querier: &QuerierWrapper,
+        trader: String,
+    ) -> StdResult<TraderBalanceResponse> {
+        let msg = QueryMsg::TraderBalance {
+            trader,
+            subaccount_id: None,
+        };
+
+        querier.query_wasm_smart(&self.0, &msg)
+    }
+
+    /// get the balance of a sub-account of a trader in the engine
+    pub fn get_subaccount_balance(
+        &self,
+        querier: &QuerierWrapper,
+        trader: String,
+        subaccount_id: u32,
+    ) -> StdResult<TraderBalanceResponse> {
+        let msg = QueryMsg::TraderBalance {
+            trader,
+            subaccount_id: Some(subaccount_id),
+        };
+
+        querier.query_wasm_smart(&self.0, &msg)
+    }
+
+    /// get the payouts of an address that failed to be transferred
+    pub fn get_claimable_balance(
+        &self,
+        querier: &QuerierWrapper,
+        address: String,
+    ) -> StdResult<Uint128> {
+        let msg = QueryMsg::ClaimableBalance { address };
+
+        querier.query_wasm_smart(&self.0, &msg)
+    }
+
+    /// get the public key that signs the orders of a trader
+    pub fn get_signing_key(
+        &self,
+        querier: &QuerierWrapper,
+        trader: String,
+    ) -> StdResult<Option<Binary>> {
+        let msg = QueryMsg::SigningKey { trader };
+
+        querier.query_wasm_smart(&self.0, &msg)
+    }
+
+    /// get if the nonce of a signed order is used
+    pub fn is_nonce_used(
+        &self,
+        querier: &QuerierWrapper,
+        trader: String,
+        nonce: u64,
+    ) -> StdResult<bool> {
+        let msg = QueryMsg::IsNonceUsed { trader, nonce };
+
+        querier.query_wasm_smart(&self.0, &msg)
+    }
+
+    /// get a conditional order
+    pub fn get_conditional_order(
+        &self,
+        querier: &QuerierWrapper,
+        order_id: u64,
+    ) -> StdResult<ConditionalOrder> {
+        let msg = QueryMsg::ConditionalOrder { order_id };
+
+        querier.query_wasm_smart(&self.0, &msg)
+    }
+
+    /// get the conditional orders of a trader
+    pub fn get_conditional_orders(
+        &self,
+        querier: &QuerierWrapper,
+        trader: String,
+        start_after: Option<u64>,
+        limit: Option<u32>,
+    ) -> StdResult<Vec<ConditionalOrder>> {
+        let msg = QueryMsg::ConditionalOrders {
+            trader,
+            start_after,
+            limit,
+        };
+
+        querier.query_wasm_smart(&self.0, &msg)
+    }
+
+    /// get a twap order
+    pub fn get_twap_order(&self, querier: &QuerierWrapper, order_id: u64) -> StdResult<TwapOrder> {
+        let msg = QueryMsg::TwapOrder { order_id };
+
+        querier.query_wasm_smart(&self.0, &msg)
+    }
+
+    /// get the twap orders of a trader
+    pub fn get_twap_orders(
+        &self,
+        querier: &QuerierWrapper,
+        trader: String,
+        start_after: Option<u64>,
+        limit: Option<u32>,
+    ) -> StdResult<Vec<TwapOrder>> {
+        let msg = QueryMsg::TwapOrders {
+            trader,
+            start_after,
+            limit,
+        };
+
+        querier.query_wasm_smart(&self.0, &msg)
+    }
+
+    /// get remaining take profit levels of a position
+    pub fn get_take_profit_levels(
+        &self,
+        querier: &QuerierWrapper,
+        vamm: String,
+        position_id: u64,
+    ) -> StdResult<Vec<TakeProfitLevel>> {
+        let msg = QueryMsg::TakeProfitLevels { vamm, position_id };
+
+        querier.query_wasm_smart(&self.0, &msg)
+    }
+
     /// get margin ratio
     pub fn get_margin_ratio(
         &self,
@@ -476,4 +1248,36 @@ impl EngineController {
 
         querier.query_wasm_smart(&self.0, &msg)
     }
+
+    /// get the auto-deleveraging queue for one side of a vamm
+    pub fn get_adl_ranking(
+        &self,
+        querier: &QuerierWrapper,
+        vamm: String,
+        side: Side,
+        limit: Option<u32>,
+    ) -> StdResult<AdlRankingResponse> {
+        let msg = QueryMsg::AdlRanking { vamm, side, limit };
+
+        querier.query_wasm_smart(&self.0, &msg)
+    }
+
+    /// get the auto-deleveraging queue position of a position
+    pub fn get_adl_rank(
+        &self,
+        querier: &QuerierWrapper,
+        vamm: String,
+        position_id: u64,
+    ) -> StdResult<AdlRankResponse> {
+        let msg = QueryMsg::AdlRank { vamm, position_id };
+
+        querier.query_wasm_smart(&self.0, &msg)
+    }
+
+    /// get the stages of the backfill left to run
+    pub fn get_backfill(&self, querier: &QuerierWrapper) -> StdResult<BackfillResponse> {
+        let msg = QueryMsg::Backfill {};
+
+        querier.query_wasm_smart(&self.0, &msg)
+    }
 }
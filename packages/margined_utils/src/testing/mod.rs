@@ -179,6 +179,7 @@ impl NativeTokenScenario {
                     partial_liquidation_ratio: None,
                     tp_sl_spread: None,
                     liquidation_fee: None,
+                    target_margin_ratio: None,
                 },
                 &[],
             )
@@ -462,6 +463,7 @@ impl SimpleScenario {
                     partial_liquidation_ratio: None,
                     tp_sl_spread: None,
                     liquidation_fee: None,
+                    target_margin_ratio: None,
                 },
                 &[],
             )
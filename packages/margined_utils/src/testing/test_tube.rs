@@ -209,6 +209,7 @@ impl TestTubeScenario {
                 partial_liquidation_ratio: None,
                 tp_sl_spread: None,
                 liquidation_fee: None,
+                target_margin_ratio: None,
             },
             &[],
             owner,